@@ -0,0 +1,119 @@
+//! Reference server of the remote AuthDB protocol, see [lms_core::authdb::remote].
+//! It keeps the users encrypted on local disk, like a local `authDbPath`,
+//! and needs the same `auth` keys as the lms servers pointing `authDbPath` to it.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use clap::Parser;
+use http_body_util::Full;
+use hyper::service::service_fn;
+use hyper::{Method, Response};
+use tokio::net::TcpListener;
+
+use lms_core::authdb::remote::RemoteServer;
+use lms_core::config::config_module::ConfigModule;
+use lms_core::config::Config;
+
+#[derive(Parser)]
+#[command(name = "lms-auth-server")]
+struct Cli {
+    /// Path of the lms configuration file, only the `auth` keys are used
+    #[arg(required = true)]
+    config_path: String,
+    /// File the users are stored in
+    #[arg(short, long)]
+    db_path: String,
+    #[arg(long, default_value = "127.0.0.1")]
+    host: String,
+    #[arg(short, long, default_value_t = 19195)]
+    port: u16,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::new().filter_or("LMS_LOG_LEVEL", "info")).init();
+    let cli = Cli::parse();
+
+    let config = Config::from_json(&tokio::fs::read_to_string(&cli.config_path).await?)?;
+    let auth = ConfigModule::from(config).auth_provider(cli.db_path)?;
+    let server = Arc::new(RemoteServer::new(auth, lms::cli::rt::init())?);
+
+    let listener = TcpListener::bind(format!("{}:{}", cli.host, cli.port)).await?;
+    log::info!("Listening on: http://{}", listener.local_addr()?);
+    serve(listener, server).await
+}
+
+async fn serve(listener: TcpListener, server: Arc<RemoteServer>) -> anyhow::Result<()> {
+    loop {
+        let (stream, remote_addr) = listener.accept().await?;
+        let io = hyper_util::rt::TokioIo::new(stream);
+        let server = server.clone();
+        tokio::spawn(async move {
+            let service = service_fn(move |req| handle(req, remote_addr, server.clone()));
+            if let Err(e) = hyper::server::conn::http1::Builder::new()
+                .serve_connection(io, service)
+                .await
+            {
+                log::error!("An error occurred while handling a request: {e}");
+            }
+        });
+    }
+}
+
+async fn handle(
+    req: hyper::Request<hyper::body::Incoming>,
+    remote_addr: SocketAddr,
+    server: Arc<RemoteServer>,
+) -> anyhow::Result<Response<Full<bytes::Bytes>>> {
+    let req = lms_core::http::request::Request::from_hyper(req, Some(remote_addr.ip())).await?;
+    if req.method != Method::POST {
+        return Ok(Response::builder()
+            .status(405)
+            .body(Full::new(bytes::Bytes::new()))?);
+    }
+    let response = server.handle(&req.body).await;
+    if let Some(error) = response.error.as_ref() {
+        log::warn!("{}: {}", remote_addr.ip(), error.message);
+    }
+    response.into_hyper_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lms_core::authdb::auth_actors::{Authority, User};
+    use lms_core::authdb::auth_store::{AuthStore, RemoteAuthStore};
+
+    #[tokio::test]
+    async fn test_remote_auth_store() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut config = Config::default();
+        config.auth.aes_key = "32bytebase64encodedkey".to_string();
+        config.auth.totp.totp_secret = "base32encodedkey".to_string();
+        let module = ConfigModule::from(config);
+
+        let db_path = dir.path().join("auth").to_string_lossy().to_string();
+        let server = RemoteServer::new(module.auth_provider(db_path)?, lms::cli::rt::init())?;
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}", listener.local_addr()?);
+        tokio::spawn(serve(listener, Arc::new(server)));
+
+        let store = RemoteAuthStore::new(module.auth_provider(url)?, lms::cli::rt::init());
+        let user = User {
+            username: "foo".to_string(),
+            name: "Foo".to_string(),
+            password: "password".to_string(),
+            authority: Authority::Admin,
+            batch: None,
+            two_factor: None,
+            password_reset: None,
+        };
+        store.upsert(user.clone()).await?;
+        assert_eq!(store.get("foo").await?, Some(user));
+        assert_eq!(store.list().await?.get_all().len(), 1);
+        store.delete("foo").await?;
+        assert_eq!(store.get("foo").await?, None);
+        Ok(())
+    }
+}
@@ -1,18 +1,18 @@
 use crate::is_default;
 use anyhow::{anyhow, Context, Result};
 use http_body_util::Full;
-use libaes::AES_256_KEY_LEN;
 use serde::{Deserialize, Serialize};
 
 use totp_rs::TOTP;
 
-use crate::local_crypto::{decrypt_aes, encrypt_aes, gen_totp, hash_128, hash_256};
+use crate::keyring::{Key, Keyring, DEFAULT_KEY_ID};
+use crate::local_crypto::hash_256;
+use crate::session::Claims;
 
 #[derive(Debug, Clone)]
 pub struct AuthProvider {
     auth_db_path: String,
-    totp: TOTP,
-    aes_key: Vec<u8>,
+    keyring: Keyring,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -21,6 +21,50 @@ pub struct AuthRequest {
     pub password: String,
     #[serde(default, skip_serializing_if = "is_default")]
     pub signup_details: Option<SignUpDet>,
+    /// TOTP or recovery code, required if the user enabled two-factor authentication
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub otp: Option<String>,
+}
+
+/// Body of `POST /auth/logout`, revokes the token along with the refresh token.
+#[derive(Serialize, Deserialize)]
+pub struct LogoutRequest {
+    pub token: String,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub refresh_token: Option<String>,
+}
+
+/// Body of `POST /auth/refresh`, exchanges the refresh token for a new pair of tokens.
+#[derive(Serialize, Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+/// Body of `POST /auth/revoke`, revokes all the sessions of `username`.
+/// `token` must belong to an admin.
+#[derive(Serialize, Deserialize)]
+pub struct RevokeRequest {
+    pub token: String,
+    pub username: String,
+}
+
+/// Body of `POST /auth/password`, changes the password of `username`.
+/// `password` is the current password or the reset code issued by an admin.
+#[derive(Serialize, Deserialize)]
+pub struct PasswordRequest {
+    pub username: String,
+    pub password: String,
+    pub new_password: String,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub otp: Option<String>,
+}
+
+/// Body of `POST /auth/password/reset`, issues a reset code for `username`.
+/// `token` must belong to an admin.
+#[derive(Serialize, Deserialize)]
+pub struct ResetRequest {
+    pub token: String,
+    pub username: String,
 }
 
 #[derive(PartialEq, Eq, Serialize, Deserialize)]
@@ -41,6 +85,24 @@ pub struct AuthResult {
     pub success: Option<AuthSucc>,
     #[serde(default)]
     pub code: u16,
+
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub two_factor: Option<TwoFactorSetup>,
+
+    /// One-time code issued by `/auth/password/reset`
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub reset_code: Option<String>,
+}
+
+/// Returned while enrolling a second factor, see `/auth/2fa/enroll` and `/auth/2fa/verify`.
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TwoFactorSetup {
+    /// `otpauth://` URI of the new secret, to be scanned by an authenticator app
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub uri: Option<String>,
+    /// Single use codes to login without the authenticator, shown only once
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub recovery_codes: Vec<String>,
 }
 
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -48,6 +110,8 @@ pub struct AuthSucc {
     #[serde(default, skip_serializing_if = "is_default")]
     pub name: String,
     pub token: String,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub refresh_token: Option<String>,
 }
 
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -75,8 +139,13 @@ impl AuthRequest {
             username: username.as_ref().to_string(),
             password,
             signup_details,
+            otp: None,
         })
     }
+    pub fn with_otp<T: AsRef<str>>(mut self, otp: T) -> Self {
+        self.otp = Some(otp.as_ref().to_string());
+        self
+    }
     pub fn into_serrequet(self) -> Result<String> {
         let request =
             serde_json::to_string(&self).map_err(|_| anyhow!("Unable to encode request"))?;
@@ -89,6 +158,49 @@ impl AuthRequest {
     }
 }
 
+impl LogoutRequest {
+    pub fn try_from_bytes<T: AsRef<[u8]>>(req: T) -> Result<Self> {
+        serde_json::from_slice::<Self>(req.as_ref()).map_err(|_| anyhow!("Unable to parse request"))
+    }
+}
+
+impl RefreshRequest {
+    pub fn try_from_bytes<T: AsRef<[u8]>>(req: T) -> Result<Self> {
+        serde_json::from_slice::<Self>(req.as_ref()).map_err(|_| anyhow!("Unable to parse request"))
+    }
+}
+
+impl RevokeRequest {
+    pub fn try_from_bytes<T: AsRef<[u8]>>(req: T) -> Result<Self> {
+        serde_json::from_slice::<Self>(req.as_ref()).map_err(|_| anyhow!("Unable to parse request"))
+    }
+}
+
+impl PasswordRequest {
+    /// Hashes both passwords the way [AuthRequest::new] does.
+    pub fn new<T: AsRef<str>>(username: T, password: T, new_password: T) -> Self {
+        Self {
+            username: username.as_ref().to_string(),
+            password: hash_256(password),
+            new_password: hash_256(new_password),
+            otp: None,
+        }
+    }
+    pub fn with_otp<T: AsRef<str>>(mut self, otp: T) -> Self {
+        self.otp = Some(otp.as_ref().to_string());
+        self
+    }
+    pub fn try_from_bytes<T: AsRef<[u8]>>(req: T) -> Result<Self> {
+        serde_json::from_slice::<Self>(req.as_ref()).map_err(|_| anyhow!("Unable to parse request"))
+    }
+}
+
+impl ResetRequest {
+    pub fn try_from_bytes<T: AsRef<[u8]>>(req: T) -> Result<Self> {
+        serde_json::from_slice::<Self>(req.as_ref()).map_err(|_| anyhow!("Unable to parse request"))
+    }
+}
+
 impl AuthResult {
     pub fn try_from_ser_response(response: &str) -> Result<Self> {
         let result = serde_json::from_str::<AuthResult>(response)?;
@@ -106,15 +218,15 @@ impl AuthResult {
 
 impl AuthProvider {
     pub fn init(auth_db_path: String, totp: TOTP, aes_key: String) -> Result<AuthProvider> {
-        let aes_key = hash_128(aes_key)[..32].to_string();
-        assert_eq!(aes_key.len(), AES_256_KEY_LEN, "AES key must be 16 bytes");
+        let key = Key::derive(DEFAULT_KEY_ID, totp, aes_key)?;
+        Ok(Self::from_keyring(auth_db_path, Keyring::new(key)))
+    }
 
-        let provider = Self {
+    pub fn from_keyring(auth_db_path: String, keyring: Keyring) -> AuthProvider {
+        Self {
             auth_db_path,
-            totp,
-            aes_key: aes_key.into_bytes(),
-        };
-        Ok(provider)
+            keyring,
+        }
     }
 
     pub async fn authenticate(&self, username: &str, password: &str) -> Result<AuthSucc> {
@@ -135,23 +247,34 @@ impl AuthProvider {
 
         result.success.context("Internal error: Empty response")
     }
+    /// Encrypts with the active key.
     pub fn encrypt_aes<T: AsRef<str>>(&self, content: T) -> Result<String> {
-        encrypt_aes(&self.aes_key, content.as_ref())
+        self.keyring.encrypt(content.as_ref())
     }
 
+    /// Decrypts with the active or any of the retired keys.
     pub fn decrypt_aes<T: AsRef<[u8]>>(&self, content: T) -> Result<String> {
-        decrypt_aes(&self.aes_key, content.as_ref())
+        self.keyring.decrypt(content)
+    }
+    pub fn sign_session(&self, claims: &Claims) -> Result<String> {
+        self.keyring.sign(claims)
+    }
+
+    pub fn verify_session(&self, token: &str, now: u128) -> Result<Claims> {
+        self.keyring.verify(token, now)
+    }
+    pub fn gen_sig(&self, a: &str, b: &str, now: u128) -> Result<String> {
+        self.keyring.gen_sig(a, b, now)
     }
-    pub fn gen_sig(&self, a: &str, b: &str) -> Result<String> {
-        let totp = gen_totp(&self.totp)?;
-        Ok(hash_256(format!("{}ssdd{}{}", totp, a, b)))
+    pub fn verify_sig(&self, a: &str, b: &str, sig: &str, now: u128) -> bool {
+        self.keyring.verify_sig(a, b, sig, now)
+    }
+    pub fn keyring(&self) -> &Keyring {
+        &self.keyring
     }
     pub fn db_path(&self) -> &str {
         &self.auth_db_path
     }
-    pub fn get_pw(&self) -> &[u8] {
-        &self.aes_key
-    }
 }
 
 #[cfg(test)]
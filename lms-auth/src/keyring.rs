@@ -0,0 +1,271 @@
+use anyhow::{anyhow, Result};
+use libaes::AES_256_KEY_LEN;
+use totp_rs::TOTP;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::local_crypto::{
+    ciphertext_key_id, decrypt_aes, encrypt_aes_with_id, hash_128, hash_256,
+};
+use crate::session::{Claims, SessionSigner};
+
+/// Id of the key when `auth.keyId` is not configured.
+pub const DEFAULT_KEY_ID: &str = "default";
+
+/// A single generation of secrets derived from `aesKey` and `totpSecret`.
+#[derive(Debug, Clone)]
+pub struct Key {
+    id: String,
+    totp: TOTP,
+    aes_key: Vec<u8>,
+    signer: SessionSigner,
+    /// Tokens signed with a retired key are accepted until this time, ms since epoch.
+    valid_until: Option<u128>,
+}
+
+impl Key {
+    pub fn derive<T: AsRef<str>>(id: T, totp: TOTP, aes_key: String) -> Result<Self> {
+        let id = id.as_ref().to_string();
+        if id.is_empty() || id.contains(':') || id.contains('.') {
+            return Err(anyhow!("Invalid key id: {}", id));
+        }
+        let signer = SessionSigner::new(hash_256(format!("session{}", aes_key)));
+        let aes_key = hash_128(aes_key)
+            .get(..AES_256_KEY_LEN)
+            .ok_or(anyhow!("AES key must be {} bytes", AES_256_KEY_LEN))?
+            .to_string();
+
+        Ok(Self {
+            id,
+            totp,
+            aes_key: aes_key.into_bytes(),
+            signer,
+            valid_until: None,
+        })
+    }
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+    pub fn aes_key(&self) -> &[u8] {
+        &self.aes_key
+    }
+}
+
+/// The active key along with the retired ones.
+/// Everything is encrypted and signed with the active key,
+/// retired keys are only used to read data written before the rotation.
+#[derive(Debug, Clone)]
+pub struct Keyring {
+    active: Key,
+    retired: Vec<Key>,
+}
+
+impl Keyring {
+    pub fn new(active: Key) -> Self {
+        Self {
+            active,
+            retired: vec![],
+        }
+    }
+
+    /// Adds a retired key, tokens signed with it are accepted until `valid_until`.
+    pub fn retire(mut self, mut key: Key, valid_until: u128) -> Result<Self> {
+        if self.get(&key.id).is_some() {
+            return Err(anyhow!("Duplicate key id: {}", key.id));
+        }
+        key.valid_until = Some(valid_until);
+        self.retired.push(key);
+        Ok(self)
+    }
+
+    pub fn active(&self) -> &Key {
+        &self.active
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Key> {
+        self.keys().find(|key| key.id == id)
+    }
+
+    /// All the keys, starting with the active one.
+    pub fn keys(&self) -> impl Iterator<Item = &Key> {
+        std::iter::once(&self.active).chain(self.retired.iter())
+    }
+
+    pub fn encrypt(&self, data: &str) -> Result<String> {
+        encrypt_aes_with_id(&self.active.id, &self.active.aes_key, data)
+    }
+
+    /// Decrypts with the key the data is tagged with,
+    /// untagged data is tried against every key.
+    pub fn decrypt<T: AsRef<[u8]>>(&self, data: T) -> Result<String> {
+        let data = data.as_ref();
+        if let Some(key_id) = ciphertext_key_id(data) {
+            let key = self
+                .get(key_id)
+                .ok_or(anyhow!("Unknown key id: {}", key_id))?;
+            return decrypt_aes(&key.aes_key, data);
+        }
+        let mut last_err = anyhow!("No keys found");
+        for key in self.keys() {
+            match decrypt_aes(&key.aes_key, data) {
+                Ok(decrypted) => return Ok(decrypted),
+                Err(e) => last_err = e,
+            }
+        }
+        Err(last_err)
+    }
+
+    /// Signs the claims with the active key, the token is prefixed with the key id: `<key id>.<payload>.<signature>`
+    pub fn sign(&self, claims: &Claims) -> Result<String> {
+        Ok(format!(
+            "{}.{}",
+            self.active.id,
+            self.active.signer.sign(claims)?
+        ))
+    }
+
+    pub fn verify(&self, token: &str, now: u128) -> Result<Claims> {
+        let (key, token) = match token.split('.').count() {
+            // issued before tokens carried a key id
+            2 => (&self.active, token),
+            3 => {
+                let (key_id, token) = token.split_once('.').ok_or(anyhow!("Invalid token"))?;
+                let key = self.get(key_id).ok_or(anyhow!("Invalid token"))?;
+                (key, token)
+            }
+            _ => return Err(anyhow!("Invalid token")),
+        };
+        if let Some(valid_until) = key.valid_until {
+            if valid_until <= now {
+                return Err(anyhow!("Token expired, please re-login"));
+            }
+        }
+        key.signer.verify(token, now)
+    }
+
+    /// Signs `a` and `b` with the active key, the signature is only valid for the TOTP step of `now` (ms since epoch).
+    pub fn gen_sig(&self, a: &str, b: &str, now: u128) -> Result<String> {
+        gen_sig(&self.active.totp, a, b, now)
+    }
+
+    /// Verifies a signature generated by [Keyring::gen_sig] with any of the keys.
+    pub fn verify_sig(&self, a: &str, b: &str, sig: &str, now: u128) -> bool {
+        let Ok(sig) = hex::decode(sig) else {
+            return false;
+        };
+        self.keys().any(|key| {
+            sig_mac(&key.totp, a, b, now).is_ok_and(|mac| mac.verify_slice(&sig).is_ok())
+        })
+    }
+}
+
+fn gen_sig(totp: &TOTP, a: &str, b: &str, now: u128) -> Result<String> {
+    Ok(hex::encode(
+        sig_mac(totp, a, b, now)?.finalize().into_bytes(),
+    ))
+}
+
+/// HMAC-SHA256 keyed with the TOTP secret over the code of the step and both inputs.
+fn sig_mac(totp: &TOTP, a: &str, b: &str, now: u128) -> Result<Hmac<Sha256>> {
+    let code = totp.generate((now / 1000) as u64);
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(&totp.secret).map_err(|_| anyhow!("Invalid signing key"))?;
+    mac.update(code.as_bytes());
+    // length prefixed, so moving bytes from `a` to `b` changes the signature
+    mac.update(&(a.len() as u64).to_be_bytes());
+    mac.update(a.as_bytes());
+    mac.update(b.as_bytes());
+    Ok(mac)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::local_crypto::encrypt_aes;
+    use totp_rs::{Algorithm, Secret};
+
+    fn totp(secret: &str) -> TOTP {
+        TOTP::new(
+            Algorithm::SHA1,
+            6,
+            1,
+            30,
+            Secret::Raw(secret.as_bytes().to_vec()).to_bytes().unwrap(),
+        )
+        .unwrap()
+    }
+
+    fn claims(exp: u128) -> Claims {
+        Claims {
+            sub: "foo".to_string(),
+            authority: 2,
+            batch: None,
+            iat: 1,
+            exp,
+            jti: "jti".to_string(),
+        }
+    }
+
+    fn old_key() -> Key {
+        Key::derive("old", totp("JBSWY3DPEHPK3PXP"), "old aes key".to_string()).unwrap()
+    }
+
+    fn new_key() -> Key {
+        Key::derive("new", totp("KRSXG5CTMVRXEZLU"), "new aes key".to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_decrypt_retired() -> Result<()> {
+        let old = Keyring::new(old_key());
+        let tagged = old.encrypt("tagged")?;
+        let untagged = encrypt_aes(old_key().aes_key(), "untagged")?;
+
+        let rotated = Keyring::new(new_key()).retire(old_key(), 10)?;
+        assert_eq!(rotated.decrypt(tagged)?, "tagged");
+        assert_eq!(rotated.decrypt(untagged)?, "untagged");
+
+        let encrypted = rotated.encrypt("new")?;
+        assert!(encrypted.starts_with("v2:new:"));
+        assert!(old.decrypt(encrypted).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_retired_token() -> Result<()> {
+        let token = Keyring::new(old_key()).sign(&claims(100))?;
+        assert!(token.starts_with("old."));
+
+        let rotated = Keyring::new(new_key()).retire(old_key(), 10)?;
+        assert_eq!(rotated.verify(&token, 5)?, claims(100));
+        // grace period is over
+        assert!(rotated.verify(&token, 10).is_err());
+
+        let token = rotated.sign(&claims(100))?;
+        assert!(token.starts_with("new."));
+        assert_eq!(rotated.verify(&token, 50)?, claims(100));
+        assert!(Keyring::new(old_key()).verify(&token, 5).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_keys() {
+        assert!(Key::derive("a:b", totp("JBSWY3DPEHPK3PXP"), "key".to_string()).is_err());
+        assert!(Key::derive("", totp("JBSWY3DPEHPK3PXP"), "key".to_string()).is_err());
+        assert!(Keyring::new(old_key()).retire(old_key(), 10).is_err());
+    }
+
+    #[test]
+    fn test_verify_sig_retired() -> Result<()> {
+        let now = 1_700_000_000_000;
+        let sig = Keyring::new(old_key()).gen_sig("a", "b", now)?;
+        let rotated = Keyring::new(new_key()).retire(old_key(), 10)?;
+        assert!(rotated.verify_sig("a", "b", &sig, now));
+        assert!(!rotated.verify_sig("a", "c", &sig, now));
+        assert!(!rotated.verify_sig("ab", "", &sig, now));
+        assert!(!rotated.verify_sig("a", "b", "not hex", now));
+        // the signature is bound to the TOTP step
+        assert!(!rotated.verify_sig("a", "b", &sig, now + 60_000));
+        Ok(())
+    }
+}
@@ -1,5 +1,7 @@
 pub mod auth;
+pub mod keyring;
 pub mod local_crypto;
+pub mod session;
 
 pub fn is_default<T: Default + Eq>(val: &T) -> bool {
     *val == T::default()
@@ -1,12 +1,27 @@
-use anyhow::Result;
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use anyhow::{anyhow, Result};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
 use base64::prelude::BASE64_STANDARD;
 use base64::Engine;
 use sha1::Sha1;
 use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
 use totp_rs::TOTP;
 
+/// IV of the legacy AES-256-CBC scheme, only used to decrypt old blobs.
 const IV: [u8; 16] = [7; 16];
 
+/// Prefix of the AES-256-GCM envelope: `v1:base64(nonce || ciphertext || tag)`.
+/// Ciphertexts without a known prefix are treated as legacy AES-256-CBC.
+const ENVELOPE_V1: &str = "v1:";
+/// Same as v1, but tagged with the id of the key: `v2:<key id>:base64(nonce || ciphertext || tag)`.
+const ENVELOPE_V2: &str = "v2:";
+pub const NONCE_LEN: usize = 12;
+/// Size of the GCM tag appended to a ciphertext.
+pub const TAG_LEN: usize = 16;
+
 pub fn gen_totp(totp: &TOTP) -> Result<String> {
     Ok(totp.generate_current()?)
 }
@@ -15,15 +30,103 @@ pub fn verify_totp(totp: &TOTP, code: &str) -> Result<bool> {
     Ok(totp.check_current(code)?)
 }
 
+/// Encrypts the data with AES-256-GCM under a random nonce.
 pub fn encrypt_aes(key: &[u8], data: &str) -> Result<String> {
-    let key: &[u8; 32] = key.try_into()?;
-    let cipher = libaes::Cipher::new_256(key);
-    let encrypted = cipher.cbc_encrypt(&IV, data.as_bytes());
-    let encrypted = BASE64_STANDARD.encode(encrypted);
-    Ok(encrypted)
+    Ok(format!("{}{}", ENVELOPE_V1, seal(key, data)?))
 }
 
+/// Same as [encrypt_aes], but tags the ciphertext with the id of the key,
+/// so that it can be decrypted after the key is rotated.
+pub fn encrypt_aes_with_id(key_id: &str, key: &[u8], data: &str) -> Result<String> {
+    if key_id.contains(':') {
+        return Err(anyhow!("Key id must not contain ':'"));
+    }
+    Ok(format!("{}{}:{}", ENVELOPE_V2, key_id, seal(key, data)?))
+}
+
+/// Decrypts data encrypted by [encrypt_aes], [encrypt_aes_with_id]
+/// or by the legacy AES-256-CBC scheme.
+/// Fails if the ciphertext was tampered with.
 pub fn decrypt_aes<T: AsRef<[u8]>>(key: &[u8], data: T) -> Result<String> {
+    let data = data.as_ref();
+    if let Some(data) = data.strip_prefix(ENVELOPE_V1.as_bytes()) {
+        open(key, data)
+    } else if let Some(data) = data.strip_prefix(ENVELOPE_V2.as_bytes()) {
+        let (_, data) = std::str::from_utf8(data)?
+            .split_once(':')
+            .ok_or(anyhow!("Invalid ciphertext"))?;
+        open(key, data.as_bytes())
+    } else {
+        decrypt_aes_cbc(key, data)
+    }
+}
+
+/// Returns the id of the key the data was encrypted with, if it was tagged.
+pub fn ciphertext_key_id(data: &[u8]) -> Option<&str> {
+    let data = std::str::from_utf8(data.strip_prefix(ENVELOPE_V2.as_bytes())?).ok()?;
+    data.split_once(':').map(|(key_id, _)| key_id)
+}
+
+/// Returns true if the ciphertext was produced by the legacy AES-256-CBC scheme
+/// and should be re-encrypted.
+pub fn is_legacy_ciphertext<T: AsRef<[u8]>>(data: T) -> bool {
+    let data = data.as_ref();
+    !data.starts_with(ENVELOPE_V1.as_bytes()) && !data.starts_with(ENVELOPE_V2.as_bytes())
+}
+
+/// Encrypts raw bytes with AES-256-GCM under a random nonce: `nonce || ciphertext || tag`.
+pub fn encrypt_bytes(key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    let cipher = Aes256Gcm::new_from_slice(key).map_err(|_| anyhow!("Invalid key length"))?;
+    let mut nonce = [0u8; NONCE_LEN];
+    getrandom::getrandom(&mut nonce).map_err(|e| anyhow!("Unable to generate nonce: {}", e))?;
+    let encrypted = cipher
+        .encrypt(Nonce::from_slice(&nonce), data)
+        .map_err(|_| anyhow!("Unable to encrypt data"))?;
+
+    let mut envelope = nonce.to_vec();
+    envelope.extend(encrypted);
+    Ok(envelope)
+}
+
+/// Decrypts bytes encrypted by [encrypt_bytes], fails if they were tampered with.
+pub fn decrypt_bytes(key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    let cipher = Aes256Gcm::new_from_slice(key).map_err(|_| anyhow!("Invalid key length"))?;
+    if data.len() < NONCE_LEN {
+        return Err(anyhow!("Invalid ciphertext"));
+    }
+    let (nonce, encrypted) = data.split_at(NONCE_LEN);
+    cipher
+        .decrypt(Nonce::from_slice(nonce), encrypted)
+        .map_err(|_| anyhow!("Unable to decrypt data, invalid key or tampered ciphertext"))
+}
+
+/// Encrypts raw bytes with AES-256-GCM under `nonce`, authenticating `aad` along with them:
+/// `ciphertext || tag`. A nonce must never be used twice with the same key.
+pub fn seal_bytes(key: &[u8], nonce: &[u8; NONCE_LEN], aad: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    let cipher = Aes256Gcm::new_from_slice(key).map_err(|_| anyhow!("Invalid key length"))?;
+    cipher
+        .encrypt(Nonce::from_slice(nonce), Payload { msg: data, aad })
+        .map_err(|_| anyhow!("Unable to encrypt data"))
+}
+
+/// Decrypts bytes sealed by [seal_bytes], fails if they or `aad` were tampered with.
+pub fn open_bytes(key: &[u8], nonce: &[u8; NONCE_LEN], aad: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    let cipher = Aes256Gcm::new_from_slice(key).map_err(|_| anyhow!("Invalid key length"))?;
+    cipher
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: data, aad })
+        .map_err(|_| anyhow!("Unable to decrypt data, invalid key or tampered ciphertext"))
+}
+
+fn seal(key: &[u8], data: &str) -> Result<String> {
+    Ok(BASE64_STANDARD.encode(encrypt_bytes(key, data.as_bytes())?))
+}
+
+fn open(key: &[u8], data: &[u8]) -> Result<String> {
+    let data = BASE64_STANDARD.decode(data)?;
+    Ok(String::from_utf8(decrypt_bytes(key, &data)?)?)
+}
+
+fn decrypt_aes_cbc<T: AsRef<[u8]>>(key: &[u8], data: T) -> Result<String> {
     let key: &[u8; 32] = key.try_into()?;
     let cipher = libaes::Cipher::new_256(key);
     let data = BASE64_STANDARD.decode(data.as_ref())?;
@@ -47,6 +150,36 @@ pub fn hash_128<T: AsRef<str>>(data: T) -> String {
     hex::encode(data)
 }
 
+/// Hashes the password with Argon2id and a random per-user salt,
+/// the result is a PHC string which carries the salt and params.
+pub fn hash_password<T: AsRef<str>>(password: T) -> Result<String> {
+    let mut salt = [0u8; 16];
+    getrandom::getrandom(&mut salt).map_err(|e| anyhow!("Unable to generate salt: {}", e))?;
+    let salt =
+        SaltString::encode_b64(&salt).map_err(|e| anyhow!("Unable to encode salt: {}", e))?;
+    let hash = Argon2::default()
+        .hash_password(password.as_ref().as_bytes(), &salt)
+        .map_err(|e| anyhow!("Unable to hash password: {}", e))?;
+    Ok(hash.to_string())
+}
+
+/// Verifies the password against a stored hash in constant time.
+/// The stored hash is either an Argon2 PHC string or a legacy plain sha256 hex digest.
+pub fn verify_password<T: AsRef<str>>(password: T, stored: &str) -> Result<bool> {
+    if is_legacy_hash(stored) {
+        return Ok(password.as_ref().as_bytes().ct_eq(stored.as_bytes()).into());
+    }
+    let hash = PasswordHash::new(stored).map_err(|e| anyhow!("Invalid password hash: {}", e))?;
+    Ok(Argon2::default()
+        .verify_password(password.as_ref().as_bytes(), &hash)
+        .is_ok())
+}
+
+/// Returns true if the stored hash isn't in Argon2 PHC format and should be rehashed.
+pub fn is_legacy_hash(stored: &str) -> bool {
+    !stored.starts_with("$argon2")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -61,6 +194,81 @@ mod tests {
         assert_eq!(data, decrypted);
     }
 
+    #[test]
+    fn test_encrypt_random_nonce() {
+        let key = [1; 32];
+        let encrypted1 = encrypt_aes(&key, "hello world").unwrap();
+        let encrypted2 = encrypt_aes(&key, "hello world").unwrap();
+        assert!(encrypted1.starts_with("v1:"));
+        assert!(!is_legacy_ciphertext(&encrypted1));
+        assert_ne!(encrypted1, encrypted2);
+    }
+
+    #[test]
+    fn test_decrypt_tampered() {
+        let key = [1; 32];
+        let encrypted = encrypt_aes(&key, "hello world").unwrap();
+        let mut envelope = BASE64_STANDARD
+            .decode(encrypted.strip_prefix("v1:").unwrap())
+            .unwrap();
+        let last = envelope.len() - 1;
+        envelope[last] ^= 1;
+        let tampered = format!("v1:{}", BASE64_STANDARD.encode(envelope));
+
+        assert!(decrypt_aes(&key, tampered).is_err());
+        assert!(decrypt_aes(&[2; 32], encrypted).is_err());
+    }
+
+    #[test]
+    fn test_encrypt_bytes() {
+        let key = [1; 32];
+        let data = [0x25, 0x50, 0x44, 0x46, 0x00, 0xff];
+        let encrypted = encrypt_bytes(&key, &data).unwrap();
+        assert_eq!(encrypted.len(), NONCE_LEN + data.len() + 16);
+        assert_eq!(decrypt_bytes(&key, &encrypted).unwrap(), data);
+        assert!(decrypt_bytes(&[2; 32], &encrypted).is_err());
+        assert!(decrypt_bytes(&key, &encrypted[..NONCE_LEN - 1]).is_err());
+    }
+
+    #[test]
+    fn test_seal_bytes() {
+        let key = [1; 32];
+        let nonce = [3; NONCE_LEN];
+        let sealed = seal_bytes(&key, &nonce, b"id/file", b"data").unwrap();
+        assert_eq!(sealed.len(), 4 + TAG_LEN);
+        assert_eq!(
+            open_bytes(&key, &nonce, b"id/file", &sealed).unwrap(),
+            b"data"
+        );
+        assert!(open_bytes(&key, &nonce, b"id/other", &sealed).is_err());
+        assert!(open_bytes(&key, &[4; NONCE_LEN], b"id/file", &sealed).is_err());
+    }
+
+    #[test]
+    fn test_encrypt_with_id() {
+        let key = [1; 32];
+        let encrypted = encrypt_aes_with_id("2024", &key, "hello world").unwrap();
+        assert!(encrypted.starts_with("v2:2024:"));
+        assert_eq!(ciphertext_key_id(encrypted.as_bytes()), Some("2024"));
+        assert!(!is_legacy_ciphertext(&encrypted));
+        assert_eq!(decrypt_aes(&key, &encrypted).unwrap(), "hello world");
+        assert!(decrypt_aes(&[2; 32], &encrypted).is_err());
+
+        assert!(encrypt_aes_with_id("20:24", &key, "hello world").is_err());
+        let encrypted = encrypt_aes(&key, "hello world").unwrap();
+        assert_eq!(ciphertext_key_id(encrypted.as_bytes()), None);
+    }
+
+    #[test]
+    fn test_decrypt_legacy_cbc() {
+        let key = [1; 32];
+        let cipher = libaes::Cipher::new_256(&key);
+        let legacy = BASE64_STANDARD.encode(cipher.cbc_encrypt(&IV, b"hello world"));
+
+        assert!(is_legacy_ciphertext(&legacy));
+        assert_eq!(decrypt_aes(&key, legacy).unwrap(), "hello world");
+    }
+
     #[test]
     fn test_gen_verify_totp() {
         let totp = TOTP::new(
@@ -91,6 +299,34 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_hash_verify_password() {
+        let hash = hash_password("secret").unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(!is_legacy_hash(&hash));
+        assert!(verify_password("secret", &hash).unwrap());
+        assert!(!verify_password("not secret", &hash).unwrap());
+    }
+
+    #[test]
+    fn test_hash_password_salted() {
+        let hash1 = hash_password("secret").unwrap();
+        let hash2 = hash_password("secret").unwrap();
+        assert_ne!(hash1, hash2);
+    }
+
+    #[test]
+    fn test_verify_legacy_password() {
+        let legacy = hash_256("secret");
+        assert!(is_legacy_hash(&legacy));
+        assert!(verify_password(hash_256("secret"), &legacy).unwrap());
+        assert!(!verify_password(hash_256("not secret"), &legacy).unwrap());
+        assert!(!matches!(
+            verify_password("secret", "$argon2id$invalid"),
+            Ok(true)
+        ));
+    }
+
     #[test]
     fn test_hash_128() {
         let data = "hello world";
@@ -0,0 +1,141 @@
+use anyhow::{anyhow, Result};
+use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Claims carried by a session token.
+/// All timestamps are milliseconds since epoch, same as `Instance::now`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Claims {
+    /// Username of the caller
+    pub sub: String,
+    /// Authority of the caller, encoded the same way as `SignUpDet::authority`
+    pub authority: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch: Option<String>,
+    /// Issued at
+    pub iat: u128,
+    /// Expires at
+    pub exp: u128,
+    /// Unique id of the token
+    pub jti: String,
+}
+
+/// Signs and verifies session tokens of the form `<payload>.<signature>`,
+/// where payload is the base64url encoded claims and signature is HMAC-SHA256 of the payload.
+#[derive(Clone)]
+pub struct SessionSigner {
+    key: Vec<u8>,
+}
+
+impl std::fmt::Debug for SessionSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionSigner").finish_non_exhaustive()
+    }
+}
+
+impl SessionSigner {
+    pub fn new<T: AsRef<[u8]>>(key: T) -> Self {
+        Self {
+            key: key.as_ref().to_vec(),
+        }
+    }
+
+    pub fn sign(&self, claims: &Claims) -> Result<String> {
+        let payload = serde_json::to_vec(claims)?;
+        let payload = BASE64_URL_SAFE_NO_PAD.encode(payload);
+        let signature = BASE64_URL_SAFE_NO_PAD.encode(self.mac(&payload)?.finalize().into_bytes());
+        Ok(format!("{}.{}", payload, signature))
+    }
+
+    /// Verifies the signature and expiry of the token and returns its claims.
+    pub fn verify(&self, token: &str, now: u128) -> Result<Claims> {
+        let (payload, signature) = token.split_once('.').ok_or(anyhow!("Invalid token"))?;
+        let signature = BASE64_URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| anyhow!("Invalid token"))?;
+        self.mac(payload)?
+            .verify_slice(&signature)
+            .map_err(|_| anyhow!("Invalid token signature"))?;
+
+        let payload = BASE64_URL_SAFE_NO_PAD
+            .decode(payload)
+            .map_err(|_| anyhow!("Invalid token"))?;
+        let claims =
+            serde_json::from_slice::<Claims>(&payload).map_err(|_| anyhow!("Invalid token"))?;
+        if claims.exp <= now {
+            return Err(anyhow!("Token expired, please re-login"));
+        }
+        Ok(claims)
+    }
+
+    fn mac(&self, payload: &str) -> Result<HmacSha256> {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.key).map_err(|_| anyhow!("Invalid signing key"))?;
+        mac.update(payload.as_bytes());
+        Ok(mac)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims(exp: u128) -> Claims {
+        Claims {
+            sub: "foo".to_string(),
+            authority: 2,
+            batch: Some("22BCS".to_string()),
+            iat: 1,
+            exp,
+            jti: "jti".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_sign_verify() -> Result<()> {
+        let signer = SessionSigner::new("key");
+        let token = signer.sign(&claims(10))?;
+        assert_eq!(signer.verify(&token, 5)?, claims(10));
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_expired() -> Result<()> {
+        let signer = SessionSigner::new("key");
+        let token = signer.sign(&claims(10))?;
+        let result = signer.verify(&token, 10);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Token expired, please re-login"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_wrong_key() -> Result<()> {
+        let token = SessionSigner::new("key").sign(&claims(10))?;
+        let result = SessionSigner::new("other key").verify(&token, 5);
+        assert_eq!(result.unwrap_err().to_string(), "Invalid token signature");
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_tampered() -> Result<()> {
+        let signer = SessionSigner::new("key");
+        let token = signer.sign(&claims(10))?;
+        let (_, signature) = token.split_once('.').unwrap();
+        let mut forged = claims(10);
+        forged.authority = 0;
+        let payload = BASE64_URL_SAFE_NO_PAD.encode(serde_json::to_vec(&forged)?);
+
+        let result = signer.verify(&format!("{}.{}", payload, signature), 5);
+        assert!(result.is_err());
+        assert!(signer.verify("garbage", 5).is_err());
+        Ok(())
+    }
+}
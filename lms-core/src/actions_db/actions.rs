@@ -1,4 +1,6 @@
-use crate::file_db::file_config::{FileHolder, InsertionInfo, Metadata};
+use crate::actions_db::group_id::GroupId;
+use crate::blueprint::Blueprint;
+use crate::file_db::file_config::{Encoding, FileHolder, GradeInfo, LatePolicy, Metadata};
 use crate::file_db::request_handler::FileRequestHandler;
 use crate::is_default;
 use anyhow::{anyhow, Result};
@@ -6,6 +8,8 @@ use dashmap::DashMap;
 use http_body_util::Full;
 
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::str::FromStr;
 
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ActionsResult {
@@ -22,6 +26,9 @@ pub struct ActionsRequest {
     pub read: Option<ActionsRead>,
     #[serde(default, skip_serializing_if = "is_default")]
     pub write: Option<ActionsWrite>,
+    /// Releases the grades of this assignment to the students of the group
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub release: Option<String>,
 }
 
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -29,6 +36,22 @@ pub struct ActionsRead {
     pub content_id: String,
     #[serde(default, skip_serializing_if = "is_default")]
     pub file_name: Option<String>,
+    /// Lists the submissions to the assignment `content_id` instead of reading it
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub submissions: Option<Listing>,
+    /// Lists the grades of the submissions to the assignment `content_id` instead of reading it,
+    /// students only see their own grades once they're released
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub grades: Option<Listing>,
+}
+
+/// Submissions or grades listed by [ActionsRead], oldest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Listing {
+    All,
+    /// Only the ones of the caller, the last one is the latest
+    Mine,
 }
 
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -39,53 +62,254 @@ pub struct ActionsWrite {
     pub files: Option<Vec<FileWrite>>,
     #[serde(default, skip_serializing_if = "is_default")]
     pub end_time: Option<u128>,
+    /// `notice`, `assignment`, `material` or the id of the assignment submitted to
     pub reference: String,
+    /// Points of an assignment, required along with its `end_time`
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub max_points: Option<u32>,
+    /// Extensions of the files allowed in the submissions of an assignment, any when unset
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub allowed_types: Option<Vec<String>>,
+    /// Grades the submission `reference`, the description is the feedback and the files are
+    /// the feedback files
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub grade: Option<GradeInfo>,
+    /// How late submissions to an assignment are handled, a cutoff at `end_time` when unset
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub late_policy: Option<LatePolicy>,
+    /// Grants this student an extension of the assignment `reference` until `end_time`, the
+    /// latest one granted wins
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub extension: Option<String>,
 }
 
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct FileWrite {
     pub file_name: String,
+    /// Set to `base64` for binary files
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub encoding: Encoding,
     pub content: String,
 }
 
+/// A file of some content, streamed through `/fs/files/{content_id}/{file_name}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActionsFile {
+    pub token: String,
+    pub group_id: String,
+    pub content_id: String,
+    pub file_name: String,
+}
+
 #[derive(Clone, Default, Debug, Serialize, Deserialize)]
 pub struct ActionsActivity {
     pub actions: DashMap<String, Vec<ActionsContent>>,
 }
 
+/// Kind of a content, set by the `reference` of the write posting it.
+#[derive(Clone, Copy, Default, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentKind {
+    #[default]
+    Notice,
+    Assignment,
+    /// Answer of a student to an assignment
+    Submission,
+    /// Course material, read like a notice
+    Material,
+    /// Grade of a submission, see [ActionsWrite::grade]
+    Grade,
+    /// Deadline extension of a student, see [ActionsWrite::extension]
+    Extension,
+}
+
 #[derive(Clone, Default, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(from = "StoredContent", into = "StoredContent")]
 pub struct ActionsContent {
-    pub is_notif: bool,
+    pub kind: ContentKind,
     pub content_id: String,
+    /// Assignment of a submission, a grade or an extension
+    pub assignment: Option<String>,
+    /// Username of the student who made a submission, whose submission is graded or who is
+    /// granted an extension
+    pub submitter: Option<String>,
+    /// Submission of a grade
+    pub submission: Option<String>,
+    /// Whether a grade is released to its student
+    pub released: bool,
 }
 
-impl ActionsActivity {
-    pub async fn insert(
-        &self,
-        group_id: String,
-        info: InsertionInfo,
-        files: Vec<FileHolder>,
-        file_request_handler: &FileRequestHandler,
-        is_notif: bool,
-    ) -> Result<String> {
-        let content_id = file_request_handler.insert(info, files).await?;
-        let new_action = ActionsContent {
-            is_notif,
-            content_id: content_id.clone(),
-        };
+/// Json form of an [ActionsContent]. `is_notif` is still written for older clients, actions
+/// stored before there were kinds are read by it, see [ContentKind::legacy].
+#[derive(Serialize, Deserialize)]
+struct StoredContent {
+    #[serde(default)]
+    is_notif: bool,
+    #[serde(default, skip_serializing_if = "is_default")]
+    kind: Option<ContentKind>,
+    content_id: String,
+    #[serde(default, skip_serializing_if = "is_default")]
+    assignment: Option<String>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    submitter: Option<String>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    submission: Option<String>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    released: bool,
+}
+
+impl From<StoredContent> for ActionsContent {
+    fn from(content: StoredContent) -> Self {
+        Self {
+            kind: content
+                .kind
+                .unwrap_or(ContentKind::legacy(content.is_notif)),
+            content_id: content.content_id,
+            assignment: content.assignment,
+            submitter: content.submitter,
+            submission: content.submission,
+            released: content.released,
+        }
+    }
+}
+
+impl From<ActionsContent> for StoredContent {
+    fn from(content: ActionsContent) -> Self {
+        Self {
+            is_notif: content.kind == ContentKind::Notice,
+            kind: Some(content.kind),
+            content_id: content.content_id,
+            assignment: content.assignment,
+            submitter: content.submitter,
+            submission: content.submission,
+            released: content.released,
+        }
+    }
+}
 
-        if let Some(mut actions) = self.get_actions(&group_id) {
-            actions.push(new_action);
-            self.actions.insert(group_id.to_string(), actions);
+impl ContentKind {
+    /// Kind of an action stored with only `is_notif`. Every write but a notice referenced
+    /// another content then, so it's a submission, one without a submitter or an assignment
+    /// which only staff can read.
+    pub fn legacy(is_notif: bool) -> Self {
+        if is_notif {
+            ContentKind::Notice
         } else {
-            self.actions.insert(group_id.to_string(), vec![new_action]);
+            ContentKind::Submission
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentKind::Notice => "notice",
+            ContentKind::Assignment => "assignment",
+            ContentKind::Submission => "submission",
+            ContentKind::Material => "material",
+            ContentKind::Grade => "grade",
+            ContentKind::Extension => "extension",
+        }
+    }
+}
+
+impl FromStr for ContentKind {
+    type Err = anyhow::Error;
+
+    fn from_str(kind: &str) -> Result<Self> {
+        match kind {
+            "notice" => Ok(ContentKind::Notice),
+            "assignment" => Ok(ContentKind::Assignment),
+            "submission" => Ok(ContentKind::Submission),
+            "material" => Ok(ContentKind::Material),
+            "grade" => Ok(ContentKind::Grade),
+            "extension" => Ok(ContentKind::Extension),
+            _ => Err(anyhow!("Unknown content kind {}", kind)),
+        }
+    }
+}
+
+impl ActionsContent {
+    pub fn new(kind: ContentKind, content_id: &str) -> Self {
+        Self {
+            kind,
+            content_id: content_id.to_string(),
+            assignment: None,
+            submitter: None,
+            submission: None,
+            released: false,
         }
-        Ok(content_id)
     }
-    pub fn get_actions(&self, group_id: &str) -> Option<Vec<ActionsContent>> {
-        let val = self.actions.get(group_id)?;
+}
+
+impl ActionsActivity {
+    /// Appends `content` to the group, atomically with other changes of the group.
+    pub fn push(&self, group_id: &GroupId, content: ActionsContent) {
+        self.actions
+            .entry(group_id.to_string())
+            .or_default()
+            .push(content);
+    }
+    pub fn get_actions(&self, group_id: &GroupId) -> Option<Vec<ActionsContent>> {
+        let val = self.actions.get(&group_id.to_string())?;
         Some(val.value().clone())
     }
+    /// Action of `content_id` in the group.
+    pub fn find(&self, group_id: &GroupId, content_id: &str) -> Option<ActionsContent> {
+        let actions = self.actions.get(&group_id.to_string())?;
+        actions
+            .iter()
+            .find(|action| action.content_id == content_id)
+            .cloned()
+    }
+    /// Submissions or grades to `assignment` in the group, oldest first, only the ones of
+    /// `submitter` when set.
+    pub fn linked(
+        &self,
+        group_id: &GroupId,
+        kind: ContentKind,
+        assignment: &str,
+        submitter: Option<&str>,
+    ) -> Vec<ActionsContent> {
+        let Some(actions) = self.actions.get(&group_id.to_string()) else {
+            return vec![];
+        };
+        actions
+            .iter()
+            .filter(|action| {
+                action.kind == kind
+                    && action.assignment.as_deref() == Some(assignment)
+                    && submitter
+                        .is_none_or(|submitter| action.submitter.as_deref() == Some(submitter))
+            })
+            .cloned()
+            .collect()
+    }
+    /// Moves the actions of the renamed groups to their new ids, merging them with
+    /// the actions already there, and returns the ids that are still unknown.
+    pub fn migrate_groups(
+        &self,
+        renames: &BTreeMap<String, GroupId>,
+        blueprint: &Blueprint,
+    ) -> Result<Vec<String>> {
+        for new_id in renames.values() {
+            new_id.validate(blueprint)?;
+        }
+        for (old_id, new_id) in renames {
+            if let Some((_, mut moved)) = self.actions.remove(old_id) {
+                self.actions
+                    .entry(new_id.to_string())
+                    .or_default()
+                    .append(&mut moved);
+            }
+        }
+        let mut unknown = self
+            .actions
+            .iter()
+            .map(|entry| entry.key().clone())
+            .filter(|id| GroupId::parse(id, blueprint).is_err())
+            .collect::<Vec<_>>();
+        unknown.sort();
+        Ok(unknown)
+    }
     pub async fn get_config(
         &self,
         content_id: &str,
@@ -103,6 +327,17 @@ impl ActionsActivity {
     }
 }
 
+impl TryFrom<FileWrite> for FileHolder {
+    type Error = anyhow::Error;
+
+    fn try_from(file: FileWrite) -> Result<Self> {
+        Ok(FileHolder {
+            content: file.encoding.decode(file.content)?,
+            name: file.file_name,
+        })
+    }
+}
+
 impl ActionsResult {
     pub fn into_hyper_response(self) -> Result<hyper::Response<Full<bytes::Bytes>>> {
         let body = serde_json::to_string(&self)?;
@@ -1,17 +1,102 @@
-use super::actions::{ActionsActivity, ActionsRequest, ActionsResult, ActionsWrite};
+use super::actions::{
+    ActionsActivity, ActionsContent, ActionsFile, ActionsRequest, ActionsResult, ActionsWrite,
+    ContentKind, Listing,
+};
+use super::group_id::GroupId;
+use super::policy::{authorize, visible, Operation};
+use super::wal::{ActionsEvent, ActionsLog};
 use crate::app_ctx::AppContext;
-use crate::file_db::file_config::{FileHolder, InsertionInfo};
-use crate::file_db::request_handler::FileRequestHandler;
+use crate::authdb::auth_actors::Authority;
+use crate::authdb::auth_db::verify_token;
+use crate::authdb::auth_store::{self, AuthStore};
+use crate::file_db::file_config::{
+    AssignmentInfo, FileHolder, InsertionInfo, Metadata, SubmissionInfo,
+};
+use crate::file_db::request_handler::{validate_files, FileRequestHandler};
+use crate::file_db::safe_name::SafeFileName;
+use crate::http::multipart::{self, Part};
 use crate::runtime::TargetRuntime;
-use anyhow::{anyhow, Context, Result};
+#[cfg(feature = "sqlite")]
+use crate::sqlite::SqliteDb;
+use crate::stream::{self, ByteRange, ByteStream, FileStream, RangeNotSatisfiable, TooLarge};
+use anyhow::{anyhow, Result};
 use base64::prelude::BASE64_STANDARD;
 use base64::Engine;
+use lms_auth::session::Claims;
+use std::collections::BTreeMap;
 use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// `reference` of a write posting a notice
+const NOTICE: &str = "notice";
+/// `reference` of a write posting an assignment, any other reference is a submission against it
+const ASSIGNMENT: &str = "assignment";
+/// `reference` of a write posting course material
+const MATERIAL: &str = "material";
+/// Fields of a form posted to `/fs/upload` besides the files, `allowed_types` is comma separated
+const FORM_FIELDS: [&str; 7] = [
+    "title",
+    "description",
+    "group",
+    "reference",
+    "end_time",
+    "max_points",
+    "allowed_types",
+];
+
+/// A form of `/fs/upload` read by [ActionsDB::read_form].
+struct Form {
+    group_id: GroupId,
+    write: ActionsWrite,
+    files: Vec<FileHolder>,
+}
+
+/// Errors of a form by field, files are keyed by `files[{name}]` and errors of the whole form
+/// by `form`.
+type FormErrors = BTreeMap<String, String>;
+
+/// An error of a write about one of its fields, see [FormErrors]. Errors of a write without a
+/// field are about its `reference`.
+#[derive(Debug)]
+struct FieldError {
+    field: String,
+    message: String,
+}
+
+impl std::fmt::Display for FieldError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for FieldError {}
+
+fn field_error<T: std::fmt::Display>(field: &str, message: T) -> anyhow::Error {
+    FieldError {
+        field: field.to_string(),
+        message: message.to_string(),
+    }
+    .into()
+}
+
+fn file_field(file_name: &str) -> String {
+    format!("files[{}]", file_name)
+}
 
 pub struct ActionsDB {
     app_context: Arc<AppContext>,
     file_request_handler: FileRequestHandler,
     activity: ActionsActivity,
+    /// Users of the AuthDB, an extension is only granted to a student of the group
+    users: Arc<dyn AuthStore>,
+    /// Set for a local file, writes are appended to its log
+    log: Option<ActionsLog>,
+    /// Held from applying a change to the activity until it's persisted, so changes are
+    /// persisted in the order they're applied
+    writes: Mutex<()>,
+    /// Set for a `sqlite://` path, writes only touch the changed groups
+    #[cfg(feature = "sqlite")]
+    sqlite: Option<SqliteDb>,
 }
 
 impl ActionsDB {
@@ -21,17 +106,57 @@ impl ActionsDB {
         let file_request_handler = FileRequestHandler::new(
             app_context.runtime.clone(),
             app_context.blueprint.server.file_db.clone(),
+        )?
+        .with_keyring(
+            app_context.blueprint.extensions.auth.keyring().clone(),
+            app_context.blueprint.server.encrypt_files,
         );
-        let activity = Self::fetch_activity(actions_db_path, &app_context.runtime)
-            .await
-            .unwrap_or_default();
+        let users = auth_store::init(&app_context.blueprint.extensions.auth, &app_context.runtime)?;
+        #[cfg(feature = "sqlite")]
+        if let Some(path) = crate::sqlite_path(actions_db_path) {
+            let db = SqliteDb::open(path)?;
+            return Ok(Self {
+                activity: db.load_actions()?,
+                app_context,
+                file_request_handler,
+                users,
+                log: None,
+                writes: Mutex::default(),
+                sqlite: Some(db),
+            });
+        }
+        if actions_db_path.starts_with("http") {
+            let activity = Self::fetch_activity(actions_db_path, &app_context.runtime)
+                .await
+                .unwrap_or_default();
+            return Ok(Self {
+                app_context,
+                file_request_handler,
+                activity,
+                users,
+                log: None,
+                writes: Mutex::default(),
+                #[cfg(feature = "sqlite")]
+                sqlite: None,
+            });
+        }
+        let log = ActionsLog::new(app_context.runtime.file.clone(), actions_db_path);
+        let activity = log.recover().await?;
         Ok(Self {
             app_context,
             file_request_handler,
             activity,
+            users,
+            log: Some(log),
+            writes: Mutex::default(),
+            #[cfg(feature = "sqlite")]
+            sqlite: None,
         })
     }
-    async fn fetch_activity(path: &str, target_runtime: &TargetRuntime) -> Result<ActionsActivity> {
+    pub(crate) async fn fetch_activity(
+        path: &str,
+        target_runtime: &TargetRuntime,
+    ) -> Result<ActionsActivity> {
         if path.starts_with("http") {
             let url = url::Url::parse(path)?;
             let req = reqwest::Request::new(reqwest::Method::GET, url);
@@ -39,22 +164,45 @@ impl ActionsDB {
             let body = resp.body;
             Ok(serde_json::from_slice(&body)?)
         } else {
-            let body = target_runtime.file.read(path).await?;
-            Ok(serde_json::from_str(&body)?)
+            let log = ActionsLog::new(target_runtime.file.clone(), path);
+            Ok(log.replay().await?.0)
         }
     }
     pub async fn handle_request(&self, body: bytes::Bytes) -> ActionsResult {
         let actions_request = ActionsRequest::try_from_bytes(&body);
         match actions_request {
             Ok(actions_request) => match verify_token(&actions_request.token, &self.app_context) {
-                Ok(_) => {
-                    if actions_request.write.is_some() {
-                        match self.handle_write(actions_request).await {
+                Ok(claims) => {
+                    let group_id = match GroupId::parse(
+                        &actions_request.group_id,
+                        &self.app_context.blueprint,
+                    ) {
+                        Ok(group_id) => group_id,
+                        Err(e) => return actions_status(400, e.to_string()),
+                    };
+                    if let Err(e) = authorize(
+                        &claims,
+                        &group_id,
+                        &operation(&actions_request),
+                        &self.activity,
+                    ) {
+                        return actions_forbidden(e.to_string());
+                    }
+                    if let Some(assignment) = actions_request.release {
+                        match self.handle_release(&claims, &group_id, assignment).await {
+                            Ok(msg) => actions_success(msg),
+                            Err(e) => actions_error(e.to_string()),
+                        }
+                    } else if actions_request.write.is_some() {
+                        match self.handle_write(&claims, &group_id, actions_request).await {
                             Ok(msg) => actions_success(msg),
+                            Err(e) if e.downcast_ref::<FieldError>().is_some() => {
+                                actions_status(400, e.to_string())
+                            }
                             Err(e) => actions_error(e.to_string()),
                         }
                     } else {
-                        match self.handle_read(actions_request).await {
+                        match self.handle_read(&claims, &group_id, actions_request).await {
                             Ok(msg) => actions_success(msg),
                             Err(e) => actions_error(e.to_string()),
                         }
@@ -65,208 +213,1986 @@ impl ActionsDB {
             Err(e) => actions_error(e.to_string()),
         }
     }
-    async fn handle_read(&self, actions_request: ActionsRequest) -> Result<String> {
-        if actions_request.read.is_none() {
-            let val = self
+    /// Streams a file into a content, see [Operation::Attach]. The upload fails with 413 once
+    /// it's over `server.maxUploadSize`, as announced by `content_length` or while streaming.
+    pub async fn handle_upload(
+        &self,
+        file: ActionsFile,
+        content_length: Option<u64>,
+        stream: ByteStream,
+    ) -> ActionsResult {
+        let (claims, group_id) = match self.verify_file(&file) {
+            Ok(verified) => verified,
+            Err(result) => return result,
+        };
+        let author = self
+            .file_request_handler
+            .get_metadata(&file.content_id)
+            .await
+            .ok()
+            .and_then(|metadata| metadata.author);
+        let operation = Operation::Attach {
+            content_id: &file.content_id,
+            author: author.as_deref(),
+        };
+        if let Err(e) = authorize(&claims, &group_id, &operation, &self.activity) {
+            return actions_forbidden(e.to_string());
+        }
+        if let Err(e) = self.validate_attach(&group_id, &file).await {
+            return actions_status(400, e.to_string());
+        }
+
+        let max = self.app_context.blueprint.server.max_upload_size;
+        if content_length.is_some_and(|len| len > max) {
+            return actions_status(413, TooLarge { max }.to_string());
+        }
+        let stream = stream::limit(stream, max);
+        match self
+            .file_request_handler
+            .put_stream(&file.content_id, &file.file_name, stream)
+            .await
+        {
+            Ok(size) => {
+                log::info!(
+                    "{} attached {} ({} bytes) to {}",
+                    claims.sub,
+                    file.file_name,
+                    size,
+                    file.content_id
+                );
+                actions_success(size.to_string())
+            }
+            Err(e) if e.downcast_ref::<TooLarge>().is_some() => actions_status(413, e.to_string()),
+            Err(e) => actions_error(e.to_string()),
+        }
+    }
+
+    /// Streams `range` of a file of a content, or the whole file without a range.
+    pub async fn handle_download(
+        &self,
+        file: ActionsFile,
+        range: Option<ByteRange>,
+    ) -> std::result::Result<FileStream, ActionsResult> {
+        let (claims, group_id) = self.verify_file(&file)?;
+        let operation = Operation::ReadContent {
+            content_id: &file.content_id,
+        };
+        authorize(&claims, &group_id, &operation, &self.activity)
+            .map_err(|e| actions_forbidden(e.to_string()))?;
+        self.file_request_handler
+            .get_stream(&file.content_id, &file.file_name, range)
+            .await
+            .map_err(|e| match e.downcast_ref::<RangeNotSatisfiable>() {
+                Some(_) => actions_status(416, e.to_string()),
+                None => actions_status(404, e.to_string()),
+            })
+    }
+
+    fn verify_file(
+        &self,
+        file: &ActionsFile,
+    ) -> std::result::Result<(Claims, GroupId), ActionsResult> {
+        let claims = verify_token(&file.token, &self.app_context)
+            .map_err(|e| actions_status(401, e.to_string()))?;
+        let group_id = GroupId::parse(&file.group_id, &self.app_context.blueprint)
+            .map_err(|e| actions_status(400, e.to_string()))?;
+        Ok((claims, group_id))
+    }
+
+    /// Posts a `multipart/form-data` body, like a write of [ActionsDB::handle_request].
+    ///
+    /// The fields are `title`, `description`, `group`, `reference`, `end_time`, `max_points`
+    /// and `allowed_types`, see [FORM_FIELDS], every part with a file name is a file. A rejected
+    /// form is answered with 400 and a json object of the errors by field, see [FormErrors].
+    /// The body is read up to `server.maxUploadSize`, a larger one is answered with 413 like
+    /// [ActionsDB::handle_upload].
+    pub async fn handle_form(
+        &self,
+        token: Option<&str>,
+        content_type: Option<&str>,
+        content_length: Option<u64>,
+        body: ByteStream,
+    ) -> ActionsResult {
+        let claims = match token.map(|token| verify_token(token, &self.app_context)) {
+            Some(Ok(claims)) => claims,
+            Some(Err(e)) => return actions_status(401, e.to_string()),
+            None => return actions_status(401, "Missing bearer token"),
+        };
+        let max = self.app_context.blueprint.server.max_upload_size;
+        if content_length.is_some_and(|len| len > max) {
+            return actions_status(413, TooLarge { max }.to_string());
+        }
+        let body = match stream::collect(stream::limit(body, max)).await {
+            Ok(body) => bytes::Bytes::from(body),
+            Err(e) if e.downcast_ref::<TooLarge>().is_some() => {
+                return actions_status(413, e.to_string())
+            }
+            Err(e) => return actions_status(400, e.to_string()),
+        };
+        let parts = content_type
+            .ok_or_else(|| anyhow!("Missing Content-Type"))
+            .and_then(|content_type| multipart::parse(content_type, body));
+        let parts = match parts {
+            Ok(parts) => parts,
+            Err(e) => return form_errors(FormErrors::from([("form".to_string(), e.to_string())])),
+        };
+        let form = match self.read_form(parts) {
+            Ok(form) => form,
+            Err(errors) => return form_errors(errors),
+        };
+        let operation = write_operation(&form.write);
+        if let Err(e) = authorize(&claims, &form.group_id, &operation, &self.activity) {
+            return actions_forbidden(e.to_string());
+        }
+        let stamp = match self
+            .validate_write(&claims, &form.group_id, &form.write, &form.files)
+            .await
+        {
+            Ok(stamp) => stamp,
+            Err(e) => {
+                let field = e
+                    .downcast_ref::<FieldError>()
+                    .map_or("reference", |e| e.field.as_str());
+                return form_errors(FormErrors::from([(field.to_string(), e.to_string())]));
+            }
+        };
+        match self
+            .post(&claims, &form.group_id, form.write, form.files, stamp)
+            .await
+        {
+            Ok(content_id) => actions_success(content_id),
+            Err(e) => actions_error(e.to_string()),
+        }
+    }
+
+    /// Checks every part of the form and collects all the errors at once.
+    fn read_form(&self, parts: Vec<Part>) -> std::result::Result<Form, FormErrors> {
+        let mut errors = FormErrors::new();
+        let mut fields = BTreeMap::new();
+        let mut files: Vec<FileHolder> = vec![];
+        for part in parts {
+            if let Some(file_name) = part.file_name {
+                // browsers send an empty part for a file input left empty
+                if file_name.is_empty() && part.content.is_empty() {
+                    continue;
+                }
+                let name = match SafeFileName::new(&file_name) {
+                    Ok(name) => name,
+                    Err(e) => {
+                        errors.insert(file_field(&file_name), e.to_string());
+                        continue;
+                    }
+                };
+                let file = FileHolder {
+                    name: name.to_string(),
+                    content: part.content.to_vec(),
+                };
+                if let Err(e) = validate_files(std::slice::from_ref(&file)) {
+                    errors.insert(file_field(&file.name), e.to_string());
+                } else if files.iter().any(|other| other.name == file.name) {
+                    errors.insert(file_field(&file.name), "Duplicate file".to_string());
+                } else {
+                    files.push(file);
+                }
+                continue;
+            }
+            if !FORM_FIELDS.contains(&part.name.as_str()) {
+                errors.insert(part.name, "Unknown field".to_string());
+                continue;
+            }
+            match String::from_utf8(part.content.to_vec()) {
+                Ok(value) if fields.contains_key(&part.name) => {
+                    errors.insert(
+                        part.name,
+                        format!("Duplicate field, found {:?} again", value),
+                    );
+                }
+                Ok(value) => {
+                    fields.insert(part.name, value);
+                }
+                Err(_) => {
+                    errors.insert(part.name, "Not valid utf-8".to_string());
+                }
+            }
+        }
+
+        let mut required = |name: &str| match fields.remove(name) {
+            Some(value) if !value.trim().is_empty() => Some(value),
+            _ => {
+                errors
+                    .entry(name.to_string())
+                    .or_insert("Missing field".to_string());
+                None
+            }
+        };
+        let title = required("title");
+        let reference = required("reference");
+        let group_id = required("group");
+        let group_id = group_id.and_then(|group_id| {
+            GroupId::parse(&group_id, &self.app_context.blueprint)
+                .map_err(|e| errors.insert("group".to_string(), e.to_string()))
+                .ok()
+        });
+        let end_time = match fields.remove("end_time").filter(|v| !v.trim().is_empty()) {
+            Some(end_time) => match end_time.trim().parse::<u128>() {
+                Ok(end_time) => Some(end_time),
+                Err(_) => {
+                    errors.insert(
+                        "end_time".to_string(),
+                        "Expected a timestamp in milliseconds".to_string(),
+                    );
+                    None
+                }
+            },
+            None => None,
+        };
+        let max_points = match fields.remove("max_points").filter(|v| !v.trim().is_empty()) {
+            Some(max_points) => match max_points.trim().parse::<u32>() {
+                Ok(max_points) => Some(max_points),
+                Err(_) => {
+                    errors.insert(
+                        "max_points".to_string(),
+                        "Expected a whole number".to_string(),
+                    );
+                    None
+                }
+            },
+            None => None,
+        };
+        let allowed_types = fields
+            .remove("allowed_types")
+            .filter(|v| !v.trim().is_empty())
+            .map(|types| types.split(',').map(|t| t.trim().to_string()).collect());
+
+        match (title, reference, group_id) {
+            (Some(title), Some(reference), Some(group_id)) if errors.is_empty() => Ok(Form {
+                group_id,
+                write: ActionsWrite {
+                    title,
+                    description: fields.remove("description").unwrap_or_default(),
+                    files: None,
+                    end_time,
+                    reference,
+                    max_points,
+                    allowed_types,
+                    grade: None,
+                    late_policy: None,
+                    extension: None,
+                },
+                files,
+            }),
+            _ => Err(errors),
+        }
+    }
+
+    async fn handle_read(
+        &self,
+        claims: &Claims,
+        group_id: &GroupId,
+        actions_request: ActionsRequest,
+    ) -> Result<String> {
+        let Some(read) = actions_request.read else {
+            let mut val = self.activity.get_actions(group_id).unwrap_or_default();
+            val.retain(|action| visible(claims, action));
+            let data =
+                serde_json::to_string(&val).map_err(|_| anyhow!("Unable to serialize data"))?;
+            return Ok(data);
+        };
+        let listing = match (read.submissions, read.grades) {
+            (Some(listing), _) => Some((ContentKind::Submission, listing)),
+            (None, Some(listing)) => Some((ContentKind::Grade, listing)),
+            (None, None) => None,
+        };
+        if let Some((kind, listing)) = listing {
+            let submitter = match listing {
+                Listing::All => None,
+                Listing::Mine => Some(claims.sub.as_str()),
+            };
+            let mut val = self
                 .activity
-                .get_actions(&actions_request.group_id)
-                .context("Invalid group id")?;
+                .linked(group_id, kind, &read.content_id, submitter);
+            val.retain(|action| visible(claims, action));
             let data =
                 serde_json::to_string(&val).map_err(|_| anyhow!("Unable to serialize data"))?;
             Ok(data)
+        } else if let Some(file_name) = read.file_name {
+            let file = self
+                .activity
+                .get_file_content(&read.content_id, &file_name, &self.file_request_handler)
+                .await?;
+            let data =
+                serde_json::to_string(&file).map_err(|_| anyhow!("Unable to serialize data"))?;
+            Ok(data)
         } else {
-            let read = actions_request.read.unwrap();
-            if let Some(file_name) = read.file_name {
-                let file = self
-                    .activity
-                    .get_file_content(&read.content_id, &file_name, &self.file_request_handler)
-                    .await?;
-                let data = serde_json::to_string(&file)
-                    .map_err(|_| anyhow!("Unable to serialize data"))?;
-                Ok(data)
-            } else {
-                let metadata = self
-                    .activity
-                    .get_config(&read.content_id, &self.file_request_handler)
-                    .await?;
-                let data = serde_json::to_string(&metadata)
-                    .map_err(|_| anyhow!("Unable to serialize data"))?;
-                Ok(data)
+            let metadata = self
+                .activity
+                .get_config(&read.content_id, &self.file_request_handler)
+                .await?;
+            let data = serde_json::to_string(&metadata)
+                .map_err(|_| anyhow!("Unable to serialize data"))?;
+            Ok(data)
+        }
+    }
+
+    async fn handle_write(
+        &self,
+        claims: &Claims,
+        group_id: &GroupId,
+        actions_request: ActionsRequest,
+    ) -> Result<String> {
+        if actions_request.write.is_none() {
+            return Err(anyhow!("Invalid Actions request"));
+        }
+        let mut write = actions_request.write.unwrap();
+
+        let files = write
+            .files
+            .take()
+            .unwrap_or_default()
+            .into_iter()
+            .map(FileHolder::try_from)
+            .collect::<Result<Vec<_>>>()?;
+        let stamp = self
+            .validate_write(claims, group_id, &write, &files)
+            .await?;
+        self.post(claims, group_id, write, files, stamp).await
+    }
+
+    /// Inserts a validated write along with its files, `write.files` is ignored. `stamp` is
+    /// the one [ActionsDB::validate_write] gave a submission.
+    async fn post(
+        &self,
+        claims: &Claims,
+        group_id: &GroupId,
+        write: ActionsWrite,
+        files: Vec<FileHolder>,
+        stamp: Option<SubmissionInfo>,
+    ) -> Result<String> {
+        let timestamp = self.app_context.runtime.instance.now()?;
+        let kind = kind_of(&write);
+        let assignment = match write.max_points {
+            Some(max_points) => Some(AssignmentInfo {
+                late_policy: write.late_policy.unwrap_or_default(),
+                ..AssignmentInfo::new(max_points, write.allowed_types.unwrap_or_default())?
+            }),
+            None => None,
+        };
+        let graded = match kind {
+            ContentKind::Grade => self.activity.find(group_id, &write.reference),
+            _ => None,
+        };
+        let mut grade = write.grade;
+        if let (Some(grade), Some(submission)) = (&mut grade, &graded) {
+            let metadata = self
+                .file_request_handler
+                .get_metadata(&submission.content_id)
+                .await?;
+            grade.penalty = metadata.submission.map_or(0, |stamp| stamp.penalty);
+        }
+
+        let info = InsertionInfo {
+            title: write.title,
+            description: write.description,
+            timestamp,
+            end_time: write.end_time,
+            author: Some(claims.sub.clone()),
+            assignment,
+            grade,
+            submission: stamp,
+        };
+
+        let content_id = self.file_request_handler.insert(info, files).await?;
+        let mut content = ActionsContent::new(kind, &content_id);
+        match kind {
+            ContentKind::Submission => {
+                content.assignment = Some(write.reference);
+                content.submitter = Some(claims.sub.clone());
+            }
+            ContentKind::Grade => {
+                if let Some(submission) = graded {
+                    content.assignment = submission.assignment;
+                    content.submitter = submission.submitter;
+                    content.submission = Some(submission.content_id);
+                }
+            }
+            ContentKind::Extension => {
+                content.assignment = Some(write.reference);
+                content.submitter = write.extension;
+            }
+            _ => {}
+        }
+
+        let _guard = self.writes.lock().await;
+        let event = ActionsEvent::Insert {
+            group_id: group_id.to_string(),
+            content,
+        };
+        self.commit(&[event]).await?;
+        log::info!("{} posted {} to {}", claims.sub, content_id, group_id);
+
+        Ok(content_id)
+    }
+
+    /// Releases the grades of an assignment, see [Operation::Release].
+    async fn handle_release(
+        &self,
+        claims: &Claims,
+        group_id: &GroupId,
+        assignment: String,
+    ) -> Result<String> {
+        let is_assignment = self
+            .activity
+            .find(group_id, &assignment)
+            .is_some_and(|action| action.kind == ContentKind::Assignment);
+        if !is_assignment {
+            return Err(anyhow!(
+                "{} is not an assignment in {}",
+                assignment,
+                group_id
+            ));
+        }
+
+        let _guard = self.writes.lock().await;
+        let released = self
+            .activity
+            .linked(group_id, ContentKind::Grade, &assignment, None)
+            .iter()
+            .filter(|grade| !grade.released)
+            .count();
+        let event = ActionsEvent::Release {
+            group_id: group_id.to_string(),
+            assignment,
+        };
+        self.commit(&[event]).await?;
+        log::info!(
+            "{} released {} grades in {}",
+            claims.sub,
+            released,
+            group_id
+        );
+        Ok(released.to_string())
+    }
+
+    /// Re-keys the groups in `renames`, see [ActionsActivity::migrate_groups].
+    pub async fn migrate_groups(&self, renames: &BTreeMap<String, GroupId>) -> Result<Vec<String>> {
+        let _guard = self.writes.lock().await;
+        let mut events = vec![];
+        for (old_id, new_id) in renames {
+            for action in self
+                .activity
+                .actions
+                .get(old_id)
+                .iter()
+                .flat_map(|a| a.iter())
+            {
+                events.push(ActionsEvent::Delete {
+                    group_id: old_id.clone(),
+                    content_id: action.content_id.clone(),
+                });
+                events.push(ActionsEvent::Insert {
+                    group_id: new_id.to_string(),
+                    content: action.clone(),
+                });
+            }
+        }
+        let unknown = self
+            .activity
+            .migrate_groups(renames, &self.app_context.blueprint)?;
+        self.persist(&events).await?;
+        Ok(unknown)
+    }
+
+    /// Applies the events to the activity and persists them, while holding `writes`. The groups
+    /// they change are put back as they were if persisting fails, so that the activity never
+    /// serves a change that was lost.
+    async fn commit(&self, events: &[ActionsEvent]) -> Result<()> {
+        let before = changed_groups(events)
+            .into_iter()
+            .map(|group_id| {
+                let actions = self.activity.actions.get(&group_id).map(|a| a.clone());
+                (group_id, actions)
+            })
+            .collect::<Vec<_>>();
+        for event in events {
+            event.apply(&self.activity);
+        }
+        if let Err(err) = self.persist(events).await {
+            for (group_id, actions) in before {
+                match actions {
+                    Some(actions) => {
+                        self.activity.actions.insert(group_id, actions);
+                    }
+                    None => {
+                        self.activity.actions.remove(&group_id);
+                    }
+                }
+            }
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Writes the events, already applied to the activity while holding `writes`. A local file
+    /// only appends them to its log, a SQLite database only rewrites the actions of the changed
+    /// groups.
+    async fn persist(&self, events: &[ActionsEvent]) -> Result<()> {
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = &self.sqlite {
+            return db.save_actions(&self.activity, &changed_groups(events));
+        }
+        if let Some(log) = &self.log {
+            return log.append(events, &self.activity).await;
+        }
+        let url = url::Url::parse(&self.app_context.blueprint.server.actions_db)?;
+        let mut req = reqwest::Request::new(reqwest::Method::POST, url);
+        *req.body_mut() = Some(reqwest::Body::from(serde_json::to_vec(&self.activity)?));
+        self.app_context.runtime.http.execute(req).await?;
+        Ok(())
+    }
+    /// Checks the reference of a write, and for a submission its assignment and files. A
+    /// submission is stamped against the late policy of its assignment.
+    async fn validate_write(
+        &self,
+        claims: &Claims,
+        group_id: &GroupId,
+        write: &ActionsWrite,
+        files: &[FileHolder],
+    ) -> Result<Option<SubmissionInfo>> {
+        if write.reference.is_empty() {
+            return Err(anyhow!("Invalid reference"));
+        }
+        if write.grade.is_some() && write.extension.is_some() {
+            return Err(field_error(
+                "extension",
+                "A write can't both grade and grant an extension",
+            ));
+        }
+        let kind = kind_of(write);
+        let assignment_fields = [
+            ("max_points", write.max_points.is_some()),
+            ("allowed_types", write.allowed_types.is_some()),
+            ("late_policy", write.late_policy.is_some()),
+        ];
+        if kind != ContentKind::Assignment {
+            if let Some((field, _)) = assignment_fields.iter().find(|(_, set)| *set) {
+                return Err(field_error(
+                    field,
+                    "Only assignments have max_points, allowed_types and late_policy",
+                ));
+            }
+        }
+        match kind {
+            ContentKind::Assignment => {
+                if write.max_points.is_none() {
+                    return Err(field_error("max_points", "An assignment needs max_points"));
+                }
+                if write.end_time.is_none() {
+                    return Err(field_error("end_time", "An assignment needs an end_time"));
+                }
+                AssignmentInfo::new(0, write.allowed_types.clone().unwrap_or_default())
+                    .map_err(|e| field_error("allowed_types", e))?;
+                if let Some(late_policy) = &write.late_policy {
+                    late_policy
+                        .validate()
+                        .map_err(|e| field_error("late_policy", e))?;
+                }
+                Ok(None)
             }
+            ContentKind::Submission => self
+                .validate_submission(claims, group_id, write, files)
+                .await
+                .map(Some),
+            ContentKind::Grade => self.validate_grade(group_id, write).await.map(|_| None),
+            ContentKind::Extension => self.validate_extension(group_id, write).await.map(|_| None),
+            ContentKind::Notice | ContentKind::Material => Ok(None),
+        }
+    }
+
+    async fn validate_submission(
+        &self,
+        claims: &Claims,
+        group_id: &GroupId,
+        write: &ActionsWrite,
+        files: &[FileHolder],
+    ) -> Result<SubmissionInfo> {
+        let metadata = self.assignment_metadata(group_id, &write.reference).await?;
+        let due = self
+            .due_date(group_id, &write.reference, &metadata, &claims.sub)
+            .await?;
+        let info = metadata.assignment.unwrap_or_default();
+        // the deadline is the assignment's, not a field of the submission
+        let stamp = info
+            .late_policy
+            .stamp(due, self.app_context.runtime.instance.now()?)
+            .map_err(|e| field_error("form", e))?;
+        allowed_files(&info, files.iter().map(|file| file.name.as_str()))?;
+        Ok(stamp)
+    }
+
+    /// Checks an extension is granted to a student of the group past the due date of its
+    /// assignment.
+    async fn validate_extension(&self, group_id: &GroupId, write: &ActionsWrite) -> Result<()> {
+        let student = write.extension.as_deref().unwrap_or_default();
+        if student.is_empty() {
+            return Err(field_error("extension", "An extension needs a student"));
+        }
+        let enrolled = self.users.get(student).await?.is_some_and(|user| {
+            user.authority == Authority::Student && user.batch.as_deref() == Some(group_id.batch())
+        });
+        if !enrolled {
+            return Err(field_error(
+                "extension",
+                format!("{} is not a student of {}", student, group_id),
+            ));
+        }
+        let metadata = self.assignment_metadata(group_id, &write.reference).await?;
+        match (write.end_time, metadata.end_time) {
+            (None, _) => Err(field_error("end_time", "An extension needs an end_time")),
+            (Some(end_time), Some(due)) if end_time <= due => Err(field_error(
+                "end_time",
+                "An extension must end after the due date",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Metadata of the assignment `reference` in the group.
+    async fn assignment_metadata(&self, group_id: &GroupId, reference: &str) -> Result<Metadata> {
+        let assignment = self
+            .activity
+            .find(group_id, reference)
+            .filter(|action| action.kind == ContentKind::Assignment)
+            .ok_or_else(|| anyhow!("{} is not an assignment in {}", reference, group_id))?;
+        self.file_request_handler
+            .get_metadata(&assignment.content_id)
+            .await
+    }
+
+    /// Due date of an assignment for the student, the latest extension granted to them wins.
+    async fn due_date(
+        &self,
+        group_id: &GroupId,
+        assignment: &str,
+        metadata: &Metadata,
+        student: &str,
+    ) -> Result<Option<u128>> {
+        let extension = self
+            .activity
+            .linked(group_id, ContentKind::Extension, assignment, Some(student))
+            .pop();
+        match extension {
+            Some(extension) => Ok(self
+                .file_request_handler
+                .get_metadata(&extension.content_id)
+                .await?
+                .end_time),
+            None => Ok(metadata.end_time),
+        }
+    }
+
+    /// Checks the grade against the points of the assignment of the graded submission.
+    async fn validate_grade(&self, group_id: &GroupId, write: &ActionsWrite) -> Result<()> {
+        let submission = self
+            .activity
+            .find(group_id, &write.reference)
+            .filter(|action| action.kind == ContentKind::Submission)
+            .ok_or_else(|| anyhow!("{} is not a submission in {}", write.reference, group_id))?;
+        let max_points = match &submission.assignment {
+            Some(assignment) => self
+                .file_request_handler
+                .get_metadata(assignment)
+                .await?
+                .assignment
+                .map(|info| info.max_points),
+            None => None,
+        };
+        match &write.grade {
+            Some(grade) => grade
+                .validate(max_points)
+                .map_err(|e| field_error("grade", e)),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks a file streamed into a submission like [ActionsDB::validate_submission]. Graded
+    /// submissions are closed, and files can't be attached later than the submission is stamped.
+    async fn validate_attach(&self, group_id: &GroupId, file: &ActionsFile) -> Result<()> {
+        let Some(submission) = self
+            .activity
+            .find(group_id, &file.content_id)
+            .filter(|action| action.kind == ContentKind::Submission)
+        else {
+            return Ok(());
+        };
+        let (Some(assignment), Some(submitter)) = (&submission.assignment, &submission.submitter)
+        else {
+            return Ok(());
+        };
+        let graded = self
+            .activity
+            .linked(group_id, ContentKind::Grade, assignment, Some(submitter))
+            .iter()
+            .any(|grade| grade.submission.as_ref() == Some(&submission.content_id));
+        if graded {
+            return Err(anyhow!("{} is already graded", submission.content_id));
+        }
+
+        let metadata = self.assignment_metadata(group_id, assignment).await?;
+        let due = self
+            .due_date(group_id, assignment, &metadata, submitter)
+            .await?;
+        let info = metadata.assignment.unwrap_or_default();
+        let stamp = info
+            .late_policy
+            .stamp(due, self.app_context.runtime.instance.now()?)?;
+        let stamped = self
+            .file_request_handler
+            .get_metadata(&submission.content_id)
+            .await?
+            .submission
+            .unwrap_or_default();
+        if stamp.penalty > stamped.penalty {
+            return Err(anyhow!(
+                "Files attached now would be penalized, submit again instead"
+            ));
+        }
+        allowed_files(&info, [file.file_name.as_str()])
+    }
+}
+
+fn operation(actions_request: &ActionsRequest) -> Operation<'_> {
+    if let Some(assignment) = &actions_request.release {
+        return Operation::Release { assignment };
+    }
+    match (&actions_request.write, &actions_request.read) {
+        (Some(write), _) => write_operation(write),
+        (None, Some(read)) => match read.submissions.or(read.grades) {
+            Some(listing) => Operation::ReadSubmissions {
+                assignment: &read.content_id,
+                mine: listing == Listing::Mine,
+            },
+            None => Operation::ReadContent {
+                content_id: &read.content_id,
+            },
+        },
+        (None, None) => Operation::ReadGroup,
+    }
+}
+
+fn write_operation(write: &ActionsWrite) -> Operation<'_> {
+    if write.grade.is_some() {
+        return Operation::Grade {
+            submission: &write.reference,
+        };
+    }
+    if write.extension.is_some() {
+        return Operation::Extend {
+            assignment: &write.reference,
+        };
+    }
+    match write.reference.as_str() {
+        NOTICE => Operation::PostNotice,
+        ASSIGNMENT => Operation::PostAssignment,
+        MATERIAL => Operation::PostMaterial,
+        reference => Operation::Submit { reference },
+    }
+}
+
+fn kind_of(write: &ActionsWrite) -> ContentKind {
+    if write.grade.is_some() {
+        return ContentKind::Grade;
+    }
+    if write.extension.is_some() {
+        return ContentKind::Extension;
+    }
+    match write.reference.as_str() {
+        NOTICE => ContentKind::Notice,
+        ASSIGNMENT => ContentKind::Assignment,
+        MATERIAL => ContentKind::Material,
+        _ => ContentKind::Submission,
+    }
+}
+
+fn allowed_files<'a>(
+    info: &AssignmentInfo,
+    file_names: impl IntoIterator<Item = &'a str>,
+) -> Result<()> {
+    for file_name in file_names {
+        if !info.allows(file_name) {
+            let message = format!(
+                "{} is not one of the allowed types: {}",
+                file_name,
+                info.allowed_types.join(", ")
+            );
+            return Err(field_error(&file_field(file_name), message));
+        }
+    }
+    Ok(())
+}
+
+/// Ids of the groups the events change, each once.
+fn changed_groups(events: &[ActionsEvent]) -> Vec<String> {
+    let mut groups = events
+        .iter()
+        .map(|event| event.group_id().to_string())
+        .collect::<Vec<_>>();
+    groups.sort();
+    groups.dedup();
+    groups
+}
+
+fn actions_forbidden<T: AsRef<[u8]>>(message: T) -> ActionsResult {
+    actions_status(403, message)
+}
+
+fn form_errors(errors: FormErrors) -> ActionsResult {
+    match serde_json::to_string(&errors) {
+        Ok(errors) => actions_status(400, errors),
+        Err(e) => actions_error(e.to_string()),
+    }
+}
+
+pub(crate) fn actions_status<T: AsRef<[u8]>>(status: u16, message: T) -> ActionsResult {
+    let message = BASE64_STANDARD.encode(message.as_ref());
+    ActionsResult { status, message }
+}
+
+fn actions_error<T: AsRef<[u8]>>(message: T) -> ActionsResult {
+    let message = BASE64_STANDARD.encode(message.as_ref());
+    ActionsResult {
+        status: 500,
+        message,
+    }
+}
+
+fn actions_success<T: AsRef<[u8]>>(message: T) -> ActionsResult {
+    let message = BASE64_STANDARD.encode(message.as_ref());
+    ActionsResult {
+        status: 200,
+        message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions_db::actions::{ActionsRead, FileWrite};
+    use crate::actions_db::wal::LOG_SUFFIX;
+    use crate::authdb::auth_actors::{Authority, User, Users};
+    use crate::authdb::auth_db::gen_token;
+    use crate::blueprint::Blueprint;
+    use crate::config::batch_info::BatchInfo;
+    use crate::config::config_module::ConfigModule;
+    use crate::config::course_info::CourseInfo;
+    use crate::file_db::file_config::{Encoding, GradeInfo, LatePolicy, RubricScore};
+    use lms_auth::auth::AuthProvider;
+    use lms_auth::local_crypto::hash_256;
+    use std::path::PathBuf;
+
+    fn app_ctx<T: AsRef<str>>(file_db: T, actions_db: T) -> Result<AppContext> {
+        let mut module = ConfigModule::default();
+        module.auth.aes_key = "32bytebase64encodedkey".to_string();
+        module.auth.totp.totp_secret = "base32encodedkey".to_string();
+        module.auth.auth_db_path = "invalid".to_string();
+
+        module.courses.insert(
+            "course1".to_string(),
+            CourseInfo {
+                name: "Course 1".to_string(),
+                description: Some("Course 1 description".to_string()),
+            },
+        );
+        module.courses.insert(
+            "course2".to_string(),
+            CourseInfo {
+                name: "Course 2".to_string(),
+                description: None,
+            },
+        );
+
+        module.batches = vec![BatchInfo {
+            id: "22BCS".to_string(),
+            courses: vec!["course1".to_string(), "course2".to_string()],
+        }];
+
+        module.server.actions_db = actions_db.as_ref().to_string();
+        module.server.file_db = file_db.as_ref().to_string();
+
+        module.extensions.users = Some(Users::default());
+
+        let totp = module.config.auth.totp.clone().into_totp()?;
+        let auth = AuthProvider::init(
+            module.config.auth.auth_db_path.clone(),
+            totp,
+            hash_256(&module.config.auth.aes_key).clone(),
+        )?;
+        module.extensions.auth = Some(auth);
+
+        let blueprint = Blueprint::try_from(module)?;
+        let runtime = crate::runtime::tests::init();
+        let app_ctx = AppContext { blueprint, runtime };
+        Ok(app_ctx)
+    }
+
+    fn token(app_context: &AppContext) -> Result<String> {
+        token_for(app_context, Authority::Admin, None)
+    }
+
+    fn token_for(
+        app_context: &AppContext,
+        authority: Authority,
+        batch: Option<&str>,
+    ) -> Result<String> {
+        token_as(app_context, "username", authority, batch)
+    }
+
+    fn token_as(
+        app_context: &AppContext,
+        username: &str,
+        authority: Authority,
+        batch: Option<&str>,
+    ) -> Result<String> {
+        gen_token(&user(username, authority, batch), app_context)
+    }
+
+    fn user(username: &str, authority: Authority, batch: Option<&str>) -> User {
+        User {
+            username: username.to_string(),
+            name: "name".to_string(),
+            password: hash_256("password"),
+            authority,
+            batch: batch.map(|b| b.to_string()),
+            two_factor: None,
+            password_reset: None,
+        }
+    }
+
+    /// Writes without files, assignments are worth 10 points and due far in the future.
+    async fn write(
+        actions_db: &ActionsDB,
+        token: &str,
+        group_id: &str,
+        reference: &str,
+    ) -> Result<ActionsResult> {
+        let is_assignment = reference == "assignment";
+        let actions_request = ActionsRequest {
+            token: token.to_string(),
+            group_id: group_id.to_string(),
+            read: None,
+            write: Some(ActionsWrite {
+                title: "title".to_string(),
+                description: "desc".to_string(),
+                files: None,
+                end_time: is_assignment.then_some(99999999999999),
+                reference: reference.to_string(),
+                max_points: is_assignment.then_some(10),
+                allowed_types: None,
+                grade: None,
+                late_policy: None,
+                extension: None,
+            }),
+            release: None,
+        };
+        let actions_request = actions_request.into_serrequet()?;
+        Ok(actions_db
+            .handle_request(bytes::Bytes::from(actions_request))
+            .await)
+    }
+
+    async fn read(
+        actions_db: &ActionsDB,
+        token: &str,
+        group_id: &str,
+        content_id: Option<&str>,
+    ) -> Result<ActionsResult> {
+        let actions_request = ActionsRequest {
+            token: token.to_string(),
+            group_id: group_id.to_string(),
+            read: content_id.map(|content_id| ActionsRead {
+                content_id: content_id.to_string(),
+                file_name: None,
+                submissions: None,
+                grades: None,
+            }),
+            write: None,
+            release: None,
+        };
+        let actions_request = actions_request.into_serrequet()?;
+        Ok(actions_db
+            .handle_request(bytes::Bytes::from(actions_request))
+            .await)
+    }
+
+    fn decode(result: &ActionsResult) -> Result<String> {
+        Ok(String::from_utf8(BASE64_STANDARD.decode(&result.message)?)?)
+    }
+
+    #[tokio::test]
+    async fn test_policy() -> Result<()> {
+        let tmp_file = tempfile::NamedTempFile::new()?;
+        let tmp_file_path = tmp_file.path().to_str().unwrap();
+        let tmp_dir = tempfile::tempdir()?;
+        let tmp_dir_path = tmp_dir.path().to_str().unwrap();
+
+        let app_context = app_ctx(tmp_dir_path, tmp_file_path)?;
+        let faculty = token_for(&app_context, Authority::Faculty, None)?;
+        let student = token_for(&app_context, Authority::Student, Some("22BCS"))?;
+        let outsider = token_for(&app_context, Authority::Student, Some("23BCS"))?;
+        let actions_db = ActionsDB::init(Arc::new(app_context)).await?;
+
+        let result = write(&actions_db, &student, "22BCS_course1", "notice").await?;
+        assert_eq!(result.status, 403);
+        let result = write(&actions_db, &student, "22BCS_course1", "assignment").await?;
+        assert_eq!(result.status, 403);
+
+        let result = write(&actions_db, &faculty, "22BCS_course1", "assignment").await?;
+        assert_eq!(result.status, 200);
+        let assignment = decode(&result)?;
+        let result = write(&actions_db, &faculty, "22BCS_course1", "notice").await?;
+        assert_eq!(result.status, 200);
+        let notice = decode(&result)?;
+
+        // faculties post, they don't submit
+        let result = write(&actions_db, &faculty, "22BCS_course1", &assignment).await?;
+        assert_eq!(result.status, 403);
+
+        let result = write(&actions_db, &student, "22BCS_course1", &assignment).await?;
+        assert_eq!(result.status, 200);
+        let result = write(&actions_db, &student, "22BCS_course1", &notice).await?;
+        assert_eq!(result.status, 403);
+        let result = write(&actions_db, &student, "22BCS_course2", &assignment).await?;
+        assert_eq!(result.status, 403);
+        let result = write(&actions_db, &outsider, "22BCS_course1", &assignment).await?;
+        assert_eq!(result.status, 403);
+        assert_eq!(decode(&result)?, "Not enrolled in 22BCS_course1");
+
+        let result = read(&actions_db, &student, "22BCS_course1", None).await?;
+        assert_eq!(result.status, 200);
+        let result = read(&actions_db, &student, "22BCS_course1", Some(&notice)).await?;
+        assert_eq!(result.status, 200);
+        let result = read(&actions_db, &student, "22BCS_course2", Some(&notice)).await?;
+        assert_eq!(result.status, 403);
+        let result = read(&actions_db, &outsider, "22BCS_course1", None).await?;
+        assert_eq!(result.status, 403);
+        let result = read(&actions_db, &faculty, "22BCS_course2", Some(&notice)).await?;
+        assert_eq!(result.status, 200);
+
+        Ok(())
+    }
+
+    /// Lists the submissions to an assignment, or their grades.
+    async fn list(
+        actions_db: &ActionsDB,
+        token: &str,
+        assignment: &str,
+        kind: ContentKind,
+        listing: Listing,
+    ) -> Result<ActionsResult> {
+        let grades = kind == ContentKind::Grade;
+        let actions_request = ActionsRequest {
+            token: token.to_string(),
+            group_id: "22BCS_course1".to_string(),
+            read: Some(ActionsRead {
+                content_id: assignment.to_string(),
+                file_name: None,
+                submissions: (!grades).then_some(listing),
+                grades: grades.then_some(listing),
+            }),
+            write: None,
+            release: None,
+        };
+        let actions_request = actions_request.into_serrequet()?;
+        Ok(actions_db
+            .handle_request(bytes::Bytes::from(actions_request))
+            .await)
+    }
+
+    #[tokio::test]
+    async fn test_content_kinds() -> Result<()> {
+        let tmp_file = tempfile::NamedTempFile::new()?;
+        let tmp_file_path = tmp_file.path().to_str().unwrap();
+        let tmp_dir = tempfile::tempdir()?;
+        let tmp_dir_path = tmp_dir.path().to_str().unwrap();
+
+        let app_context = app_ctx(tmp_dir_path, tmp_file_path)?;
+        let faculty = token_as(&app_context, "faculty", Authority::Faculty, None)?;
+        let alice = token_as(&app_context, "alice", Authority::Student, Some("22BCS"))?;
+        let bob = token_as(&app_context, "bob", Authority::Student, Some("22BCS"))?;
+        let actions_db = ActionsDB::init(Arc::new(app_context)).await?;
+
+        let result = write(&actions_db, &faculty, "22BCS_course1", "assignment").await?;
+        let assignment = decode(&result)?;
+        let result = write(&actions_db, &faculty, "22BCS_course1", "material").await?;
+        assert_eq!(result.status, 200);
+        let material = decode(&result)?;
+        let result = write(&actions_db, &alice, "22BCS_course1", "material").await?;
+        assert_eq!(result.status, 403);
+
+        let first = decode(&write(&actions_db, &alice, "22BCS_course1", &assignment).await?)?;
+        let second = decode(&write(&actions_db, &alice, "22BCS_course1", &assignment).await?)?;
+        let theirs = decode(&write(&actions_db, &bob, "22BCS_course1", &assignment).await?)?;
+        let result = write(&actions_db, &alice, "22BCS_course1", &material).await?;
+        assert_eq!(result.status, 403);
+        let result = write(&actions_db, &alice, "22BCS_course1", &theirs).await?;
+        assert_eq!(result.status, 403);
+
+        let group = GroupId::new("22BCS", "course1");
+        let submission = actions_db.activity.find(&group, &first).unwrap();
+        assert_eq!(submission.kind, ContentKind::Submission);
+        assert_eq!(submission.assignment.as_deref(), Some(assignment.as_str()));
+        assert_eq!(submission.submitter.as_deref(), Some("alice"));
+        let metadata = actions_db
+            .file_request_handler
+            .get_metadata(&assignment)
+            .await?;
+        assert_eq!(metadata.assignment.map(|a| a.max_points), Some(10));
+
+        let ids = |result: &ActionsResult| -> Result<Vec<String>> {
+            let actions: Vec<ActionsContent> = serde_json::from_str(&decode(result)?)?;
+            Ok(actions.into_iter().map(|a| a.content_id).collect())
+        };
+        let result = list(
+            &actions_db,
+            &faculty,
+            &assignment,
+            ContentKind::Submission,
+            Listing::All,
+        )
+        .await?;
+        assert_eq!(ids(&result)?, [&*first, &second, &theirs]);
+        let result = list(
+            &actions_db,
+            &alice,
+            &assignment,
+            ContentKind::Submission,
+            Listing::Mine,
+        )
+        .await?;
+        assert_eq!(ids(&result)?, [&*first, &second]);
+        let result = list(
+            &actions_db,
+            &alice,
+            &assignment,
+            ContentKind::Submission,
+            Listing::All,
+        )
+        .await?;
+        assert_eq!(result.status, 403);
+        let result = list(
+            &actions_db,
+            &alice,
+            &material,
+            ContentKind::Submission,
+            Listing::Mine,
+        )
+        .await?;
+        assert_eq!(result.status, 403);
+
+        // students don't see the submissions of others
+        let result = read(&actions_db, &bob, "22BCS_course1", None).await?;
+        assert_eq!(ids(&result)?, [&*assignment, &material, &theirs]);
+        let result = read(&actions_db, &bob, "22BCS_course1", Some(&first)).await?;
+        assert_eq!(result.status, 403);
+        let result = read(&actions_db, &faculty, "22BCS_course1", Some(&first)).await?;
+        assert_eq!(result.status, 200);
+
+        // only assignments have points, and they need them along with a due date
+        let points = |reference: &str| ActionsRequest {
+            token: faculty.clone(),
+            group_id: "22BCS_course1".to_string(),
+            read: None,
+            write: Some(ActionsWrite {
+                title: "title".to_string(),
+                description: "desc".to_string(),
+                files: None,
+                end_time: None,
+                reference: reference.to_string(),
+                max_points: Some(10),
+                allowed_types: None,
+                grade: None,
+                late_policy: None,
+                extension: None,
+            }),
+            release: None,
+        };
+        let request = points("assignment").into_serrequet()?;
+        let result = actions_db.handle_request(request.into()).await;
+        assert_eq!(decode(&result)?, "An assignment needs an end_time");
+        let request = points("notice").into_serrequet()?;
+        let result = actions_db.handle_request(request.into()).await;
+        assert_eq!(
+            decode(&result)?,
+            "Only assignments have max_points, allowed_types and late_policy"
+        );
+
+        // actions stored before there were kinds
+        let legacy: Vec<ActionsContent> = serde_json::from_str(
+            r#"[{"is_notif":true,"content_id":"a"},{"is_notif":false,"content_id":"b"}]"#,
+        )?;
+        assert_eq!(
+            legacy.iter().map(|a| a.kind).collect::<Vec<_>>(),
+            [ContentKind::Notice, ContentKind::Submission]
+        );
+        Ok(())
+    }
+
+    /// Grades a submission with `points`, split between correctness and style.
+    async fn grade(
+        actions_db: &ActionsDB,
+        token: &str,
+        submission: &str,
+        points: u32,
+    ) -> Result<ActionsResult> {
+        let actions_request = ActionsRequest {
+            token: token.to_string(),
+            group_id: "22BCS_course1".to_string(),
+            read: None,
+            write: Some(ActionsWrite {
+                title: "Grade".to_string(),
+                description: "Good work".to_string(),
+                files: Some(vec![FileWrite {
+                    file_name: "feedback.txt".to_string(),
+                    encoding: Encoding::Utf8,
+                    content: "see line 3".to_string(),
+                }]),
+                end_time: None,
+                reference: submission.to_string(),
+                max_points: None,
+                allowed_types: None,
+                grade: Some(GradeInfo {
+                    points,
+                    rubric: vec![
+                        RubricScore {
+                            criterion: "correctness".to_string(),
+                            points: points.min(5),
+                        },
+                        RubricScore {
+                            criterion: "style".to_string(),
+                            points: points.saturating_sub(5),
+                        },
+                    ],
+                    penalty: 0,
+                }),
+                late_policy: None,
+                extension: None,
+            }),
+            release: None,
+        };
+        let actions_request = actions_request.into_serrequet()?;
+        Ok(actions_db
+            .handle_request(bytes::Bytes::from(actions_request))
+            .await)
+    }
+
+    async fn release(
+        actions_db: &ActionsDB,
+        token: &str,
+        assignment: &str,
+    ) -> Result<ActionsResult> {
+        let actions_request = ActionsRequest {
+            token: token.to_string(),
+            group_id: "22BCS_course1".to_string(),
+            read: None,
+            write: None,
+            release: Some(assignment.to_string()),
+        };
+        let actions_request = actions_request.into_serrequet()?;
+        Ok(actions_db
+            .handle_request(bytes::Bytes::from(actions_request))
+            .await)
+    }
+
+    #[tokio::test]
+    async fn test_grading() -> Result<()> {
+        let tmp_file = tempfile::NamedTempFile::new()?;
+        let tmp_file_path = tmp_file.path().to_str().unwrap();
+        let tmp_dir = tempfile::tempdir()?;
+        let tmp_dir_path = tmp_dir.path().to_str().unwrap();
+
+        let app_context = Arc::new(app_ctx(tmp_dir_path, tmp_file_path)?);
+        let faculty = token_as(&app_context, "faculty", Authority::Faculty, None)?;
+        let alice = token_as(&app_context, "alice", Authority::Student, Some("22BCS"))?;
+        let bob = token_as(&app_context, "bob", Authority::Student, Some("22BCS"))?;
+        let actions_db = ActionsDB::init(app_context.clone()).await?;
+
+        let assignment =
+            decode(&write(&actions_db, &faculty, "22BCS_course1", "assignment").await?)?;
+        let submission = decode(&write(&actions_db, &alice, "22BCS_course1", &assignment).await?)?;
+        let theirs = decode(&write(&actions_db, &bob, "22BCS_course1", &assignment).await?)?;
+
+        let result = grade(&actions_db, &alice, &submission, 8).await?;
+        assert_eq!(result.status, 403);
+        let result = grade(&actions_db, &faculty, &submission, 11).await?;
+        assert_eq!(
+            decode(&result)?,
+            "Grade of 11 points exceeds the 10 of the assignment"
+        );
+        let result = grade(&actions_db, &faculty, &assignment, 8).await?;
+        assert_eq!(result.status, 500);
+
+        let result = grade(&actions_db, &faculty, &submission, 8).await?;
+        assert_eq!(result.status, 200, "{}", decode(&result)?);
+        let graded = decode(&result)?;
+        let result = grade(&actions_db, &faculty, &theirs, 6).await?;
+        assert_eq!(result.status, 200);
+        let group = GroupId::new("22BCS", "course1");
+        let action = actions_db.activity.find(&group, &graded).unwrap();
+        assert_eq!(action.kind, ContentKind::Grade);
+        assert_eq!(action.submission.as_deref(), Some(submission.as_str()));
+        assert_eq!(action.assignment.as_deref(), Some(assignment.as_str()));
+        assert_eq!(action.submitter.as_deref(), Some("alice"));
+        assert!(!action.released);
+
+        // grades stay with the faculty until they're released
+        let ids = |result: &ActionsResult| -> Result<Vec<String>> {
+            let actions: Vec<ActionsContent> = serde_json::from_str(&decode(result)?)?;
+            Ok(actions.into_iter().map(|a| a.content_id).collect())
+        };
+        let result = list(
+            &actions_db,
+            &faculty,
+            &assignment,
+            ContentKind::Grade,
+            Listing::All,
+        )
+        .await?;
+        assert_eq!(ids(&result)?.len(), 2);
+        let result = list(
+            &actions_db,
+            &alice,
+            &assignment,
+            ContentKind::Grade,
+            Listing::Mine,
+        )
+        .await?;
+        assert!(ids(&result)?.is_empty());
+        let result = read(&actions_db, &alice, "22BCS_course1", Some(&graded)).await?;
+        assert_eq!(result.status, 403);
+        let result = read(&actions_db, &alice, "22BCS_course1", None).await?;
+        assert!(!ids(&result)?.contains(&graded));
+
+        let result = release(&actions_db, &alice, &assignment).await?;
+        assert_eq!(result.status, 403);
+        let result = release(&actions_db, &faculty, &submission).await?;
+        assert_eq!(result.status, 500);
+        let result = release(&actions_db, &faculty, &assignment).await?;
+        assert_eq!((result.status, decode(&result)?), (200, "2".to_string()));
+
+        let result = list(
+            &actions_db,
+            &alice,
+            &assignment,
+            ContentKind::Grade,
+            Listing::Mine,
+        )
+        .await?;
+        assert_eq!(ids(&result)?, [&*graded]);
+        let result = list(
+            &actions_db,
+            &alice,
+            &assignment,
+            ContentKind::Grade,
+            Listing::All,
+        )
+        .await?;
+        assert_eq!(result.status, 403);
+        let result = read(&actions_db, &bob, "22BCS_course1", Some(&graded)).await?;
+        assert_eq!(result.status, 403);
+        let result = read(&actions_db, &alice, "22BCS_course1", Some(&graded)).await?;
+        let metadata: Metadata = serde_json::from_str(&decode(&result)?)?;
+        assert_eq!(metadata.description, "Good work");
+        assert_eq!(metadata.grade.map(|grade| grade.rubric.len()), Some(2));
+        let feedback = actions_db
+            .file_request_handler
+            .get(&graded, "feedback.txt")
+            .await?;
+        assert_eq!(feedback.content, b"see line 3");
+
+        // the release is logged along with the grades
+        let restarted = ActionsDB::init(app_context).await?;
+        assert!(restarted.activity.find(&group, &graded).unwrap().released);
+        Ok(())
+    }
+
+    /// Writes to `22BCS_course1`.
+    async fn send(
+        actions_db: &ActionsDB,
+        token: &str,
+        write: ActionsWrite,
+    ) -> Result<ActionsResult> {
+        let actions_request = ActionsRequest {
+            token: token.to_string(),
+            group_id: "22BCS_course1".to_string(),
+            read: None,
+            write: Some(write),
+            release: None,
+        };
+        let actions_request = actions_request.into_serrequet()?;
+        Ok(actions_db
+            .handle_request(bytes::Bytes::from(actions_request))
+            .await)
+    }
+
+    fn late_write(reference: &str, end_time: Option<u128>) -> ActionsWrite {
+        ActionsWrite {
+            title: "title".to_string(),
+            description: "desc".to_string(),
+            files: None,
+            end_time,
+            reference: reference.to_string(),
+            max_points: None,
+            allowed_types: None,
+            grade: None,
+            late_policy: None,
+            extension: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_late_submissions() -> Result<()> {
+        let tmp_file = tempfile::NamedTempFile::new()?;
+        let tmp_file_path = tmp_file.path().to_str().unwrap();
+        let tmp_dir = tempfile::tempdir()?;
+        let tmp_dir_path = tmp_dir.path().to_str().unwrap();
+
+        let app_context = Arc::new(app_ctx(tmp_dir_path, tmp_file_path)?);
+        let faculty = token_as(&app_context, "faculty", Authority::Faculty, None)?;
+        let alice = token_as(&app_context, "alice", Authority::Student, Some("22BCS"))?;
+        let bob = token_as(&app_context, "bob", Authority::Student, Some("22BCS"))?;
+        let actions_db = ActionsDB::init(app_context.clone()).await?;
+        let now = app_context.runtime.instance.now()?;
+        let hour = 60 * 60 * 1000;
+        for (username, authority, batch) in [
+            ("alice", Authority::Student, Some("22BCS")),
+            ("carol", Authority::Student, Some("23BCS")),
+            ("faculty", Authority::Faculty, None),
+        ] {
+            actions_db
+                .users
+                .upsert(user(username, authority, batch))
+                .await?;
+        }
+
+        let assignment = |end_time, late_policy| ActionsWrite {
+            max_points: Some(10),
+            late_policy,
+            ..late_write("assignment", Some(end_time))
+        };
+        let result = send(
+            &actions_db,
+            &faculty,
+            assignment(now, Some(LatePolicy::Penalty { per_day: 0, cap: 0 })),
+        )
+        .await?;
+        assert_eq!(
+            decode(&result)?,
+            "Penalty per day must be between 1 and 100 percent"
+        );
+        let result = send(
+            &actions_db,
+            &faculty,
+            assignment(
+                now,
+                Some(LatePolicy::Penalty {
+                    per_day: 30,
+                    cap: 20,
+                }),
+            ),
+        )
+        .await?;
+        assert_eq!(
+            (result.status, decode(&result)?),
+            (400, "Penalty per day can't exceed the cap".to_string())
+        );
+        let result = send(
+            &actions_db,
+            &faculty,
+            ActionsWrite {
+                late_policy: Some(LatePolicy::Cutoff),
+                ..late_write("notice", None)
+            },
+        )
+        .await?;
+        assert_eq!(result.status, 400);
+
+        // a day and a half late costs two days of penalty, capped
+        let penalty = LatePolicy::Penalty {
+            per_day: 10,
+            cap: 15,
+        };
+        let result = send(
+            &actions_db,
+            &faculty,
+            assignment(now - 36 * hour, Some(penalty)),
+        )
+        .await?;
+        let penalized = decode(&result)?;
+        let submission = decode(&write(&actions_db, &alice, "22BCS_course1", &penalized).await?)?;
+        let metadata = actions_db
+            .file_request_handler
+            .get_metadata(&submission)
+            .await?;
+        let stamp = metadata.submission.unwrap();
+        assert!(stamp.is_late() && stamp.late_by >= 36 * hour);
+        assert_eq!((stamp.due, stamp.penalty), (Some(now - 36 * hour), 15));
+
+        let result = grade(&actions_db, &faculty, &submission, 8).await?;
+        let graded = decode(&result)?;
+        let metadata = actions_db
+            .file_request_handler
+            .get_metadata(&graded)
+            .await?;
+        let grade = metadata.grade.unwrap();
+        assert_eq!((grade.points, grade.penalty, grade.total()), (8, 15, 6));
+
+        // a grace period accepts late submissions without a penalty
+        let grace = LatePolicy::Grace { grace: hour as u64 };
+        let result = send(&actions_db, &faculty, assignment(now - 1000, Some(grace))).await?;
+        let graced = decode(&result)?;
+        let submission = decode(&write(&actions_db, &alice, "22BCS_course1", &graced).await?)?;
+        let metadata = actions_db
+            .file_request_handler
+            .get_metadata(&submission)
+            .await?;
+        let stamp = metadata.submission.unwrap();
+        assert!(stamp.is_late());
+        assert_eq!(stamp.penalty, 0);
+
+        // only faculties grant extensions, past the due date
+        let result = send(&actions_db, &faculty, assignment(now - 1000, None)).await?;
+        let cutoff = decode(&result)?;
+        let result = write(&actions_db, &alice, "22BCS_course1", &cutoff).await?;
+        assert_eq!(decode(&result)?, "Submission time has passed");
+        let extension_to = |student: &str, end_time| ActionsWrite {
+            extension: Some(student.to_string()),
+            ..late_write(&cutoff, end_time)
+        };
+        let extension = |end_time| extension_to("alice", end_time);
+        let result = send(&actions_db, &alice, extension(Some(now + hour))).await?;
+        assert_eq!(result.status, 403);
+        // only to a student of the batch of the group
+        for student in ["nobody", "carol", "faculty"] {
+            let result = send(
+                &actions_db,
+                &faculty,
+                extension_to(student, Some(now + hour)),
+            )
+            .await?;
+            assert_eq!(result.status, 400);
+            assert_eq!(
+                decode(&result)?,
+                format!("{} is not a student of 22BCS_course1", student)
+            );
         }
+        let result = send(&actions_db, &faculty, extension(None)).await?;
+        assert_eq!(decode(&result)?, "An extension needs an end_time");
+        let result = send(&actions_db, &faculty, extension(Some(now - 2000))).await?;
+        assert_eq!(decode(&result)?, "An extension must end after the due date");
+        let result = send(&actions_db, &faculty, extension(Some(now + hour))).await?;
+        assert_eq!(result.status, 200, "{}", decode(&result)?);
+        let extended = decode(&result)?;
+
+        let result = write(&actions_db, &alice, "22BCS_course1", &cutoff).await?;
+        assert_eq!(result.status, 200, "{}", decode(&result)?);
+        let submission = decode(&result)?;
+        let metadata = actions_db
+            .file_request_handler
+            .get_metadata(&submission)
+            .await?;
+        let stamp = metadata.submission.unwrap();
+        assert!(!stamp.is_late());
+        assert_eq!(stamp.due, Some(now + hour));
+        let result = write(&actions_db, &bob, "22BCS_course1", &cutoff).await?;
+        assert_eq!(decode(&result)?, "Submission time has passed");
+
+        // files are attached within the deadline of the submitter too
+        let attach = || {
+            let file = ActionsFile {
+                token: alice.clone(),
+                group_id: "22BCS_course1".to_string(),
+                content_id: submission.clone(),
+                file_name: "notes.txt".to_string(),
+            };
+            actions_db.handle_upload(
+                file,
+                None,
+                stream::once(bytes::Bytes::from_static(b"notes")),
+            )
+        };
+        assert_eq!(attach().await.status, 200);
+        let result = send(&actions_db, &faculty, extension(Some(now - 500))).await?;
+        assert_eq!(result.status, 200, "{}", decode(&result)?);
+        let result = attach().await;
+        assert_eq!(
+            (result.status, decode(&result)?),
+            (400, "Submission time has passed".to_string())
+        );
+
+        let ids = |result: &ActionsResult| -> Result<Vec<String>> {
+            let actions: Vec<ActionsContent> = serde_json::from_str(&decode(result)?)?;
+            Ok(actions.into_iter().map(|a| a.content_id).collect())
+        };
+        let result = read(&actions_db, &alice, "22BCS_course1", None).await?;
+        assert!(ids(&result)?.contains(&extended));
+        let result = read(&actions_db, &bob, "22BCS_course1", None).await?;
+        assert!(!ids(&result)?.contains(&extended));
+        Ok(())
     }
 
-    async fn handle_write(&self, actions_request: ActionsRequest) -> Result<String> {
-        if actions_request.write.is_none() {
-            return Err(anyhow!("Invalid Actions request"));
-        }
-        let write = actions_request.write.unwrap();
+    #[tokio::test]
+    async fn test_stream_files() -> Result<()> {
+        let tmp_file = tempfile::NamedTempFile::new()?;
+        let tmp_file_path = tmp_file.path().to_str().unwrap();
+        let tmp_dir = tempfile::tempdir()?;
+        let tmp_dir_path = tmp_dir.path().to_str().unwrap();
 
-        self.validate_write(&write).await?;
+        let mut app_context = app_ctx(tmp_dir_path, tmp_file_path)?;
+        app_context.blueprint.server.max_upload_size = 10;
+        let faculty = token_as(&app_context, "faculty", Authority::Faculty, None)?;
+        let other = token_as(&app_context, "other", Authority::Faculty, None)?;
+        let student = token_as(&app_context, "student", Authority::Student, Some("22BCS"))?;
+        let actions_db = ActionsDB::init(Arc::new(app_context)).await?;
 
-        let timestamp = self.app_context.runtime.instance.now()?;
+        let result = write(&actions_db, &faculty, "22BCS_course1", "assignment").await?;
+        let assignment = decode(&result)?;
+        let result = write(&actions_db, &student, "22BCS_course1", &assignment).await?;
+        let submission = decode(&result)?;
 
-        let info = InsertionInfo {
-            title: write.title,
-            description: write.description,
-            timestamp,
-            end_time: write.end_time,
+        let file = |token: &str, content_id: &str| ActionsFile {
+            token: token.to_string(),
+            group_id: "22BCS_course1".to_string(),
+            content_id: content_id.to_string(),
+            file_name: "notes.pdf".to_string(),
+        };
+        let chunks = || {
+            Box::pin(futures_util::stream::iter(vec![
+                Ok(bytes::Bytes::from_static(b"01234")),
+                Ok(bytes::Bytes::from_static(b"56789")),
+            ])) as ByteStream
         };
 
-        let content_id = self
-            .activity
-            .insert(
-                actions_request.group_id, // TODO add validation for invalid grp id
-                info,
-                write
-                    .files
-                    .unwrap_or_default()
-                    .into_iter()
-                    .map(|file| FileHolder {
-                        name: file.file_name,
-                        content: file.content,
-                    })
-                    .collect(),
-                &self.file_request_handler,
-                write.reference.eq("notice"),
-            )
-            .await?;
+        // only the author attaches files
+        let result = actions_db
+            .handle_upload(file(&faculty, &assignment), None, chunks())
+            .await;
+        assert_eq!((result.status, decode(&result)?), (200, "10".to_string()));
+        let result = actions_db
+            .handle_upload(file(&other, &assignment), None, chunks())
+            .await;
+        assert_eq!(result.status, 403);
+        let result = actions_db
+            .handle_upload(file(&student, &assignment), None, chunks())
+            .await;
+        assert_eq!(result.status, 403);
+        let result = actions_db
+            .handle_upload(file(&student, &submission), None, chunks())
+            .await;
+        assert_eq!(result.status, 200);
+        let result = actions_db
+            .handle_upload(file("invalid", &submission), None, chunks())
+            .await;
+        assert_eq!(result.status, 401);
+        // admins attach to course content only
+        let admin = token(&actions_db.app_context)?;
+        let result = actions_db
+            .handle_upload(file(&admin, &assignment), None, chunks())
+            .await;
+        assert_eq!(result.status, 200);
+        let result = actions_db
+            .handle_upload(file(&admin, &submission), None, chunks())
+            .await;
+        assert_eq!(result.status, 403);
 
-        let actions_db_path = &self.app_context.blueprint.server.actions_db;
-        if actions_db_path.starts_with("http") {
-            let url = url::Url::parse(actions_db_path)?;
-            let mut req = reqwest::Request::new(reqwest::Method::POST, url);
-            *req.body_mut() = Some(reqwest::Body::from(serde_json::to_vec(&self.activity)?));
+        // the cap is checked against the announced length and while streaming
+        let result = actions_db
+            .handle_upload(file(&faculty, &assignment), Some(11), chunks())
+            .await;
+        assert_eq!(result.status, 413);
+        let more =
+            futures_util::StreamExt::chain(chunks(), stream::once(bytes::Bytes::from_static(b"!")));
+        let more = Box::pin(more);
+        let result = actions_db
+            .handle_upload(file(&faculty, &assignment), None, more)
+            .await;
+        assert_eq!(result.status, 413);
 
-            self.app_context.runtime.http.execute(req).await?;
-        } else {
-            self.app_context
-                .runtime
-                .file
-                .write(actions_db_path, &serde_json::to_vec(&self.activity)?)
-                .await?;
-        }
+        let range = ByteRange::From {
+            start: 3,
+            end: Some(5),
+        };
+        let Ok(download) = actions_db
+            .handle_download(file(&student, &assignment), Some(range))
+            .await
+        else {
+            panic!("download failed");
+        };
+        assert_eq!(download.content_range(), "bytes 3-5/10");
+        assert_eq!(stream::collect(download.stream).await?, b"345");
+        let result = actions_db
+            .handle_download(file(&student, &assignment), Some(ByteRange::Suffix(0)))
+            .await;
+        assert_eq!(result.err().map(|result| result.status), Some(416));
+        let mut unknown = file(&student, &assignment);
+        unknown.file_name = "unknown.pdf".to_string();
+        let result = actions_db.handle_download(unknown, None).await;
+        assert_eq!(result.err().map(|result| result.status), Some(404));
+
+        let metadata = actions_db
+            .file_request_handler
+            .get_metadata(&assignment)
+            .await?;
+        assert_eq!(metadata.author.as_deref(), Some("faculty"));
 
-        Ok(content_id)
-    }
-    async fn validate_write(&self, write: &ActionsWrite) -> Result<()> {
-        if write.reference.is_empty() {
-            return Err(anyhow!("Invalid reference"));
-        }
-        if write.reference != "notice" {
-            let metadata = self
-                .file_request_handler
-                .get_metadata(&write.reference)
-                .await?;
-            if let Some(end_time) = metadata.end_time {
-                if end_time < self.app_context.runtime.instance.now()? {
-                    return Err(anyhow!("Submission time has passed"));
-                }
-            }
-        }
+        // graded submissions are closed
+        let result = grade(&actions_db, &faculty, &submission, 8).await?;
+        assert_eq!(result.status, 200, "{}", decode(&result)?);
+        let result = actions_db
+            .handle_upload(file(&student, &submission), None, chunks())
+            .await;
+        assert_eq!(
+            (result.status, decode(&result)?),
+            (400, format!("{} is already graded", submission))
+        );
         Ok(())
     }
-}
 
-fn verify_token(token: &str, app_context: &AppContext) -> Result<()> {
-    let token = app_context
-        .blueprint
-        .extensions
-        .auth
-        .decrypt_aes(token)
-        .map_err(|_| anyhow!("Unable to decrypt token"))?;
-    let token = token.split('_').collect::<Vec<&str>>();
-    if token.len() != 2 {
-        return Err(anyhow!("Invalid token"));
-    }
-    let token = token[1];
-    let token = app_context
-        .blueprint
-        .server
-        .totp
-        .check_current(token)
-        .map_err(|_| anyhow!("Invalid token"))?;
-    if !token {
-        // || username != user_name .. maybe add this in future
-        return Err(anyhow!("Invalid token, please re-login"));
+    const FORM: &str = "multipart/form-data; boundary=boundary";
+
+    /// A form of `(name, file name, content)` parts.
+    fn form(parts: &[(&str, Option<&str>, &[u8])]) -> bytes::Bytes {
+        let mut body = vec![];
+        for (name, file_name, content) in parts {
+            let file_name = file_name
+                .map(|file_name| format!("; filename=\"{}\"", file_name))
+                .unwrap_or_default();
+            body.extend_from_slice(
+                format!(
+                    "--boundary\r\nContent-Disposition: form-data; name=\"{}\"{}\r\n\r\n",
+                    name, file_name
+                )
+                .as_bytes(),
+            );
+            body.extend_from_slice(content);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(b"--boundary--\r\n");
+        bytes::Bytes::from(body)
     }
 
-    Ok(())
-}
+    #[tokio::test]
+    async fn test_form_too_large() -> Result<()> {
+        let tmp_file = tempfile::NamedTempFile::new()?;
+        let tmp_file_path = tmp_file.path().to_str().unwrap();
+        let tmp_dir = tempfile::tempdir()?;
+        let tmp_dir_path = tmp_dir.path().to_str().unwrap();
 
-fn actions_error<T: AsRef<[u8]>>(message: T) -> ActionsResult {
-    let message = BASE64_STANDARD.encode(message.as_ref());
-    ActionsResult {
-        status: 500,
-        message,
-    }
-}
+        let mut app_context = app_ctx(tmp_dir_path, tmp_file_path)?;
+        app_context.blueprint.server.max_upload_size = 100;
+        let faculty = token_as(&app_context, "faculty", Authority::Faculty, None)?;
+        let actions_db = ActionsDB::init(Arc::new(app_context)).await?;
 
-fn actions_success<T: AsRef<[u8]>>(message: T) -> ActionsResult {
-    let message = BASE64_STANDARD.encode(message.as_ref());
-    ActionsResult {
-        status: 200,
-        message,
+        // the announced length is rejected before reading anything
+        let unread = Box::pin(futures_util::stream::once(async {
+            Err(anyhow!("the body was read"))
+        })) as ByteStream;
+        let result = actions_db
+            .handle_form(Some(&faculty), Some(FORM), Some(101), unread)
+            .await;
+        assert_eq!(result.status, 413);
+
+        let chunk = bytes::Bytes::from_static(&[b'a'; 60]);
+        let chunks = futures_util::stream::iter([Ok(chunk.clone()), Ok(chunk)]);
+        let result = actions_db
+            .handle_form(Some(&faculty), Some(FORM), None, Box::pin(chunks))
+            .await;
+        assert_eq!(
+            (result.status, decode(&result)?),
+            (413, TooLarge { max: 100 }.to_string())
+        );
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::actions_db::actions::{ActionsContent, ActionsRead, FileWrite};
-    use crate::authdb::auth_actors::Users;
-    use crate::blueprint::Blueprint;
-    use crate::config::batch_info::BatchInfo;
-    use crate::config::config_module::ConfigModule;
-    use crate::config::course_info::CourseInfo;
-    use crate::file_db::file_config::Metadata;
-    use lms_auth::auth::AuthProvider;
-    use lms_auth::local_crypto::hash_256;
-    use std::path::PathBuf;
+    #[tokio::test]
+    async fn test_handle_form() -> Result<()> {
+        let tmp_file = tempfile::NamedTempFile::new()?;
+        let tmp_file_path = tmp_file.path().to_str().unwrap();
+        let tmp_dir = tempfile::tempdir()?;
+        let tmp_dir_path = tmp_dir.path().to_str().unwrap();
 
-    fn app_ctx<T: AsRef<str>>(file_db: T, actions_db: T) -> Result<AppContext> {
-        let mut module = ConfigModule::default();
-        module.auth.aes_key = "32bytebase64encodedkey".to_string();
-        module.auth.totp.totp_secret = "base32encodedkey".to_string();
-        module.auth.auth_db_path = "invalid".to_string();
+        let app_context = app_ctx(tmp_dir_path, tmp_file_path)?;
+        let faculty = token_for(&app_context, Authority::Faculty, None)?;
+        let student = token_for(&app_context, Authority::Student, Some("22BCS"))?;
+        let admin = token(&app_context)?;
+        let actions_db = ActionsDB::init(Arc::new(app_context)).await?;
 
-        module.courses.insert(
-            "course1".to_string(),
-            CourseInfo {
-                name: "Course 1".to_string(),
-                description: Some("Course 1 description".to_string()),
-            },
+        let assignment = form(&[
+            ("title", None, b"Week 1"),
+            ("description", None, b"Read both"),
+            ("group", None, b"22BCS_course1"),
+            ("reference", None, b"assignment"),
+            ("end_time", None, b"99999999999999"),
+            ("max_points", None, b"20"),
+            ("allowed_types", None, b"pdf, .TXT"),
+            ("files", Some("notes.pdf"), b"%PDF\r\n\x00\xff"),
+            ("files", Some("notes.txt"), b"notes"),
+            ("files", Some(""), b""),
+        ]);
+        let result = actions_db
+            .handle_form(Some(&faculty), Some(FORM), None, stream::once(assignment))
+            .await;
+        assert_eq!(result.status, 200, "{}", decode(&result)?);
+        let assignment = decode(&result)?;
+        let metadata = actions_db
+            .file_request_handler
+            .get_metadata(&assignment)
+            .await?;
+        assert_eq!(metadata.title, "Week 1");
+        assert_eq!(metadata.end_time, Some(99999999999999));
+        assert_eq!(
+            metadata.assignment,
+            Some(AssignmentInfo {
+                max_points: 20,
+                allowed_types: vec!["pdf".to_string(), "txt".to_string()],
+                late_policy: LatePolicy::Cutoff,
+            })
         );
-        module.courses.insert(
-            "course2".to_string(),
-            CourseInfo {
-                name: "Course 2".to_string(),
-                description: None,
-            },
+        let file = actions_db
+            .file_request_handler
+            .get(&assignment, "notes.pdf")
+            .await?;
+        assert_eq!(file.content, b"%PDF\r\n\x00\xff");
+
+        let submission = form(&[
+            ("title", None, b"Answers"),
+            ("group", None, b"22BCS_course1"),
+            ("reference", None, assignment.as_bytes()),
+            ("files", Some("answers.pdf"), b"%PDF"),
+        ]);
+        let result = actions_db
+            .handle_form(
+                Some(&student),
+                Some(FORM),
+                None,
+                stream::once(submission.clone()),
+            )
+            .await;
+        assert_eq!(result.status, 200, "{}", decode(&result)?);
+        let result = actions_db
+            .handle_form(None, Some(FORM), None, stream::once(submission))
+            .await;
+        assert_eq!(result.status, 401);
+        let executable = form(&[
+            ("title", None, b"Answers"),
+            ("group", None, b"22BCS_course1"),
+            ("reference", None, assignment.as_bytes()),
+            ("files", Some("answers.exe"), b"MZ"),
+        ]);
+        let result = actions_db
+            .handle_form(Some(&student), Some(FORM), None, stream::once(executable))
+            .await;
+        assert_eq!(result.status, 400);
+        let errors: FormErrors = serde_json::from_str(&decode(&result)?)?;
+        assert!(
+            errors["files[answers.exe]"].starts_with("answers.exe is not one of the allowed types")
         );
 
-        module.batches = vec![BatchInfo {
-            id: "22BCS".to_string(),
-            courses: vec!["course1".to_string(), "course2".to_string()],
-        }];
-
-        module.server.actions_db = actions_db.as_ref().to_string();
-        module.server.file_db = file_db.as_ref().to_string();
-
-        module.extensions.users = Some(Users::default());
-
-        let totp = module.config.auth.totp.clone().into_totp()?;
-        let auth = AuthProvider::init(
-            module.config.auth.auth_db_path.clone(),
-            totp,
-            hash_256(&module.config.auth.aes_key).clone(),
-        )?;
-        module.extensions.auth = Some(auth);
+        // errors of the write are keyed by their field
+        let unbounded = form(&[
+            ("title", None, b"Week 2"),
+            ("group", None, b"22BCS_course1"),
+            ("reference", None, b"assignment"),
+            ("max_points", None, b"20"),
+        ]);
+        let result = actions_db
+            .handle_form(Some(&faculty), Some(FORM), None, stream::once(unbounded))
+            .await;
+        assert_eq!(result.status, 400);
+        let errors: FormErrors = serde_json::from_str(&decode(&result)?)?;
+        assert_eq!(
+            errors,
+            FormErrors::from([(
+                "end_time".to_string(),
+                "An assignment needs an end_time".to_string()
+            )])
+        );
 
-        let blueprint = Blueprint::try_from(module)?;
-        let runtime = crate::runtime::tests::init();
-        let app_ctx = AppContext { blueprint, runtime };
-        Ok(app_ctx)
+        // every field is checked at once
+        let invalid = form(&[
+            ("group", None, b"22BCS_course9"),
+            ("reference", None, b"notice"),
+            ("end_time", None, b"tomorrow"),
+            ("max_points", None, b"ten"),
+            ("colour", None, b"red"),
+            ("files", Some("../notes.pdf"), b"%PDF"),
+            ("files", Some("notes.pdf"), b"%PDF"),
+            ("files", Some("notes.pdf"), b"%PDF"),
+        ]);
+        let result = actions_db
+            .handle_form(Some(&faculty), Some(FORM), None, stream::once(invalid))
+            .await;
+        assert_eq!(result.status, 400);
+        let errors: FormErrors = serde_json::from_str(&decode(&result)?)?;
+        assert_eq!(
+            errors.keys().collect::<Vec<_>>(),
+            vec![
+                "colour",
+                "end_time",
+                "files[../notes.pdf]",
+                "files[notes.pdf]",
+                "group",
+                "max_points",
+                "title"
+            ]
+        );
+        assert_eq!(errors["title"], "Missing field");
+        assert_eq!(errors["files[notes.pdf]"], "Duplicate file");
+
+        let notice = form(&[
+            ("title", None, b"Notice"),
+            ("group", None, b"22BCS_course1"),
+            ("reference", None, b"notice"),
+        ]);
+        let result = actions_db
+            .handle_form(
+                Some(&student),
+                Some(FORM),
+                None,
+                stream::once(notice.clone()),
+            )
+            .await;
+        assert_eq!(result.status, 403);
+        let result = actions_db
+            .handle_form(
+                Some(&faculty),
+                Some("application/json"),
+                None,
+                stream::once(notice),
+            )
+            .await;
+        assert_eq!(result.status, 400);
+        assert!(decode(&result)?.contains("\"form\""));
+
+        let unknown = form(&[
+            ("title", None, b"Answers"),
+            ("group", None, b"22BCS_course1"),
+            ("reference", None, b"unknown"),
+        ]);
+        let result = actions_db
+            .handle_form(
+                Some(&student),
+                Some(FORM),
+                None,
+                stream::once(unknown.clone()),
+            )
+            .await;
+        assert_eq!(result.status, 403);
+        let result = actions_db
+            .handle_form(Some(&admin), Some(FORM), None, stream::once(unknown))
+            .await;
+        assert_eq!(result.status, 400);
+        let errors: FormErrors = serde_json::from_str(&decode(&result)?)?;
+        assert!(errors.contains_key("reference"));
+        Ok(())
     }
 
     #[tokio::test]
@@ -278,9 +2204,7 @@ mod tests {
         let tmp_dir_path = tmp_dir.path().to_str().unwrap();
 
         let app_context = app_ctx(tmp_dir_path, tmp_file_path)?;
-        let totp = &app_context.blueprint.server.totp;
-        let token = format!("{}_{}", "username", totp.generate_current()?);
-        let token = app_context.blueprint.extensions.auth.encrypt_aes(token)?;
+        let token = token(&app_context)?;
 
         let actions_db = ActionsDB::init(Arc::new(app_context)).await?;
 
@@ -290,13 +2214,19 @@ mod tests {
             files: None,
             end_time: None,
             reference: "notice".to_string(),
+            max_points: None,
+            allowed_types: None,
+            grade: None,
+            late_policy: None,
+            extension: None,
         };
 
         let actions_request = ActionsRequest {
             token: token.clone(),
-            group_id: "2BCS_PSD".to_string(),
+            group_id: "22BCS_course1".to_string(),
             read: None,
             write: Some(write),
+            release: None,
         };
         let actions_request = actions_request.into_serrequet()?;
 
@@ -308,28 +2238,28 @@ mod tests {
 
         let actions_result = actions_result.into_hyper_response()?;
         assert_eq!(actions_result.status(), 200);
-        let expected = r#"{"actions":{"2BCS_PSD":[{"is_notif":true,"content_id":"REPLACE"}]}}"#
-            .replace("REPLACE", &content_id);
-        assert_eq!(
-            actions_db
-                .app_context
-                .runtime
-                .file
-                .read(tmp_file_path)
-                .await?,
-            expected
-        );
+        // the post is appended to the log, the snapshot isn't written yet
+        let expected =
+            r#"{"op":"insert","group_id":"22BCS_course1","is_notif":true,"kind":"notice","content_id":"REPLACE"}"#
+                .replace("REPLACE", &content_id);
+        let runtime = &actions_db.app_context.runtime;
+        let log_path = format!("{}{}", tmp_file_path, LOG_SUFFIX);
+        assert_eq!(runtime.file.read(&log_path).await?, expected + "\n");
+        assert!(runtime.file.read(tmp_file_path).await.is_err());
 
         let read = ActionsRead {
             content_id: content_id.clone(),
             file_name: None,
+            submissions: None,
+            grades: None,
         };
 
         let actions_request = ActionsRequest {
             token: token.clone(),
-            group_id: "2BCS_PSD".to_string(),
+            group_id: "22BCS_course1".to_string(),
             read: Some(read),
             write: None,
+            release: None,
         };
         let actions_request = actions_request.into_serrequet()?;
 
@@ -347,9 +2277,10 @@ mod tests {
 
         let actions_request = ActionsRequest {
             token: token.clone(),
-            group_id: "2BCS_PSD".to_string(),
+            group_id: "22BCS_course1".to_string(),
             read: None,
             write: None,
+            release: None,
         };
 
         let actions_request = actions_request.into_serrequet()?;
@@ -363,7 +2294,7 @@ mod tests {
 
         assert_eq!(actions.len(), 1);
         assert_eq!(actions[0].content_id, content_id);
-        assert!(actions[0].is_notif);
+        assert_eq!(actions[0].kind, ContentKind::Notice);
 
         let actions_result = actions_result.into_hyper_response()?;
         assert_eq!(actions_result.status(), 200);
@@ -374,13 +2305,19 @@ mod tests {
             files: None,
             end_time: None,
             reference: "notice".to_string(),
+            max_points: None,
+            allowed_types: None,
+            grade: None,
+            late_policy: None,
+            extension: None,
         };
 
         let actions_request = ActionsRequest {
             token: token.clone(),
-            group_id: "2BCS_OOP".to_string(),
+            group_id: "22BCS_course2".to_string(),
             read: None,
             write: Some(write),
+            release: None,
         };
         let actions_request = actions_request.into_serrequet()?;
 
@@ -392,28 +2329,28 @@ mod tests {
 
         let actions_result = actions_result.into_hyper_response()?;
         assert_eq!(actions_result.status(), 200);
-        let expected = r#"{"actions":{"2BCS_PSD":[{"is_notif":true,"content_id":"REPLACE"}],"2BCS_OOP":[{"is_notif":true,"content_id":"REP_NEW"}]}}"#
+        let expected = r#"{"actions":{"22BCS_course1":[{"is_notif":true,"content_id":"REPLACE"}],"22BCS_course2":[{"is_notif":true,"content_id":"REP_NEW"}]}}"#
             .replace("REPLACE", &content_id).replace("REP_NEW", &content_id_new);
 
         let expected = serde_json::from_str::<ActionsActivity>(&expected)?;
-        let actual = serde_json::from_str::<ActionsActivity>(
-            &actions_db
-                .app_context
-                .runtime
-                .file
-                .read(tmp_file_path)
-                .await?,
-        )?;
-
-        let map1 = expected
-            .actions
-            .into_iter()
-            .collect::<std::collections::HashMap<_, _>>();
-        let map2 = actual
-            .actions
-            .into_iter()
-            .collect::<std::collections::HashMap<_, _>>();
-        assert_eq!(map1, map2);
+        let replayed = ActionsDB::fetch_activity(tmp_file_path, runtime).await?;
+
+        // a restart compacts the log into the snapshot
+        let actions_db = ActionsDB::init(actions_db.app_context.clone()).await?;
+        let runtime = &actions_db.app_context.runtime;
+        assert_eq!(runtime.file.read(&log_path).await?, "");
+        let compacted =
+            serde_json::from_str::<ActionsActivity>(&runtime.file.read(tmp_file_path).await?)?;
+
+        let actions = |activity: ActionsActivity| {
+            activity
+                .actions
+                .into_iter()
+                .collect::<std::collections::HashMap<_, _>>()
+        };
+        let expected = actions(expected);
+        assert_eq!(actions(replayed), expected);
+        assert_eq!(actions(compacted), expected);
 
         Ok(())
     }
@@ -427,30 +2364,43 @@ mod tests {
         let tmp_dir_path = tmp_dir.path().to_str().unwrap();
 
         let app_context = app_ctx(tmp_dir_path, tmp_file_path)?;
-        let totp = &app_context.blueprint.server.totp;
-        let token = format!("{}_{}", "username", totp.generate_current()?);
-        let token = app_context.blueprint.extensions.auth.encrypt_aes(token)?;
+        let token = token(&app_context)?;
 
         let actions_db = ActionsDB::init(Arc::new(app_context)).await?;
 
         let file_content = "file1 content";
+        let binary_content = [0x89, b'P', b'N', b'G', 0x00, 0xff, 0xfe];
 
         let write = ActionsWrite {
             title: "False title".to_string(),
             description: "False desc".to_string(),
-            files: Some(vec![FileWrite {
-                file_name: "file1".to_string(),
-                content: file_content.to_string(),
-            }]),
+            files: Some(vec![
+                FileWrite {
+                    file_name: "file1".to_string(),
+                    encoding: Encoding::Utf8,
+                    content: file_content.to_string(),
+                },
+                FileWrite {
+                    file_name: "image.png".to_string(),
+                    encoding: Encoding::Base64,
+                    content: BASE64_STANDARD.encode(binary_content),
+                },
+            ]),
             end_time: None,
             reference: "notice".to_string(),
+            max_points: None,
+            allowed_types: None,
+            grade: None,
+            late_policy: None,
+            extension: None,
         };
 
         let actions_request = ActionsRequest {
             token: token.clone(),
-            group_id: "2BCS_PSD".to_string(),
+            group_id: "22BCS_course1".to_string(),
             read: None,
             write: Some(write),
+            release: None,
         };
 
         let actions_request = actions_request.into_serrequet()?;
@@ -483,13 +2433,16 @@ mod tests {
         let read = ActionsRead {
             content_id: content_id.clone(),
             file_name: Some("file1".to_string()),
+            submissions: None,
+            grades: None,
         };
 
         let actions_request = ActionsRequest {
             token: token.clone(),
-            group_id: "2BCS_PSD".to_string(),
+            group_id: "22BCS_course1".to_string(),
             read: Some(read),
             write: None,
+            release: None,
         };
         let actions_request = actions_request.into_serrequet()?;
 
@@ -502,7 +2455,28 @@ mod tests {
         let file = serde_json::from_str::<FileHolder>(&file)?;
 
         assert_eq!(file.name, "file1");
-        assert_eq!(file.content, file_content);
+        assert_eq!(file.content, file_content.as_bytes());
+
+        let read = ActionsRead {
+            content_id: content_id.clone(),
+            file_name: Some("image.png".to_string()),
+            submissions: None,
+            grades: None,
+        };
+        let actions_request = ActionsRequest {
+            token: token.clone(),
+            group_id: "22BCS_course1".to_string(),
+            read: Some(read),
+            write: None,
+            release: None,
+        };
+        let actions_result = actions_db
+            .handle_request(bytes::Bytes::from(actions_request.into_serrequet()?))
+            .await;
+        let file = String::from_utf8(BASE64_STANDARD.decode(actions_result.message)?)?;
+        assert!(file.contains(r#""encoding":"base64""#));
+        let file = serde_json::from_str::<FileHolder>(&file)?;
+        assert_eq!(file.content, binary_content);
 
         Ok(())
     }
@@ -510,25 +2484,26 @@ mod tests {
     #[tokio::test]
     async fn test_verify_token() -> Result<()> {
         let app_ctx = app_ctx("invalid", "invalid")?;
-        let token = format!(
-            "{}_{}",
-            "username",
-            app_ctx.blueprint.server.totp.generate_current()?
-        );
-        let token = app_ctx.blueprint.extensions.auth.encrypt_aes(token)?;
+        let token = token(&app_ctx)?;
 
         let app_ctx = Arc::new(app_ctx);
 
         let actions_db = ActionsDB::init(app_ctx.clone()).await?;
 
-        let result = verify_token(&token, &actions_db.app_context);
-        assert!(result.is_ok());
+        let claims = verify_token(&token, &actions_db.app_context)?;
+        assert_eq!(claims.sub, "username");
+        assert_eq!(claims.authority, Authority::Admin.as_int());
 
         let token = "invalid_token";
         let result = verify_token(token, &actions_db.app_context);
         assert!(result.is_err());
 
-        let token = app_ctx.blueprint.server.totp.generate_current()?;
+        // legacy `{username}_{totp}` tokens are no longer accepted
+        let token = format!(
+            "{}_{}",
+            "username",
+            app_ctx.blueprint.server.totp.generate_current()?
+        );
         let token = app_ctx.blueprint.extensions.auth.encrypt_aes(token)?;
         let result = verify_token(&token, &actions_db.app_context);
         assert!(result.is_err());
@@ -543,9 +2518,10 @@ mod tests {
 
         let actions_request = ActionsRequest {
             token: "invalid_token".to_string(),
-            group_id: "2BCS_PSD".to_string(),
+            group_id: "22BCS_course1".to_string(),
             read: None,
             write: None,
+            release: None,
         };
 
         let actions_request = serde_json::to_string(&actions_request).unwrap();
@@ -556,7 +2532,7 @@ mod tests {
         assert_eq!(actions_result.status, 500);
         let decoded_msg =
             String::from_utf8(BASE64_STANDARD.decode(actions_result.message).unwrap()).unwrap();
-        assert_eq!("Unable to decrypt token", decoded_msg);
+        assert_eq!("Invalid token", decoded_msg);
     }
 
     #[tokio::test]
@@ -564,24 +2540,19 @@ mod tests {
         let app_context = app_ctx("invalid", "invalid").unwrap();
         let actions_db = ActionsDB::init(Arc::new(app_context)).await.unwrap();
 
-        let totp = &actions_db.app_context.blueprint.server.totp;
-        let token = format!("{}_{}", "username", totp.generate_current().unwrap());
-        let token = actions_db
-            .app_context
-            .blueprint
-            .extensions
-            .auth
-            .encrypt_aes(token)
-            .unwrap();
+        let token = token(&actions_db.app_context).unwrap();
 
         let actions_request = ActionsRequest {
             token: token.clone(),
-            group_id: "invalid_group_id".to_string(),
+            group_id: "22BCS_course1".to_string(),
             read: Some(ActionsRead {
                 content_id: "content_id".to_string(),
                 file_name: None,
+                submissions: None,
+                grades: None,
             }),
             write: None,
+            release: None,
         };
 
         let actions_request = actions_request.into_serrequet().unwrap();
@@ -593,10 +2564,208 @@ mod tests {
         assert_eq!(actions_result.status, 500);
         let decoded_msg =
             String::from_utf8(BASE64_STANDARD.decode(actions_result.message).unwrap()).unwrap();
+        assert_eq!(decoded_msg, "File invalid/content_id/config.json not found");
+    }
+
+    #[tokio::test]
+    async fn test_invalid_group_id() -> Result<()> {
+        let app_context = app_ctx("invalid", "invalid")?;
+        let token = token(&app_context)?;
+        let actions_db = ActionsDB::init(Arc::new(app_context)).await?;
+
+        let result = write(&actions_db, &token, "2BCS_PSD", "notice").await?;
+        assert_eq!(result.status, 400);
+        assert_eq!(decode(&result)?, "Unknown batch 2BCS in group 2BCS_PSD");
+
+        let result = read(&actions_db, &token, "22BCS_course3", None).await?;
+        assert_eq!(result.status, 400);
+        assert_eq!(
+            decode(&result)?,
+            "Course course3 is not offered to batch 22BCS"
+        );
+
+        let result = read(&actions_db, &token, "invalid", None).await?;
+        assert_eq!(result.status, 400);
+        assert_eq!(
+            decode(&result)?,
+            "Invalid group id invalid, expected {batch}_{course}"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_legacy_submissions() -> Result<()> {
+        let tmp_file = tempfile::NamedTempFile::new()?;
+        let tmp_file_path = tmp_file.path().to_str().unwrap();
+        let app_context = app_ctx("invalid", tmp_file_path)?;
+        let faculty = token_for(&app_context, Authority::Faculty, None)?;
+        let student = token_for(&app_context, Authority::Student, Some("22BCS"))?;
+        // a notice and a submission to it, stored before there were kinds
+        let legacy = r#"{"actions":{"22BCS_course1":[{"is_notif":true,"content_id":"a"},{"is_notif":false,"content_id":"b"}]}}"#;
+        app_context
+            .runtime
+            .file
+            .write(tmp_file_path, legacy.as_bytes())
+            .await?;
+        let actions_db = ActionsDB::init(Arc::new(app_context)).await?;
+
+        let result = read(&actions_db, &faculty, "22BCS_course1", None).await?;
+        let actions: Vec<ActionsContent> = serde_json::from_str(&decode(&result)?)?;
+        assert_eq!(
+            actions,
+            [
+                ActionsContent::new(ContentKind::Notice, "a"),
+                ActionsContent::new(ContentKind::Submission, "b")
+            ]
+        );
+        // nobody is known to have made the submission, so students don't see it
+        let result = read(&actions_db, &student, "22BCS_course1", None).await?;
+        let actions: Vec<ActionsContent> = serde_json::from_str(&decode(&result)?)?;
+        assert_eq!(actions, [ActionsContent::new(ContentKind::Notice, "a")]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_migrate_groups() -> Result<()> {
+        let tmp_file = tempfile::NamedTempFile::new()?;
+        let tmp_file_path = tmp_file.path().to_str().unwrap();
+        let app_context = app_ctx("invalid", tmp_file_path)?;
+        let legacy = r#"{"actions":{"2BCS_PSD":[{"is_notif":true,"content_id":"a"}],"22BCS_course1":[{"is_notif":false,"content_id":"b"}],"OOP":[]}}"#;
+        app_context
+            .runtime
+            .file
+            .write(tmp_file_path, legacy.as_bytes())
+            .await?;
+        let actions_db = ActionsDB::init(Arc::new(app_context)).await?;
+
+        let renames = BTreeMap::from([("2BCS_PSD".to_string(), GroupId::new("22BCS", "course1"))]);
+        let unknown = actions_db.migrate_groups(&renames).await?;
+        assert_eq!(unknown, vec!["OOP".to_string()]);
+
+        let migrated =
+            ActionsDB::fetch_activity(tmp_file_path, &actions_db.app_context.runtime).await?;
+        let actions = migrated
+            .get_actions(&GroupId::new("22BCS", "course1"))
+            .unwrap();
+        let ids = actions
+            .iter()
+            .map(|a| a.content_id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec!["b", "a"]);
+        assert!(migrated.actions.get("2BCS_PSD").is_none());
+
+        let renames = BTreeMap::from([("OOP".to_string(), GroupId::new("22BCS", "OOP"))]);
+        assert!(actions_db.migrate_groups(&renames).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn test_concurrent_writes() -> Result<()> {
+        const WRITES: usize = 300;
+        let tmp_file = tempfile::NamedTempFile::new()?;
+        let tmp_file_path = tmp_file.path().to_str().unwrap();
+        let tmp_dir = tempfile::tempdir()?;
+        let app_context = Arc::new(app_ctx(tmp_dir.path().to_str().unwrap(), tmp_file_path)?);
+        let token = token(&app_context)?;
+        let actions_db = Arc::new(ActionsDB::init(app_context.clone()).await?);
+
+        let tasks = (0..WRITES)
+            .map(|_| {
+                let actions_db = actions_db.clone();
+                let token = token.clone();
+                tokio::spawn(
+                    async move { write(&actions_db, &token, "22BCS_course1", "notice").await },
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut posted = vec![];
+        for task in tasks {
+            let result = task.await??;
+            assert_eq!(result.status, 200, "{}", decode(&result)?);
+            posted.push(decode(&result)?);
+        }
+        posted.sort();
+
+        let ids = |activity: &ActionsActivity| {
+            activity
+                .get_actions(&GroupId::new("22BCS", "course1"))
+                .unwrap_or_default()
+                .into_iter()
+                .map(|action| action.content_id)
+                .collect::<Vec<_>>()
+        };
+        let result = read(&actions_db, &token, "22BCS_course1", None).await?;
+        let actions = serde_json::from_str::<Vec<ActionsContent>>(&decode(&result)?)?;
+        let mut read_ids = actions
+            .into_iter()
+            .map(|action| action.content_id)
+            .collect::<Vec<_>>();
+        read_ids.sort();
+        assert_eq!(read_ids, posted);
+
+        // the log holds the posts in the order they were applied
+        let replayed = ActionsDB::fetch_activity(tmp_file_path, &app_context.runtime).await?;
+        assert_eq!(ids(&replayed), ids(&actions_db.activity));
+        let restarted = ActionsDB::init(app_context).await?;
+        assert_eq!(ids(&restarted.activity), ids(&actions_db.activity));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_persist() -> Result<()> {
+        let mut app_context = app_ctx("/lms/files", "/lms/actions.json")?;
+        let (runtime, faults) = crate::runtime::tests::init_with_faults();
+        app_context.runtime = runtime;
+        let app_context = Arc::new(app_context);
+        let faculty = token_as(&app_context, "faculty", Authority::Faculty, None)?;
+        let alice = token_as(&app_context, "alice", Authority::Student, Some("22BCS"))?;
+        let actions_db = ActionsDB::init(app_context.clone()).await?;
+
+        let assignment =
+            decode(&write(&actions_db, &faculty, "22BCS_course1", "assignment").await?)?;
+        let submission = decode(&write(&actions_db, &alice, "22BCS_course1", &assignment).await?)?;
+        let result = grade(&actions_db, &faculty, &submission, 8).await?;
+        assert_eq!(result.status, 200, "{}", decode(&result)?);
+        let before = serde_json::to_string(&actions_db.activity)?;
+
+        // the log can't be appended to, so neither change is kept
+        *faults.writes.lock().unwrap() = Some(format!("/lms/actions.json{}", LOG_SUFFIX));
+        let result = write(&actions_db, &faculty, "22BCS_course1", "notice").await?;
+        assert_eq!(result.status, 500);
+        let result = release(&actions_db, &faculty, &assignment).await?;
+        assert_eq!(result.status, 500);
+        assert_eq!(serde_json::to_string(&actions_db.activity)?, before);
+
+        *faults.writes.lock().unwrap() = None;
+        let restarted = ActionsDB::init(app_context).await?;
+        assert_eq!(serde_json::to_string(&restarted.activity)?, before);
+        Ok(())
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_sqlite_round_trip() -> Result<()> {
+        let tmp_dir = tempfile::tempdir()?;
+        let db = format!("sqlite://{}", tmp_dir.path().join("lms.db").display());
+        let app_context = Arc::new(app_ctx(&db, &db)?);
+        let token = token(&app_context)?;
+
+        let actions_db = ActionsDB::init(app_context.clone()).await?;
+        let result = write(&actions_db, &token, "22BCS_course1", "notice").await?;
+        assert_eq!(result.status, 200);
+        let content_id = decode(&result)?;
+
+        // a new instance reads the actions and metadata back from the database
+        let actions_db = ActionsDB::init(app_context).await?;
+        let result = read(&actions_db, &token, "22BCS_course1", None).await?;
+        let actions: Vec<ActionsContent> = serde_json::from_str(&decode(&result)?)?;
         assert_eq!(
-            decoded_msg,
-            "File: invalid/content_id/config.json not found"
+            actions,
+            vec![ActionsContent::new(ContentKind::Notice, &content_id)]
         );
+        let result = read(&actions_db, &token, "22BCS_course1", Some(&content_id)).await?;
+        let metadata: Metadata = serde_json::from_str(&decode(&result)?)?;
+        assert_eq!(metadata.title, "title");
+        Ok(())
     }
-    // TODO add validation for invalid grp id
 }
@@ -0,0 +1,130 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+
+use crate::blueprint::Blueprint;
+
+/// Id of a group of actions, `{batch}_{course}`. Batch and course ids may contain `_` too, the
+/// split is resolved against the config by [GroupId::parse].
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct GroupId {
+    batch: String,
+    course: String,
+}
+
+impl GroupId {
+    pub fn new<T: AsRef<str>>(batch: T, course: T) -> Self {
+        Self {
+            batch: batch.as_ref().to_string(),
+            course: course.as_ref().to_string(),
+        }
+    }
+
+    /// Parses the group id and checks that the course is offered to the batch.
+    pub fn parse(group_id: &str, blueprint: &Blueprint) -> Result<Self> {
+        Self::resolve(group_id, &blueprint.batch_courses)
+    }
+
+    /// Splits the group id at the `_` naming a course offered to a batch.
+    fn resolve(group_id: &str, batch_courses: &BTreeMap<String, Vec<String>>) -> Result<Self> {
+        let mut offered = group_id
+            .match_indices('_')
+            .map(|(i, _)| GroupId::new(&group_id[..i], &group_id[i + 1..]))
+            .filter(|id| id.offered(batch_courses).is_ok());
+        match (offered.next(), offered.next()) {
+            (Some(id), None) => Ok(id),
+            (Some(_), Some(_)) => Err(anyhow!("Ambiguous group id {}", group_id)),
+            // for the error of the plain split
+            (None, _) => {
+                GroupId::from_str(group_id).and_then(|id| id.offered(batch_courses).map(|_| id))
+            }
+        }
+    }
+
+    pub fn validate(&self, blueprint: &Blueprint) -> Result<()> {
+        self.offered(&blueprint.batch_courses)
+    }
+
+    fn offered(&self, batch_courses: &BTreeMap<String, Vec<String>>) -> Result<()> {
+        let courses = batch_courses.get(&self.batch).ok_or(anyhow!(
+            "Unknown batch {} in group {}",
+            self.batch,
+            self
+        ))?;
+        if !courses.contains(&self.course) {
+            return Err(anyhow!(
+                "Course {} is not offered to batch {}",
+                self.course,
+                self.batch
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn batch(&self) -> &str {
+        &self.batch
+    }
+
+    pub fn course(&self) -> &str {
+        &self.course
+    }
+}
+
+impl FromStr for GroupId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('_') {
+            Some((batch, course)) if !batch.is_empty() && !course.is_empty() => {
+                Ok(GroupId::new(batch, course))
+            }
+            _ => Err(anyhow!(
+                "Invalid group id {}, expected {{batch}}_{{course}}",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for GroupId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}_{}", self.batch, self.course)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        let group_id = GroupId::from_str("22BCS_course_1")?;
+        assert_eq!(group_id.batch(), "22BCS");
+        assert_eq!(group_id.course(), "course_1");
+        assert_eq!(group_id.to_string(), "22BCS_course_1");
+
+        assert!(GroupId::from_str("22BCS").is_err());
+        assert!(GroupId::from_str("_course").is_err());
+        assert!(GroupId::from_str("22BCS_").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve() -> Result<()> {
+        let batch_courses = BTreeMap::from([
+            ("22_BCS".to_string(), vec!["course1".to_string()]),
+            ("23BCS".to_string(), vec!["course_1".to_string()]),
+        ]);
+        let group_id = GroupId::resolve("22_BCS_course1", &batch_courses)?;
+        assert_eq!((group_id.batch(), group_id.course()), ("22_BCS", "course1"));
+        let group_id = GroupId::resolve("23BCS_course_1", &batch_courses)?;
+        assert_eq!((group_id.batch(), group_id.course()), ("23BCS", "course_1"));
+
+        let err = GroupId::resolve("22_BCS_course2", &batch_courses).unwrap_err();
+        assert_eq!(err.to_string(), "Unknown batch 22 in group 22_BCS_course2");
+        assert!(GroupId::resolve("22BCS", &batch_courses).is_err());
+        Ok(())
+    }
+}
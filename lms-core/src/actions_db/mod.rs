@@ -1,2 +1,5 @@
 pub mod actions;
 pub mod actions_db;
+pub mod group_id;
+pub mod policy;
+pub mod wal;
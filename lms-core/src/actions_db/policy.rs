@@ -0,0 +1,173 @@
+use anyhow::{anyhow, Result};
+use lms_auth::session::Claims;
+
+use crate::actions_db::actions::{ActionsActivity, ActionsContent, ContentKind};
+use crate::actions_db::group_id::GroupId;
+use crate::authdb::auth_actors::Authority;
+
+/// Operations on `/fs`, checked against the authority of the caller by [authorize].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operation<'a> {
+    /// List the actions of the group
+    ReadGroup,
+    /// Read the metadata or a file of some content in the group
+    ReadContent {
+        content_id: &'a str,
+    },
+    PostNotice,
+    PostAssignment,
+    PostMaterial,
+    /// Submit against an assignment posted to the group
+    Submit {
+        reference: &'a str,
+    },
+    /// List the submissions to an assignment or their grades, or only the ones of the caller
+    ReadSubmissions {
+        assignment: &'a str,
+        mine: bool,
+    },
+    /// Grade a submission posted to the group
+    Grade {
+        submission: &'a str,
+    },
+    /// Release the grades of an assignment to the students of the group
+    Release {
+        assignment: &'a str,
+    },
+    /// Extend the deadline of an assignment for a student
+    Extend {
+        assignment: &'a str,
+    },
+    /// Stream a file into some content in the group, posted by `author`
+    Attach {
+        content_id: &'a str,
+        author: Option<&'a str>,
+    },
+}
+
+/// Checks if the caller is allowed to perform the operation on the group.
+///
+/// - Admins can do everything.
+/// - Faculties can read everything, post notices, assignments and material, grade and grant
+///   extensions.
+/// - Students can read and submit only in the courses of their own batch, and can't read the
+///   submissions and grades of others, see [visible].
+/// - Files are attached by the author of the content, admins may also attach to the notices,
+///   assignments and material of others but never to submissions, grades or extensions.
+pub fn authorize(
+    claims: &Claims,
+    group_id: &GroupId,
+    operation: &Operation,
+    activity: &ActionsActivity,
+) -> Result<()> {
+    let authority = Authority::from_int(claims.authority)?;
+    match authority {
+        Authority::Admin => match operation {
+            Operation::Attach { content_id, author } => {
+                attach(claims, true, group_id, content_id, *author, activity)
+            }
+            _ => Ok(()),
+        },
+        Authority::Faculty => match operation {
+            Operation::Submit { .. } => Err(anyhow!("Only students can submit")),
+            Operation::Attach { content_id, author } => {
+                attach(claims, false, group_id, content_id, *author, activity)
+            }
+            _ => Ok(()),
+        },
+        Authority::Student => {
+            // the group is validated, so the course is offered to the batch
+            if claims.batch.as_deref() != Some(group_id.batch()) {
+                return Err(anyhow!("Not enrolled in {}", group_id));
+            }
+            match operation {
+                Operation::ReadGroup => Ok(()),
+                Operation::ReadContent { content_id } => {
+                    match activity.find(group_id, content_id) {
+                        Some(action) if visible(claims, &action) => Ok(()),
+                        _ => Err(anyhow!("Content {} is not in {}", content_id, group_id)),
+                    }
+                }
+                Operation::Submit { reference } => {
+                    if is_assignment(activity, group_id, reference) {
+                        Ok(())
+                    } else {
+                        Err(anyhow!("Invalid reference for {}", group_id))
+                    }
+                }
+                Operation::ReadSubmissions { assignment, mine } => {
+                    if !mine {
+                        Err(anyhow!("Students can only read their own submissions"))
+                    } else if is_assignment(activity, group_id, assignment) {
+                        Ok(())
+                    } else {
+                        Err(anyhow!(
+                            "{} is not an assignment in {}",
+                            assignment,
+                            group_id
+                        ))
+                    }
+                }
+                Operation::PostNotice | Operation::PostAssignment | Operation::PostMaterial => {
+                    Err(anyhow!("Only faculties can post to {}", group_id))
+                }
+                Operation::Grade { .. } | Operation::Release { .. } => {
+                    Err(anyhow!("Only faculties can grade in {}", group_id))
+                }
+                Operation::Extend { .. } => Err(anyhow!(
+                    "Only faculties can grant extensions in {}",
+                    group_id
+                )),
+                Operation::Attach { content_id, author } => {
+                    attach(claims, false, group_id, content_id, *author, activity)
+                }
+            }
+        }
+    }
+}
+
+/// `admin` may also attach to the course content of others.
+fn attach(
+    claims: &Claims,
+    admin: bool,
+    group_id: &GroupId,
+    content_id: &str,
+    author: Option<&str>,
+    activity: &ActionsActivity,
+) -> Result<()> {
+    let action = activity
+        .find(group_id, content_id)
+        .ok_or_else(|| anyhow!("Content {} is not in {}", content_id, group_id))?;
+    let course_content = matches!(
+        action.kind,
+        ContentKind::Notice | ContentKind::Assignment | ContentKind::Material
+    );
+    if author == Some(claims.sub.as_str()) || (admin && course_content) {
+        return Ok(());
+    }
+    Err(anyhow!(
+        "Only the author can attach files to {}",
+        content_id
+    ))
+}
+
+/// Whether the caller may see the action when listing its group, students only see their own
+/// submissions and extensions, and their own grades once released.
+pub fn visible(claims: &Claims, action: &ActionsContent) -> bool {
+    let staff = matches!(
+        Authority::from_int(claims.authority),
+        Ok(Authority::Admin | Authority::Faculty)
+    );
+    let own = action.submitter.as_deref() == Some(claims.sub.as_str());
+    match action.kind {
+        ContentKind::Submission | ContentKind::Extension => staff || own,
+        ContentKind::Grade => staff || (own && action.released),
+        _ => true,
+    }
+}
+
+fn is_assignment(activity: &ActionsActivity, group_id: &GroupId, content_id: &str) -> bool {
+    activity
+        .find(group_id, content_id)
+        .is_some_and(|action| action.kind == ContentKind::Assignment)
+}
@@ -0,0 +1,366 @@
+//! Write-ahead log of a local `actionsDb`. Every insert or delete of an action is appended
+//! to `{actionsDb}.log` as a json line, and the log is compacted into the snapshot at
+//! `actionsDb` every [COMPACT_EVERY] events and at startup.
+//!
+//! Replaying is idempotent, an insert of a content already in the group is skipped,
+//! so a crash between writing the snapshot and truncating the log loses nothing.
+
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::stream;
+use crate::{FileIO, NotFound};
+
+use super::actions::{ActionsActivity, ActionsContent, ContentKind};
+
+/// Suffix of the log next to the snapshot.
+pub const LOG_SUFFIX: &str = ".log";
+/// Events appended before the log is compacted.
+const COMPACT_EVERY: usize = 1000;
+
+/// A single change of [ActionsActivity], a line of the log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ActionsEvent {
+    Insert {
+        group_id: String,
+        #[serde(flatten)]
+        content: ActionsContent,
+    },
+    Delete {
+        group_id: String,
+        content_id: String,
+    },
+    /// Releases the grades of an assignment to their students
+    Release {
+        group_id: String,
+        assignment: String,
+    },
+}
+
+impl ActionsEvent {
+    pub fn group_id(&self) -> &str {
+        match self {
+            ActionsEvent::Insert { group_id, .. }
+            | ActionsEvent::Delete { group_id, .. }
+            | ActionsEvent::Release { group_id, .. } => group_id,
+        }
+    }
+
+    pub fn apply(&self, activity: &ActionsActivity) {
+        match self {
+            ActionsEvent::Insert { group_id, content } => {
+                let mut actions = activity.actions.entry(group_id.clone()).or_default();
+                if !actions
+                    .iter()
+                    .any(|action| action.content_id == content.content_id)
+                {
+                    actions.push(content.clone());
+                }
+            }
+            ActionsEvent::Delete {
+                group_id,
+                content_id,
+            } => {
+                let emptied = match activity.actions.get_mut(group_id) {
+                    Some(mut actions) => {
+                        actions.retain(|action| &action.content_id != content_id);
+                        actions.is_empty()
+                    }
+                    None => false,
+                };
+                if emptied {
+                    activity
+                        .actions
+                        .remove_if(group_id, |_, actions| actions.is_empty());
+                }
+            }
+            ActionsEvent::Release {
+                group_id,
+                assignment,
+            } => {
+                if let Some(mut actions) = activity.actions.get_mut(group_id) {
+                    for action in actions.iter_mut() {
+                        if action.kind == ContentKind::Grade
+                            && action.assignment.as_deref() == Some(assignment)
+                        {
+                            action.released = true;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub struct ActionsLog {
+    file: Arc<dyn FileIO>,
+    snapshot_path: String,
+    log_path: String,
+    /// Events appended since the last compaction, held while appending or compacting
+    appended: Mutex<usize>,
+}
+
+impl ActionsLog {
+    pub fn new(file: Arc<dyn FileIO>, snapshot_path: &str) -> Self {
+        Self {
+            file,
+            snapshot_path: snapshot_path.to_string(),
+            log_path: format!("{}{}", snapshot_path, LOG_SUFFIX),
+            appended: Mutex::new(0),
+        }
+    }
+
+    /// Replays the log and compacts it unless it's empty, for the owner of the log before it
+    /// appends. A torn last line is only dropped from the log here, else the next append
+    /// would follow it on the same line.
+    pub async fn recover(&self) -> Result<ActionsActivity> {
+        let (activity, _, log_len) = self.read().await?;
+        if log_len > 0 {
+            self.compact(&activity).await?;
+        }
+        Ok(activity)
+    }
+
+    /// Reads the snapshot and replays the log over it, a missing or empty file counts as empty.
+    /// A torn last line is dropped, as left by a crash while appending.
+    pub async fn replay(&self) -> Result<(ActionsActivity, usize)> {
+        let (activity, events, _) = self.read().await?;
+        Ok((activity, events))
+    }
+
+    /// See [ActionsLog::replay], along with the length of the log.
+    async fn read(&self) -> Result<(ActionsActivity, usize, usize)> {
+        let activity = match self.read_or_empty(&self.snapshot_path).await? {
+            snapshot if !snapshot.trim().is_empty() => serde_json::from_str(&snapshot)
+                .map_err(|e| anyhow!("Invalid snapshot {}: {}", self.snapshot_path, e))?,
+            _ => ActionsActivity::default(),
+        };
+        let log = self.read_or_empty(&self.log_path).await?;
+        let mut lines = log.split_inclusive('\n').peekable();
+        let mut events = 0;
+        while let Some(line) = lines.next() {
+            match serde_json::from_str::<ActionsEvent>(line) {
+                Ok(event) => {
+                    event.apply(&activity);
+                    events += 1;
+                }
+                Err(_) if lines.peek().is_none() && !line.ends_with('\n') => {
+                    log::warn!("Dropped a torn event at the end of {}", self.log_path);
+                }
+                Err(e) => {
+                    return Err(anyhow!(
+                        "Invalid event {} in {}: {}",
+                        events + 1,
+                        self.log_path,
+                        e
+                    ))
+                }
+            }
+        }
+        Ok((activity, events, log.len()))
+    }
+
+    /// Content of the file, empty if it doesn't exist. Any other failure is returned, else
+    /// a file that couldn't be read would be replayed as empty and compacted away.
+    async fn read_or_empty(&self, path: &str) -> Result<String> {
+        match self.file.read(path).await {
+            Err(err) if err.is::<NotFound>() => Ok(String::new()),
+            result => result,
+        }
+    }
+
+    /// Appends the events, already applied to `activity`, and compacts the log once it's long.
+    pub async fn append(&self, events: &[ActionsEvent], activity: &ActionsActivity) -> Result<()> {
+        let mut lines = vec![];
+        for event in events {
+            serde_json::to_writer(&mut lines, event)?;
+            lines.push(b'\n');
+        }
+        let mut appended = self.appended.lock().await;
+        self.file.append(&self.log_path, &lines).await?;
+        *appended += events.len();
+        if *appended >= COMPACT_EVERY {
+            self.write_snapshot(activity).await?;
+            *appended = 0;
+        }
+        Ok(())
+    }
+
+    /// Writes `activity` as the snapshot and empties the log.
+    pub async fn compact(&self, activity: &ActionsActivity) -> Result<()> {
+        let mut appended = self.appended.lock().await;
+        self.write_snapshot(activity).await?;
+        *appended = 0;
+        Ok(())
+    }
+
+    async fn write_snapshot(&self, activity: &ActionsActivity) -> Result<()> {
+        // replaced atomically on native, see `NativeFileIO::write_stream`
+        let snapshot = Bytes::from(serde_json::to_vec(activity)?);
+        self.file
+            .write_stream(&self.snapshot_path, stream::once(snapshot))
+            .await?;
+        self.file.write(&self.log_path, b"").await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use super::*;
+
+    fn insert(group_id: &str, content_id: &str) -> ActionsEvent {
+        ActionsEvent::Insert {
+            group_id: group_id.to_string(),
+            content: ActionsContent::new(ContentKind::Assignment, content_id),
+        }
+    }
+
+    fn content_ids(activity: &ActionsActivity, group_id: &str) -> Vec<String> {
+        activity
+            .actions
+            .get(group_id)
+            .map(|actions| actions.iter().map(|a| a.content_id.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn test_event_format() -> Result<()> {
+        assert_eq!(
+            serde_json::to_string(&insert("22BCS_course1", "abc"))?,
+            r#"{"op":"insert","group_id":"22BCS_course1","is_notif":false,"kind":"assignment","content_id":"abc"}"#
+        );
+        let delete = ActionsEvent::Delete {
+            group_id: "22BCS_course1".to_string(),
+            content_id: "abc".to_string(),
+        };
+        assert_eq!(
+            serde_json::from_str::<ActionsEvent>(
+                r#"{"op":"delete","group_id":"22BCS_course1","content_id":"abc"}"#
+            )?,
+            delete
+        );
+        let release = ActionsEvent::Release {
+            group_id: "22BCS_course1".to_string(),
+            assignment: "abc".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_string(&release)?,
+            r#"{"op":"release","group_id":"22BCS_course1","assignment":"abc"}"#
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replay_and_compact() -> Result<()> {
+        let rt = crate::runtime::tests::init();
+        let log = ActionsLog::new(rt.file.clone(), "/lms/actions.json");
+        let (activity, events) = log.replay().await?;
+        assert_eq!(events, 0);
+
+        let events = [insert("a", "1"), insert("a", "2"), insert("b", "3")];
+        for event in &events {
+            event.apply(&activity);
+        }
+        log.append(&events, &activity).await?;
+        let delete = ActionsEvent::Delete {
+            group_id: "a".to_string(),
+            content_id: "1".to_string(),
+        };
+        delete.apply(&activity);
+        log.append(&[delete], &activity).await?;
+
+        let (replayed, events) = log.replay().await?;
+        assert_eq!(events, 4);
+        assert_eq!(content_ids(&replayed, "a"), ["2"]);
+        assert_eq!(content_ids(&replayed, "b"), ["3"]);
+
+        // replaying the log over a snapshot which already holds it changes nothing
+        let snapshot = serde_json::to_vec(&replayed)?;
+        rt.file.write("/lms/actions.json", &snapshot).await?;
+        let (replayed, _) = log.replay().await?;
+        assert_eq!(content_ids(&replayed, "a"), ["2"]);
+
+        log.compact(&replayed).await?;
+        assert_eq!(rt.file.read("/lms/actions.json.log").await?, "");
+        let (replayed, events) = log.replay().await?;
+        assert_eq!(events, 0);
+        assert_eq!(content_ids(&replayed, "b"), ["3"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_torn_and_corrupt_log() -> Result<()> {
+        let rt = crate::runtime::tests::init();
+        let log = ActionsLog::new(rt.file.clone(), "/lms/actions.json");
+        let line = serde_json::to_string(&insert("a", "1"))?;
+
+        let torn = format!("{}\n{}", line, &line[..line.len() / 2]);
+        rt.file
+            .write("/lms/actions.json.log", torn.as_bytes())
+            .await?;
+        let (activity, events) = log.replay().await?;
+        assert_eq!(events, 1);
+        assert_eq!(content_ids(&activity, "a"), ["1"]);
+
+        let corrupt = format!("{{\"op\":\"unknown\"}}\n{}\n", line);
+        rt.file
+            .write("/lms/actions.json.log", corrupt.as_bytes())
+            .await?;
+        assert!(log.replay().await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_recover_torn_log() -> Result<()> {
+        let rt = crate::runtime::tests::init();
+        let log = ActionsLog::new(rt.file.clone(), "/lms/actions.json");
+        let line = serde_json::to_string(&insert("a", "1"))?;
+
+        // a crash while appending the first event leaves only a torn line
+        rt.file
+            .write("/lms/actions.json.log", &line.as_bytes()[..line.len() / 2])
+            .await?;
+        let activity = log.recover().await?;
+        assert_eq!(rt.file.read("/lms/actions.json.log").await?, "");
+
+        let event = insert("a", "2");
+        event.apply(&activity);
+        log.append(&[event], &activity).await?;
+        let (replayed, events) = log.replay().await?;
+        assert_eq!(events, 1);
+        assert_eq!(content_ids(&replayed, "a"), ["2"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_recover_failing_read() -> Result<()> {
+        let (rt, faults) = crate::runtime::tests::init_with_faults();
+        let log = ActionsLog::new(rt.file.clone(), "/lms/actions.json");
+        let snapshot = ActionsActivity::default();
+        insert("a", "1").apply(&snapshot);
+        rt.file
+            .write("/lms/actions.json", &serde_json::to_vec(&snapshot)?)
+            .await?;
+        let event = insert("a", "2");
+        event.apply(&snapshot);
+        log.append(&[event], &snapshot).await?;
+
+        faults.reads.store(true, Ordering::SeqCst);
+        assert!(log.recover().await.is_err());
+        assert!(log.replay().await.is_err());
+        faults.reads.store(false, Ordering::SeqCst);
+
+        // nothing was compacted over the files that couldn't be read
+        let (replayed, events) = log.replay().await?;
+        assert_eq!(events, 1);
+        assert_eq!(content_ids(&replayed, "a"), ["1", "2"]);
+        Ok(())
+    }
+}
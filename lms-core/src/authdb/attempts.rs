@@ -0,0 +1,322 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::config::LoginLimitSettings;
+use crate::FileIO;
+
+/// Failed login attempts of a username or a client ip.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attempts {
+    pub failures: u32,
+    /// ms since epoch
+    pub last_failure: u128,
+}
+
+impl Attempts {
+    /// Counts a failure at `now`, forgetting the failures if the last one is `window` ms old.
+    pub fn fail(&mut self, now: u128, window: u128) {
+        if self.last_failure + window <= now {
+            self.failures = 0;
+        }
+        self.failures += 1;
+        self.last_failure = now;
+    }
+}
+
+/// Returned while a username or client ip has to wait before trying again.
+#[derive(Debug)]
+pub struct TooManyAttempts {
+    /// seconds
+    pub retry_after: u128,
+}
+
+impl std::fmt::Display for TooManyAttempts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Too many failed attempts, try again in {} seconds",
+            self.retry_after
+        )
+    }
+}
+
+impl std::error::Error for TooManyAttempts {}
+
+/// Counters of failed login attempts, keyed by `user:{username}` or `ip:{ip}`.
+#[async_trait::async_trait]
+pub trait AttemptStore: Debug + Send + Sync {
+    async fn get(&self, key: &str) -> Result<Attempts>;
+    /// Applies [Attempts::fail] atomically, so concurrent failures are all counted.
+    async fn record_failure(&self, key: &str, now: u128, window: u128) -> Result<Attempts>;
+    async fn clear(&self, key: &str) -> Result<()>;
+}
+
+/// Counters lost on restart.
+#[derive(Default, Debug)]
+pub struct InMemoryAttempts {
+    attempts: DashMap<String, Attempts>,
+}
+
+#[async_trait::async_trait]
+impl AttemptStore for InMemoryAttempts {
+    async fn get(&self, key: &str) -> Result<Attempts> {
+        Ok(self.attempts.get(key).map(|a| *a).unwrap_or_default())
+    }
+
+    async fn record_failure(&self, key: &str, now: u128, window: u128) -> Result<Attempts> {
+        let mut attempts = self.attempts.entry(key.to_string()).or_default();
+        attempts.fail(now, window);
+        Ok(*attempts)
+    }
+
+    async fn clear(&self, key: &str) -> Result<()> {
+        self.attempts.remove(key);
+        Ok(())
+    }
+}
+
+/// Counters written to a file on every change, so lockouts survive a restart.
+pub struct FileAttempts {
+    path: String,
+    /// Failures older than this many ms are dropped when writing
+    ttl: u128,
+    file: Arc<dyn FileIO>,
+    attempts: DashMap<String, Attempts>,
+    lock: Mutex<()>,
+}
+
+impl Debug for FileAttempts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FileAttempts")
+            .field("path", &self.path)
+            .field("attempts", &self.attempts)
+            .finish()
+    }
+}
+
+impl FileAttempts {
+    pub async fn load(path: &str, limits: &LoginLimits, file: Arc<dyn FileIO>) -> Result<Self> {
+        let attempts = match file.read(path).await {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|_| anyhow!("Unable to parse login attempts in {}", path))?,
+            Err(_) => DashMap::new(),
+        };
+        Ok(Self {
+            path: path.to_string(),
+            ttl: limits.lockout_duration as u128 * 1000,
+            file,
+            attempts,
+            lock: Mutex::new(()),
+        })
+    }
+
+    /// Writes the counters, dropping the ones with no failures in the last `ttl` ms.
+    async fn persist(&self, now: u128) -> Result<()> {
+        // serialize the writes, so a slower write can't overwrite a newer state
+        let _guard = self.lock.lock().await;
+        self.attempts.retain(|_, a| a.last_failure + self.ttl > now);
+        let attempts = serde_json::to_string(&self.attempts)?;
+        self.file.write(&self.path, attempts.as_bytes()).await
+    }
+}
+
+#[async_trait::async_trait]
+impl AttemptStore for FileAttempts {
+    async fn get(&self, key: &str) -> Result<Attempts> {
+        Ok(self.attempts.get(key).map(|a| *a).unwrap_or_default())
+    }
+
+    async fn record_failure(&self, key: &str, now: u128, window: u128) -> Result<Attempts> {
+        let attempts = {
+            let mut attempts = self.attempts.entry(key.to_string()).or_default();
+            attempts.fail(now, window);
+            *attempts
+        };
+        self.persist(now).await?;
+        Ok(attempts)
+    }
+
+    async fn clear(&self, key: &str) -> Result<()> {
+        if let Some((_, attempts)) = self.attempts.remove(key) {
+            self.persist(attempts.last_failure).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Thresholds of the login throttling, see [LoginLimitSettings].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoginLimits {
+    pub max_attempts: u32,
+    pub max_attempts_per_ip: u32,
+    /// ms
+    pub backoff: u64,
+    /// seconds
+    pub lockout_duration: u64,
+}
+
+impl Default for LoginLimits {
+    fn default() -> Self {
+        Self::from(&LoginLimitSettings::default())
+    }
+}
+
+impl From<&LoginLimitSettings> for LoginLimits {
+    fn from(settings: &LoginLimitSettings) -> Self {
+        Self {
+            max_attempts: settings.max_attempts.unwrap_or(5),
+            max_attempts_per_ip: settings.max_attempts_per_ip.unwrap_or(50),
+            backoff: settings.backoff.unwrap_or(1000),
+            lockout_duration: settings.lockout_duration.unwrap_or(15 * 60),
+        }
+    }
+}
+
+impl LoginLimits {
+    /// Errors if the key has to wait before trying again.
+    pub async fn check(&self, store: &dyn AttemptStore, key: &str, now: u128) -> Result<()> {
+        let attempts = store.get(key).await?;
+        let until = self.blocked_until(key, &attempts);
+        if now < until {
+            return Err(TooManyAttempts {
+                retry_after: (until - now).div_ceil(1000),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Counts a failure, every failure doubles the wait until the next attempt,
+    /// reaching the maximum attempts locks the key out for `lockout_duration`.
+    pub async fn failed(&self, store: &dyn AttemptStore, key: &str, now: u128) -> Result<()> {
+        store
+            .record_failure(key, now, self.lockout_duration as u128 * 1000)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn succeeded(&self, store: &dyn AttemptStore, key: &str) -> Result<()> {
+        store.clear(key).await
+    }
+
+    fn blocked_until(&self, key: &str, attempts: &Attempts) -> u128 {
+        if attempts.failures == 0 {
+            return 0;
+        }
+        let lockout = self.lockout_duration as u128 * 1000;
+        // an ip may be shared by many users, so it's only locked out once over the limit
+        let wait = if key.starts_with("ip:") {
+            if attempts.failures >= self.max_attempts_per_ip {
+                lockout
+            } else {
+                0
+            }
+        } else if attempts.failures >= self.max_attempts {
+            lockout
+        } else {
+            let shift = (attempts.failures - 1).min(32);
+            (self.backoff as u128 * (1 << shift)).min(lockout)
+        };
+        attempts.last_failure + wait
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits() -> LoginLimits {
+        LoginLimits {
+            max_attempts: 3,
+            max_attempts_per_ip: 5,
+            backoff: 1000,
+            lockout_duration: 60,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_backoff_and_lockout() -> Result<()> {
+        let limits = limits();
+        let store = InMemoryAttempts::default();
+        let key = "user:foo";
+
+        limits.check(&store, key, 0).await?;
+        limits.failed(&store, key, 0).await?;
+        assert!(limits.check(&store, key, 999).await.is_err());
+        limits.check(&store, key, 1000).await?;
+
+        limits.failed(&store, key, 1000).await?;
+        let err = limits.check(&store, key, 2000).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Too many failed attempts, try again in 1 seconds"
+        );
+        limits.check(&store, key, 3000).await?;
+
+        // locked out after the third failure
+        limits.failed(&store, key, 3000).await?;
+        assert!(limits.check(&store, key, 62_999).await.is_err());
+        limits.check(&store, key, 63_000).await?;
+        // the failures are forgotten after the lockout
+        limits.failed(&store, key, 63_000).await?;
+        limits.check(&store, key, 64_000).await?;
+
+        // ips aren't slowed down, only locked out once over their limit
+        for now in 0..4 {
+            limits.failed(&store, "ip:127.0.0.1", now).await?;
+        }
+        limits.check(&store, "ip:127.0.0.1", 4).await?;
+        limits.failed(&store, "ip:127.0.0.1", 4).await?;
+        assert!(limits.check(&store, "ip:127.0.0.1", 5).await.is_err());
+
+        limits.succeeded(&store, key).await?;
+        assert_eq!(store.get(key).await?, Attempts::default());
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_failures() -> Result<()> {
+        let limits = Arc::new(limits());
+        let runtime = crate::runtime::tests::init();
+        let stores: [Arc<dyn AttemptStore>; 2] = [
+            Arc::new(InMemoryAttempts::default()),
+            Arc::new(FileAttempts::load("attempts", &limits, runtime.file.clone()).await?),
+        ];
+        for store in stores {
+            let failures = (0..50).map(|_| {
+                let (limits, store) = (limits.clone(), store.clone());
+                tokio::spawn(async move { limits.failed(store.as_ref(), "user:foo", 0).await })
+            });
+            for failure in failures.collect::<Vec<_>>() {
+                failure.await??;
+            }
+            assert_eq!(store.get("user:foo").await?.failures, 50);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_file_attempts_persisted() -> Result<()> {
+        let runtime = crate::runtime::tests::init();
+        let limits = limits();
+        let store = FileAttempts::load("attempts", &limits, runtime.file.clone()).await?;
+        for now in [0, 1000, 3000] {
+            limits.failed(&store, "user:foo", now).await?;
+        }
+
+        let store = FileAttempts::load("attempts", &limits, runtime.file.clone()).await?;
+        assert!(limits.check(&store, "user:foo", 3000).await.is_err());
+
+        // expired counters are dropped on the next write
+        limits.failed(&store, "user:bar", 100_000).await?;
+        let store = FileAttempts::load("attempts", &limits, runtime.file.clone()).await?;
+        assert_eq!(store.get("user:foo").await?, Attempts::default());
+        assert_eq!(store.get("user:bar").await?.failures, 1);
+        Ok(())
+    }
+}
@@ -4,6 +4,8 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::str::FromStr;
 
+use crate::authdb::two_factor::TwoFactor;
+
 #[derive(Default, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
 pub enum Authority {
     Admin,
@@ -35,6 +37,13 @@ impl Authority {
             _ => Err(anyhow!("Unable to determine Authority")),
         }
     }
+    pub fn as_int(&self) -> u8 {
+        match self {
+            Authority::Admin => 0,
+            Authority::Faculty => 1,
+            Authority::Student => 2,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -44,6 +53,13 @@ pub struct User {
     pub password: String,
     pub authority: Authority,
     pub batch: Option<String>,
+    /// Second factor, if the user enrolled one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub two_factor: Option<TwoFactor>,
+    /// Set when an admin replaced the password with a reset code, ms since epoch the code expires at.
+    /// The password has to be changed before the user can login again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_reset: Option<u128>,
 }
 
 #[derive(Clone, Default, Debug, Serialize, Deserialize, PartialEq)]
@@ -82,6 +98,8 @@ mod tests {
                 password: "foopassword".to_string(),
                 authority: Authority::Admin,
                 batch: Some("22BCS".to_string()),
+                two_factor: None,
+                password_reset: None,
             },
         );
         let users = Users { users };
@@ -105,6 +123,8 @@ mod tests {
                 password: "foopassword".to_string(),
                 authority: Authority::Admin,
                 batch: Some("22BCS".to_string()),
+                two_factor: None,
+                password_reset: None,
             },
         );
         let users = Users { users };
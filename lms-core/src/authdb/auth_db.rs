@@ -1,42 +1,66 @@
+use std::net::IpAddr;
 use std::ops::Deref;
 use std::sync::Arc;
 
-use anyhow::{anyhow, Context, Result};
-use reqwest::{Body, Method, Request};
-use serde_json::json;
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
 
-use lms_auth::auth::{AuthError, AuthRequest, AuthResult, AuthSucc};
+use lms_auth::auth::{
+    AuthError, AuthRequest, AuthResult, AuthSucc, LogoutRequest, PasswordRequest, RefreshRequest,
+    ResetRequest, RevokeRequest, TwoFactorSetup,
+};
+use lms_auth::local_crypto::{hash_256, hash_password, is_legacy_hash, verify_password};
+use lms_auth::session::Claims;
 
 use crate::app_ctx::AppContext;
+use crate::authdb::attempts::TooManyAttempts;
 use crate::authdb::auth_actors::{Authority, User, Users};
+use crate::authdb::auth_store::{self, AuthStore};
+use crate::authdb::two_factor::{random_code, TwoFactor};
+use crate::uid_gen::UidGenerator;
+
+/// Issuer shown by authenticator apps
+const ISSUER: &str = "lms";
+const RESET_CODE_LEN: usize = 12;
 
 #[derive(Clone)]
 pub struct AuthDB {
+    /// Users as of the last write, reads don't go to the store
     users: Users,
+    store: Arc<dyn AuthStore>,
     app_context: Arc<AppContext>,
 }
 
 impl AuthDB {
     pub async fn init(app_context: Arc<AppContext>) -> Result<Self> {
         let users = app_context.blueprint.extensions.users.clone();
-        Ok(Self { users, app_context })
+        let store = auth_store::init(&app_context.blueprint.extensions.auth, &app_context.runtime)?;
+        Ok(Self {
+            users,
+            store,
+            app_context,
+        })
     }
-    pub async fn handle_request(&mut self, body: bytes::Bytes) -> AuthResult {
+    pub async fn handle_request(
+        &mut self,
+        body: bytes::Bytes,
+        client_ip: Option<IpAddr>,
+    ) -> AuthResult {
         let auth_request = AuthRequest::try_from_bytes(&body);
 
         match auth_request {
             Ok(auth_request) => {
                 if auth_request.signup_details.is_some() {
-                    self.signup(auth_request).await
+                    self.signup(auth_request, client_ip).await
                 } else {
-                    self.login(auth_request).await
+                    self.login(auth_request, client_ip).await
                 }
             }
             Err(e) => auth_err(e.to_string()),
         }
     }
 
-    async fn signup(&mut self, req: AuthRequest) -> AuthResult {
+    async fn signup(&mut self, req: AuthRequest, client_ip: Option<IpAddr>) -> AuthResult {
         let signup_details = req.signup_details;
 
         let signup_details = match signup_details {
@@ -44,11 +68,14 @@ impl AuthDB {
             None => return auth_err("No necessary signup details found"),
         };
 
-        match verify(
-            &signup_details.admin_username,
-            &signup_details.admin_password,
-            &self.users,
-        ) {
+        match self
+            .verify_credentials(
+                &signup_details.admin_username,
+                &signup_details.admin_password,
+                client_ip,
+            )
+            .await
+        {
             Ok(_) => {
                 let authority = Authority::from_int(signup_details.authority);
                 match authority {
@@ -63,110 +90,572 @@ impl AuthDB {
                                 return auth_err("Invalid batch selected");
                             }
                         }
+                        let password = match hash_password(&req.password) {
+                            Ok(password) => password,
+                            Err(_) => return auth_err("Unable to hash password"),
+                        };
                         let user = User {
                             username: req.username,
                             name: signup_details.name.clone(),
-                            password: req.password,
+                            password,
                             authority,
                             batch: signup_details.batch,
+                            two_factor: None,
+                            password_reset: None,
                         };
 
-                        let token = gen_token(&user.username, self.app_context.deref());
-
-                        self.users.insert(user);
-                        match user_entry(self.app_context.deref(), self.users.clone()).await {
-                            Ok(users) => self.users = users,
-                            Err(e) => {
-                                return auth_err(format!("Unable to register user: {}", e));
-                            }
-                        };
-                        match token {
-                            Ok(token) => auth_succ(signup_details.name, token),
-                            Err(_) => auth_err("Unable to generate token"),
+                        if let Err(e) = self.save(user.clone()).await {
+                            return auth_err(format!("Unable to register user: {}", e));
                         }
+                        self.session(user).await
                     }
                     Err(e) => auth_err(e.to_string()),
                 }
             }
-            Err(e) => auth_err(e.to_string()),
+            Err(e) => auth_failure(e),
+        }
+    }
+    async fn login(&mut self, req: AuthRequest, client_ip: Option<IpAddr>) -> AuthResult {
+        let mut user = match self
+            .verify_credentials(&req.username, &req.password, client_ip)
+            .await
+        {
+            Ok(user) => user,
+            Err(e) => return auth_failure(e),
+        };
+        if user.password_reset.is_some() {
+            if let Err(e) = self.check_reset_code(&user) {
+                return auth_err(e.to_string());
+            }
+            return AuthResult {
+                code: 403,
+                ..auth_err("Password was reset, change it at /auth/password")
+            };
+        }
+        let verified = match self
+            .second_factor(&mut user, req.otp.as_deref(), client_ip)
+            .await
+        {
+            Ok(verified) => verified,
+            Err(e) => return auth_err(e.to_string()),
+        };
+        self.reset_attempts(&user.username).await;
+        // upgrade users still on the legacy sha256 format to Argon2id
+        let rehashed = is_legacy_hash(&user.password)
+            && match hash_password(&req.password) {
+                Ok(password) => {
+                    user.password = password;
+                    true
+                }
+                Err(e) => {
+                    log::warn!("Unable to rehash password for {}: {}", user.username, e);
+                    false
+                }
+            };
+        if verified || rehashed {
+            if let Err(e) = self.save(user.clone()).await {
+                // the used code must not be accepted again
+                if verified {
+                    return auth_err(format!("Unable to login: {}", e));
+                }
+                log::warn!("Unable to rehash password for {}: {}", user.username, e);
+            }
+        }
+        self.session(user).await
+    }
+
+    /// Checks the password, throttling repeated failures of the username and of the client ip.
+    /// Unknown users and wrong passwords fail alike, so usernames can't be enumerated.
+    async fn verify_credentials(
+        &self,
+        username: &str,
+        password: &str,
+        client_ip: Option<IpAddr>,
+    ) -> Result<User> {
+        let now = self.app_context.runtime.instance.now()?;
+        let limits = &self.app_context.blueprint.login_limits;
+        let attempts = self.app_context.blueprint.extensions.attempts.as_ref();
+        for key in attempt_keys(username, client_ip) {
+            limits.check(attempts, &key, now).await?;
+        }
+        match verify(username, password, &self.users) {
+            Ok(user) => Ok(user),
+            Err(e) => {
+                self.failed_attempt(username, client_ip, now).await;
+                Err(e)
+            }
         }
     }
-    async fn login(&self, req: AuthRequest) -> AuthResult {
-        // TODO respond with token
-        match verify(&req.username, &req.password, &self.users) {
-            Ok(user) => match gen_token(&user.username, self.app_context.deref()) {
-                Ok(token) => auth_succ(user.name, token),
-                Err(_) => auth_err("Unable to generate token"),
+
+    /// Called once both factors are verified, else a known password allows guessing otps.
+    async fn reset_attempts(&self, username: &str) {
+        let limits = &self.app_context.blueprint.login_limits;
+        let attempts = self.app_context.blueprint.extensions.attempts.as_ref();
+        if let Err(e) = limits.succeeded(attempts, &user_key(username)).await {
+            log::warn!("Unable to reset login attempts of {}: {}", username, e);
+        }
+    }
+
+    async fn failed_attempt(&self, username: &str, client_ip: Option<IpAddr>, now: u128) {
+        let limits = &self.app_context.blueprint.login_limits;
+        let attempts = self.app_context.blueprint.extensions.attempts.as_ref();
+        for key in attempt_keys(username, client_ip) {
+            if let Err(e) = limits.failed(attempts, &key, now).await {
+                log::warn!("Unable to count failed login of {}: {}", key, e);
+            }
+        }
+    }
+
+    /// Checks the otp of users with two-factor authentication enabled,
+    /// returns true if a code was used up and the user has to be saved.
+    async fn second_factor(
+        &self,
+        user: &mut User,
+        otp: Option<&str>,
+        client_ip: Option<IpAddr>,
+    ) -> Result<bool> {
+        let now = self.app_context.runtime.instance.now()?;
+        let mandatory = self.is_two_factor_mandatory(&user.authority);
+        match user.two_factor.as_mut() {
+            Some(two_factor) if two_factor.is_enabled() => {
+                let otp = otp.ok_or(anyhow!("OTP required"))?;
+                if two_factor.verify(otp, now)? {
+                    Ok(true)
+                } else {
+                    self.failed_attempt(&user.username, client_ip, now).await;
+                    Err(anyhow!("Invalid OTP"))
+                }
+            }
+            _ if mandatory => Err(anyhow!(
+                "Two-factor authentication is mandatory, enroll at /auth/2fa/enroll"
+            )),
+            _ => Ok(false),
+        }
+    }
+
+    fn is_two_factor_mandatory(&self, authority: &Authority) -> bool {
+        self.app_context
+            .blueprint
+            .mandatory_two_factor
+            .contains(authority)
+    }
+
+    /// Starts enrolling a new second factor, it's enabled once confirmed with `/auth/2fa/verify`.
+    pub async fn handle_enroll_2fa(
+        &mut self,
+        body: bytes::Bytes,
+        client_ip: Option<IpAddr>,
+    ) -> AuthResult {
+        let mut user = match self.verify_request(&body, client_ip).await {
+            Ok((user, _)) => user,
+            Err(e) => return auth_failure(e),
+        };
+        if user.two_factor.as_ref().is_some_and(|t| t.is_enabled()) {
+            return auth_err("Two-factor authentication is already enabled");
+        }
+        let two_factor = match TwoFactor::generate() {
+            Ok(two_factor) => two_factor,
+            Err(e) => return auth_err(e.to_string()),
+        };
+        let uri = match two_factor.uri(ISSUER, &user.username) {
+            Ok(uri) => uri,
+            Err(e) => return auth_err(e.to_string()),
+        };
+        user.two_factor = Some(two_factor);
+        if let Err(e) = self.save(user).await {
+            return auth_err(format!("Unable to enroll: {}", e));
+        }
+        auth_two_factor(TwoFactorSetup {
+            uri: Some(uri),
+            recovery_codes: vec![],
+        })
+    }
+
+    /// Enables the pending second factor and returns the recovery codes.
+    pub async fn handle_verify_2fa(
+        &mut self,
+        body: bytes::Bytes,
+        client_ip: Option<IpAddr>,
+    ) -> AuthResult {
+        let (mut user, otp) = match self.verify_request(&body, client_ip).await {
+            Ok(verified) => verified,
+            Err(e) => return auth_failure(e),
+        };
+        let now = match self.app_context.runtime.instance.now() {
+            Ok(now) => now,
+            Err(e) => return auth_err(e.to_string()),
+        };
+        let recovery_codes = match (user.two_factor.as_mut(), otp) {
+            (Some(two_factor), Some(otp)) => match two_factor.confirm(&otp, now) {
+                Ok(codes) => codes,
+                Err(e) => return auth_err(e.to_string()),
             },
-            Err(e) => auth_err(e.to_string()),
+            (None, _) => return auth_err("No pending two-factor enrollment"),
+            (_, None) => return auth_err("OTP required"),
+        };
+        if let Err(e) = self.save(user).await {
+            return auth_err(format!("Unable to enable two-factor authentication: {}", e));
+        }
+        auth_two_factor(TwoFactorSetup {
+            uri: None,
+            recovery_codes,
+        })
+    }
+
+    /// Removes the second factor, unless it's mandatory for the user.
+    pub async fn handle_disable_2fa(
+        &mut self,
+        body: bytes::Bytes,
+        client_ip: Option<IpAddr>,
+    ) -> AuthResult {
+        let (mut user, otp) = match self.verify_request(&body, client_ip).await {
+            Ok(verified) => verified,
+            Err(e) => return auth_failure(e),
+        };
+        if self.is_two_factor_mandatory(&user.authority) {
+            return AuthResult {
+                code: 403,
+                ..auth_err("Two-factor authentication is mandatory")
+            };
+        }
+        if let Err(e) = self
+            .second_factor(&mut user, otp.as_deref(), client_ip)
+            .await
+        {
+            return auth_err(e.to_string());
+        }
+        user.two_factor = None;
+        match self.save(user).await {
+            Ok(()) => auth_done(),
+            Err(e) => auth_err(format!(
+                "Unable to disable two-factor authentication: {}",
+                e
+            )),
+        }
+    }
+
+    /// Parses an [AuthRequest] and checks the password, returns the user along with the otp.
+    async fn verify_request(
+        &self,
+        body: &[u8],
+        client_ip: Option<IpAddr>,
+    ) -> Result<(User, Option<String>)> {
+        let req = AuthRequest::try_from_bytes(body)?;
+        let user = self
+            .verify_credentials(&req.username, &req.password, client_ip)
+            .await?;
+        Ok((user, req.otp))
+    }
+
+    /// Issues an access token along with a new refresh token.
+    async fn session(&self, user: User) -> AuthResult {
+        let token = match gen_token(&user, self.app_context.deref()) {
+            Ok(token) => token,
+            Err(_) => return auth_err("Unable to generate token"),
+        };
+        let extensions = &self.app_context.blueprint.extensions;
+        match extensions
+            .refresh_tokens
+            .issue(
+                &user.username,
+                self.app_context.blueprint.server.refresh_token_ttl,
+                &extensions.auth,
+                &self.app_context.runtime,
+            )
+            .await
+        {
+            Ok(refresh_token) => auth_succ(user.name, token, Some(refresh_token)),
+            Err(_) => auth_err("Unable to generate refresh token"),
+        }
+    }
+
+    /// Exchanges a refresh token for a new access token and a rotated refresh token.
+    pub async fn handle_refresh(&self, body: bytes::Bytes) -> AuthResult {
+        let req = match RefreshRequest::try_from_bytes(&body) {
+            Ok(req) => req,
+            Err(e) => return auth_err(e.to_string()),
+        };
+        let extensions = &self.app_context.blueprint.extensions;
+        let (username, refresh_token) = match extensions
+            .refresh_tokens
+            .rotate(
+                &req.refresh_token,
+                self.app_context.blueprint.server.refresh_token_ttl,
+                &extensions.auth,
+                &self.app_context.runtime,
+            )
+            .await
+        {
+            Ok(rotated) => rotated,
+            Err(e) => return auth_err(e.to_string()),
+        };
+        // pick up changes to the user since the last refresh
+        let user = match self.users.get(&username) {
+            Some(user) => user,
+            None => return auth_err("No such user found"),
+        };
+        match gen_token(&user, self.app_context.deref()) {
+            Ok(token) => auth_succ(user.name, token, Some(refresh_token)),
+            Err(_) => auth_err("Unable to generate token"),
+        }
+    }
+
+    /// Revokes the token of the caller along with its refresh token.
+    pub async fn handle_logout(&self, body: bytes::Bytes) -> AuthResult {
+        let req = match LogoutRequest::try_from_bytes(&body) {
+            Ok(req) => req,
+            Err(e) => return auth_err(e.to_string()),
+        };
+        let claims = match verify_token(&req.token, &self.app_context) {
+            Ok(claims) => claims,
+            Err(e) => return auth_err(e.to_string()),
+        };
+        let extensions = &self.app_context.blueprint.extensions;
+        if let Some(refresh_token) = req.refresh_token.as_ref() {
+            if let Err(e) = extensions
+                .refresh_tokens
+                .revoke(refresh_token, &extensions.auth, &self.app_context.runtime)
+                .await
+            {
+                return auth_err(format!("Unable to logout: {}", e));
+            }
+        }
+        match extensions
+            .revocations
+            .revoke_token(&claims, &extensions.auth, &self.app_context.runtime)
+            .await
+        {
+            Ok(()) => auth_done(),
+            Err(e) => auth_err(format!("Unable to logout: {}", e)),
+        }
+    }
+
+    /// Revokes every session of a user, only admins are allowed to do so.
+    pub async fn handle_revoke(&self, body: bytes::Bytes) -> AuthResult {
+        let req = match RevokeRequest::try_from_bytes(&body) {
+            Ok(req) => req,
+            Err(e) => return auth_err(e.to_string()),
+        };
+        let claims = match verify_token(&req.token, &self.app_context) {
+            Ok(claims) => claims,
+            Err(e) => return auth_err(e.to_string()),
+        };
+        if claims.authority != Authority::Admin.as_int() {
+            return AuthResult {
+                code: 403,
+                ..auth_err("Only admins can revoke sessions")
+            };
+        }
+        if self.users.get(&req.username).is_none() {
+            return auth_err("No such user found");
+        }
+        match self.end_sessions(&req.username).await {
+            Ok(()) => {
+                log::info!("{} revoked the sessions of {}", claims.sub, req.username);
+                auth_done()
+            }
+            Err(e) => auth_err(format!("Unable to revoke sessions: {}", e)),
+        }
+    }
+
+    /// Revokes the access and refresh tokens issued to the user so far.
+    async fn end_sessions(&self, username: &str) -> Result<()> {
+        let extensions = &self.app_context.blueprint.extensions;
+        let runtime = &self.app_context.runtime;
+        extensions
+            .refresh_tokens
+            .revoke_user(username, &extensions.auth, runtime)
+            .await?;
+        extensions
+            .revocations
+            .revoke_user(username, &extensions.auth, runtime)
+            .await
+    }
+
+    /// Changes the password given the current one or a reset code,
+    /// every session of the user ends so they have to login again.
+    pub async fn handle_change_password(
+        &mut self,
+        body: bytes::Bytes,
+        client_ip: Option<IpAddr>,
+    ) -> AuthResult {
+        let req = match PasswordRequest::try_from_bytes(&body) {
+            Ok(req) => req,
+            Err(e) => return auth_err(e.to_string()),
+        };
+        let mut user = match self
+            .verify_credentials(&req.username, &req.password, client_ip)
+            .await
+        {
+            Ok(user) => user,
+            Err(e) => return auth_failure(e),
+        };
+        if let Err(e) = self.check_reset_code(&user) {
+            return auth_err(e.to_string());
+        }
+        if let Err(e) = self
+            .second_factor(&mut user, req.otp.as_deref(), client_ip)
+            .await
+        {
+            return auth_err(e.to_string());
+        }
+        self.reset_attempts(&user.username).await;
+
+        user.password = match hash_password(&req.new_password) {
+            Ok(password) => password,
+            Err(_) => return auth_err("Unable to hash password"),
+        };
+        user.password_reset = None;
+        let username = user.username.clone();
+        if let Err(e) = self.save(user).await {
+            return auth_err(format!("Unable to change password: {}", e));
+        }
+        match self.end_sessions(&username).await {
+            Ok(()) => auth_done(),
+            Err(e) => auth_err(format!("Unable to end sessions: {}", e)),
+        }
+    }
+
+    /// Replaces the password of a user with a one-time reset code, only admins are allowed to do so.
+    /// The user has to change the password with the code before they can login again.
+    pub async fn handle_reset_password(&mut self, body: bytes::Bytes) -> AuthResult {
+        let req = match ResetRequest::try_from_bytes(&body) {
+            Ok(req) => req,
+            Err(e) => return auth_err(e.to_string()),
+        };
+        let claims = match verify_token(&req.token, &self.app_context) {
+            Ok(claims) => claims,
+            Err(e) => return auth_err(e.to_string()),
+        };
+        if claims.authority != Authority::Admin.as_int() {
+            return AuthResult {
+                code: 403,
+                ..auth_err("Only admins can reset passwords")
+            };
+        }
+        let mut user = match self.users.get(&req.username) {
+            Some(user) => user,
+            None => return auth_err("No such user found"),
+        };
+        let now = match self.app_context.runtime.instance.now() {
+            Ok(now) => now,
+            Err(e) => return auth_err(e.to_string()),
+        };
+        let code = match random_code(RESET_CODE_LEN) {
+            Ok(code) => code,
+            Err(e) => return auth_err(e.to_string()),
+        };
+        // clients send sha256 of whatever is typed in, see `AuthRequest::new`
+        user.password = match hash_password(hash_256(&code)) {
+            Ok(password) => password,
+            Err(_) => return auth_err("Unable to hash password"),
+        };
+        let ttl = self.app_context.blueprint.server.password_reset_ttl;
+        user.password_reset = Some(now + ttl as u128 * 1000);
+        if let Err(e) = self.save(user).await {
+            return auth_err(format!("Unable to reset password: {}", e));
+        }
+        if let Err(e) = self.end_sessions(&req.username).await {
+            return auth_err(format!("Unable to end sessions: {}", e));
+        }
+        log::info!("{} reset the password of {}", claims.sub, req.username);
+        AuthResult {
+            reset_code: Some(code),
+            ..auth_done()
+        }
+    }
+
+    /// Errors if the password was reset and the code has expired.
+    fn check_reset_code(&self, user: &User) -> Result<()> {
+        match user.password_reset {
+            Some(expires_at) if expires_at <= self.app_context.runtime.instance.now()? => {
+                Err(anyhow!("Reset code expired, ask an admin for a new one"))
+            }
+            _ => Ok(()),
         }
     }
+
+    /// Stores the updated user.
+    async fn save(&mut self, user: User) -> Result<()> {
+        self.store.upsert(user.clone()).await?;
+        self.users.insert(user);
+        Ok(())
+    }
 }
 
-fn gen_token(username: &str, app_context: &AppContext) -> Result<String> {
-    let token = app_context
-        .blueprint
-        .server
-        .totp
-        .generate_current()
+/// Issues a signed access token for the user,
+/// valid for `server.accessTokenTtl` seconds.
+pub fn gen_token(user: &User, app_context: &AppContext) -> Result<String> {
+    let now = app_context
+        .runtime
+        .instance
+        .now()
         .map_err(|_| anyhow!("Unable to generate token"))?;
-    let format = format!("{}_{}", username, token);
+    let claims = Claims {
+        sub: user.username.clone(),
+        authority: user.authority.as_int(),
+        batch: user.batch.clone(),
+        iat: now,
+        exp: now + app_context.blueprint.server.access_token_ttl as u128 * 1000,
+        jti: UidGenerator::default().generate(now),
+    };
     let token = app_context
         .blueprint
         .extensions
         .auth
-        .encrypt_aes(format)
-        .map_err(|_| anyhow!("Unable to generate token, encryption err"))?;
+        .sign_session(&claims)
+        .map_err(|_| anyhow!("Unable to generate token, signing err"))?;
     Ok(token)
 }
 
-pub async fn user_entry(app_context: &AppContext, users: Users) -> Result<Users> {
-    let password = String::from_utf8(app_context.blueprint.extensions.auth.get_pw().to_vec())?;
-
-    let db_path = app_context.blueprint.extensions.auth.db_path();
-
-    if db_path.starts_with("http") {
-        let url = url::Url::parse(db_path)?;
-        let mut req = Request::new(Method::POST, url);
-
-        let users = serde_json::to_string(&users)?;
-        // TODO (optional) encrypt users before sending
-        let resp = json!({
-            "operation": "put_user",
-            "users": users,
-            "pw": &password
-        })
-        .to_string();
-
-        *req.body_mut() = Some(Body::from(resp));
+/// Verifies the signature and expiry of a session token and returns the claims of the caller.
+/// Tokens revoked by logout or by an admin are rejected.
+pub fn verify_token(token: &str, app_context: &AppContext) -> Result<Claims> {
+    let now = app_context.runtime.instance.now()?;
+    let extensions = &app_context.blueprint.extensions;
+    let claims = extensions.auth.verify_session(token, now)?;
+    if extensions.revocations.is_revoked(&claims) {
+        return Err(anyhow!("Token revoked, please re-login"));
+    }
+    Ok(claims)
+}
 
-        let result = app_context.runtime.http.execute(req).await?;
-        let users = serde_json::from_slice::<Users>(&result.body)?;
+lazy_static! {
+    /// Checked against for unknown users, so they take as long as a wrong password
+    static ref DUMMY_HASH: String = hash_password("dummy password").unwrap_or_default();
+}
 
-        Ok(users)
+fn verify(username: &str, pw: &str, users: &Users) -> Result<User> {
+    let invalid = || anyhow!("Invalid username or password");
+    let Some(user) = users.get(username) else {
+        let _ = verify_password(pw, &DUMMY_HASH);
+        return Err(invalid());
+    };
+    if verify_password(pw, &user.password)? {
+        Ok(user)
     } else {
-        let users_str = serde_json::to_string(&users)?;
-        let users_str = app_context
-            .blueprint
-            .extensions
-            .auth
-            .encrypt_aes(&users_str)?;
-        app_context
-            .runtime
-            .file
-            .write(db_path, users_str.as_bytes())
-            .await?; // store encrypted directly
+        Err(invalid())
+    }
+}
 
-        Ok(users)
+fn user_key(username: &str) -> String {
+    format!("user:{}", username)
+}
+
+fn attempt_keys(username: &str, client_ip: Option<IpAddr>) -> Vec<String> {
+    let mut keys = vec![user_key(username)];
+    if let Some(ip) = client_ip {
+        keys.push(format!("ip:{}", ip));
     }
+    keys
 }
 
-fn verify(username: &str, pw: &str, users: &Users) -> Result<User> {
-    let user = users.get(username).context("No such user found")?;
-    if user.password.eq(pw) {
-        Ok(user)
-    } else {
-        Err(anyhow!("Invalid password for user: {}", username))
+/// Answers throttled attempts with 429.
+fn auth_failure(e: anyhow::Error) -> AuthResult {
+    let code = if e.is::<TooManyAttempts>() { 429 } else { 500 };
+    AuthResult {
+        code,
+        ..auth_err(e.to_string())
     }
 }
 
@@ -177,14 +666,39 @@ fn auth_err<T: AsRef<str>>(message: T) -> AuthResult {
         }),
         success: None,
         code: 500,
+        two_factor: None,
+        reset_code: None,
+    }
+}
+
+fn auth_done() -> AuthResult {
+    AuthResult {
+        error: None,
+        success: None,
+        code: 200,
+        two_factor: None,
+        reset_code: None,
+    }
+}
+
+fn auth_two_factor(setup: TwoFactorSetup) -> AuthResult {
+    AuthResult {
+        two_factor: Some(setup),
+        ..auth_done()
     }
 }
 
-fn auth_succ(name: String, token: String) -> AuthResult {
+fn auth_succ(name: String, token: String, refresh_token: Option<String>) -> AuthResult {
     AuthResult {
         error: None,
-        success: Some(AuthSucc { name, token }),
+        success: Some(AuthSucc {
+            name,
+            token,
+            refresh_token,
+        }),
         code: 200,
+        two_factor: None,
+        reset_code: None,
     }
 }
 
@@ -192,12 +706,18 @@ fn auth_succ(name: String, token: String) -> AuthResult {
 mod tests {
     use std::sync::Arc;
 
-    use lms_auth::auth::{AuthProvider, AuthRequest, SignUpDet};
-    use lms_auth::local_crypto::hash_256;
+    use lms_auth::auth::{
+        AuthProvider, AuthRequest, AuthSucc, LogoutRequest, PasswordRequest, RefreshRequest,
+        ResetRequest, RevokeRequest, SignUpDet,
+    };
+    use lms_auth::local_crypto::{hash_256, is_legacy_hash, verify_password};
+    use totp_rs::{Algorithm, Secret, TOTP};
 
     use crate::app_ctx::AppContext;
+    use crate::authdb::attempts::LoginLimits;
     use crate::authdb::auth_actors::{Authority, User, Users};
-    use crate::authdb::auth_db::{user_entry, AuthDB};
+    use crate::authdb::auth_db::{verify_token, AuthDB};
+    use crate::authdb::revocation::Revocations;
     use crate::blueprint::Blueprint;
     use crate::config::batch_info::BatchInfo;
     use crate::config::config_module::ConfigModule;
@@ -216,6 +736,8 @@ mod tests {
         module.auth.totp.totp_secret = "base32encodedkey".to_string();
         module.auth.auth_db_path = db_path.as_ref().to_string();
         module.extensions.users = Some(Users::default());
+        // failed attempts don't slow down the tests, see test_login_throttled
+        module.auth.login_limits.backoff = Some(0);
         let totp = module.config.auth.totp.clone().into_totp()?;
         let auth = AuthProvider::init(
             module.config.auth.auth_db_path.clone(),
@@ -256,6 +778,8 @@ mod tests {
             password: hash_256("admin"),
             authority: Authority::Admin,
             batch: None,
+            two_factor: None,
+            password_reset: None,
         };
         auth_db.users.insert(admin);
         let signup = SignUpDet {
@@ -267,13 +791,17 @@ mod tests {
         };
 
         let auth_req = AuthRequest::new("new", "bie", Some(signup))?;
-        let result = auth_db.signup(auth_req).await;
+        let result = auth_db.signup(auth_req, None).await;
 
         println!("{:?}", result);
 
         assert!(result.success.is_some());
         let succ = result.success.unwrap();
         assert_eq!(succ.name, "newbie");
+
+        let stored = auth_db.users.get("new").unwrap().password;
+        assert!(stored.starts_with("$argon2id$"));
+        assert!(verify_password(hash_256("bie"), &stored)?);
         Ok(())
     }
 
@@ -282,7 +810,7 @@ mod tests {
         let mut auth_db = get_db().await?;
         let auth_req = AuthRequest::new("new", "bie", None)?;
 
-        let result = auth_db.signup(auth_req).await;
+        let result = auth_db.signup(auth_req, None).await;
         assert!(result.error.is_some());
         let err = result.error.unwrap();
         assert_eq!(err.message, "No necessary signup details found");
@@ -290,25 +818,571 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_user_entry_url() -> anyhow::Result<()> {
+    async fn test_login() -> anyhow::Result<()> {
+        let mut auth_db = get_db().await?;
+
+        let newbie = User {
+            username: "newbie".to_string(),
+            name: "newbie".to_string(),
+            password: hash_256("newbie"),
+            authority: Authority::Student,
+            batch: Some("22BCS".to_string()),
+            two_factor: None,
+            password_reset: None,
+        };
+        auth_db.users.insert(newbie);
+
+        let auth_req = AuthRequest::new("newbie", "newbie", None)?;
+        let result = auth_db.login(auth_req, None).await;
+        assert!(result.success.is_some());
+        let succ = result.success.unwrap();
+        assert_eq!(succ.name, "newbie");
+
+        let claims = verify_token(&succ.token, &auth_db.app_context)?;
+        assert_eq!(claims.sub, "newbie");
+        assert_eq!(claims.authority, Authority::Student.as_int());
+        assert_eq!(claims.batch.as_deref(), Some("22BCS"));
+        assert_eq!(
+            claims.exp - claims.iat,
+            auth_db.app_context.blueprint.server.access_token_ttl as u128 * 1000
+        );
+        Ok(())
+    }
+
+    async fn login(auth_db: &mut AuthDB, username: &str) -> anyhow::Result<AuthSucc> {
+        let auth_req = AuthRequest::new(username, username, None)?;
+        let result = auth_db.login(auth_req, None).await;
+        Ok(result.success.unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_logout() -> anyhow::Result<()> {
+        let mut auth_db = get_db().await?;
+        auth_db.users.insert(User {
+            username: "newbie".to_string(),
+            name: "newbie".to_string(),
+            password: hash_256("newbie"),
+            authority: Authority::Student,
+            batch: Some("22BCS".to_string()),
+            two_factor: None,
+            password_reset: None,
+        });
+        let session = login(&mut auth_db, "newbie").await?;
+        let token = session.token;
+        let other = login(&mut auth_db, "newbie").await?.token;
+
+        let body = serde_json::to_vec(&LogoutRequest {
+            token: token.clone(),
+            refresh_token: session.refresh_token.clone(),
+        })?;
+        let result = auth_db.handle_logout(body.into()).await;
+        assert_eq!(result.code, 200);
+
+        let body = serde_json::to_vec(&RefreshRequest {
+            refresh_token: session.refresh_token.unwrap(),
+        })?;
+        assert_eq!(auth_db.handle_refresh(body.into()).await.code, 500);
+
+        let err = verify_token(&token, &auth_db.app_context).unwrap_err();
+        assert_eq!(err.to_string(), "Token revoked, please re-login");
+        // other sessions are untouched
+        assert!(verify_token(&other, &auth_db.app_context).is_ok());
+
+        // the revocation survives a restart
+        let revocations = Revocations::fetch(
+            &auth_db.app_context.blueprint.extensions.auth,
+            &auth_db.app_context.runtime,
+        )
+        .await?;
+        let claims = auth_db
+            .app_context
+            .blueprint
+            .extensions
+            .auth
+            .verify_session(&token, auth_db.app_context.runtime.instance.now()?)?;
+        assert!(revocations.is_revoked(&claims));
+
+        let body = serde_json::to_vec(&LogoutRequest {
+            token,
+            refresh_token: None,
+        })?;
+        assert_eq!(auth_db.handle_logout(body.into()).await.code, 500);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_refresh() -> anyhow::Result<()> {
+        let mut auth_db = get_db().await?;
+        auth_db.users.insert(User {
+            username: "newbie".to_string(),
+            name: "newbie".to_string(),
+            password: hash_256("newbie"),
+            authority: Authority::Student,
+            batch: Some("22BCS".to_string()),
+            two_factor: None,
+            password_reset: None,
+        });
+        let session = login(&mut auth_db, "newbie").await?;
+        let first = session.refresh_token.unwrap();
+
+        let refresh = |refresh_token: &str| {
+            serde_json::to_vec(&RefreshRequest {
+                refresh_token: refresh_token.to_string(),
+            })
+            .unwrap()
+        };
+        let result = auth_db.handle_refresh(refresh(&first).into()).await;
+        let succ = result.success.unwrap();
+        assert_eq!(succ.name, "newbie");
+        assert_eq!(
+            verify_token(&succ.token, &auth_db.app_context)?.sub,
+            "newbie"
+        );
+        let second = succ.refresh_token.unwrap();
+        assert_ne!(first, second);
+
+        // reusing a rotated token revokes the session
+        let result = auth_db.handle_refresh(refresh(&first).into()).await;
+        assert_eq!(
+            result.error.unwrap().message,
+            "Refresh token reused, please re-login"
+        );
+        let result = auth_db.handle_refresh(refresh(&second).into()).await;
+        assert!(result.success.is_none());
+
+        let result = auth_db.handle_refresh(refresh("garbage").into()).await;
+        assert_eq!(result.error.unwrap().message, "Invalid refresh token");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_revoke_user() -> anyhow::Result<()> {
+        let mut auth_db = get_db().await?;
+        auth_db.users.insert(User {
+            username: "admin".to_string(),
+            name: "admin".to_string(),
+            password: hash_256("admin"),
+            authority: Authority::Admin,
+            batch: None,
+            two_factor: None,
+            password_reset: None,
+        });
+        auth_db.users.insert(User {
+            username: "newbie".to_string(),
+            name: "newbie".to_string(),
+            password: hash_256("newbie"),
+            authority: Authority::Student,
+            batch: Some("22BCS".to_string()),
+            two_factor: None,
+            password_reset: None,
+        });
+        let admin = login(&mut auth_db, "admin").await?.token;
+        let first = login(&mut auth_db, "newbie").await?.token;
+        let newbie = login(&mut auth_db, "newbie").await?;
+        let second = newbie.token;
+
+        // students can't revoke sessions
+        let body = serde_json::to_vec(&RevokeRequest {
+            token: first.clone(),
+            username: "admin".to_string(),
+        })?;
+        assert_eq!(auth_db.handle_revoke(body.into()).await.code, 403);
+        assert!(verify_token(&admin, &auth_db.app_context).is_ok());
+
+        let body = serde_json::to_vec(&RevokeRequest {
+            token: admin.clone(),
+            username: "newbie".to_string(),
+        })?;
+        assert_eq!(auth_db.handle_revoke(body.into()).await.code, 200);
+        assert!(verify_token(&first, &auth_db.app_context).is_err());
+        assert!(verify_token(&second, &auth_db.app_context).is_err());
+        assert!(verify_token(&admin, &auth_db.app_context).is_ok());
+        let body = serde_json::to_vec(&RefreshRequest {
+            refresh_token: newbie.refresh_token.unwrap(),
+        })?;
+        let result = auth_db.handle_refresh(body.into()).await;
+        assert_eq!(result.error.unwrap().message, "Invalid refresh token");
+
+        // sessions started after the revocation work
+        tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+        let third = login(&mut auth_db, "newbie").await?.token;
+        assert!(verify_token(&third, &auth_db.app_context).is_ok());
+        Ok(())
+    }
+
+    fn otp(uri: &str, now: u128) -> String {
+        let secret = uri
+            .split("secret=")
+            .nth(1)
+            .unwrap()
+            .split('&')
+            .next()
+            .unwrap();
+        let secret = Secret::Encoded(secret.to_string()).to_bytes().unwrap();
+        TOTP::new(Algorithm::SHA1, 6, 0, 30, secret)
+            .unwrap()
+            .generate((now / 1000) as u64)
+    }
+
+    fn two_factor_req(username: &str, otp: Option<&str>) -> bytes::Bytes {
+        let mut req = AuthRequest::new(username, username, None).unwrap();
+        if let Some(otp) = otp {
+            req = req.with_otp(otp);
+        }
+        serde_json::to_vec(&req).unwrap().into()
+    }
+
+    /// Enrolls a second factor, returns the uri and the recovery codes.
+    async fn enable_2fa(auth_db: &mut AuthDB, username: &str) -> (String, Vec<String>) {
+        let result = auth_db
+            .handle_enroll_2fa(two_factor_req(username, None), None)
+            .await;
+        let uri = result.two_factor.unwrap().uri.unwrap();
+        let now = auth_db.app_context.runtime.instance.now().unwrap();
+        let result = auth_db
+            .handle_verify_2fa(two_factor_req(username, Some(&otp(&uri, now))), None)
+            .await;
+        (uri, result.two_factor.unwrap().recovery_codes)
+    }
+
+    #[tokio::test]
+    async fn test_two_factor() -> anyhow::Result<()> {
+        let mut auth_db = get_db().await?;
+        auth_db.users.insert(User {
+            username: "newbie".to_string(),
+            name: "newbie".to_string(),
+            password: hash_256("newbie"),
+            authority: Authority::Student,
+            batch: Some("22BCS".to_string()),
+            two_factor: None,
+            password_reset: None,
+        });
+
+        // a pending enrollment doesn't affect login
+        let result = auth_db
+            .handle_enroll_2fa(two_factor_req("newbie", None), None)
+            .await;
+        let uri = result.two_factor.unwrap().uri.unwrap();
+        assert!(uri.starts_with("otpauth://totp/lms:newbie?secret="));
+        login(&mut auth_db, "newbie").await?;
+        let result = auth_db
+            .handle_verify_2fa(two_factor_req("newbie", Some("garbage")), None)
+            .await;
+        assert_eq!(result.error.unwrap().message, "Invalid OTP");
+
+        let (uri, recovery_codes) = enable_2fa(&mut auth_db, "newbie").await;
+        assert_eq!(recovery_codes.len(), 10);
+        let result = auth_db
+            .handle_enroll_2fa(two_factor_req("newbie", None), None)
+            .await;
+        assert_eq!(
+            result.error.unwrap().message,
+            "Two-factor authentication is already enabled"
+        );
+
+        let login = |otp: Option<&str>| {
+            let req = AuthRequest::new("newbie", "newbie", None).unwrap();
+            match otp {
+                Some(otp) => req.with_otp(otp),
+                None => req,
+            }
+        };
+        let result = auth_db.login(login(None), None).await;
+        assert_eq!(result.error.unwrap().message, "OTP required");
+        let result = auth_db.login(login(Some("garbage")), None).await;
+        assert_eq!(result.error.unwrap().message, "Invalid OTP");
+
+        // the code used for enrollment is spent, the next one works
+        let next = otp(&uri, auth_db.app_context.runtime.instance.now()? + 30_000);
+        assert!(auth_db
+            .login(login(Some(&next)), None)
+            .await
+            .success
+            .is_some());
+        assert!(auth_db
+            .login(login(Some(&next)), None)
+            .await
+            .error
+            .is_some());
+
+        // recovery codes work once
+        let code = recovery_codes[0].as_str();
+        assert!(auth_db
+            .login(login(Some(code)), None)
+            .await
+            .success
+            .is_some());
+        assert!(auth_db.login(login(Some(code)), None).await.error.is_some());
+
+        let result = auth_db
+            .handle_disable_2fa(two_factor_req("newbie", Some(&recovery_codes[1])), None)
+            .await;
+        assert_eq!(result.code, 200);
+        assert!(auth_db.users.get("newbie").unwrap().two_factor.is_none());
+        assert!(auth_db.login(login(None), None).await.success.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_mandatory_two_factor() -> anyhow::Result<()> {
+        let mut app_ctx = app_ctx("foobar")?;
+        app_ctx.blueprint.mandatory_two_factor = vec![Authority::Faculty];
+        let mut auth_db = AuthDB::init(Arc::new(app_ctx)).await?;
+        auth_db.users.insert(User {
+            username: "faculty".to_string(),
+            name: "faculty".to_string(),
+            password: hash_256("faculty"),
+            authority: Authority::Faculty,
+            batch: None,
+            two_factor: None,
+            password_reset: None,
+        });
+
+        let auth_req = AuthRequest::new("faculty", "faculty", None)?;
+        let result = auth_db.login(auth_req, None).await;
+        assert_eq!(
+            result.error.unwrap().message,
+            "Two-factor authentication is mandatory, enroll at /auth/2fa/enroll"
+        );
+
+        let (_, recovery_codes) = enable_2fa(&mut auth_db, "faculty").await;
+        let auth_req = AuthRequest::new("faculty", "faculty", None)?.with_otp(&recovery_codes[0]);
+        assert!(auth_db.login(auth_req, None).await.success.is_some());
+
+        let result = auth_db
+            .handle_disable_2fa(two_factor_req("faculty", Some(&recovery_codes[1])), None)
+            .await;
+        assert_eq!(result.code, 403);
+        assert!(auth_db.users.get("faculty").unwrap().two_factor.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_login_throttled() -> anyhow::Result<()> {
+        let mut app_ctx = app_ctx("foobar")?;
+        app_ctx.blueprint.login_limits = LoginLimits {
+            max_attempts_per_ip: 3,
+            ..LoginLimits::default()
+        };
+        let mut auth_db = AuthDB::init(Arc::new(app_ctx)).await?;
+        for username in ["newbie", "other"] {
+            auth_db.users.insert(User {
+                username: username.to_string(),
+                name: username.to_string(),
+                password: hash_256(username),
+                authority: Authority::Student,
+                batch: Some("22BCS".to_string()),
+                two_factor: None,
+                password_reset: None,
+            });
+        }
+        let attacker = Some("10.0.0.1".parse()?);
+        let client = Some("10.0.0.2".parse()?);
+
+        // unknown users and wrong passwords can't be told apart
+        let result = auth_db
+            .login(AuthRequest::new("nobody", "newbie", None)?, attacker)
+            .await;
+        assert_eq!(
+            result.error.unwrap().message,
+            "Invalid username or password"
+        );
+        let result = auth_db
+            .login(AuthRequest::new("newbie", "wrong", None)?, attacker)
+            .await;
+        assert_eq!(
+            result.error.unwrap().message,
+            "Invalid username or password"
+        );
+
+        // the username has to wait, even with the right password
+        let result = auth_db
+            .login(AuthRequest::new("newbie", "newbie", None)?, client)
+            .await;
+        assert_eq!(result.code, 429);
+        assert_eq!(
+            result.error.unwrap().message,
+            "Too many failed attempts, try again in 1 seconds"
+        );
+
+        // the attacker's ip is locked out after the third failure
+        let result = auth_db
+            .login(AuthRequest::new("other", "wrong", None)?, attacker)
+            .await;
+        assert_eq!(result.code, 500);
+        let result = auth_db
+            .login(AuthRequest::new("nobody", "nobody", None)?, attacker)
+            .await;
+        assert_eq!(result.code, 429);
+
+        tokio::time::sleep(std::time::Duration::from_millis(1000)).await;
+        let result = auth_db
+            .login(AuthRequest::new("newbie", "newbie", None)?, client)
+            .await;
+        assert!(result.success.is_some());
+        Ok(())
+    }
+
+    fn newbie() -> User {
+        User {
+            username: "newbie".to_string(),
+            name: "newbie".to_string(),
+            password: hash_256("newbie"),
+            authority: Authority::Student,
+            batch: Some("22BCS".to_string()),
+            two_factor: None,
+            password_reset: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_change_password() -> anyhow::Result<()> {
+        let mut auth_db = get_db().await?;
+        auth_db.users.insert(newbie());
+        let token = login(&mut auth_db, "newbie").await?.token;
+
+        let change = |password: &str, new_password: &str| {
+            let req = PasswordRequest::new("newbie", password, new_password);
+            bytes::Bytes::from(serde_json::to_vec(&req).unwrap())
+        };
+        let result = auth_db
+            .handle_change_password(change("wrong", "secret"), None)
+            .await;
+        assert_eq!(
+            result.error.unwrap().message,
+            "Invalid username or password"
+        );
+
+        let result = auth_db
+            .handle_change_password(change("newbie", "secret"), None)
+            .await;
+        assert_eq!(result.code, 200);
+        assert!(verify_token(&token, &auth_db.app_context).is_err());
+
+        // persisted to the auth db
+        let app_ctx = &auth_db.app_context;
+        let stored = app_ctx.runtime.file.read("foobar").await?;
+        let stored = app_ctx.blueprint.extensions.auth.decrypt_aes(stored)?;
+        let stored = serde_json::from_str::<Users>(&stored)?;
+        assert!(verify_password(
+            hash_256("secret"),
+            &stored.get("newbie").unwrap().password
+        )?);
+
+        tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+        let auth_req = AuthRequest::new("newbie", "newbie", None)?;
+        assert!(auth_db.login(auth_req, None).await.error.is_some());
+        let auth_req = AuthRequest::new("newbie", "secret", None)?;
+        assert!(auth_db.login(auth_req, None).await.success.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_change_password_remote() -> anyhow::Result<()> {
         let server = start_mock_server();
-        server.mock(|w, t| {
+        let done = r#"{"version":1,"reply":{"type":"done"}}"#;
+        let upsert_user = server.mock(|w, t| {
+            w.method(httpmock::Method::POST)
+                .body_contains(r#"\"operation\":\"upsert_user\""#)
+                .body_contains(r#"\"username\":\"newbie\""#);
+            t.status(200).body(done);
+        });
+        let put_store = server.mock(|w, t| {
             w.method(httpmock::Method::POST)
-                .path("/")
-                .body(r#"{"operation":"put_user","pw":"db1a2fca194f1034c91ea5d6a2df0c1a","users":"{\"users\":{}}"}"#)
-            ;
-            t.status(200).body(r#"{"users":{}}"#);
+                .body_contains(r#"\"operation\":\"put_store\""#);
+            t.status(200).body(done);
         });
-        let app_ctx = app_ctx(server.base_url())?;
-        let expected = Users::default();
-        let actual = user_entry(&app_ctx, expected.clone()).await?;
+        let mut auth_db = AuthDB::init(Arc::new(app_ctx(server.base_url())?)).await?;
+        auth_db.users.insert(newbie());
 
-        assert_eq!(expected, actual);
+        let req = PasswordRequest::new("newbie", "newbie", "secret");
+        let result = auth_db
+            .handle_change_password(serde_json::to_vec(&req)?.into(), None)
+            .await;
+        assert_eq!(result.code, 200);
+        upsert_user.assert();
+        // the refresh tokens and the revocations
+        put_store.assert_hits(2);
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_login() -> anyhow::Result<()> {
+    async fn test_reset_password() -> anyhow::Result<()> {
+        let mut auth_db = get_db().await?;
+        auth_db.users.insert(User {
+            username: "admin".to_string(),
+            name: "admin".to_string(),
+            password: hash_256("admin"),
+            authority: Authority::Admin,
+            batch: None,
+            two_factor: None,
+            password_reset: None,
+        });
+        auth_db.users.insert(newbie());
+        let admin = login(&mut auth_db, "admin").await?.token;
+        let student = login(&mut auth_db, "newbie").await?.token;
+
+        let reset = |token: &str| {
+            let req = ResetRequest {
+                token: token.to_string(),
+                username: "newbie".to_string(),
+            };
+            bytes::Bytes::from(serde_json::to_vec(&req).unwrap())
+        };
+        assert_eq!(
+            auth_db.handle_reset_password(reset(&student)).await.code,
+            403
+        );
+
+        let result = auth_db.handle_reset_password(reset(&admin)).await;
+        let code = result.reset_code.unwrap();
+        assert!(verify_token(&student, &auth_db.app_context).is_err());
+
+        // neither the old password nor the code can be used to login
+        let auth_req = AuthRequest::new("newbie", "newbie", None)?;
+        assert!(auth_db.login(auth_req, None).await.error.is_some());
+        let auth_req = AuthRequest::new("newbie", code.as_str(), None)?;
+        let result = auth_db.login(auth_req, None).await;
+        assert_eq!(result.code, 403);
+        assert_eq!(
+            result.error.unwrap().message,
+            "Password was reset, change it at /auth/password"
+        );
+
+        let req = PasswordRequest::new("newbie", code.as_str(), "secret");
+        let result = auth_db
+            .handle_change_password(serde_json::to_vec(&req)?.into(), None)
+            .await;
+        assert_eq!(result.code, 200);
+        assert!(auth_db
+            .users
+            .get("newbie")
+            .unwrap()
+            .password_reset
+            .is_none());
+
+        // the code is single use
+        let result = auth_db
+            .handle_change_password(serde_json::to_vec(&req)?.into(), None)
+            .await;
+        assert!(result.error.is_some());
+        let auth_req = AuthRequest::new("newbie", "secret", None)?;
+        assert!(auth_db.login(auth_req, None).await.success.is_some());
+
+        // expired codes are rejected
+        let mut user = auth_db.users.get("newbie").unwrap();
+        user.password_reset = Some(1);
+        auth_db.users.insert(user);
+        let auth_req = AuthRequest::new("newbie", "secret", None)?;
+        assert_eq!(
+            auth_db.login(auth_req, None).await.error.unwrap().message,
+            "Reset code expired, ask an admin for a new one"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_login_rehashes_legacy_password() -> anyhow::Result<()> {
         let mut auth_db = get_db().await?;
 
         let newbie = User {
@@ -317,14 +1391,22 @@ mod tests {
             password: hash_256("newbie"),
             authority: Authority::Student,
             batch: Some("22BCS".to_string()),
+            two_factor: None,
+            password_reset: None,
         };
         auth_db.users.insert(newbie);
 
         let auth_req = AuthRequest::new("newbie", "newbie", None)?;
-        let result = auth_db.login(auth_req).await;
-        assert!(result.success.is_some());
-        let succ = result.success.unwrap();
-        assert_eq!(succ.name, "newbie");
+        assert!(auth_db.login(auth_req, None).await.success.is_some());
+
+        let stored = auth_db.users.get("newbie").unwrap().password;
+        assert!(!is_legacy_hash(&stored));
+
+        // still able to login after the upgrade
+        let auth_req = AuthRequest::new("newbie", "newbie", None)?;
+        assert!(auth_db.login(auth_req, None).await.success.is_some());
+        let auth_req = AuthRequest::new("newbie", "wrong", None)?;
+        assert!(auth_db.login(auth_req, None).await.error.is_some());
         Ok(())
     }
 
@@ -338,6 +1420,8 @@ mod tests {
             password: hash_256("newbie"),
             authority: Authority::Student,
             batch: Some("22BCS".to_string()),
+            two_factor: None,
+            password_reset: None,
         };
         auth_db.users.insert(newbie);
 
@@ -352,7 +1436,7 @@ mod tests {
         let encrypted_req = serde_json::to_string(&auth_req)?;
 
         let result = auth_db
-            .handle_request(bytes::Bytes::from(encrypted_req))
+            .handle_request(bytes::Bytes::from(encrypted_req), None)
             .await;
 
         assert!(result.success.is_some());
@@ -370,6 +1454,8 @@ mod tests {
             password: hash_256("admin"),
             authority: Authority::Admin,
             batch: None,
+            two_factor: None,
+            password_reset: None,
         };
         auth_db.users.insert(admin);
         let signup = SignUpDet {
@@ -390,7 +1476,7 @@ mod tests {
         .encrypt_aes(serde_json::to_string(&auth_req)?)?;*/
         let encrypted_req = serde_json::to_string(&auth_req)?;
         let result = auth_db
-            .handle_request(bytes::Bytes::from(encrypted_req))
+            .handle_request(bytes::Bytes::from(encrypted_req), None)
             .await;
 
         assert!(result.success.is_some());
@@ -0,0 +1,336 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use tokio::sync::Mutex;
+
+use lms_auth::auth::AuthProvider;
+
+use crate::authdb::auth_actors::{User, Users};
+use crate::authdb::remote::{Operation, RemoteClient, Reply};
+use crate::runtime::TargetRuntime;
+#[cfg(feature = "sqlite")]
+use crate::sqlite::SqliteDb;
+use crate::NotFound;
+
+/// Storage of the users, the encrypted `authDbPath` file or a remote AuthDB.
+#[async_trait::async_trait]
+pub trait AuthStore: Send + Sync {
+    async fn get(&self, username: &str) -> Result<Option<User>>;
+    /// Inserts the user or replaces the user with the same username.
+    async fn upsert(&self, user: User) -> Result<()>;
+    async fn delete(&self, username: &str) -> Result<()>;
+    async fn list(&self) -> Result<Users>;
+}
+
+/// Picks the store for `authDbPath`, remote if it's a url, SQLite for a `sqlite://` path.
+pub fn init(auth: &AuthProvider, runtime: &TargetRuntime) -> Result<Arc<dyn AuthStore>> {
+    if auth.db_path().starts_with("http") {
+        return Ok(Arc::new(RemoteAuthStore::new(
+            auth.clone(),
+            runtime.clone(),
+        )));
+    }
+    if let Some(_path) = crate::sqlite_path(auth.db_path()) {
+        #[cfg(feature = "sqlite")]
+        return Ok(Arc::new(SqliteAuthStore::open(auth.clone(), _path)?));
+        #[cfg(not(feature = "sqlite"))]
+        return Err(anyhow!(
+            "sqlite:// paths need lms built with the `sqlite` feature"
+        ));
+    }
+    Ok(Arc::new(LocalAuthStore::new(auth.clone(), runtime.clone())))
+}
+
+/// Users encrypted as a single file.
+/// Every write re-reads the file under a lock, so concurrent writes don't drop each other.
+pub struct LocalAuthStore {
+    auth: AuthProvider,
+    runtime: TargetRuntime,
+    lock: Mutex<()>,
+}
+
+impl LocalAuthStore {
+    pub fn new(auth: AuthProvider, runtime: TargetRuntime) -> Self {
+        Self {
+            auth,
+            runtime,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Rewrites the file with the active key, returns the users.
+    pub async fn reencrypt(&self) -> Result<Users> {
+        self.update(|_| ()).await
+    }
+
+    async fn read(&self) -> Result<Users> {
+        match self.runtime.file.read(self.auth.db_path()).await {
+            Ok(encrypted) => Ok(serde_json::from_str(&self.auth.decrypt_aes(encrypted)?)?),
+            Err(err) if err.is::<NotFound>() => Ok(Users::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn update<F: FnOnce(&mut Users)>(&self, f: F) -> Result<Users> {
+        let _guard = self.lock.lock().await;
+        let mut users = self.read().await?;
+        f(&mut users);
+        let encrypted = self.auth.encrypt_aes(serde_json::to_string(&users)?)?;
+        self.runtime
+            .file
+            .write(self.auth.db_path(), encrypted.as_bytes())
+            .await?;
+        Ok(users)
+    }
+}
+
+#[async_trait::async_trait]
+impl AuthStore for LocalAuthStore {
+    async fn get(&self, username: &str) -> Result<Option<User>> {
+        Ok(self.read().await?.get(username))
+    }
+
+    async fn upsert(&self, user: User) -> Result<()> {
+        self.update(|users| users.insert(user)).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, username: &str) -> Result<()> {
+        self.update(|users| users.delete(username)).await?;
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Users> {
+        self.read().await
+    }
+}
+
+/// Users in a SQLite database, a row per user encrypted with the auth key.
+#[cfg(feature = "sqlite")]
+pub struct SqliteAuthStore {
+    auth: AuthProvider,
+    db: SqliteDb,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteAuthStore {
+    pub fn open(auth: AuthProvider, path: &str) -> Result<Self> {
+        Ok(Self {
+            auth,
+            db: SqliteDb::open(path)?,
+        })
+    }
+
+    /// Re-encrypts every user with the active key in a single transaction, returns the number of users.
+    pub fn reencrypt(&self) -> Result<usize> {
+        self.db
+            .update_users(|data| self.auth.encrypt_aes(self.auth.decrypt_aes(data)?))
+    }
+
+    fn decrypt(&self, data: &str) -> Result<User> {
+        Ok(serde_json::from_str(&self.auth.decrypt_aes(data)?)?)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+#[async_trait::async_trait]
+impl AuthStore for SqliteAuthStore {
+    async fn get(&self, username: &str) -> Result<Option<User>> {
+        self.db
+            .get_user(username)?
+            .map(|data| self.decrypt(&data))
+            .transpose()
+    }
+
+    async fn upsert(&self, user: User) -> Result<()> {
+        let data = self.auth.encrypt_aes(serde_json::to_string(&user)?)?;
+        self.db.upsert_user(&user.username, &data)
+    }
+
+    async fn delete(&self, username: &str) -> Result<()> {
+        self.db.delete_user(username)
+    }
+
+    async fn list(&self) -> Result<Users> {
+        let mut users = Users::default();
+        for data in self.db.list_users()? {
+            users.insert(self.decrypt(&data)?);
+        }
+        Ok(users)
+    }
+}
+
+/// Users kept by a remote AuthDB, every operation is a single call, see [crate::authdb::remote].
+/// The remote is expected to apply each operation atomically.
+pub struct RemoteAuthStore {
+    client: RemoteClient,
+}
+
+impl RemoteAuthStore {
+    pub fn new(auth: AuthProvider, runtime: TargetRuntime) -> Self {
+        Self {
+            client: RemoteClient::new(auth, runtime),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl AuthStore for RemoteAuthStore {
+    async fn get(&self, username: &str) -> Result<Option<User>> {
+        let username = username.to_string();
+        match self.client.call(Operation::GetUser { username }).await? {
+            Reply::User(user) => Ok(user),
+            _ => Err(anyhow!("Invalid response from AuthDB")),
+        }
+    }
+
+    async fn upsert(&self, user: User) -> Result<()> {
+        self.client.call(Operation::UpsertUser { user }).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, username: &str) -> Result<()> {
+        let username = username.to_string();
+        self.client.call(Operation::DeleteUser { username }).await?;
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Users> {
+        match self.client.call(Operation::ListUsers).await? {
+            Reply::Users(users) => Ok(users),
+            _ => Err(anyhow!("Invalid response from AuthDB")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use super::*;
+    use crate::authdb::auth_actors::Authority;
+    use totp_rs::{Algorithm, Secret, TOTP};
+
+    fn auth(db_path: &str) -> AuthProvider {
+        let totp = TOTP::new(
+            Algorithm::SHA1,
+            6,
+            1,
+            30,
+            Secret::Raw("JBSWY3DPEHPK3PXP".as_bytes().to_vec())
+                .to_bytes()
+                .unwrap(),
+        )
+        .unwrap();
+        AuthProvider::init(db_path.to_string(), totp, "aes key".to_string()).unwrap()
+    }
+
+    fn user(username: &str) -> User {
+        User {
+            username: username.to_string(),
+            name: username.to_string(),
+            password: "password".to_string(),
+            authority: Authority::Admin,
+            batch: None,
+            two_factor: None,
+            password_reset: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_local_concurrent_upserts() -> Result<()> {
+        let runtime = crate::runtime::tests::init();
+        let store = Arc::new(LocalAuthStore::new(auth("auth"), runtime.clone()));
+
+        let writes = (0..20).map(|i| {
+            let store = store.clone();
+            tokio::spawn(async move { store.upsert(user(&format!("user{}", i))).await })
+        });
+        for write in writes {
+            write.await??;
+        }
+        // a second store on the same file sees every write
+        let other = LocalAuthStore::new(auth("auth"), runtime);
+        assert_eq!(other.list().await?.get_all().len(), 20);
+
+        store.delete("user0").await?;
+        assert!(other.get("user0").await?.is_none());
+        assert_eq!(other.get("user1").await?, Some(user("user1")));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_local_failing_read() -> Result<()> {
+        let (runtime, faults) = crate::runtime::tests::init_with_faults();
+        let store = LocalAuthStore::new(auth("auth"), runtime);
+        store.upsert(user("user0")).await?;
+
+        // an upsert doesn't rewrite the users it couldn't read with only its own
+        faults.reads.store(true, Ordering::SeqCst);
+        assert!(store.upsert(user("user1")).await.is_err());
+        faults.reads.store(false, Ordering::SeqCst);
+        assert_eq!(store.get("user0").await?, Some(user("user0")));
+        assert!(store.get("user1").await?.is_none());
+        Ok(())
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_sqlite_operations() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = format!("sqlite://{}", dir.path().join("lms.db").display());
+        let store = init(&auth(&path), &crate::runtime::tests::init())?;
+        store.upsert(user("foo")).await?;
+        store.upsert(user("bar")).await?;
+        store.delete("bar").await?;
+        assert_eq!(store.get("foo").await?, Some(user("foo")));
+        assert_eq!(store.list().await?.get_all().len(), 1);
+
+        // rows are encrypted
+        let db = SqliteDb::open(crate::sqlite_path(&path).unwrap())?;
+        let data = db.get_user("foo")?.unwrap();
+        assert!(!data.contains("password"));
+
+        let store = SqliteAuthStore::open(auth(&path), crate::sqlite_path(&path).unwrap())?;
+        assert_eq!(store.reencrypt()?, 1);
+        assert_eq!(store.get("foo").await?, Some(user("foo")));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_remote_operations() -> Result<()> {
+        let server = httpmock::MockServer::start();
+        let done = r#"{"version":1,"reply":{"type":"done"}}"#;
+        // the operation is inside the signed json payload, so its quotes are escaped
+        let upsert = server.mock(|w, t| {
+            w.method(httpmock::Method::POST)
+                .body_contains(r#"\"operation\":\"upsert_user\""#)
+                .body_contains(r#"\"username\":\"foo\""#)
+                .body_contains(r#""signature":""#);
+            t.status(200).body(done);
+        });
+        let get = server.mock(|w, t| {
+            w.method(httpmock::Method::POST)
+                .body_contains(r#"\"operation\":\"get_user\""#);
+            t.status(200).json_body(serde_json::json!({
+                "version": 1,
+                "reply": {"type": "user", "value": user("foo")}
+            }));
+        });
+        let delete = server.mock(|w, t| {
+            w.method(httpmock::Method::POST)
+                .body_contains(r#"\"operation\":\"delete_user\""#)
+                .body_contains(r#"\"username\":\"foo\""#);
+            t.status(200).body(done);
+        });
+
+        let store = RemoteAuthStore::new(auth(&server.base_url()), crate::runtime::tests::init());
+        store.upsert(user("foo")).await?;
+        assert_eq!(store.get("foo").await?, Some(user("foo")));
+        store.delete("foo").await?;
+        upsert.assert();
+        get.assert();
+        delete.assert();
+        Ok(())
+    }
+}
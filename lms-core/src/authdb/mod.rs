@@ -1,2 +1,9 @@
+pub mod attempts;
 pub mod auth_actors;
 pub mod auth_db;
+pub mod auth_store;
+pub mod refresh;
+pub mod remote;
+pub mod revocation;
+pub mod store;
+pub mod two_factor;
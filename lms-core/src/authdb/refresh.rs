@@ -0,0 +1,259 @@
+use anyhow::{anyhow, Result};
+use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+use base64::Engine;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use lms_auth::auth::AuthProvider;
+use lms_auth::local_crypto::hash_256;
+
+use crate::authdb::store;
+use crate::runtime::TargetRuntime;
+
+const STORE: &str = "sessions";
+
+/// Refresh tokens of the form `<family>.<secret>`.
+/// Every use rotates the secret and slides the expiry of the family,
+/// presenting an already rotated secret revokes the whole family.
+#[derive(Default, Debug, Serialize, Deserialize)]
+pub struct RefreshTokens {
+    families: DashMap<String, Family>,
+    #[serde(skip)]
+    lock: Mutex<()>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Family {
+    /// Username the tokens are issued to
+    sub: String,
+    /// sha256 of the current secret
+    secret: String,
+    /// ms since epoch
+    exp: u128,
+}
+
+enum Rotation {
+    Rotated(String),
+    Expired,
+    Reused(String),
+    Unknown,
+}
+
+impl RefreshTokens {
+    pub async fn fetch(auth: &AuthProvider, runtime: &TargetRuntime) -> Result<Self> {
+        store::fetch(STORE, auth, runtime).await
+    }
+
+    /// Starts a new family for the user and returns its first token.
+    pub async fn issue(
+        &self,
+        username: &str,
+        ttl: u64,
+        auth: &AuthProvider,
+        runtime: &TargetRuntime,
+    ) -> Result<String> {
+        let family_id = random_string()?;
+        let secret = random_string()?;
+        let family = Family {
+            sub: username.to_string(),
+            secret: hash_256(&secret),
+            exp: runtime.instance.now()? + ttl as u128 * 1000,
+        };
+        self.families.insert(family_id.clone(), family);
+        self.persist(auth, runtime).await?;
+        Ok(format!("{}.{}", family_id, secret))
+    }
+
+    /// Exchanges the token for a new one, returns the username along with the new token.
+    pub async fn rotate(
+        &self,
+        token: &str,
+        ttl: u64,
+        auth: &AuthProvider,
+        runtime: &TargetRuntime,
+    ) -> Result<(String, String)> {
+        let (family_id, secret) = token
+            .split_once('.')
+            .ok_or(anyhow!("Invalid refresh token"))?;
+        let now = runtime.instance.now()?;
+        let new_secret = random_string()?;
+
+        let rotation = match self.families.get_mut(family_id) {
+            Some(mut family) => {
+                if family.exp <= now {
+                    Rotation::Expired
+                } else if family.secret != hash_256(secret) {
+                    Rotation::Reused(family.sub.clone())
+                } else {
+                    family.secret = hash_256(&new_secret);
+                    family.exp = now + ttl as u128 * 1000;
+                    Rotation::Rotated(family.sub.clone())
+                }
+            }
+            None => Rotation::Unknown,
+        };
+
+        match rotation {
+            Rotation::Rotated(sub) => {
+                self.persist(auth, runtime).await?;
+                Ok((sub, format!("{}.{}", family_id, new_secret)))
+            }
+            Rotation::Expired => {
+                self.families.remove(family_id);
+                self.persist(auth, runtime).await?;
+                Err(anyhow!("Refresh token expired, please re-login"))
+            }
+            Rotation::Reused(sub) => {
+                // either the client or someone who stole the token holds a newer one
+                log::warn!("Refresh token reused for {}, revoking the session", sub);
+                self.families.remove(family_id);
+                self.persist(auth, runtime).await?;
+                Err(anyhow!("Refresh token reused, please re-login"))
+            }
+            Rotation::Unknown => Err(anyhow!("Invalid refresh token")),
+        }
+    }
+
+    /// Revokes the family of the token, used on logout.
+    pub async fn revoke(
+        &self,
+        token: &str,
+        auth: &AuthProvider,
+        runtime: &TargetRuntime,
+    ) -> Result<()> {
+        let (family_id, _) = token
+            .split_once('.')
+            .ok_or(anyhow!("Invalid refresh token"))?;
+        if self.families.remove(family_id).is_some() {
+            self.persist(auth, runtime).await?;
+        }
+        Ok(())
+    }
+
+    /// Revokes every family of the user.
+    pub async fn revoke_user(
+        &self,
+        username: &str,
+        auth: &AuthProvider,
+        runtime: &TargetRuntime,
+    ) -> Result<()> {
+        self.families.retain(|_, family| family.sub != username);
+        self.persist(auth, runtime).await
+    }
+
+    async fn persist(&self, auth: &AuthProvider, runtime: &TargetRuntime) -> Result<()> {
+        // serialize the writes, so a slower write can't overwrite a newer list
+        let _guard = self.lock.lock().await;
+        let now = runtime.instance.now()?;
+        self.families.retain(|_, family| family.exp > now);
+
+        let families = serde_json::to_string(self)
+            .map_err(|_| anyhow!("Unable to serialize refresh tokens"))?;
+        store::put(STORE, families, auth, runtime).await
+    }
+}
+
+fn random_string() -> Result<String> {
+    let mut bytes = [0u8; 32];
+    getrandom::getrandom(&mut bytes).map_err(|_| anyhow!("Unable to generate refresh token"))?;
+    Ok(BASE64_URL_SAFE_NO_PAD.encode(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use super::*;
+    use totp_rs::{Algorithm, Secret, TOTP};
+
+    fn auth() -> AuthProvider {
+        let totp = TOTP::new(
+            Algorithm::SHA1,
+            6,
+            1,
+            30,
+            Secret::Raw("JBSWY3DPEHPK3PXP".as_bytes().to_vec())
+                .to_bytes()
+                .unwrap(),
+        )
+        .unwrap();
+        AuthProvider::init("auth".to_string(), totp, "aes key".to_string()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_rotate() -> Result<()> {
+        let runtime = crate::runtime::tests::init();
+        let auth = auth();
+        let tokens = RefreshTokens::fetch(&auth, &runtime).await?;
+
+        let first = tokens.issue("foo", 60, &auth, &runtime).await?;
+        let (sub, second) = tokens.rotate(&first, 60, &auth, &runtime).await?;
+        assert_eq!(sub, "foo");
+        assert_ne!(first, second);
+
+        // rotation survives a restart
+        let tokens = RefreshTokens::fetch(&auth, &runtime).await?;
+        let (_, third) = tokens.rotate(&second, 60, &auth, &runtime).await?;
+
+        let err = tokens
+            .rotate(&second, 60, &auth, &runtime)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Refresh token reused, please re-login");
+        // the whole family is revoked on reuse
+        let err = tokens
+            .rotate(&third, 60, &auth, &runtime)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Invalid refresh token");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fetch_failing_read() -> Result<()> {
+        let (runtime, faults) = crate::runtime::tests::init_with_faults();
+        let auth = auth();
+        let tokens = RefreshTokens::fetch(&auth, &runtime).await?;
+        tokens.issue("foo", 60, &auth, &runtime).await?;
+
+        // the stored tokens aren't replaced by none when they can't be read
+        faults.reads.store(true, Ordering::SeqCst);
+        assert!(RefreshTokens::fetch(&auth, &runtime).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_expired_and_revoked() -> Result<()> {
+        let runtime = crate::runtime::tests::init();
+        let auth = auth();
+        let tokens = RefreshTokens::default();
+
+        tokens.families.insert(
+            "old".to_string(),
+            Family {
+                sub: "foo".to_string(),
+                secret: hash_256("secret"),
+                exp: 1,
+            },
+        );
+        let err = tokens
+            .rotate("old.secret", 60, &auth, &runtime)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Refresh token expired, please re-login");
+        assert!(tokens.families.get("old").is_none());
+
+        let first = tokens.issue("foo", 60, &auth, &runtime).await?;
+        let second = tokens.issue("foo", 60, &auth, &runtime).await?;
+        let other = tokens.issue("bar", 60, &auth, &runtime).await?;
+        tokens.revoke(&first, &auth, &runtime).await?;
+        assert!(tokens.rotate(&first, 60, &auth, &runtime).await.is_err());
+        assert!(tokens.rotate(&second, 60, &auth, &runtime).await.is_ok());
+
+        tokens.revoke_user("foo", &auth, &runtime).await?;
+        assert!(tokens.families.iter().all(|family| family.sub == "bar"));
+        assert!(tokens.rotate(&other, 60, &auth, &runtime).await.is_ok());
+        Ok(())
+    }
+}
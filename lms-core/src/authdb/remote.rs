@@ -0,0 +1,488 @@
+//! Protocol of a remote AuthDB, used when `authDbPath` is a url.
+//!
+//! Every call is a POST of a [SignedRequest] to `authDbPath` answered with a [RemoteResponse].
+//! The payload is a [RemoteRequest] serialized as json and signed with [AuthProvider::gen_sig],
+//! so both ends need the same `aesKey` and `totpSecret` but the key itself is never sent.
+//! A request is rejected if its timestamp is more than [MAX_SKEW] ms away from the clock of the
+//! remote, or if its nonce was already seen.
+//!
+//! Errors are reported in [RemoteResponse::error] with a 200 status, so they reach the client
+//! whatever its HTTP stack does with error statuses.
+//!
+//! The client starts with its newest version, a remote that doesn't speak it answers with
+//! [ErrorKind::UnsupportedVersion] along with its versions and the client retries with the
+//! newest common one.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use dashmap::DashMap;
+use http_body_util::Full;
+use reqwest::{Body, Method, Request};
+use serde::{Deserialize, Serialize};
+
+use lms_auth::auth::AuthProvider;
+
+use crate::authdb::auth_actors::{User, Users};
+use crate::authdb::auth_store::{AuthStore, LocalAuthStore};
+use crate::authdb::store;
+use crate::authdb::two_factor::random_code;
+use crate::runtime::TargetRuntime;
+
+/// Versions of the protocol spoken by this build, oldest first.
+pub const VERSIONS: &[u32] = &[1];
+/// ms a request timestamp may differ from the clock of the remote.
+pub const MAX_SKEW: u128 = 5 * 60 * 1000;
+/// First input of [AuthProvider::gen_sig], the payload is the second.
+const SIG_CONTEXT: &str = "lms-auth-db";
+const NONCE_LEN: usize = 24;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "operation", rename_all = "snake_case")]
+pub enum Operation {
+    GetUser {
+        username: String,
+    },
+    /// Inserts the user or replaces the user with the same username
+    UpsertUser {
+        user: User,
+    },
+    DeleteUser {
+        username: String,
+    },
+    ListUsers,
+    /// Session data stored next to the users, see [store::fetch]
+    GetStore {
+        name: String,
+    },
+    PutStore {
+        name: String,
+        data: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RemoteRequest {
+    pub version: u32,
+    /// ms since epoch
+    pub timestamp: u128,
+    /// Random, single use
+    pub nonce: String,
+    #[serde(flatten)]
+    pub operation: Operation,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SignedRequest {
+    /// [RemoteRequest] as json
+    pub payload: String,
+    pub signature: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum Reply {
+    Done,
+    User(Option<User>),
+    Users(Users),
+    /// The stored json, if any
+    Store(Option<String>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    UnsupportedVersion,
+    /// Invalid signature, stale timestamp or replayed nonce
+    Unauthorized,
+    BadRequest,
+    Internal,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RemoteError {
+    pub kind: ErrorKind,
+    pub message: String,
+    /// Versions spoken by the remote, set along with [ErrorKind::UnsupportedVersion]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub versions: Vec<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RemoteResponse {
+    /// Version the request was handled with
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply: Option<Reply>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RemoteError>,
+}
+
+impl RemoteError {
+    fn new<T: Into<String>>(kind: ErrorKind, message: T) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            versions: vec![],
+        }
+    }
+}
+
+impl RemoteResponse {
+    fn reply(version: u32, reply: Reply) -> Self {
+        Self {
+            version,
+            reply: Some(reply),
+            error: None,
+        }
+    }
+
+    fn error(version: u32, error: RemoteError) -> Self {
+        Self {
+            version,
+            reply: None,
+            error: Some(error),
+        }
+    }
+
+    pub fn into_hyper_response(self) -> Result<hyper::Response<Full<bytes::Bytes>>> {
+        let body = serde_json::to_string(&self)?;
+        let response = hyper::Response::builder()
+            .status(200)
+            .header("Content-Type", "application/json")
+            .body(Full::new(bytes::Bytes::from(body)))?;
+        Ok(response)
+    }
+}
+
+/// Client of a remote AuthDB at `authDbPath`.
+pub struct RemoteClient {
+    auth: AuthProvider,
+    runtime: TargetRuntime,
+    /// Version to speak, lowered when the remote doesn't support it
+    version: AtomicU32,
+}
+
+impl RemoteClient {
+    pub fn new(auth: AuthProvider, runtime: TargetRuntime) -> Self {
+        Self {
+            auth,
+            runtime,
+            version: AtomicU32::new(*VERSIONS.last().unwrap()),
+        }
+    }
+
+    pub async fn call(&self, operation: Operation) -> Result<Reply> {
+        let mut response = self.send(&operation).await?;
+        if let Some(error) = response
+            .error
+            .as_ref()
+            .filter(|e| e.kind == ErrorKind::UnsupportedVersion)
+        {
+            let version = VERSIONS
+                .iter()
+                .rev()
+                .find(|v| error.versions.contains(v))
+                .ok_or_else(|| {
+                    anyhow!(
+                        "AuthDB speaks versions {:?}, supported versions are {:?}",
+                        error.versions,
+                        VERSIONS
+                    )
+                })?;
+            self.version.store(*version, Ordering::Relaxed);
+            response = self.send(&operation).await?;
+        }
+        match response {
+            RemoteResponse {
+                reply: Some(reply),
+                error: None,
+                ..
+            } => Ok(reply),
+            RemoteResponse {
+                error: Some(error), ..
+            } => Err(anyhow!("AuthDB error: {}", error.message)),
+            _ => Err(anyhow!("Invalid response from AuthDB")),
+        }
+    }
+
+    async fn send(&self, operation: &Operation) -> Result<RemoteResponse> {
+        let now = self.runtime.instance.now()?;
+        let request = RemoteRequest {
+            version: self.version.load(Ordering::Relaxed),
+            timestamp: now,
+            nonce: random_code(NONCE_LEN)?,
+            operation: operation.clone(),
+        };
+        let payload = serde_json::to_string(&request)?;
+        let signed = SignedRequest {
+            signature: self.auth.gen_sig(SIG_CONTEXT, &payload, now)?,
+            payload,
+        };
+
+        let url = url::Url::parse(self.auth.db_path())?;
+        let mut req = Request::new(Method::POST, url);
+        *req.body_mut() = Some(Body::from(serde_json::to_string(&signed)?));
+        let response = self.runtime.http.execute(req).await?;
+        serde_json::from_slice(&response.body).map_err(|_| anyhow!("Invalid response from AuthDB"))
+    }
+}
+
+/// Serves the protocol from a local AuthDB, used by the `lms-auth-server` reference server.
+pub struct RemoteServer {
+    auth: AuthProvider,
+    runtime: TargetRuntime,
+    store: Arc<dyn AuthStore>,
+    /// Nonces seen along with their timestamp, dropped once older than [MAX_SKEW]
+    nonces: DashMap<String, u128>,
+}
+
+impl RemoteServer {
+    /// `auth` points to the local file the users are stored in.
+    pub fn new(auth: AuthProvider, runtime: TargetRuntime) -> Result<Self> {
+        if auth.db_path().starts_with("http") {
+            return Err(anyhow!("The AuthDB served must be local"));
+        }
+        Ok(Self {
+            store: Arc::new(LocalAuthStore::new(auth.clone(), runtime.clone())),
+            auth,
+            runtime,
+            nonces: DashMap::new(),
+        })
+    }
+
+    pub async fn handle(&self, body: &[u8]) -> RemoteResponse {
+        let latest = *VERSIONS.last().unwrap();
+        let request = match self.verify(body) {
+            Ok(request) => request,
+            Err(error) => return RemoteResponse::error(latest, error),
+        };
+        if !VERSIONS.contains(&request.version) {
+            let error = RemoteError {
+                versions: VERSIONS.to_vec(),
+                ..RemoteError::new(
+                    ErrorKind::UnsupportedVersion,
+                    format!("Unsupported version {}", request.version),
+                )
+            };
+            return RemoteResponse::error(latest, error);
+        }
+        match self.execute(request.operation).await {
+            Ok(reply) => RemoteResponse::reply(request.version, reply),
+            Err(e) => RemoteResponse::error(
+                request.version,
+                RemoteError::new(ErrorKind::Internal, e.to_string()),
+            ),
+        }
+    }
+
+    fn verify(&self, body: &[u8]) -> std::result::Result<RemoteRequest, RemoteError> {
+        let unauthorized = |message: &str| RemoteError::new(ErrorKind::Unauthorized, message);
+        let signed: SignedRequest = serde_json::from_slice(body)
+            .map_err(|_| RemoteError::new(ErrorKind::BadRequest, "Unable to parse request"))?;
+        let request: RemoteRequest = serde_json::from_str(&signed.payload)
+            .map_err(|_| RemoteError::new(ErrorKind::BadRequest, "Unable to parse payload"))?;
+        let now = self
+            .runtime
+            .instance
+            .now()
+            .map_err(|e| RemoteError::new(ErrorKind::Internal, e.to_string()))?;
+        if request.timestamp.abs_diff(now) > MAX_SKEW {
+            return Err(unauthorized("Request expired"));
+        }
+        if !self.auth.verify_sig(
+            SIG_CONTEXT,
+            &signed.payload,
+            &signed.signature,
+            request.timestamp,
+        ) {
+            return Err(unauthorized("Invalid signature"));
+        }
+        self.nonces.retain(|_, seen| seen.abs_diff(now) <= MAX_SKEW);
+        if self
+            .nonces
+            .insert(request.nonce.clone(), request.timestamp)
+            .is_some()
+        {
+            return Err(unauthorized("Replayed request"));
+        }
+        Ok(request)
+    }
+
+    async fn execute(&self, operation: Operation) -> Result<Reply> {
+        match operation {
+            Operation::GetUser { username } => Ok(Reply::User(self.store.get(&username).await?)),
+            Operation::UpsertUser { user } => {
+                self.store.upsert(user).await?;
+                Ok(Reply::Done)
+            }
+            Operation::DeleteUser { username } => {
+                self.store.delete(&username).await?;
+                Ok(Reply::Done)
+            }
+            Operation::ListUsers => Ok(Reply::Users(self.store.list().await?)),
+            Operation::GetStore { name } => {
+                validate_store(&name)?;
+                let data: serde_json::Value =
+                    store::fetch(&name, &self.auth, &self.runtime).await?;
+                Ok(Reply::Store((!data.is_null()).then(|| data.to_string())))
+            }
+            Operation::PutStore { name, data } => {
+                validate_store(&name)?;
+                serde_json::from_str::<serde_json::Value>(&data)
+                    .map_err(|_| anyhow!("Store {} must be json", name))?;
+                store::put(&name, data, &self.auth, &self.runtime).await?;
+                Ok(Reply::Done)
+            }
+        }
+    }
+}
+
+/// Store names end up in file names, see [store::fetch].
+fn validate_store(name: &str) -> Result<()> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_lowercase() || c == '_') {
+        return Err(anyhow!("Invalid store {}", name));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::authdb::auth_actors::Authority;
+    use crate::http::response::Response;
+    use crate::HttpIO;
+    use totp_rs::{Algorithm, Secret, TOTP};
+
+    fn auth(db_path: &str, totp_secret: &str) -> AuthProvider {
+        let totp = TOTP::new(
+            Algorithm::SHA1,
+            6,
+            1,
+            30,
+            Secret::Raw(totp_secret.as_bytes().to_vec())
+                .to_bytes()
+                .unwrap(),
+        )
+        .unwrap();
+        AuthProvider::init(db_path.to_string(), totp, "aes key".to_string()).unwrap()
+    }
+
+    /// Routes the requests of a client to a server in the same process.
+    struct Loopback(Arc<RemoteServer>);
+
+    #[async_trait::async_trait]
+    impl HttpIO for Loopback {
+        async fn execute(&self, request: reqwest::Request) -> Result<Response<bytes::Bytes>> {
+            let body = request
+                .body()
+                .and_then(|b| b.as_bytes())
+                .unwrap_or_default();
+            let response = self.0.handle(body).await;
+            Ok(Response {
+                status: reqwest::StatusCode::OK,
+                headers: Default::default(),
+                body: serde_json::to_vec(&response)?.into(),
+            })
+        }
+    }
+
+    fn client(server: Arc<RemoteServer>, totp_secret: &str) -> RemoteClient {
+        let mut runtime = crate::runtime::tests::init();
+        runtime.http = Arc::new(Loopback(server));
+        RemoteClient::new(auth("http://localhost/auth", totp_secret), runtime)
+    }
+
+    fn user(username: &str) -> User {
+        User {
+            username: username.to_string(),
+            name: username.to_string(),
+            password: "password".to_string(),
+            authority: Authority::Admin,
+            batch: None,
+            two_factor: None,
+            password_reset: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() -> Result<()> {
+        let runtime = crate::runtime::tests::init();
+        let server = Arc::new(RemoteServer::new(
+            auth("auth", "JBSWY3DPEHPK3PXP"),
+            runtime,
+        )?);
+        let client = client(server, "JBSWY3DPEHPK3PXP");
+
+        let upsert = Operation::UpsertUser { user: user("foo") };
+        assert_eq!(client.call(upsert).await?, Reply::Done);
+        let get = Operation::GetUser {
+            username: "foo".to_string(),
+        };
+        assert_eq!(client.call(get).await?, Reply::User(Some(user("foo"))));
+
+        let put = Operation::PutStore {
+            name: "sessions".to_string(),
+            data: r#"{"a":1}"#.to_string(),
+        };
+        client.call(put).await?;
+        let get = Operation::GetStore {
+            name: "sessions".to_string(),
+        };
+        let store = Some(r#"{"a":1}"#.to_string());
+        assert_eq!(client.call(get).await?, Reply::Store(store));
+        let traversal = Operation::GetStore {
+            name: "../sessions".to_string(),
+        };
+        assert!(client.call(traversal).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rejected_requests() -> Result<()> {
+        let runtime = crate::runtime::tests::init();
+        let server = Arc::new(RemoteServer::new(
+            auth("auth", "JBSWY3DPEHPK3PXP"),
+            runtime,
+        )?);
+
+        // signed with another key
+        let err = client(server.clone(), "KRSXG5CTMVRXEZLU")
+            .call(Operation::ListUsers)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "AuthDB error: Invalid signature");
+
+        let client = client(server.clone(), "JBSWY3DPEHPK3PXP");
+        let now = client.runtime.instance.now()?;
+        let request = |timestamp: u128, version: u32, nonce: &str| -> Result<Vec<u8>> {
+            let payload = serde_json::to_string(&RemoteRequest {
+                version,
+                timestamp,
+                nonce: nonce.to_string(),
+                operation: Operation::ListUsers,
+            })?;
+            let signed = SignedRequest {
+                signature: client.auth.gen_sig(SIG_CONTEXT, &payload, timestamp)?,
+                payload,
+            };
+            Ok(serde_json::to_vec(&signed)?)
+        };
+
+        let stale = server
+            .handle(&request(now - MAX_SKEW - 1000, 1, "a")?)
+            .await;
+        assert_eq!(stale.error.unwrap().message, "Request expired");
+
+        let error = server.handle(&request(now, 2, "b")?).await.error.unwrap();
+        assert_eq!(error.kind, ErrorKind::UnsupportedVersion);
+        assert_eq!(error.versions, VERSIONS.to_vec());
+
+        let body = request(now, 1, "c")?;
+        assert!(server.handle(&body).await.error.is_none());
+        let replayed = server.handle(&body).await;
+        assert_eq!(replayed.error.unwrap().message, "Replayed request");
+        Ok(())
+    }
+}
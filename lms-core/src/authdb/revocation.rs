@@ -0,0 +1,128 @@
+use anyhow::{anyhow, Result};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use lms_auth::auth::AuthProvider;
+use lms_auth::session::Claims;
+
+use crate::authdb::store;
+use crate::runtime::TargetRuntime;
+
+const STORE: &str = "revocations";
+
+/// Sessions revoked before they expired.
+/// Stored next to the users, see [store::fetch].
+#[derive(Default, Debug, Serialize, Deserialize)]
+pub struct Revocations {
+    /// Ids of the revoked tokens along with their expiry, so they can be dropped once expired
+    tokens: DashMap<String, u128>,
+    /// Tokens of the user issued at or before this time are revoked
+    users: DashMap<String, u128>,
+    #[serde(skip)]
+    lock: Mutex<()>,
+}
+
+impl Revocations {
+    pub async fn fetch(auth: &AuthProvider, runtime: &TargetRuntime) -> Result<Self> {
+        store::fetch(STORE, auth, runtime).await
+    }
+
+    pub fn is_revoked(&self, claims: &Claims) -> bool {
+        self.tokens.contains_key(&claims.jti)
+            || self
+                .users
+                .get(&claims.sub)
+                .is_some_and(|cutoff| claims.iat <= *cutoff)
+    }
+
+    /// Revokes a single token, used on logout.
+    pub async fn revoke_token(
+        &self,
+        claims: &Claims,
+        auth: &AuthProvider,
+        runtime: &TargetRuntime,
+    ) -> Result<()> {
+        self.tokens.insert(claims.jti.clone(), claims.exp);
+        self.persist(auth, runtime).await
+    }
+
+    /// Revokes every token of the user issued until `now`.
+    pub async fn revoke_user(
+        &self,
+        username: &str,
+        auth: &AuthProvider,
+        runtime: &TargetRuntime,
+    ) -> Result<()> {
+        let now = runtime.instance.now()?;
+        self.users.insert(username.to_string(), now);
+        self.persist(auth, runtime).await
+    }
+
+    async fn persist(&self, auth: &AuthProvider, runtime: &TargetRuntime) -> Result<()> {
+        // serialize the writes, so a slower write can't overwrite a newer list
+        let _guard = self.lock.lock().await;
+        let now = runtime.instance.now()?;
+        self.tokens.retain(|_, exp| *exp > now);
+
+        let revocations =
+            serde_json::to_string(self).map_err(|_| anyhow!("Unable to serialize revocations"))?;
+        store::put(STORE, revocations, auth, runtime).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use totp_rs::{Algorithm, Secret, TOTP};
+
+    fn auth() -> AuthProvider {
+        let totp = TOTP::new(
+            Algorithm::SHA1,
+            6,
+            1,
+            30,
+            Secret::Raw("JBSWY3DPEHPK3PXP".as_bytes().to_vec())
+                .to_bytes()
+                .unwrap(),
+        )
+        .unwrap();
+        AuthProvider::init("auth".to_string(), totp, "aes key".to_string()).unwrap()
+    }
+
+    fn claims(sub: &str, jti: &str, iat: u128, exp: u128) -> Claims {
+        Claims {
+            sub: sub.to_string(),
+            authority: 2,
+            batch: None,
+            iat,
+            exp,
+            jti: jti.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_revocations_persisted() -> Result<()> {
+        let runtime = crate::runtime::tests::init();
+        let auth = auth();
+        let now = runtime.instance.now()?;
+
+        let revocations = Revocations::fetch(&auth, &runtime).await?;
+        let logged_out = claims("foo", "a", now, now + 1000);
+        let expired = claims("foo", "b", 0, 1);
+        revocations.revoke_token(&expired, &auth, &runtime).await?;
+        revocations
+            .revoke_token(&logged_out, &auth, &runtime)
+            .await?;
+        revocations.revoke_user("bar", &auth, &runtime).await?;
+
+        let revocations = Revocations::fetch(&auth, &runtime).await?;
+        assert!(revocations.is_revoked(&logged_out));
+        assert!(!revocations.is_revoked(&claims("foo", "c", now, now + 1000)));
+        assert!(revocations.is_revoked(&claims("bar", "d", now, now + 1000)));
+        assert!(!revocations.is_revoked(&claims("bar", "e", now + 60_000, now + 100_000)));
+        // expired tokens are dropped from the list
+        assert!(!revocations.tokens.contains_key("b"));
+        Ok(())
+    }
+}
@@ -0,0 +1,63 @@
+use anyhow::{anyhow, Result};
+use serde::de::DeserializeOwned;
+
+use lms_auth::auth::AuthProvider;
+
+use crate::authdb::remote::{Operation, RemoteClient, Reply};
+use crate::runtime::TargetRuntime;
+use crate::NotFound;
+
+/// Reads the session data stored next to the users,
+/// in `{authDbPath}.{name}` (next to the database for a `sqlite://` path)
+/// or through the `get_store` operation of the remote AuthDB. Missing data is empty, any
+/// other failure is returned so that sessions aren't dropped over a read error.
+pub async fn fetch<T: DeserializeOwned + Default>(
+    name: &str,
+    auth: &AuthProvider,
+    runtime: &TargetRuntime,
+) -> Result<T> {
+    if auth.db_path().starts_with("http") {
+        let client = RemoteClient::new(auth.clone(), runtime.clone());
+        let name = name.to_string();
+        match client.call(Operation::GetStore { name }).await? {
+            Reply::Store(Some(data)) => Ok(serde_json::from_str(&data)?),
+            Reply::Store(None) => Ok(T::default()),
+            _ => Err(anyhow!("Invalid response from AuthDB")),
+        }
+    } else {
+        match runtime.file.read(&path(name, auth)).await {
+            Ok(encrypted) => Ok(serde_json::from_str(&auth.decrypt_aes(encrypted)?)?),
+            Err(err) if err.is::<NotFound>() => Ok(T::default()),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Writes the serialized data, see [fetch].
+pub async fn put(
+    name: &str,
+    data: String,
+    auth: &AuthProvider,
+    runtime: &TargetRuntime,
+) -> Result<()> {
+    if auth.db_path().starts_with("http") {
+        let client = RemoteClient::new(auth.clone(), runtime.clone());
+        let name = name.to_string();
+        client.call(Operation::PutStore { name, data }).await?;
+    } else {
+        runtime
+            .file
+            .write(&path(name, auth), auth.encrypt_aes(data)?.as_bytes())
+            .await?;
+    }
+    Ok(())
+}
+
+fn path(name: &str, auth: &AuthProvider) -> String {
+    let db_path = auth.db_path();
+    format!(
+        "{}.{}",
+        crate::sqlite_path(db_path).unwrap_or(db_path),
+        name
+    )
+}
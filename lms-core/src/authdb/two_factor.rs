@@ -0,0 +1,172 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use totp_rs::{Algorithm, TOTP};
+
+use lms_auth::local_crypto::hash_256;
+
+const DIGITS: usize = 6;
+const STEP: u64 = 30;
+const RECOVERY_CODES: usize = 10;
+const RECOVERY_CODE_LEN: usize = 10;
+const CODE_CHARS: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+/// Second factor of a user, a TOTP secret along with single use recovery codes.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct TwoFactor {
+    /// Raw TOTP secret
+    secret: Vec<u8>,
+    /// Set once the user proves they hold the secret, see [TwoFactor::confirm]
+    enabled: bool,
+    /// sha256 of the unused recovery codes
+    #[serde(default)]
+    recovery_codes: Vec<String>,
+    /// Last accepted TOTP step, so a code can't be replayed
+    #[serde(default)]
+    last_step: u64,
+}
+
+impl TwoFactor {
+    /// A new secret, disabled until confirmed.
+    pub fn generate() -> Result<Self> {
+        let mut secret = vec![0u8; 20];
+        getrandom::getrandom(&mut secret).map_err(|_| anyhow!("Unable to generate secret"))?;
+        Ok(Self {
+            secret,
+            enabled: false,
+            recovery_codes: vec![],
+            last_step: 0,
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// `otpauth://` URI to be scanned by an authenticator app.
+    pub fn uri(&self, issuer: &str, username: &str) -> Result<String> {
+        let secret = self.totp()?.get_secret_base32();
+        let encode = |s: &str| {
+            url::form_urlencoded::byte_serialize(s.as_bytes())
+                .collect::<String>()
+                .replace('+', "%20")
+        };
+        Ok(format!(
+            "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+            encode(issuer),
+            encode(username),
+            secret,
+            encode(issuer),
+            DIGITS,
+            STEP
+        ))
+    }
+
+    /// Enables the second factor if the code is valid and returns the recovery codes.
+    pub fn confirm(&mut self, code: &str, now: u128) -> Result<Vec<String>> {
+        if self.enabled {
+            return Err(anyhow!("Two-factor authentication is already enabled"));
+        }
+        if !self.verify_totp(code, now)? {
+            return Err(anyhow!("Invalid OTP"));
+        }
+        let codes = (0..RECOVERY_CODES)
+            .map(|_| random_code(RECOVERY_CODE_LEN))
+            .collect::<Result<Vec<_>>>()?;
+        self.recovery_codes = codes.iter().map(hash_256).collect();
+        self.enabled = true;
+        Ok(codes)
+    }
+
+    /// Checks a TOTP code or consumes a recovery code.
+    /// The caller must persist the user afterwards, as the state changes either way.
+    pub fn verify(&mut self, code: &str, now: u128) -> Result<bool> {
+        if self.verify_totp(code, now)? {
+            return Ok(true);
+        }
+        let hashed = hash_256(code.trim().to_ascii_uppercase());
+        let before = self.recovery_codes.len();
+        self.recovery_codes.retain(|c| *c != hashed);
+        Ok(self.recovery_codes.len() < before)
+    }
+
+    fn verify_totp(&mut self, code: &str, now: u128) -> Result<bool> {
+        let totp = self.totp()?;
+        let current = (now / 1000) as u64 / STEP;
+        // allow one step of clock drift on either side
+        for step in current.saturating_sub(1)..=current + 1 {
+            if step > self.last_step && totp.generate(step * STEP) == code.trim() {
+                self.last_step = step;
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn totp(&self) -> Result<TOTP> {
+        Ok(TOTP::new(
+            Algorithm::SHA1,
+            DIGITS,
+            0,
+            STEP,
+            self.secret.clone(),
+        )?)
+    }
+}
+
+/// Random code of unambiguous uppercase letters and digits, easy to type in.
+pub fn random_code(len: usize) -> Result<String> {
+    let mut bytes = vec![0u8; len];
+    getrandom::getrandom(&mut bytes).map_err(|_| anyhow!("Unable to generate code"))?;
+    Ok(bytes
+        .iter()
+        .map(|b| CODE_CHARS[*b as usize % CODE_CHARS.len()] as char)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u128 = 1_700_000_000_000;
+
+    fn code(two_factor: &TwoFactor, now: u128) -> String {
+        two_factor.totp().unwrap().generate((now / 1000) as u64)
+    }
+
+    #[test]
+    fn test_confirm_and_verify() -> Result<()> {
+        let mut two_factor = TwoFactor {
+            // secret of the RFC 6238 test vectors
+            secret: b"12345678901234567890".to_vec(),
+            ..TwoFactor::generate()?
+        };
+        assert_eq!(code(&two_factor, 59_000), "287082");
+        let stale = code(&two_factor, NOW - 10 * STEP as u128 * 1000);
+        assert!(two_factor.confirm(&stale, NOW).is_err());
+        assert!(!two_factor.is_enabled());
+
+        let codes = two_factor.confirm(&code(&two_factor, NOW), NOW)?;
+        assert!(two_factor.is_enabled());
+        assert_eq!(codes.len(), RECOVERY_CODES);
+
+        // replaying the code used for confirmation fails
+        assert!(!two_factor.verify(&code(&two_factor, NOW), NOW)?);
+        let later = NOW + STEP as u128 * 1000;
+        assert!(two_factor.verify(&code(&two_factor, later), later)?);
+
+        // recovery codes are single use
+        assert!(two_factor.verify(&codes[0].to_lowercase(), later)?);
+        assert!(!two_factor.verify(&codes[0], later)?);
+        assert_eq!(two_factor.recovery_codes.len(), RECOVERY_CODES - 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_uri() -> Result<()> {
+        let two_factor = TwoFactor::generate()?;
+        let uri = two_factor.uri("lms", "foo bar")?;
+        assert!(uri.starts_with("otpauth://totp/lms:foo%20bar?secret="));
+        assert!(uri.ends_with("&issuer=lms&algorithm=SHA1&digits=6&period=30"));
+        Ok(())
+    }
+}
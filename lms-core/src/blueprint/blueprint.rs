@@ -1,12 +1,18 @@
+use std::collections::BTreeMap;
 use std::net::IpAddr;
+use std::sync::Arc;
 
 use anyhow::anyhow;
 use totp_rs::{Algorithm, Secret, TOTP};
 
 use lms_auth::auth::AuthProvider;
 
+use crate::authdb::attempts::{AttemptStore, InMemoryAttempts, LoginLimits};
 use crate::authdb::auth_actors::{Authority, Users};
+use crate::authdb::refresh::RefreshTokens;
+use crate::authdb::revocation::Revocations;
 use crate::config;
+use crate::config::batch_info::BatchInfo;
 use crate::config::config_module::ConfigModule;
 
 #[derive(Debug, Clone)]
@@ -14,12 +20,22 @@ pub struct Blueprint {
     pub server: Server,
     pub extensions: Extensions,
     pub batch_info: Vec<String>,
+    /// Courses of each batch, keyed by batch id
+    pub batch_courses: BTreeMap<String, Vec<String>>,
+    /// Authorities which must have two-factor authentication enabled to login
+    pub mandatory_two_factor: Vec<Authority>,
+    pub login_limits: LoginLimits,
 }
 
 #[derive(Debug, Clone)]
 pub struct Extensions {
     pub users: Users,
     pub auth: AuthProvider,
+    /// Shared by every clone of the blueprint
+    pub revocations: Arc<Revocations>,
+    pub refresh_tokens: Arc<RefreshTokens>,
+    /// Failed login attempts, in memory unless `auth.loginLimits.attemptsPath` is set
+    pub attempts: Arc<dyn AttemptStore>,
 }
 
 #[derive(Debug, Clone)]
@@ -27,7 +43,19 @@ pub struct Server {
     pub port: u16,
     pub hostname: IpAddr,
     pub totp: TOTP,
+    /// Step of the server totp in seconds
+    pub request_timeout: u64,
+    /// Lifetime of an access token in seconds
+    pub access_token_ttl: u64,
+    /// Lifetime of a refresh token in seconds
+    pub refresh_token_ttl: u64,
+    /// Lifetime of a password reset code in seconds
+    pub password_reset_ttl: u64,
+    /// Size cap of a streamed upload in bytes
+    pub max_upload_size: u64,
     pub file_db: String,
+    /// Encrypt the files of new contents, see [crate::file_db::encryption]
+    pub encrypt_files: bool,
     pub actions_db: String,
 }
 
@@ -43,6 +71,7 @@ impl TryFrom<config::Server> for Server {
         }?;
 
         let port = server.port.unwrap_or(19194);
+        let request_timeout = server.request_timeout.unwrap_or(86400);
 
         Ok(Server {
             port,
@@ -51,10 +80,16 @@ impl TryFrom<config::Server> for Server {
                 Algorithm::SHA1,
                 8,
                 1,
-                server.request_timeout.unwrap_or(86400),
+                request_timeout,
                 Secret::Raw(server.timeout_key.unwrap().as_bytes().to_vec()).to_bytes()?,
             )?,
+            request_timeout,
+            access_token_ttl: server.access_token_ttl.unwrap_or(15 * 60),
+            refresh_token_ttl: server.refresh_token_ttl.unwrap_or(30 * 24 * 60 * 60),
+            password_reset_ttl: server.password_reset_ttl.unwrap_or(24 * 60 * 60),
+            max_upload_size: server.max_upload_size.unwrap_or(100 * 1024 * 1024),
             file_db: server.file_db,
+            encrypt_files: server.encrypt_files.unwrap_or_default(),
             actions_db: server.actions_db,
         })
     }
@@ -71,6 +106,11 @@ impl TryFrom<config::config_module::Extensions> for Extensions {
             auth: ext
                 .auth
                 .ok_or_else(|| anyhow!("Auth Provider not found in config"))?,
+            revocations: ext.revocations.unwrap_or_default(),
+            refresh_tokens: ext.refresh_tokens.unwrap_or_default(),
+            attempts: ext
+                .attempts
+                .unwrap_or_else(|| Arc::new(InMemoryAttempts::default())),
         })
     }
 }
@@ -81,6 +121,13 @@ impl TryFrom<ConfigModule> for Blueprint {
     fn try_from(mut config_module: ConfigModule) -> Result<Self, Self::Error> {
         let cfg = config_module.clone();
         let batch_info = config_module.batches.iter().map(|v| v.id.clone()).collect();
+        let batch_courses = config_module
+            .batches
+            .iter()
+            .map(|v| (v.id.clone(), v.courses.clone()))
+            .collect();
+        let mandatory_two_factor = config_module.auth.mandatory_two_factor.clone();
+        let login_limits = LoginLimits::from(&config_module.auth.login_limits);
 
         config_module.config.server.timeout_key =
             Some(config_module.config.server.timeout_key.unwrap_or(format!(
@@ -95,6 +142,9 @@ impl TryFrom<ConfigModule> for Blueprint {
         Ok(Self {
             server,
             batch_info,
+            batch_courses,
+            mandatory_two_factor,
+            login_limits,
             extensions: Extensions::try_from(config_module.extensions)?,
         })
     }
@@ -135,6 +185,20 @@ fn validate_config(
         return Err(anyhow!("FileDB dir is required"));
     }
 
+    #[cfg(not(feature = "sqlite"))]
+    for path in [
+        &config.auth.auth_db_path,
+        &config.server.actions_db,
+        &config.server.file_db,
+    ] {
+        if crate::sqlite_path(path).is_some() {
+            return Err(anyhow!(
+                "{} needs lms built with the `sqlite` feature",
+                path
+            ));
+        }
+    }
+
     if config.auth.aes_key.is_empty() || config.auth.aes_key.len() < 8 {
         return Err(anyhow::anyhow!(
             "aes_key is required and must be 8 characters long"
@@ -147,12 +211,16 @@ fn validate_config(
     }
     let batches = &config.batches;
     for batch in batches {
+        if batch.id.is_empty() {
+            return Err(anyhow::anyhow!("Batch ids can't be empty"));
+        }
         for course in batch.courses.iter() {
             if !config.courses.contains_key(course) {
                 return Err(anyhow::anyhow!("Course {} not found in courses", course));
             }
         }
     }
+    validate_group_ids(batches)?;
 
     if let Some(users) = config.extensions.users.as_ref() {
         for user in users.get_all().values() {
@@ -174,13 +242,37 @@ fn validate_config(
     Ok(())
 }
 
+/// Group ids are `{batch}_{course}`, every one of them has to name a single batch.
+fn validate_group_ids(batches: &[BatchInfo]) -> anyhow::Result<()> {
+    let mut groups = BTreeMap::new();
+    for batch in batches {
+        for course in batch.courses.iter() {
+            let group_id = format!("{}_{}", batch.id, course);
+            match groups.insert(group_id, &batch.id) {
+                Some(other) if *other != batch.id => {
+                    return Err(anyhow!(
+                        "Batches {} and {} both have the group {}_{}, rename one of them",
+                        other,
+                        batch.id,
+                        batch.id,
+                        course
+                    ));
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use lms_auth::auth::AuthProvider;
     use totp_rs::{Algorithm, Secret, TOTP};
 
     use crate::authdb::auth_actors::Users;
-    use crate::blueprint::blueprint::validate_config;
+    use crate::blueprint::blueprint::{validate_config, validate_group_ids};
+    use crate::config::batch_info::BatchInfo;
     use crate::config::config_module::{ConfigModule, Extensions};
 
     #[test]
@@ -197,12 +289,18 @@ mod tests {
             extensions: Extensions {
                 users: Some(Users::default()),
                 auth: None,
+                revocations: None,
+                refresh_tokens: None,
+                attempts: None,
             },
             ..Default::default()
         };
         config_module.extensions = Extensions {
             users: Some(Users::default()),
             auth: None,
+            revocations: None,
+            refresh_tokens: None,
+            attempts: None,
         };
 
         let result = validate_config(config_module, None);
@@ -244,4 +342,18 @@ mod tests {
             "totp_key is required and must be at least 8 bytes long"
         );
     }
+
+    #[test]
+    fn test_validate_group_ids() {
+        let batch = |id: &str, course: &str| BatchInfo {
+            id: id.to_string(),
+            courses: vec![course.to_string()],
+        };
+        assert!(validate_group_ids(&[batch("22_BCS", "course"), batch("23BCS", "a_b")]).is_ok());
+        let err = validate_group_ids(&[batch("22", "BCS_course"), batch("22_BCS", "course")]);
+        assert_eq!(
+            err.unwrap_err().to_string(),
+            "Batches 22 and 22_BCS both have the group 22_BCS_course, rename one of them"
+        );
+    }
 }
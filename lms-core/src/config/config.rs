@@ -1,3 +1,4 @@
+use crate::authdb::auth_actors::Authority;
 use crate::config::batch_info::BatchInfo;
 use crate::config::course_info::CourseInfo;
 use crate::config::hash_algo::Algorithm;
@@ -45,7 +46,27 @@ pub struct Server {
     pub request_timeout: Option<u64>,
     #[serde(default, skip_serializing_if = "is_default")]
     pub timeout_key: Option<String>,
+    /// Lifetime of an access token in seconds, defaults to 15 minutes
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub access_token_ttl: Option<u64>,
+    /// Lifetime of a refresh token in seconds, extended on every refresh, defaults to 30 days
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub refresh_token_ttl: Option<u64>,
+    /// Seconds for which a reset code issued by an admin can be used, defaults to a day
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub password_reset_ttl: Option<u64>,
+    /// Bytes a file streamed to `/fs/files` can have, defaults to 100 MiB
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub max_upload_size: Option<u64>,
+    /// Dir of the FileDB, a `sqlite://` path with the `sqlite` feature, or the base url of a
+    /// remote FileDB such as `lms-file-server`
     pub file_db: String,
+    /// Encrypts the files of new contents with a key of their own, wrapped by the active key
+    /// of `auth`. Run `lms encrypt-files` to encrypt the contents stored before
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub encrypt_files: Option<bool>,
+    /// File of the ActionsDB, changes are logged to `{actionsDb}.log` until they are compacted
+    /// into it. A url or a `sqlite://` path with the `sqlite` feature
     pub actions_db: String,
 }
 
@@ -58,9 +79,58 @@ impl Server {
 #[derive(Default, Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct AuthInfo {
+    /// Encrypted file of the users, a url or a `sqlite://` path with the `sqlite` feature
     pub auth_db_path: String,
     pub totp: TotpSettings,
     pub aes_key: String,
+    /// Id of the active key, ciphertexts and tokens are tagged with it
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub key_id: Option<String>,
+    /// Keys replaced by the active key, still used to read data written with them
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub retired_keys: Vec<RetiredKey>,
+    /// Seconds for which tokens signed with a retired key keep working, defaults to a day
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub key_grace_period: Option<u64>,
+    /// Authorities which can't login without a second factor, e.g. `["Admin", "Faculty"]`
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub mandatory_two_factor: Vec<Authority>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub login_limits: LoginLimitSettings,
+}
+
+/// Throttling of failed logins, counted per username and per client ip.
+#[derive(Default, Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct LoginLimitSettings {
+    /// Failed attempts of a username before it's locked out, defaults to 5
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub max_attempts: Option<u32>,
+    /// Failed attempts from a client ip before it's locked out, defaults to 50
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub max_attempts_per_ip: Option<u32>,
+    /// Wait in ms after the first failure, doubled with every further failure, defaults to 1000
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub backoff: Option<u64>,
+    /// Seconds a lockout lasts, failures older than this are forgotten, defaults to 900
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub lockout_duration: Option<u64>,
+    /// File the counters are written to, so lockouts survive a restart.
+    /// The counters are kept in memory if not set
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub attempts_path: Option<String>,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RetiredKey {
+    pub id: String,
+    pub aes_key: String,
+    /// Defaults to the active totp settings
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub totp: Option<TotpSettings>,
+    /// Seconds since epoch when the key was replaced
+    pub retired_at: u64,
 }
 
 #[derive(Default, Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
@@ -1,12 +1,20 @@
+use crate::authdb::attempts::{AttemptStore, FileAttempts, LoginLimits};
 use crate::authdb::auth_actors::Users;
+use crate::authdb::auth_store;
+use crate::authdb::refresh::RefreshTokens;
+use crate::authdb::revocation::Revocations;
 use crate::config::Config;
 use crate::runtime::TargetRuntime;
-use hyper::Method;
 use lms_auth::auth::AuthProvider;
-use reqwest::{Body, Request};
-use serde_json::json;
+use lms_auth::keyring::{Key, Keyring, DEFAULT_KEY_ID};
+use lms_auth::local_crypto::hash_256;
 use std::ops::{Deref, DerefMut};
 use std::path::Path;
+use std::sync::Arc;
+use totp_rs::TOTP;
+
+/// Shortest `aesKey` accepted, for the active and the retired keys.
+const MIN_AES_KEY_LEN: usize = 9;
 
 #[derive(Default, Debug, Clone)]
 pub struct ConfigModule {
@@ -18,6 +26,9 @@ pub struct ConfigModule {
 pub struct Extensions {
     pub users: Option<Users>,
     pub auth: Option<AuthProvider>,
+    pub revocations: Option<Arc<Revocations>>,
+    pub refresh_tokens: Option<Arc<RefreshTokens>>,
+    pub attempts: Option<Arc<dyn AttemptStore>>,
 }
 
 impl Deref for ConfigModule {
@@ -40,6 +51,9 @@ impl From<Config> for ConfigModule {
             extensions: Extensions {
                 users: None,
                 auth: None,
+                revocations: None,
+                refresh_tokens: None,
+                attempts: None,
             },
         }
     }
@@ -51,10 +65,7 @@ impl ConfigModule {
         target_runtime: &TargetRuntime,
         parent_dir: Option<&Path>,
     ) -> anyhow::Result<Self> {
-        let totp = self.config.auth.totp.clone().into_totp()?;
-        if self.config.auth.aes_key.len() < 9 {
-            anyhow::bail!("authDbPath must be at least 8 characters long");
-        }
+        check_aes_key(&self.config.auth.aes_key, "aesKey")?;
 
         self.config.server.actions_db =
             ConfigModule::resolve_path(&self.config.server.actions_db, parent_dir);
@@ -62,57 +73,74 @@ impl ConfigModule {
         self.config.auth.auth_db_path =
             ConfigModule::resolve_path(&self.config.auth.auth_db_path, parent_dir);
 
-        let auth = AuthProvider::init(
-            self.config.auth.auth_db_path.clone(),
-            totp,
-            lms_auth::local_crypto::hash_256(&self.config.auth.aes_key).clone(),
-        )?;
+        let auth = self.auth_provider(self.config.auth.auth_db_path.clone())?;
 
-        let users = if !auth.db_path().starts_with("http") {
-            match target_runtime.file.read(auth.db_path()).await {
-                Ok(encrypted_users) => {
-                    let decrypted_users = auth.decrypt_aes(encrypted_users)?;
-
-                    serde_json::from_str::<Users>(&decrypted_users)?
-                }
-                Err(_) => {
-                    let users = Users::default();
-                    let encrypted_users = auth.encrypt_aes(serde_json::to_string(&users)?)?;
-                    target_runtime
-                        .file
-                        .write(auth.db_path(), encrypted_users.as_bytes())
-                        .await?;
-                    users
-                }
+        let users = auth_store::init(&auth, target_runtime)?.list().await?;
+
+        let revocations = Revocations::fetch(&auth, target_runtime).await?;
+        let refresh_tokens = RefreshTokens::fetch(&auth, target_runtime).await?;
+        let attempts = match self.config.auth.login_limits.attempts_path.as_ref() {
+            Some(path) => {
+                let path = ConfigModule::resolve_path(path, parent_dir);
+                let limits = LoginLimits::from(&self.config.auth.login_limits);
+                let attempts = FileAttempts::load(&path, &limits, target_runtime.file.clone());
+                Some(Arc::new(attempts.await?) as Arc<dyn AttemptStore>)
             }
-        } else {
-            let url = url::Url::parse(auth.db_path())?;
-
-            let mut req = Request::new(Method::POST, url);
-            let password = String::from_utf8(auth.get_pw().to_vec())?;
-            *req.body_mut() = Some(Body::from(
-                json!({
-                    "operation": "get_users",
-                    "pw": &password
-                })
-                .to_string(),
-            ));
-            let result = target_runtime.http.execute(req).await?;
-            serde_json::from_slice::<Users>(&result.body)?
+            None => None,
         };
 
         Ok(ConfigModule {
             extensions: Extensions {
                 users: Some(users),
                 auth: Some(auth),
+                revocations: Some(Arc::new(revocations)),
+                refresh_tokens: Some(Arc::new(refresh_tokens)),
+                attempts,
             },
             ..self
         })
     }
+    /// AuthProvider with the configured keys, storing the users at `auth_db_path`.
+    pub fn auth_provider(&self, auth_db_path: String) -> anyhow::Result<AuthProvider> {
+        let totp = self.config.auth.totp.clone().into_totp()?;
+        Ok(AuthProvider::from_keyring(
+            auth_db_path,
+            self.keyring(totp)?,
+        ))
+    }
+    fn keyring(&self, totp: TOTP) -> anyhow::Result<Keyring> {
+        let auth = &self.config.auth;
+        let active = Key::derive(
+            auth.key_id.as_deref().unwrap_or(DEFAULT_KEY_ID),
+            totp,
+            hash_256(&auth.aes_key),
+        )?;
+        let grace_period = auth.key_grace_period.unwrap_or(86400);
+
+        let mut keyring = Keyring::new(active);
+        for retired in &auth.retired_keys {
+            check_aes_key(
+                &retired.aes_key,
+                &format!("aesKey of retired key {}", retired.id),
+            )?;
+            let totp = retired
+                .totp
+                .clone()
+                .unwrap_or_else(|| auth.totp.clone())
+                .into_totp()?;
+            let key = Key::derive(&retired.id, totp, hash_256(&retired.aes_key))?;
+            let valid_until = (retired.retired_at + grace_period) as u128 * 1000;
+            keyring = keyring.retire(key, valid_until)?;
+        }
+        Ok(keyring)
+    }
     fn resolve_path(src: &str, root_dir: Option<&Path>) -> String {
         if src.starts_with("http") {
             return src.to_string();
         }
+        if let Some(path) = crate::sqlite_path(src) {
+            return format!("sqlite://{}", ConfigModule::resolve_path(path, root_dir));
+        }
         if Path::new(&src).is_absolute() {
             src.to_string()
         } else {
@@ -122,11 +150,117 @@ impl ConfigModule {
     }
 }
 
+fn check_aes_key(aes_key: &str, name: &str) -> anyhow::Result<()> {
+    if aes_key.len() < MIN_AES_KEY_LEN {
+        anyhow::bail!(
+            "{} must be at least {} characters long",
+            name,
+            MIN_AES_KEY_LEN
+        );
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
+    use crate::app_ctx::AppContext;
+    use crate::authdb::auth_store::{AuthStore, LocalAuthStore};
+    use crate::blueprint::Blueprint;
     use crate::config::config_module::ConfigModule;
+    use crate::config::RetiredKey;
     use std::path::{Path, PathBuf};
 
+    // `{"users":{"foo":{..}}}` encrypted with the legacy AES-256-CBC scheme and a constant IV
+    const LEGACY_USERS: &str = "97j+nFNiEoxbEQNlD4ieVQZTZGrFpWRxqk6z73C2nfzcFW2omRsEQoylRDKMKYo4TixcXkorH3496cOz/PqPfuKNOAzQBy4Gr9hsMBgdEcKk5mq/5Z2xs7TFHl3g9EyG9ewL1uDT4VjB4P1IfCVWhg==";
+
+    fn module() -> ConfigModule {
+        let mut module = ConfigModule::default();
+        module.server.actions_db = "actions.json".to_string();
+        module.server.file_db = "files".to_string();
+        module.auth.aes_key = "32bytebase64encodedkey".to_string();
+        module.auth.totp.totp_secret = "base32encodedkey".to_string();
+        module.auth.auth_db_path = "auth".to_string();
+        module
+    }
+
+    #[tokio::test]
+    async fn test_resolve_rotated_keys() -> anyhow::Result<()> {
+        let runtime = crate::runtime::tests::init();
+        let old = module().resolve(&runtime, None).await?;
+        let app_ctx = AppContext {
+            blueprint: Blueprint::try_from(old)?,
+            runtime: runtime.clone(),
+        };
+        let store = LocalAuthStore::new(app_ctx.blueprint.extensions.auth.clone(), runtime.clone());
+        let mut users = app_ctx.blueprint.extensions.users.clone();
+        users.insert(crate::authdb::auth_actors::User {
+            username: "foo".to_string(),
+            name: "Foo".to_string(),
+            password: "foopassword".to_string(),
+            authority: crate::authdb::auth_actors::Authority::Admin,
+            batch: None,
+            two_factor: None,
+            password_reset: None,
+        });
+        store.upsert(users.get("foo").unwrap()).await?;
+        let user = users.get("foo").unwrap();
+        let token = crate::authdb::auth_db::gen_token(&user, &app_ctx)?;
+
+        let mut rotated = module();
+        rotated.auth.aes_key = "a brand new aes key".to_string();
+        rotated.auth.key_id = Some("k2".to_string());
+        rotated.auth.retired_keys.push(RetiredKey {
+            id: "default".to_string(),
+            aes_key: "32bytebase64encodedkey".to_string(),
+            totp: None,
+            retired_at: (runtime.instance.now()? / 1000) as u64,
+        });
+        let resolved = rotated.clone().resolve(&runtime, None).await?;
+        assert_eq!(resolved.extensions.users, Some(users.clone()));
+
+        let app_ctx = AppContext {
+            blueprint: Blueprint::try_from(resolved)?,
+            runtime: runtime.clone(),
+        };
+        // sessions signed with the retired key survive the rotation
+        let claims = crate::authdb::auth_db::verify_token(&token, &app_ctx)?;
+        assert_eq!(claims.sub, "foo");
+
+        let store = LocalAuthStore::new(app_ctx.blueprint.extensions.auth.clone(), runtime.clone());
+        assert_eq!(store.reencrypt().await?, users);
+        assert!(runtime.file.read("auth").await?.starts_with("v2:k2:"));
+
+        // once re-encrypted, the retired key is no longer needed
+        rotated.auth.retired_keys.clear();
+        let resolved = rotated.resolve(&runtime, None).await?;
+        assert_eq!(resolved.extensions.users, Some(users));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resolve_legacy_auth_db() -> anyhow::Result<()> {
+        let runtime = crate::runtime::tests::init();
+        runtime.file.write("auth", LEGACY_USERS.as_bytes()).await?;
+
+        let resolved = module().resolve(&runtime, None).await?;
+        let users = resolved.extensions.users.clone().unwrap();
+        assert_eq!(users.get("foo").unwrap().password, "foopassword");
+
+        // next write re-encrypts the db in the authenticated format
+        let blueprint = Blueprint::try_from(resolved)?;
+        let app_ctx = AppContext {
+            blueprint,
+            runtime: runtime.clone(),
+        };
+        let store = LocalAuthStore::new(app_ctx.blueprint.extensions.auth.clone(), runtime.clone());
+        store.upsert(users.get("foo").unwrap()).await?;
+        assert!(runtime.file.read("auth").await?.starts_with("v2:default:"));
+
+        let resolved = module().resolve(&runtime, None).await?;
+        assert_eq!(resolved.extensions.users, Some(users));
+        Ok(())
+    }
+
     #[test]
     fn test_relative_path() {
         let path_dir = Path::new("abc/xyz");
@@ -140,5 +274,9 @@ mod tests {
             "/foo/bar/my.proto",
             ConfigModule::resolve_path(file_absolute, Some(path_dir))
         );
+        assert_eq!(
+            "sqlite://abc/xyz/lms.db",
+            ConfigModule::resolve_path("sqlite://lms.db", Some(path_dir))
+        );
     }
 }
@@ -0,0 +1,337 @@
+//! Envelope encryption of the files of a content. Every content gets a random [DataKey],
+//! stored in its [Metadata] wrapped by the active key of `auth`, so rotating the master key
+//! only re-wraps the data keys and never the files.
+//!
+//! A file is sealed in chunks so it can be streamed both ways: a header holding a random
+//! nonce prefix, then every [CHUNK_LEN] bytes of the file sealed with AES-256-GCM under
+//! `prefix || chunk index || last chunk flag`. The flag keeps a truncated file from passing
+//! as whole, and the id of the content along with the name of the file are authenticated
+//! with every chunk, so a file can't be swapped for another one encrypted with the same key.
+//! Whether a file is encrypted is told by the [Metadata], never by its content.
+//!
+//! [Metadata]: super::file_config::Metadata
+
+use std::ops::Range;
+
+use anyhow::{anyhow, Result};
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use bytes::{Bytes, BytesMut};
+use futures_util::{stream, Stream, StreamExt};
+use lms_auth::keyring::Keyring;
+use lms_auth::local_crypto::{open_bytes, seal_bytes, NONCE_LEN, TAG_LEN};
+
+use crate::stream::ByteStream;
+
+const KEY_LEN: usize = 32;
+/// Version of the layout, the first byte of the header.
+const FORMAT: u8 = 1;
+const PREFIX_LEN: usize = 7;
+pub const HEADER_LEN: usize = 1 + PREFIX_LEN;
+/// Plaintext bytes in every chunk but the last one.
+const CHUNK_LEN: usize = 64 * 1024;
+const SEALED_LEN: usize = CHUNK_LEN + TAG_LEN;
+
+/// AES-256-GCM key of the files of a single content.
+pub struct DataKey([u8; KEY_LEN]);
+
+impl DataKey {
+    pub fn generate() -> Result<Self> {
+        let mut key = [0u8; KEY_LEN];
+        getrandom::getrandom(&mut key).map_err(|_| anyhow!("Unable to generate data key"))?;
+        Ok(Self(key))
+    }
+
+    /// Encrypts the key with the active key of `keyring`, the result is tagged with its id.
+    pub fn wrap(&self, keyring: &Keyring) -> Result<String> {
+        keyring.encrypt(&BASE64_STANDARD.encode(self.0))
+    }
+
+    pub fn unwrap(keyring: &Keyring, wrapped: &str) -> Result<Self> {
+        let key = BASE64_STANDARD.decode(keyring.decrypt(wrapped)?)?;
+        Ok(Self(
+            key.try_into()
+                .map_err(|_| anyhow!("Invalid data key length"))?,
+        ))
+    }
+
+    /// Encrypts a whole file, `aad` is the [aad] of the file.
+    pub fn encrypt(&self, aad: &str, content: &[u8]) -> Result<Vec<u8>> {
+        let header = header()?;
+        let mut encrypted = header.to_vec();
+        let mut chunks = content.chunks(CHUNK_LEN).peekable();
+        let mut index = 0;
+        // a file which fills its last chunk still ends with an empty one
+        while let Some(chunk) = chunks.next() {
+            let last = chunks.peek().is_none() && chunk.len() < CHUNK_LEN;
+            encrypted.extend(self.seal(&header, index, last, aad, chunk)?);
+            index += 1;
+        }
+        if content.len().is_multiple_of(CHUNK_LEN) {
+            encrypted.extend(self.seal(&header, index, true, aad, &[])?);
+        }
+        Ok(encrypted)
+    }
+
+    /// Decrypts a whole file encrypted by [DataKey::encrypt] or [DataKey::encrypt_stream].
+    pub fn decrypt(&self, aad: &str, content: &[u8]) -> Result<Vec<u8>> {
+        let last = chunk_count(content.len() as u64)? - 1;
+        let (header, chunks) = content.split_at(HEADER_LEN);
+        let mut decrypted = vec![];
+        for (index, chunk) in chunks.chunks(SEALED_LEN).enumerate() {
+            let index = index as u64;
+            decrypted.extend(self.open(header, index, index == last, aad, chunk)?);
+        }
+        Ok(decrypted)
+    }
+
+    /// Encrypts a file as it streams, chunk by chunk.
+    pub fn encrypt_stream(self, aad: String, stream: ByteStream) -> Result<ByteStream> {
+        let header = header()?;
+        let chunks = chunks(stream, CHUNK_LEN)
+            .enumerate()
+            .map(move |(index, chunk)| {
+                let (chunk, last) = chunk?;
+                let sealed = self.seal(&header, index as u64, last, &aad, &chunk)?;
+                Ok(Bytes::from(sealed))
+            });
+        Ok(Box::pin(
+            stream::once(async move { Ok(Bytes::copy_from_slice(&header)) }).chain(chunks),
+        ))
+    }
+
+    /// Decrypts `range` of a file of `size` bytes as stored, out of its `header` and of the
+    /// chunks in [sealed_range].
+    pub fn decrypt_range(
+        self,
+        aad: String,
+        header: &[u8],
+        size: u64,
+        range: Range<u64>,
+        sealed: ByteStream,
+    ) -> Result<ByteStream> {
+        let header = header.to_vec();
+        let last = chunk_count(size)? - 1;
+        let first = range.start / CHUNK_LEN as u64;
+        let mut skip = (range.start % CHUNK_LEN as u64) as usize;
+        let mut left = range.end - range.start;
+        let chunks = chunks(sealed, SEALED_LEN)
+            .enumerate()
+            .map(move |(index, chunk)| {
+                let (chunk, end) = chunk?;
+                let mut plain = Bytes::new();
+                // the stream ends with an empty chunk when its last one is full
+                if !chunk.is_empty() {
+                    let index = first + index as u64;
+                    plain = self
+                        .open(&header, index, index == last, &aad, &chunk)?
+                        .into();
+                    let start = skip.min(plain.len());
+                    let end = start + (plain.len() - start).min(left as usize);
+                    skip = 0;
+                    left -= (end - start) as u64;
+                    plain = plain.slice(start..end);
+                }
+                if end && left > 0 {
+                    return Err(anyhow!("Encrypted file is truncated"));
+                }
+                Ok(plain)
+            });
+        Ok(Box::pin(chunks))
+    }
+
+    fn seal(
+        &self,
+        header: &[u8],
+        index: u64,
+        last: bool,
+        aad: &str,
+        chunk: &[u8],
+    ) -> Result<Vec<u8>> {
+        seal_bytes(&self.0, &nonce(header, index, last)?, aad.as_bytes(), chunk)
+    }
+
+    fn open(
+        &self,
+        header: &[u8],
+        index: u64,
+        last: bool,
+        aad: &str,
+        chunk: &[u8],
+    ) -> Result<Vec<u8>> {
+        open_bytes(&self.0, &nonce(header, index, last)?, aad.as_bytes(), chunk)
+    }
+}
+
+/// Data authenticated along with every chunk of a file.
+pub fn aad(content_id: &str, file_name: &str) -> String {
+    format!("{}/{}", content_id, file_name)
+}
+
+/// Size of a file encrypted into `size` bytes.
+pub fn plaintext_len(size: u64) -> Result<u64> {
+    Ok(size - HEADER_LEN as u64 - chunk_count(size)? * TAG_LEN as u64)
+}
+
+/// Bytes of a file encrypted into `size` bytes holding the chunks of the plaintext `range`,
+/// which must not be empty.
+pub fn sealed_range(range: &Range<u64>, size: u64) -> Range<u64> {
+    let chunk = |offset: u64| offset / CHUNK_LEN as u64 * SEALED_LEN as u64;
+    let start = HEADER_LEN as u64 + chunk(range.start);
+    let end = HEADER_LEN as u64 + chunk(range.end - 1) + SEALED_LEN as u64;
+    start..end.min(size)
+}
+
+/// Number of chunks of a file encrypted into `size` bytes, the last one holds at least a tag.
+fn chunk_count(size: u64) -> Result<u64> {
+    let invalid = || anyhow!("Invalid encrypted file");
+    let chunks = size.checked_sub(HEADER_LEN as u64).ok_or_else(invalid)?;
+    if chunks % (SEALED_LEN as u64) < TAG_LEN as u64 {
+        return Err(invalid());
+    }
+    Ok(chunks / SEALED_LEN as u64 + 1)
+}
+
+/// Header of a new file, its nonce prefix is random so that every file written with the
+/// same key is sealed under different nonces.
+fn header() -> Result<[u8; HEADER_LEN]> {
+    let mut header = [FORMAT; HEADER_LEN];
+    getrandom::getrandom(&mut header[1..]).map_err(|_| anyhow!("Unable to generate nonce"))?;
+    Ok(header)
+}
+
+fn nonce(header: &[u8], index: u64, last: bool) -> Result<[u8; NONCE_LEN]> {
+    if header.len() != HEADER_LEN || header[0] != FORMAT {
+        return Err(anyhow!("Unknown encrypted file format"));
+    }
+    let index = u32::try_from(index).map_err(|_| anyhow!("Encrypted file is too large"))?;
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce[..PREFIX_LEN].copy_from_slice(&header[1..]);
+    nonce[PREFIX_LEN..NONCE_LEN - 1].copy_from_slice(&index.to_be_bytes());
+    nonce[NONCE_LEN - 1] = last as u8;
+    Ok(nonce)
+}
+
+/// Splits `stream` into chunks of `len` bytes, flagging the last one,
+/// which is shorter and may be empty.
+fn chunks(stream: ByteStream, len: usize) -> impl Stream<Item = Result<(Bytes, bool)>> {
+    stream::unfold(
+        Some((stream.fuse(), BytesMut::new())),
+        move |state| async move {
+            let (mut stream, mut buf) = state?;
+            // a chunk is only full once a byte past it came, else it's the last one
+            while buf.len() <= len {
+                match stream.next().await {
+                    Some(Ok(bytes)) => buf.extend_from_slice(&bytes),
+                    Some(Err(e)) => return Some((Err(e), None)),
+                    None if buf.len() < len => return Some((Ok((buf.freeze(), true)), None)),
+                    None => break,
+                }
+            }
+            let chunk = buf.split_to(len).freeze();
+            Some((Ok((chunk, false)), Some((stream, buf))))
+        },
+    )
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::stream::collect;
+    use lms_auth::keyring::Key;
+    use totp_rs::{Algorithm, Secret, TOTP};
+
+    pub(crate) fn keyring(id: &str, aes_key: &str) -> Keyring {
+        let secret = Secret::Raw(b"JBSWY3DPEHPK3PXPJBSWY3DP".to_vec());
+        let totp = TOTP::new(Algorithm::SHA1, 6, 1, 30, secret.to_bytes().unwrap()).unwrap();
+        Keyring::new(Key::derive(id, totp, aes_key.to_string()).unwrap())
+    }
+
+    fn split(content: &[u8], len: usize) -> ByteStream {
+        let chunks: Vec<_> = content
+            .chunks(len)
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect();
+        Box::pin(stream::iter(chunks))
+    }
+
+    #[test]
+    fn test_envelope() -> Result<()> {
+        let master = keyring("2024", "master key");
+        let key = DataKey::generate()?;
+        let wrapped = key.wrap(&master)?;
+        assert!(wrapped.starts_with("v2:2024:"));
+
+        let content = b"%PDF-1.7\n\xff\x00";
+        let encrypted = key.encrypt("id/notes.pdf", content)?;
+        assert_eq!(plaintext_len(encrypted.len() as u64)?, content.len() as u64);
+        let key = DataKey::unwrap(&master, &wrapped)?;
+        assert_eq!(key.decrypt("id/notes.pdf", &encrypted)?, content);
+        assert!(key.decrypt("id/other.pdf", &encrypted).is_err());
+        assert!(key.decrypt("other/notes.pdf", &encrypted).is_err());
+        assert!(key.decrypt("id/notes.pdf", content).is_err());
+
+        let other = keyring("2025", "another master key");
+        assert!(DataKey::unwrap(&other, &wrapped).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncated() -> Result<()> {
+        let key = DataKey::generate()?;
+        let content = vec![7u8; CHUNK_LEN * 2 + 10];
+        let encrypted = key.encrypt("aad", &content)?;
+        assert_eq!(key.decrypt("aad", &encrypted)?, content);
+        // dropping the last chunk leaves a file of whole chunks, which isn't flagged as ended
+        let truncated = &encrypted[..HEADER_LEN + SEALED_LEN * 2];
+        assert!(key.decrypt("aad", truncated).is_err());
+        let truncated = &encrypted[..HEADER_LEN + SEALED_LEN];
+        assert!(key.decrypt("aad", truncated).is_err());
+        let truncated = &encrypted[..HEADER_LEN + SEALED_LEN + TAG_LEN + 1];
+        assert!(key.decrypt("aad", truncated).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stream() -> Result<()> {
+        for len in [0, 1, CHUNK_LEN - 1, CHUNK_LEN, CHUNK_LEN * 2 + 100] {
+            let content: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+            let key = DataKey::generate()?;
+            let encrypted = key.encrypt("aad", &content)?;
+            let streamed =
+                DataKey(key.0).encrypt_stream("aad".to_string(), split(&content, 1000))?;
+            let streamed = collect(streamed).await?;
+            assert_eq!(streamed.len(), encrypted.len());
+            assert_eq!(key.decrypt("aad", &streamed)?, content);
+
+            let size = encrypted.len() as u64;
+            assert_eq!(plaintext_len(size)?, len as u64);
+            let len = len as u64;
+            let ranges = [
+                0..len,
+                len / 2..len,
+                0..len / 3 + 1,
+                len.saturating_sub(1)..len,
+            ];
+            for range in ranges
+                .into_iter()
+                .filter(|range| range.start < range.end.min(len))
+            {
+                let sealed = sealed_range(&range, size);
+                let chunks = encrypted[sealed.start as usize..sealed.end as usize].to_vec();
+                let decrypted = DataKey(key.0).decrypt_range(
+                    "aad".to_string(),
+                    &encrypted[..HEADER_LEN],
+                    size,
+                    range.clone(),
+                    split(&chunks, 777),
+                )?;
+                assert_eq!(
+                    collect(decrypted).await?,
+                    &content[range.start as usize..range.end as usize]
+                );
+            }
+        }
+        Ok(())
+    }
+}
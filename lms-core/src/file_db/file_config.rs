@@ -2,26 +2,84 @@
 
 use crate::authdb::auth_actors::Authority;
 use crate::is_default;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use base64::prelude::BASE64_STANDARD;
 use base64::Engine;
 use serde::ser::SerializeStruct;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
+/// A file with its raw content, on the json wire the content is base64 encoded,
+/// see [EncodedFile].
 #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "EncodedFile", into = "EncodedFile")]
 pub struct FileHolder {
     pub name: String,
+    pub content: Vec<u8>,
+}
+
+/// How the content of a file is written in json.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    /// The content as is, only for text, assumed when the encoding is missing
+    #[default]
+    Utf8,
+    Base64,
+}
+
+/// Json form of a [FileHolder].
+#[derive(Serialize, Deserialize)]
+pub struct EncodedFile {
+    pub name: String,
+    #[serde(default)]
+    pub encoding: Encoding,
     pub content: String,
 }
 
+impl Encoding {
+    pub fn decode(self, content: String) -> Result<Vec<u8>> {
+        match self {
+            Encoding::Utf8 => Ok(content.into_bytes()),
+            Encoding::Base64 => BASE64_STANDARD
+                .decode(content)
+                .map_err(|e| anyhow!("Invalid base64 content: {}", e)),
+        }
+    }
+}
+
+impl TryFrom<EncodedFile> for FileHolder {
+    type Error = anyhow::Error;
+
+    fn try_from(file: EncodedFile) -> Result<Self> {
+        Ok(Self {
+            content: file.encoding.decode(file.content)?,
+            name: file.name,
+        })
+    }
+}
+
+impl From<FileHolder> for EncodedFile {
+    fn from(file: FileHolder) -> Self {
+        Self {
+            name: file.name,
+            encoding: Encoding::Base64,
+            content: BASE64_STANDARD.encode(file.content),
+        }
+    }
+}
+
 pub struct InsertionInfo {
     pub title: String,
     pub description: String,
     pub timestamp: u128,
     pub end_time: Option<u128>,
+    pub author: Option<String>,
+    pub assignment: Option<AssignmentInfo>,
+    pub grade: Option<GradeInfo>,
+    pub submission: Option<SubmissionInfo>,
 }
 
-#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
 pub struct RemoteFileConfig {
     #[serde(default, skip_serializing_if = "is_default")]
     pub files: Vec<FileHolder>, // file names and content
@@ -42,6 +100,201 @@ pub struct Metadata {
     pub timestamp: u128,
     #[serde(default, skip_serializing_if = "is_default")]
     pub end_time: Option<u128>,
+    /// Username of the poster, only the author can attach files later on
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub author: Option<String>,
+    /// Key of the files wrapped by the master key, set when they are encrypted,
+    /// see [super::encryption]
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub data_key: Option<String>,
+    /// Files stored in plaintext although `data_key` is set, left by an interrupted
+    /// `encrypt_all` until it's run again
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub plain_files: Vec<String>,
+    /// Set for an assignment
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub assignment: Option<AssignmentInfo>,
+    /// Set for a grade, its feedback is the description
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub grade: Option<GradeInfo>,
+    /// Set for a submission when it's made
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub submission: Option<SubmissionInfo>,
+}
+
+/// Details of an assignment, it's due at the `end_time` of its [Metadata].
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct AssignmentInfo {
+    pub max_points: u32,
+    /// Extensions of the files a submission may hold, lowercase and without the dot,
+    /// any file is allowed when empty
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub allowed_types: Vec<String>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub late_policy: LatePolicy,
+}
+
+/// How submissions after the due date of an assignment are handled, times are in milliseconds.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LatePolicy {
+    /// Nothing is accepted after the due date
+    #[default]
+    Cutoff,
+    /// Accepted without a penalty until `grace` after the due date
+    Grace { grace: u64 },
+    /// `per_day` percent of the grade is deducted for every started day, up to `cap` percent
+    Penalty { per_day: u32, cap: u32 },
+}
+
+/// Stamp of a submission, see [LatePolicy::stamp].
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct SubmissionInfo {
+    /// Due date for the submitter, extensions included
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub due: Option<u128>,
+    /// Milliseconds past the due date, 0 when on time
+    pub late_by: u128,
+    /// Percent deducted from the grade
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub penalty: u32,
+}
+
+const DAY: u128 = 24 * 60 * 60 * 1000;
+
+impl LatePolicy {
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            LatePolicy::Penalty { per_day, .. } if *per_day == 0 || *per_day > 100 => {
+                Err(anyhow!("Penalty per day must be between 1 and 100 percent"))
+            }
+            LatePolicy::Penalty { cap, .. } if *cap == 0 || *cap > 100 => {
+                Err(anyhow!("Penalty cap must be between 1 and 100 percent"))
+            }
+            LatePolicy::Penalty { per_day, cap } if per_day > cap => {
+                Err(anyhow!("Penalty per day can't exceed the cap"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Stamps a submission made at `now`, an error when it isn't accepted anymore.
+    pub fn stamp(&self, due: Option<u128>, now: u128) -> Result<SubmissionInfo> {
+        let late_by = due.map_or(0, |due| now.saturating_sub(due));
+        let penalty = match self {
+            _ if late_by == 0 => 0,
+            LatePolicy::Cutoff => return Err(anyhow!("Submission time has passed")),
+            LatePolicy::Grace { grace } if late_by > *grace as u128 => {
+                return Err(anyhow!("Submission time has passed"))
+            }
+            LatePolicy::Grace { .. } => 0,
+            LatePolicy::Penalty { per_day, cap } => {
+                let days = late_by.div_ceil(DAY);
+                (days.saturating_mul(*per_day as u128)).min(*cap as u128) as u32
+            }
+        };
+        Ok(SubmissionInfo {
+            due,
+            late_by,
+            penalty,
+        })
+    }
+}
+
+impl SubmissionInfo {
+    pub fn is_late(&self) -> bool {
+        self.late_by > 0
+    }
+}
+
+impl AssignmentInfo {
+    /// Normalizes the extensions, `.PDF` is stored as `pdf`.
+    pub fn new(max_points: u32, allowed_types: Vec<String>) -> Result<Self> {
+        let mut types = vec![];
+        for allowed in allowed_types {
+            let allowed = allowed.trim().trim_start_matches('.').to_ascii_lowercase();
+            if allowed.is_empty() || !allowed.chars().all(|c| c.is_ascii_alphanumeric()) {
+                return Err(anyhow!("Invalid file type {:?}", allowed));
+            }
+            if !types.contains(&allowed) {
+                types.push(allowed);
+            }
+        }
+        Ok(Self {
+            max_points,
+            allowed_types: types,
+            late_policy: LatePolicy::default(),
+        })
+    }
+
+    pub fn allows(&self, file_name: &str) -> bool {
+        if self.allowed_types.is_empty() {
+            return true;
+        }
+        file_name.rsplit_once('.').is_some_and(|(_, extension)| {
+            self.allowed_types
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(extension))
+        })
+    }
+}
+
+/// Grade of a submission.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct GradeInfo {
+    pub points: u32,
+    /// Points by criterion, adding up to `points`
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub rubric: Vec<RubricScore>,
+    /// Percent deducted for lateness, set from the [SubmissionInfo] of the graded submission
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub penalty: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct RubricScore {
+    pub criterion: String,
+    pub points: u32,
+}
+
+impl GradeInfo {
+    /// Points after the late penalty, rounded down.
+    pub fn total(&self) -> u32 {
+        (self.points as u64 * (100 - self.penalty.min(100)) as u64 / 100) as u32
+    }
+
+    /// Checks the grade against the points of its assignment, if it has any.
+    pub fn validate(&self, max_points: Option<u32>) -> Result<()> {
+        if let Some(max_points) = max_points.filter(|max| self.points > *max) {
+            return Err(anyhow!(
+                "Grade of {} points exceeds the {} of the assignment",
+                self.points,
+                max_points
+            ));
+        }
+        if self.rubric.is_empty() {
+            return Ok(());
+        }
+        for (i, score) in self.rubric.iter().enumerate() {
+            if score.criterion.trim().is_empty() {
+                return Err(anyhow!("Rubric criterion without a name"));
+            }
+            if self.rubric[..i]
+                .iter()
+                .any(|other| other.criterion == score.criterion)
+            {
+                return Err(anyhow!("Duplicate rubric criterion {}", score.criterion));
+            }
+        }
+        let total = self
+            .rubric
+            .iter()
+            .try_fold(0u32, |total, score| total.checked_add(score.points));
+        if total != Some(self.points) {
+            return Err(anyhow!("Rubric doesn't add up to {} points", self.points));
+        }
+        Ok(())
+    }
 }
 
 impl RemoteFileConfig {
@@ -53,6 +306,12 @@ impl RemoteFileConfig {
                 description: insertion_info.description,
                 timestamp: insertion_info.timestamp,
                 end_time: insertion_info.end_time,
+                author: insertion_info.author,
+                data_key: None,
+                plain_files: vec![],
+                assignment: insertion_info.assignment,
+                grade: insertion_info.grade,
+                submission: insertion_info.submission,
             },
         }
     }
@@ -73,6 +332,12 @@ impl LocalFileConfig {
                 description: insertion_info.description,
                 timestamp: insertion_info.timestamp,
                 end_time: insertion_info.end_time,
+                author: insertion_info.author,
+                data_key: None,
+                plain_files: vec![],
+                assignment: insertion_info.assignment,
+                grade: insertion_info.grade,
+                submission: insertion_info.submission,
             },
         }
     }
@@ -94,7 +359,7 @@ mod tests {
         let content = "Hello, world!";
         let file_holder = FileHolder {
             name: "example.txt".to_string(),
-            content: content.to_string(),
+            content: content.as_bytes().to_vec(),
         };
         let serialized = serde_json::to_string(&file_holder).unwrap();
         insta::assert_snapshot!(serialized);
@@ -104,25 +369,144 @@ mod tests {
     fn test_serialize_remote_file_config() {
         let files = vec![FileHolder {
             name: "doc.txt".to_string(),
-            content: "Sample content".to_string(),
+            content: b"Sample content".to_vec(),
         }];
         let metadata = Metadata {
             title: "Data Collection".to_string(),
             description: "Project files".to_string(),
             timestamp: 1625247600000,
             end_time: None,
+            author: None,
+            data_key: None,
+            plain_files: vec![],
+            assignment: None,
+            grade: None,
+            submission: None,
         };
         let config = RemoteFileConfig { files, metadata };
         let serialized = serde_json::to_string(&config).unwrap();
         insta::assert_snapshot!(serialized);
     }
 
+    #[test]
+    fn test_assignment_types() -> Result<()> {
+        let info = AssignmentInfo::new(10, vec![".PDF".to_string(), "zip".to_string()])?;
+        assert_eq!(info.allowed_types, vec!["pdf", "zip"]);
+        assert!(info.allows("answers.Pdf"));
+        assert!(info.allows("code.tar.zip"));
+        assert!(!info.allows("answers.pdf.exe"));
+        assert!(!info.allows("pdf"));
+        assert!(AssignmentInfo::new(10, vec![])?.allows("anything"));
+        assert!(AssignmentInfo::new(10, vec!["".to_string()]).is_err());
+        assert!(AssignmentInfo::new(10, vec!["p/df".to_string()]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_grade_rubric() {
+        let score = |criterion: &str, points| RubricScore {
+            criterion: criterion.to_string(),
+            points,
+        };
+        let mut grade = GradeInfo {
+            points: 8,
+            rubric: vec![score("correctness", 5), score("style", 3)],
+            penalty: 0,
+        };
+        assert!(grade.validate(Some(10)).is_ok());
+        assert!(grade.validate(Some(7)).is_err());
+        grade.rubric.push(score("style", 0));
+        assert!(grade.validate(None).is_err());
+        grade.rubric = vec![score("correctness", 5)];
+        assert!(grade.validate(None).is_err());
+        grade.rubric.clear();
+        assert!(grade.validate(None).is_ok());
+    }
+
+    #[test]
+    fn test_late_policy() -> Result<()> {
+        let due = Some(1_000_000_000);
+        let on_time = LatePolicy::Cutoff.stamp(due, 1_000_000_000)?;
+        assert!(!on_time.is_late());
+        assert!(LatePolicy::Cutoff.stamp(due, 1_000_000_001).is_err());
+        assert_eq!(LatePolicy::Cutoff.stamp(None, u128::MAX)?.late_by, 0);
+
+        let grace = LatePolicy::Grace { grace: 60_000 };
+        let stamp = grace.stamp(due, 1_000_060_000)?;
+        assert_eq!((stamp.late_by, stamp.penalty), (60_000, 0));
+        assert!(grace.stamp(due, 1_000_060_001).is_err());
+
+        let penalty = LatePolicy::Penalty {
+            per_day: 10,
+            cap: 25,
+        };
+        let stamp = penalty.stamp(due, 1_000_000_001)?;
+        assert_eq!((stamp.late_by, stamp.penalty), (1, 10));
+        assert_eq!(penalty.stamp(due, 1_000_000_000 + DAY + 1)?.penalty, 20);
+        assert_eq!(penalty.stamp(due, 1_000_000_000 + 30 * DAY)?.penalty, 25);
+        assert!(LatePolicy::Penalty { per_day: 0, cap: 0 }
+            .validate()
+            .is_err());
+        assert!(LatePolicy::Penalty {
+            per_day: 10,
+            cap: 101
+        }
+        .validate()
+        .is_err());
+        assert_eq!(
+            LatePolicy::Penalty {
+                per_day: 10,
+                cap: 0
+            }
+            .validate()
+            .unwrap_err()
+            .to_string(),
+            "Penalty cap must be between 1 and 100 percent"
+        );
+        assert_eq!(
+            LatePolicy::Penalty {
+                per_day: 30,
+                cap: 20
+            }
+            .validate()
+            .unwrap_err()
+            .to_string(),
+            "Penalty per day can't exceed the cap"
+        );
+        assert!(LatePolicy::Penalty {
+            per_day: 20,
+            cap: 20
+        }
+        .validate()
+        .is_ok());
+
+        let grade = GradeInfo {
+            points: 9,
+            rubric: vec![],
+            penalty: 25,
+        };
+        assert_eq!(grade.total(), 6);
+        Ok(())
+    }
+
     #[test]
     fn test_deserialize_file_holder() {
-        let json = r#"{"name":"example.txt","content":"SGVsbG8sIHdvcmxkIQ=="}"#;
+        let json = r#"{"name":"example.txt","encoding":"base64","content":"SGVsbG8sIHdvcmxkIQ=="}"#;
         let file_holder: FileHolder = serde_json::from_str(json).unwrap();
         assert_eq!(file_holder.name, "example.txt");
-        assert_eq!(file_holder.content, "SGVsbG8sIHdvcmxkIQ==");
+        assert_eq!(file_holder.content, b"Hello, world!");
+
+        // without an encoding the content is taken as is
+        let json = r#"{"name":"example.txt","content":"SGVsbG8sIHdvcmxkIQ=="}"#;
+        let file_holder: FileHolder = serde_json::from_str(json).unwrap();
+        assert_eq!(file_holder.content, b"SGVsbG8sIHdvcmxkIQ==");
+    }
+
+    #[test]
+    fn test_deserialize_file_holder_with_invalid_base64() {
+        let json = r#"{"name":"example.txt","encoding":"base64","content":"not base64!"}"#;
+        let result: Result<FileHolder, _> = serde_json::from_str(json);
+        assert!(result.is_err());
     }
 
     #[test]
@@ -142,7 +526,7 @@ mod tests {
     fn test_round_trip_file_holder() {
         let original = FileHolder {
             name: "roundtrip.txt".to_string(),
-            content: "Round trip test".to_string(),
+            content: vec![0, 159, 146, 150, 255, b'\n'],
         };
         let serialized = serde_json::to_string(&original).unwrap();
         let deserialized: FileHolder = serde_json::from_str(&serialized).unwrap();
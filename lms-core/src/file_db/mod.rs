@@ -1,2 +1,5 @@
+pub mod encryption;
 pub mod file_config;
+pub mod remote;
 pub mod request_handler;
+pub mod safe_name;
@@ -0,0 +1,522 @@
+//! Protocol of a remote FileDB, used when `fileDb` is a url.
+//!
+//! Paths are appended to `fileDb`, so a base path like `https://host/lms/files` is kept,
+//! and start with the version of the protocol, [VERSION]:
+//!
+//! | Method   | Path                                     | Body               | Answer        |
+//! |----------|------------------------------------------|--------------------|---------------|
+//! | `PUT`    | `{fileDb}/v1/contents/{id}`              | [RemoteFileConfig] |               |
+//! | `GET`    | `{fileDb}/v1/contents/{id}`              |                    | [Metadata]    |
+//! | `GET`    | `{fileDb}/v1/contents/{id}/files/{name}` |                    | the file      |
+//! | `PUT`    | `{fileDb}/v1/contents/{id}/files/{name}` | the file           |               |
+//! | `DELETE` | `{fileDb}/v1/contents/{id}`              |                    |               |
+//! | `GET`    | `{fileDb}/v1/contents`                   |                    | [ContentList] |
+//!
+//! Bodies are json, except for a file which is sent as is. A `PUT` replaces the content or
+//! file with the same id, so an insert can be retried. A file `PUT` adds a file to an existing
+//! content. A file `GET` honours a single `Range`, answered with 206 and a `Content-Range`,
+//! or 416 and `Content-Range: bytes */{size}` when no byte is in the range.
+//!
+//! Unknown contents and files are answered with 404, invalid requests with 400 and anything
+//! else with 500, the body is then a [RemoteError].
+//!
+//! [Metadata]: super::file_config::Metadata
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use hyper::Method;
+use percent_encoding::percent_decode_str;
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT_RANGES, CONTENT_RANGE, CONTENT_TYPE, RANGE};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::http::response::Response;
+use crate::stream::{self, ByteRange, RangeNotSatisfiable};
+
+use super::file_config::RemoteFileConfig;
+use super::request_handler::{validate_files, FileRequestHandler};
+use super::safe_name::{ContentId, SafeFileName};
+
+/// Version of the protocol spoken by this build, the first segment after `fileDb`.
+pub const VERSION: &str = "v1";
+const CONTENTS: &str = "contents";
+const FILES: &str = "files";
+
+/// Answer of a LIST.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContentList {
+    pub contents: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteError {
+    pub message: String,
+}
+
+/// Url of `segments` under `{file_db}/{VERSION}`, each segment is percent encoded.
+pub fn endpoint(file_db: &str, segments: &[&str]) -> Result<Url> {
+    let mut url = Url::parse(file_db)?;
+    url.path_segments_mut()
+        .map_err(|_| anyhow!("Invalid url for FileDB: {}", file_db))?
+        .pop_if_empty()
+        .push(VERSION)
+        .extend(segments);
+    Ok(url)
+}
+
+pub fn contents(file_db: &str) -> Result<Url> {
+    endpoint(file_db, &[CONTENTS])
+}
+
+pub fn content(file_db: &str, id: &str) -> Result<Url> {
+    endpoint(file_db, &[CONTENTS, id])
+}
+
+pub fn file(file_db: &str, id: &str, name: &str) -> Result<Url> {
+    endpoint(file_db, &[CONTENTS, id, FILES, name])
+}
+
+/// Serves the protocol over a FileDB that isn't a url.
+pub struct RemoteFileServer {
+    files: FileRequestHandler,
+    /// Segments of the path the protocol is served under
+    base_path: Vec<String>,
+}
+
+impl RemoteFileServer {
+    pub fn new(files: FileRequestHandler, base_path: &str) -> Self {
+        Self {
+            files,
+            base_path: base_path
+                .split('/')
+                .filter(|segment| !segment.is_empty())
+                .map(|segment| segment.to_string())
+                .collect(),
+        }
+    }
+
+    pub async fn handle(
+        &self,
+        method: &Method,
+        path: &str,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Response<Bytes> {
+        let segments = match path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(|segment| percent_decode_str(segment).decode_utf8())
+            .collect::<std::result::Result<Vec<_>, _>>()
+        {
+            Ok(segments) => segments,
+            Err(_) => return error(StatusCode::BAD_REQUEST, "Path is not valid utf-8"),
+        };
+        let Some(segments) = self
+            .base_path
+            .iter()
+            .enumerate()
+            .all(|(i, base)| segments.get(i).is_some_and(|segment| segment == base))
+            .then(|| &segments[self.base_path.len()..])
+        else {
+            return error(StatusCode::NOT_FOUND, format!("Unknown path {}", path));
+        };
+        let segments = segments.iter().map(|s| s.as_ref()).collect::<Vec<&str>>();
+
+        match segments.as_slice() {
+            [version, ..] if *version != VERSION => error(
+                StatusCode::BAD_REQUEST,
+                format!("Unsupported version {}, expected {}", version, VERSION),
+            ),
+            [_, CONTENTS] if method == Method::GET => self.list().await,
+            [_, CONTENTS, id] if method == Method::PUT => self.put(id, body).await,
+            [_, CONTENTS, id] if method == Method::GET => self.metadata(id).await,
+            [_, CONTENTS, id] if method == Method::DELETE => self.delete(id).await,
+            [_, CONTENTS, id, FILES, name] if method == Method::GET => {
+                self.file(id, name, headers.get(RANGE)).await
+            }
+            [_, CONTENTS, id, FILES, name] if method == Method::PUT => {
+                self.put_file(id, name, body).await
+            }
+            [_, CONTENTS] | [_, CONTENTS, _] | [_, CONTENTS, _, FILES, _] => {
+                error(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed")
+            }
+            _ => error(StatusCode::NOT_FOUND, format!("Unknown path {}", path)),
+        }
+    }
+
+    async fn list(&self) -> Response<Bytes> {
+        match self.files.list().await {
+            Ok(contents) => json(&ContentList { contents }),
+            Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        }
+    }
+
+    async fn put(&self, id: &str, body: &[u8]) -> Response<Bytes> {
+        let config = match serde_json::from_slice::<RemoteFileConfig>(body) {
+            Ok(config) => config,
+            Err(e) => return error(StatusCode::BAD_REQUEST, format!("Invalid content: {}", e)),
+        };
+        let names = config.files.iter().map(|file| file.name.as_str());
+        if let Err(e) = validate(id, names).and_then(|_| validate_files(&config.files)) {
+            return error(StatusCode::BAD_REQUEST, e.to_string());
+        }
+        if self.files.get_metadata(id).await.is_ok() {
+            if let Err(e) = self.files.delete(id).await {
+                return error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+            }
+        }
+        match self.files.insert_config(config, id.to_string()).await {
+            Ok(_) => empty(),
+            Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        }
+    }
+
+    async fn metadata(&self, id: &str) -> Response<Bytes> {
+        if let Err(e) = validate(id, []) {
+            return error(StatusCode::BAD_REQUEST, e.to_string());
+        }
+        match self.files.get_metadata(id).await {
+            Ok(metadata) => json(&metadata),
+            Err(_) => error(StatusCode::NOT_FOUND, format!("Content {} not found", id)),
+        }
+    }
+
+    async fn delete(&self, id: &str) -> Response<Bytes> {
+        if let Err(e) = validate(id, []) {
+            return error(StatusCode::BAD_REQUEST, e.to_string());
+        }
+        if self.files.get_metadata(id).await.is_err() {
+            return error(StatusCode::NOT_FOUND, format!("Content {} not found", id));
+        }
+        match self.files.delete(id).await {
+            Ok(_) => empty(),
+            Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        }
+    }
+
+    async fn file(&self, id: &str, name: &str, range: Option<&HeaderValue>) -> Response<Bytes> {
+        let range = validate(id, [name]).and_then(|_| {
+            range
+                .map(|range| ByteRange::parse(range.to_str()?))
+                .transpose()
+        });
+        let range = match range {
+            Ok(range) => range,
+            Err(e) => return error(StatusCode::BAD_REQUEST, e.to_string()),
+        };
+        let file = match self.files.get_stream(id, name, range).await {
+            Ok(file) => file,
+            Err(e) => {
+                return match e.downcast_ref::<RangeNotSatisfiable>() {
+                    Some(RangeNotSatisfiable { size }) => {
+                        let mut resp = error(StatusCode::RANGE_NOT_SATISFIABLE, e.to_string());
+                        let content_range = HeaderValue::from_str(&format!("bytes */{}", size));
+                        if let Ok(content_range) = content_range {
+                            resp.headers.insert(CONTENT_RANGE, content_range);
+                        }
+                        resp
+                    }
+                    None => error(
+                        StatusCode::NOT_FOUND,
+                        format!("File {} not found in {}", name, id),
+                    ),
+                };
+            }
+        };
+        let mut headers = headers("application/octet-stream");
+        headers.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+        let status = match range {
+            Some(_) => match HeaderValue::from_str(&file.content_range()) {
+                Ok(content_range) => {
+                    headers.insert(CONTENT_RANGE, content_range);
+                    StatusCode::PARTIAL_CONTENT
+                }
+                Err(e) => return error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+            },
+            None => StatusCode::OK,
+        };
+        match stream::collect(file.stream).await {
+            Ok(content) => Response::empty()
+                .status(status)
+                .headers(headers)
+                .body(Bytes::from(content)),
+            Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        }
+    }
+
+    async fn put_file(&self, id: &str, name: &str, body: &[u8]) -> Response<Bytes> {
+        if let Err(e) = validate(id, [name]) {
+            return error(StatusCode::BAD_REQUEST, e.to_string());
+        }
+        if self.files.get_metadata(id).await.is_err() {
+            return error(StatusCode::NOT_FOUND, format!("Content {} not found", id));
+        }
+        let body = stream::once(Bytes::copy_from_slice(body));
+        match self.files.put_stream(id, name, body).await {
+            Ok(_) => empty(),
+            Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        }
+    }
+}
+
+/// Ids and file names become path components on the local disk, see [super::safe_name].
+fn validate<'a>(id: &str, names: impl IntoIterator<Item = &'a str>) -> Result<()> {
+    ContentId::new(id)?;
+    for name in names {
+        SafeFileName::new(name)?;
+    }
+    Ok(())
+}
+
+fn headers(content_type: &'static str) -> reqwest::header::HeaderMap {
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    headers
+}
+
+fn json<T: Serialize>(body: &T) -> Response<Bytes> {
+    match serde_json::to_vec(body) {
+        Ok(body) => Response::empty()
+            .headers(headers("application/json"))
+            .body(Bytes::from(body)),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
+fn empty() -> Response<Bytes> {
+    Response::empty().status(StatusCode::NO_CONTENT)
+}
+
+fn error<T: Into<String>>(status: StatusCode, message: T) -> Response<Bytes> {
+    let body = serde_json::to_vec(&RemoteError {
+        message: message.into(),
+    })
+    .unwrap_or_default();
+    Response::empty()
+        .status(status)
+        .headers(headers("application/json"))
+        .body(Bytes::from(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_db::file_config::{FileHolder, Metadata};
+
+    fn server(dir: &str) -> Result<RemoteFileServer> {
+        let files = FileRequestHandler::new(crate::runtime::tests::init(), dir.to_string())?;
+        Ok(RemoteFileServer::new(files, "/lms/files/"))
+    }
+
+    fn config() -> RemoteFileConfig {
+        RemoteFileConfig {
+            files: vec![FileHolder {
+                name: "foo bar.txt".to_string(),
+                content: vec![0x00, 0xff, b'A'],
+            }],
+            metadata: Metadata {
+                title: "title".to_string(),
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_endpoint_keeps_base_path() -> Result<()> {
+        assert_eq!(
+            contents("http://localhost/lms/files/")?.as_str(),
+            "http://localhost/lms/files/v1/contents"
+        );
+        assert_eq!(
+            file("http://localhost/lms/files", "id", "foo bar.txt")?.as_str(),
+            "http://localhost/lms/files/v1/contents/id/files/foo%20bar.txt"
+        );
+        assert_eq!(
+            content("http://localhost", "id")?.as_str(),
+            "http://localhost/v1/contents/id"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() -> Result<()> {
+        let server = server("files")?;
+        let body = serde_json::to_vec(&config())?;
+
+        let resp = server
+            .handle(
+                &Method::PUT,
+                "/lms/files/v1/contents/id",
+                &HeaderMap::new(),
+                &body,
+            )
+            .await;
+        assert_eq!(resp.status, StatusCode::NO_CONTENT);
+        let resp = server
+            .handle(
+                &Method::GET,
+                "/lms/files/v1/contents/id",
+                &HeaderMap::new(),
+                &[],
+            )
+            .await;
+        assert_eq!(resp.to_json::<Metadata>()?.body, config().metadata);
+        let resp = server
+            .handle(
+                &Method::GET,
+                "/lms/files/v1/contents/id/files/foo%20bar.txt",
+                &HeaderMap::new(),
+                &[],
+            )
+            .await;
+        assert_eq!(resp.body.as_ref(), [0x00, 0xff, b'A']);
+        let resp = server
+            .handle(
+                &Method::GET,
+                "/lms/files/v1/contents",
+                &HeaderMap::new(),
+                &[],
+            )
+            .await;
+        assert_eq!(resp.to_json::<ContentList>()?.body.contents, vec!["id"]);
+
+        let resp = server
+            .handle(
+                &Method::DELETE,
+                "/lms/files/v1/contents/id",
+                &HeaderMap::new(),
+                &[],
+            )
+            .await;
+        assert_eq!(resp.status, StatusCode::NO_CONTENT);
+        let resp = server
+            .handle(
+                &Method::GET,
+                "/lms/files/v1/contents/id",
+                &HeaderMap::new(),
+                &[],
+            )
+            .await;
+        assert_eq!(resp.status, StatusCode::NOT_FOUND);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_file_put_and_ranges() -> Result<()> {
+        let server = server("files")?;
+        let path = "/lms/files/v1/contents/id/files/notes.pdf";
+        let none = HeaderMap::new();
+        let range = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(RANGE, HeaderValue::from_static(value));
+            headers
+        };
+
+        let resp = server
+            .handle(&Method::PUT, path, &none, b"0123456789")
+            .await;
+        assert_eq!(resp.status, StatusCode::NOT_FOUND);
+        let body = serde_json::to_vec(&config())?;
+        server
+            .handle(&Method::PUT, "/lms/files/v1/contents/id", &none, &body)
+            .await;
+        let resp = server
+            .handle(&Method::PUT, path, &none, b"0123456789")
+            .await;
+        assert_eq!(resp.status, StatusCode::NO_CONTENT);
+
+        let resp = server.handle(&Method::GET, path, &none, &[]).await;
+        assert_eq!(resp.status, StatusCode::OK);
+        assert_eq!(resp.body.as_ref(), b"0123456789");
+        let resp = server
+            .handle(&Method::GET, path, &range("bytes=2-4"), &[])
+            .await;
+        assert_eq!(resp.status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(resp.headers[CONTENT_RANGE], "bytes 2-4/10");
+        assert_eq!(resp.body.as_ref(), b"234");
+        let resp = server
+            .handle(&Method::GET, path, &range("bytes=10-"), &[])
+            .await;
+        assert_eq!(resp.status, StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(resp.headers[CONTENT_RANGE], "bytes */10");
+        let resp = server
+            .handle(&Method::GET, path, &range("lines=1-2"), &[])
+            .await;
+        assert_eq!(resp.status, StatusCode::BAD_REQUEST);
+
+        // the file is listed in the content along with the inserted ones
+        assert_eq!(server.files.export("id").await?.files.len(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rejected_requests() -> Result<()> {
+        let server = server("files")?;
+        let status = |resp: Response<Bytes>| resp.status;
+
+        let resp = server
+            .handle(&Method::GET, "/v1/contents", &HeaderMap::new(), &[])
+            .await;
+        assert_eq!(status(resp), StatusCode::NOT_FOUND);
+        let resp = server
+            .handle(
+                &Method::GET,
+                "/lms/files/v2/contents",
+                &HeaderMap::new(),
+                &[],
+            )
+            .await;
+        assert_eq!(status(resp), StatusCode::BAD_REQUEST);
+        let resp = server
+            .handle(
+                &Method::POST,
+                "/lms/files/v1/contents/id",
+                &HeaderMap::new(),
+                &[],
+            )
+            .await;
+        assert_eq!(status(resp), StatusCode::METHOD_NOT_ALLOWED);
+        let resp = server
+            .handle(
+                &Method::PUT,
+                "/lms/files/v1/contents/id",
+                &HeaderMap::new(),
+                b"{}",
+            )
+            .await;
+        assert_eq!(status(resp), StatusCode::BAD_REQUEST);
+
+        let mut traversal = config();
+        traversal.files[0].name = "../foo.txt".to_string();
+        let body = serde_json::to_vec(&traversal)?;
+        let resp = server
+            .handle(
+                &Method::PUT,
+                "/lms/files/v1/contents/id",
+                &HeaderMap::new(),
+                &body,
+            )
+            .await;
+        assert_eq!(status(resp), StatusCode::BAD_REQUEST);
+        let resp = server
+            .handle(
+                &Method::GET,
+                "/lms/files/v1/contents/%2E%2E",
+                &HeaderMap::new(),
+                &[],
+            )
+            .await;
+        assert_eq!(status(resp), StatusCode::BAD_REQUEST);
+        let resp = server
+            .handle(
+                &Method::DELETE,
+                "/lms/files/v1/contents/unknown",
+                &HeaderMap::new(),
+                &[],
+            )
+            .await;
+        assert_eq!(status(resp), StatusCode::NOT_FOUND);
+        Ok(())
+    }
+}
@@ -1,31 +1,60 @@
 #![allow(unused)]
 
 use anyhow::{anyhow, Context};
-use serde_json::json;
+use reqwest::header::{HeaderValue, CONTENT_RANGE, CONTENT_TYPE, RANGE};
+use reqwest::StatusCode;
 use std::path::PathBuf;
 
+use lms_auth::keyring::Keyring;
+use lms_auth::local_crypto::ciphertext_key_id;
+
 use crate::runtime::TargetRuntime;
+#[cfg(feature = "sqlite")]
+use crate::sqlite::SqliteDb;
+use crate::stream::{self, ByteRange, ByteStream, FileStream, RangeNotSatisfiable};
 use crate::uid_gen::UidGenerator;
 
+use super::encryption::{self, aad, plaintext_len, DataKey, HEADER_LEN};
 use super::file_config::{FileHolder, InsertionInfo, LocalFileConfig, Metadata, RemoteFileConfig};
+use super::remote::{self, ContentList};
+use super::safe_name::{ContentId, SafeFileName, CONFIG_FILE};
 
 const MAX_FILE_SIZE: usize = 1024 * 1024 * 10; // 10MB
 
-// TODO: Add some encryption
-
 pub struct FileRequestHandler {
     target_runtime: TargetRuntime,
     db_dir: String,
     is_url: bool,
+    /// Wraps the keys of encrypted contents, without it files are stored and read as is
+    keyring: Option<Keyring>,
+    /// Encrypt the files of new contents
+    encrypt: bool,
+    /// Set for a `sqlite://` path, the contents are stored in the database instead of `db_dir`
+    #[cfg(feature = "sqlite")]
+    sqlite: Option<SqliteDb>,
 }
 
 impl FileRequestHandler {
-    pub fn new(target_runtime: TargetRuntime, file_db_path: String) -> Self {
-        Self {
+    pub fn new(target_runtime: TargetRuntime, file_db_path: String) -> anyhow::Result<Self> {
+        Ok(Self {
             target_runtime,
             is_url: file_db_path.starts_with("http"), // assuming it's a valid url verified during config -> blueprint conversion
+            #[cfg(feature = "sqlite")]
+            sqlite: crate::sqlite_path(&file_db_path)
+                .map(SqliteDb::open)
+                .transpose()?,
             db_dir: file_db_path,
-        }
+            keyring: None,
+            encrypt: false,
+        })
+    }
+
+    /// Decrypts the files of encrypted contents with keys wrapped by `keyring`,
+    /// and encrypts the files of new contents when `encrypt` is set, see [super::encryption].
+    pub fn with_keyring(mut self, keyring: Keyring, encrypt: bool) -> Self {
+        self.keyring = Some(keyring);
+        self.encrypt = encrypt;
+        self
     }
 
     pub async fn insert(
@@ -33,29 +62,55 @@ impl FileRequestHandler {
         insertion_info: InsertionInfo,
         files: Vec<FileHolder>,
     ) -> anyhow::Result<String> {
-        self.insert_inner(insertion_info, files, gen_uid(&self.target_runtime)?)
+        let config = RemoteFileConfig::combine_info(insertion_info, files);
+        self.insert_config(config, gen_uid(&self.target_runtime)?)
             .await
     }
 
-    async fn insert_inner(
+    /// Stores `config` under `uid`, see [FileRequestHandler::insert].
+    pub(super) async fn insert_config(
         &self,
-        insertion_info: InsertionInfo,
-        files: Vec<FileHolder>,
+        mut config: RemoteFileConfig,
         uid: String,
     ) -> anyhow::Result<String> {
-        validate_files(&files)?;
+        let uid = ContentId::new(&uid)?;
+        let mut names = vec![];
+        for file in &mut config.files {
+            file.name = SafeFileName::new(&file.name)?.to_string();
+            if names.contains(&file.name) {
+                return Err(anyhow!("Duplicate file {}", file.name));
+            }
+            names.push(file.name.clone());
+        }
+        validate_files(&config.files)?;
+        if let (true, Some(keyring)) = (self.encrypt, &self.keyring) {
+            let key = DataKey::generate()?;
+            for file in &mut config.files {
+                file.content = key.encrypt(&aad(uid.as_str(), &file.name), &file.content)?;
+            }
+            config.metadata.data_key = Some(key.wrap(keyring)?);
+        }
+        self.store(&uid, config).await?;
+        Ok(uid.to_string())
+    }
+
+    /// Writes `config` as is, replacing the content with the same id.
+    async fn store(&self, uid: &ContentId, config: RemoteFileConfig) -> anyhow::Result<()> {
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = &self.sqlite {
+            return db.insert_content(uid.as_str(), &config);
+        }
         if self.is_url {
-            let mut url = url::Url::parse(&self.db_dir)?;
-            url.set_path(&uid);
-            let mut req = reqwest::Request::new(reqwest::Method::POST, url);
-            let file_config =
-                serde_json::to_string(&RemoteFileConfig::combine_info(insertion_info, files))
-                    .map_err(|e| {
-                        anyhow!(
-                            "Unable to generate body for further request with err: {}",
-                            e
-                        )
-                    })?;
+            let url = remote::content(&self.db_dir, uid.as_str())?;
+            let mut req = reqwest::Request::new(reqwest::Method::PUT, url);
+            let file_config = serde_json::to_string(&config).map_err(|e| {
+                anyhow!(
+                    "Unable to generate body for further request with err: {}",
+                    e
+                )
+            })?;
+            req.headers_mut()
+                .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
             *req.body_mut() = Some(reqwest::Body::from(file_config));
 
             let response = self
@@ -69,41 +124,79 @@ impl FileRequestHandler {
                 return Err(anyhow::anyhow!("Failed to insert into remote server"));
             }
         } else {
-            let mut pathbuf = std::path::PathBuf::from(&self.db_dir);
-            pathbuf.push(&uid);
-            let path = pathbuf.to_str().context("Unable to generate path")?;
+            let path = self.local_path(uid, None)?;
             self.target_runtime
                 .file
-                .create_dirs(path)
+                .create_dirs(&path)
                 .await
                 .map_err(|e| anyhow!("Unable to create dir for uid: {} with err: {}", uid, e))?;
 
-            let local_config = LocalFileConfig::combine_info(insertion_info, &files);
-            for file in files {
-                let path = PathBuf::from(path).join(&file.name);
-                let path = path.to_str().context("Unable to generate path1")?;
+            let local_config = LocalFileConfig {
+                files: config.files.iter().map(|file| file.name.clone()).collect(),
+                metadata: config.metadata,
+            };
+            for file in config.files {
+                let path = self.local_path(uid, Some(&SafeFileName::new(&file.name)?))?;
 
                 self.target_runtime
                     .file
-                    .write(path, file.content.as_ref())
+                    .write(&path, file.content.as_ref())
                     .await?;
             }
             let local_config = serde_json::to_string(&local_config)?;
-            let path = PathBuf::from(path).join("config.json");
-            let path = path.to_str().context("Unable to generate path2")?;
+            let path = self.config_path(uid)?;
 
             self.target_runtime
                 .file
-                .write(path, local_config.as_bytes())
+                .write(&path, local_config.as_bytes())
                 .await?;
         }
-        Ok(uid)
+        Ok(())
     }
 
+    /// Dir of a content in a local FileDB, or the path of one of its files.
+    fn local_path(
+        &self,
+        uid: &ContentId,
+        file_name: Option<&SafeFileName>,
+    ) -> anyhow::Result<String> {
+        let mut path = PathBuf::from(&self.db_dir).join(uid.as_str());
+        if let Some(file_name) = file_name {
+            path.push(file_name.as_str());
+        }
+        Ok(path
+            .to_str()
+            .context("Unable to generate path")?
+            .to_string())
+    }
+
+    /// Metadata and file names of a content in a local FileDB, see [LocalFileConfig].
+    fn config_path(&self, uid: &ContentId) -> anyhow::Result<String> {
+        let path = PathBuf::from(&self.db_dir)
+            .join(uid.as_str())
+            .join(CONFIG_FILE);
+        Ok(path
+            .to_str()
+            .context("Unable to generate path")?
+            .to_string())
+    }
+
+    /// Metadata of a content, the key of its files is only handed out without a keyring.
     pub async fn get_metadata(&self, uid: &str) -> anyhow::Result<Metadata> {
+        let mut metadata = self.metadata(&ContentId::new(uid)?).await?;
+        if self.keyring.is_some() {
+            metadata.data_key = None;
+        }
+        Ok(metadata)
+    }
+
+    async fn metadata(&self, uid: &ContentId) -> anyhow::Result<Metadata> {
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = &self.sqlite {
+            return db.get_metadata(uid.as_str());
+        }
         if self.is_url {
-            let mut url = url::Url::parse(&self.db_dir)?;
-            url.set_path(uid);
+            let url = remote::content(&self.db_dir, uid.as_str())?;
             let req = reqwest::Request::new(reqwest::Method::GET, url);
             let response = self.target_runtime.http.execute(req).await.map_err(|e| {
                 anyhow!("Failed to get metadata from remote server with err: {}", e)
@@ -116,27 +209,63 @@ impl FileRequestHandler {
             let body = response.to_json::<Metadata>()?.body;
             Ok(body)
         } else {
-            let mut pathbuf = std::path::PathBuf::from(&self.db_dir);
-            pathbuf.push(uid);
-            let path = pathbuf.join("config.json");
-            let path = path.to_str().context("Unable to generate path")?;
-            let content = self.target_runtime.file.read(path).await?;
+            let path = self.config_path(uid)?;
+            let content = self.target_runtime.file.read(&path).await?;
             let config: LocalFileConfig = serde_json::from_str(&content)?;
             Ok(config.metadata)
         }
     }
 
     pub async fn get(&self, uid: &str, file_name: &str) -> anyhow::Result<FileHolder> {
+        let uid = ContentId::new(uid)?;
+        let file_name = SafeFileName::new(file_name)?;
+        let mut content = self.read_file(&uid, &file_name).await?;
+        if let Some(key) = self.file_key(&uid, &file_name).await? {
+            content = key.decrypt(&aad(uid.as_str(), file_name.as_str()), &content)?;
+        }
+        Ok(FileHolder {
+            name: file_name.to_string(),
+            content,
+        })
+    }
+
+    /// Unwrapped key of an encrypted file, none without a keyring
+    /// or for a file left in plaintext, see [Metadata::plain_files].
+    async fn file_key(
+        &self,
+        uid: &ContentId,
+        file_name: &SafeFileName,
+    ) -> anyhow::Result<Option<DataKey>> {
+        let Some(keyring) = &self.keyring else {
+            return Ok(None);
+        };
+        let metadata = self.metadata(uid).await?;
+        if metadata
+            .plain_files
+            .iter()
+            .any(|name| name == file_name.as_str())
+        {
+            return Ok(None);
+        }
+        metadata
+            .data_key
+            .map(|wrapped| DataKey::unwrap(keyring, &wrapped))
+            .transpose()
+    }
+
+    /// A file as stored, encrypted or not.
+    async fn read_file(
+        &self,
+        uid: &ContentId,
+        file_name: &SafeFileName,
+    ) -> anyhow::Result<Vec<u8>> {
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = &self.sqlite {
+            return Ok(db.get_file(uid.as_str(), file_name.as_str())?.content);
+        }
         if self.is_url {
-            let mut url = url::Url::parse(&self.db_dir)?;
-            url.set_path(uid);
-            let mut req = reqwest::Request::new(reqwest::Method::POST, url);
-            *req.body_mut() = Some(reqwest::Body::from(
-                json!({
-                    "file_name": file_name,
-                })
-                .to_string(),
-            ));
+            let url = remote::file(&self.db_dir, uid.as_str(), file_name.as_str())?;
+            let req = reqwest::Request::new(reqwest::Method::GET, url);
             let response = self
                 .target_runtime
                 .http
@@ -148,19 +277,342 @@ impl FileRequestHandler {
                 return Err(anyhow::anyhow!("Failed to get from remote server"));
             }
 
-            let body = response.to_json::<FileHolder>()?.body;
-            Ok(body)
+            Ok(response.body.to_vec())
+        } else {
+            let path = self.local_path(uid, Some(file_name))?;
+            self.target_runtime.file.read_bytes(&path).await
+        }
+    }
+
+    /// Writes a file of a stored content from `stream`, replacing the file with the same name,
+    /// and returns its size. Only a local FileDB streams to disk, the others buffer the file.
+    /// A file of an encrypted content is encrypted as it streams.
+    pub async fn put_stream(
+        &self,
+        uid: &str,
+        file_name: &str,
+        stream: ByteStream,
+    ) -> anyhow::Result<u64> {
+        let uid = ContentId::new(uid)?;
+        let file_name = SafeFileName::new(file_name)?;
+        match self.file_key(&uid, &file_name).await? {
+            Some(key) => {
+                let aad = aad(uid.as_str(), file_name.as_str());
+                let encrypted = key.encrypt_stream(aad, stream)?;
+                plaintext_len(self.write_file(&uid, &file_name, encrypted).await?)
+            }
+            None => self.write_file(&uid, &file_name, stream).await,
+        }
+    }
+
+    /// Writes a file as is, see [FileRequestHandler::put_stream].
+    async fn write_file(
+        &self,
+        uid: &ContentId,
+        file_name: &SafeFileName,
+        stream: ByteStream,
+    ) -> anyhow::Result<u64> {
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = &self.sqlite {
+            let file = FileHolder {
+                name: file_name.to_string(),
+                content: stream::collect(stream).await?,
+            };
+            db.put_file(uid.as_str(), &file)?;
+            return Ok(file.content.len() as u64);
+        }
+        if self.is_url {
+            let content = stream::collect(stream).await?;
+            let size = content.len() as u64;
+            let url = remote::file(&self.db_dir, uid.as_str(), file_name.as_str())?;
+            let mut req = reqwest::Request::new(reqwest::Method::PUT, url);
+            req.headers_mut().insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static("application/octet-stream"),
+            );
+            *req.body_mut() = Some(reqwest::Body::from(content));
+
+            let response = self
+                .target_runtime
+                .http
+                .execute(req)
+                .await
+                .map_err(|e| anyhow!("Failed to upload to remote server with err: {}", e))?;
+
+            if !response.status.is_success() {
+                return Err(anyhow::anyhow!("Failed to upload to remote server"));
+            }
+            Ok(size)
+        } else {
+            let config_path = self.config_path(uid)?;
+            let mut config =
+                LocalFileConfig::deserialize(&self.target_runtime.file.read(&config_path).await?)?;
+
+            let path = self.local_path(uid, Some(file_name))?;
+            let size = self.target_runtime.file.write_stream(&path, stream).await?;
+
+            if !config.files.iter().any(|name| name == file_name.as_str()) {
+                config.files.push(file_name.to_string());
+                self.target_runtime
+                    .file
+                    .write(&config_path, config.serialize()?.as_bytes())
+                    .await?;
+            }
+            Ok(size)
+        }
+    }
+
+    /// Streams `range` of a file of a stored content, fails with [RangeNotSatisfiable].
+    pub async fn get_stream(
+        &self,
+        uid: &str,
+        file_name: &str,
+        range: Option<ByteRange>,
+    ) -> anyhow::Result<FileStream> {
+        let uid = ContentId::new(uid)?;
+        let file_name = SafeFileName::new(file_name)?;
+        let Some(key) = self.file_key(&uid, &file_name).await? else {
+            return self.read_stream(&uid, &file_name, range).await;
+        };
+        // the header is read first, it tells the size of the file as well
+        let header_range = ByteRange::From {
+            start: 0,
+            end: Some(HEADER_LEN as u64 - 1),
+        };
+        let header = self
+            .read_stream(&uid, &file_name, Some(header_range))
+            .await?;
+        let sealed_size = header.size;
+        let header = stream::collect(header.stream).await?;
+        let size = plaintext_len(sealed_size)?;
+        let range = stream::resolve(range, size)?;
+        if range.is_empty() {
+            return Ok(FileStream {
+                size,
+                range,
+                stream: stream::once(Default::default()),
+            });
+        }
+        // only the chunks holding the range are read and decrypted
+        let sealed = encryption::sealed_range(&range, sealed_size);
+        let sealed_range = ByteRange::From {
+            start: sealed.start,
+            end: Some(sealed.end - 1),
+        };
+        let chunks = self
+            .read_stream(&uid, &file_name, Some(sealed_range))
+            .await?;
+        if chunks.range != sealed || chunks.size != sealed_size {
+            return Err(anyhow!("File {} changed while read", file_name));
+        }
+        let aad = aad(uid.as_str(), file_name.as_str());
+        Ok(FileStream {
+            size,
+            stream: key.decrypt_range(aad, &header, sealed_size, range.clone(), chunks.stream)?,
+            range,
+        })
+    }
+
+    /// Streams `range` of a file as stored, see [FileRequestHandler::read_file].
+    async fn read_stream(
+        &self,
+        uid: &ContentId,
+        file_name: &SafeFileName,
+        range: Option<ByteRange>,
+    ) -> anyhow::Result<FileStream> {
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = &self.sqlite {
+            return FileStream::from_bytes(
+                db.get_file(uid.as_str(), file_name.as_str())?.content,
+                range,
+            );
+        }
+        if self.is_url {
+            let url = remote::file(&self.db_dir, uid.as_str(), file_name.as_str())?;
+            let mut req = reqwest::Request::new(reqwest::Method::GET, url);
+            if let Some(range) = range {
+                req.headers_mut()
+                    .insert(RANGE, HeaderValue::from_str(&range.to_string())?);
+            }
+            let response = self
+                .target_runtime
+                .http
+                .execute(req)
+                .await
+                .map_err(|e| anyhow!("Failed to get from remote server with err: {}", e))?;
+            let content_range = response
+                .headers
+                .get(CONTENT_RANGE)
+                .and_then(|value| value.to_str().ok());
+
+            match response.status {
+                StatusCode::OK => FileStream::from_bytes(response.body.to_vec(), None),
+                StatusCode::PARTIAL_CONTENT => {
+                    let content_range = content_range.context("Missing Content-Range")?;
+                    let (range, size) = FileStream::parse_content_range(content_range)?;
+                    if range.end - range.start != response.body.len() as u64 {
+                        return Err(anyhow!("Content-Range doesn't match the body"));
+                    }
+                    Ok(FileStream {
+                        size,
+                        range,
+                        stream: stream::once(response.body),
+                    })
+                }
+                StatusCode::RANGE_NOT_SATISFIABLE => {
+                    let size = content_range
+                        .and_then(|value| value.strip_prefix("bytes */"))
+                        .and_then(|size| size.parse().ok())
+                        .context("Missing Content-Range")?;
+                    Err(RangeNotSatisfiable { size }.into())
+                }
+                _ => Err(anyhow::anyhow!("Failed to get from remote server")),
+            }
+        } else {
+            let path = self.local_path(uid, Some(file_name))?;
+            self.target_runtime.file.read_stream(&path, range).await
+        }
+    }
+
+    /// Removes a content along with its files.
+    pub async fn delete(&self, uid: &str) -> anyhow::Result<()> {
+        let uid = ContentId::new(uid)?;
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = &self.sqlite {
+            if !db.delete_content(uid.as_str())? {
+                return Err(anyhow!("Content {} not found", uid));
+            }
+            return Ok(());
+        }
+        if self.is_url {
+            let url = remote::content(&self.db_dir, uid.as_str())?;
+            let req = reqwest::Request::new(reqwest::Method::DELETE, url);
+            let response = self
+                .target_runtime
+                .http
+                .execute(req)
+                .await
+                .map_err(|e| anyhow!("Failed to delete from remote server with err: {}", e))?;
+
+            if !response.status.is_success() {
+                return Err(anyhow::anyhow!("Failed to delete from remote server"));
+            }
+            Ok(())
         } else {
-            let mut pathbuf = std::path::PathBuf::from(&self.db_dir);
-            pathbuf.push(uid);
-            pathbuf.push(file_name);
-            let path = pathbuf.to_str().context("Unable to generate path")?;
-            let content = self.target_runtime.file.read(path).await?;
-            Ok(FileHolder {
+            let path = self.local_path(&uid, None)?;
+            self.target_runtime.file.delete(&path).await
+        }
+    }
+
+    /// Ids of every stored content.
+    pub async fn list(&self) -> anyhow::Result<Vec<String>> {
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = &self.sqlite {
+            return db.list_contents();
+        }
+        if self.is_url {
+            let url = remote::contents(&self.db_dir)?;
+            let req = reqwest::Request::new(reqwest::Method::GET, url);
+            let response = self
+                .target_runtime
+                .http
+                .execute(req)
+                .await
+                .map_err(|e| anyhow!("Failed to list remote server with err: {}", e))?;
+
+            if !response.status.is_success() {
+                return Err(anyhow::anyhow!("Failed to list remote server"));
+            }
+
+            Ok(response.to_json::<ContentList>()?.body.contents)
+        } else {
+            self.target_runtime.file.list(&self.db_dir).await
+        }
+    }
+
+    /// Metadata along with every file of a content as stored, used to export it.
+    /// A remote FileDB doesn't list the files of a content, so it can't be exported.
+    pub async fn export(&self, uid: &str) -> anyhow::Result<RemoteFileConfig> {
+        let uid = ContentId::new(uid)?;
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = &self.sqlite {
+            return db.get_content(uid.as_str());
+        }
+        if self.is_url {
+            return Err(anyhow!("A remote FileDB can't be exported"));
+        }
+        let path = self.config_path(&uid)?;
+        let config = LocalFileConfig::deserialize(&self.target_runtime.file.read(&path).await?)?;
+        let mut files = vec![];
+        for file_name in &config.files {
+            let file_name = SafeFileName::new(file_name)?;
+            files.push(FileHolder {
+                content: self.read_file(&uid, &file_name).await?,
                 name: file_name.to_string(),
-                content,
-            })
+            });
+        }
+        Ok(RemoteFileConfig {
+            files,
+            metadata: config.metadata,
+        })
+    }
+
+    /// Encrypts the files of every content which aren't yet, and re-wraps the keys of the
+    /// others with the active key. Returns the number of contents rewritten.
+    pub async fn encrypt_all(&self) -> anyhow::Result<usize> {
+        if self.is_url {
+            return Err(anyhow!(
+                "Encrypt a remote FileDB by passing its dir as the FileDB"
+            ));
+        }
+        let mut rewritten = 0;
+        for uid in self.list().await? {
+            if self
+                .encrypt_content(&uid)
+                .await
+                .map_err(|e| anyhow!("Unable to encrypt content {}: {}", uid, e))?
+            {
+                rewritten += 1;
+            }
+        }
+        Ok(rewritten)
+    }
+
+    /// See [FileRequestHandler::encrypt_all], false when the content was left as is.
+    async fn encrypt_content(&self, uid: &str) -> anyhow::Result<bool> {
+        let keyring = self.keyring.as_ref().context("No key to encrypt with")?;
+        let mut config = self.export(uid).await?;
+        let uid = ContentId::new(uid)?;
+        let (key, rewrap) = match &config.metadata.data_key {
+            Some(wrapped) => (
+                DataKey::unwrap(keyring, wrapped)?,
+                ciphertext_key_id(wrapped.as_bytes()) != Some(keyring.active().id()),
+            ),
+            None => {
+                config.metadata.plain_files =
+                    config.files.iter().map(|file| file.name.clone()).collect();
+                (DataKey::generate()?, true)
+            }
+        };
+        if rewrap {
+            // stored before any file is encrypted with it, so an interrupted run can be resumed
+            config.metadata.data_key = Some(key.wrap(keyring)?);
+            self.store(&uid, config.clone()).await?;
+        }
+        let plaintext = !config.metadata.plain_files.is_empty();
+        if plaintext {
+            let plain_files = std::mem::take(&mut config.metadata.plain_files);
+            for file in &mut config.files {
+                let aad = aad(uid.as_str(), &file.name);
+                // a run interrupted while storing the files leaves some of them encrypted
+                // but still listed, only those open with the key
+                if plain_files.contains(&file.name) && key.decrypt(&aad, &file.content).is_err() {
+                    file.content = key.encrypt(&aad, &file.content)?;
+                }
+            }
+            self.store(&uid, config).await?;
         }
+        Ok(rewrap || plaintext)
     }
 }
 
@@ -176,7 +628,7 @@ fn gen_uid(target_runtime: &TargetRuntime) -> anyhow::Result<String> {
     Ok(uid)
 }
 
-fn validate_files(files: &Vec<FileHolder>) -> anyhow::Result<()> {
+pub(crate) fn validate_files(files: &[FileHolder]) -> anyhow::Result<()> {
     for v in files {
         if v.content.len() > MAX_FILE_SIZE {
             return Err(anyhow!("File {} exceeds size limit", v.name));
@@ -187,6 +639,7 @@ fn validate_files(files: &Vec<FileHolder>) -> anyhow::Result<()> {
 
 #[cfg(test)]
 mod tests {
+    use super::super::encryption::tests::keyring;
     use super::super::file_config::FileHolder;
     use super::*;
     use crate::authdb::auth_actors::Authority;
@@ -198,7 +651,7 @@ mod tests {
 
     #[test]
     fn test_validate_files_exceeds_size() {
-        let large_content = String::from_utf8(vec![b'0'; MAX_FILE_SIZE + 1]).unwrap(); // content larger than 10MB
+        let large_content = vec![b'0'; MAX_FILE_SIZE + 1]; // content larger than 10MB
         let file_holder = FileHolder {
             name: "large_file.txt".to_string(),
             content: large_content,
@@ -210,7 +663,7 @@ mod tests {
 
     #[test]
     fn test_validate_files_within_limit() {
-        let content = String::from_utf8(vec![b'0'; MAX_FILE_SIZE]).unwrap(); // exactly 10MB
+        let content = vec![b'0'; MAX_FILE_SIZE]; // exactly 10MB
         let file_holder = FileHolder {
             name: "valid_size_file.txt".to_string(),
             content,
@@ -222,51 +675,58 @@ mod tests {
 
     #[tokio::test]
     async fn test_insert_into_remote() {
-        let req = r#"{"files":[{"name":"test.txt","content":"AQBF"}],"metadata":{"title":"","description":"","timestamp":0}}"#;
+        let req = r#"{"files":[{"name":"test.txt","encoding":"base64","content":"AQBF"}],"metadata":{"title":"","description":"","timestamp":0}}"#;
         let rt = crate::runtime::tests::init();
         let uid = gen_uid(&rt).unwrap();
 
         let server = start_mock_server();
         server.mock(|w, t| {
             w.body(req)
-                .path(format!("/{}", uid))
-                .method(httpmock::Method::POST);
-            t.status(200).body("ok");
+                .path(format!("/lms/files/v1/contents/{}", uid))
+                .method(httpmock::Method::PUT);
+            t.status(204);
         });
 
-        let mut handler = FileRequestHandler::new(rt, server.base_url());
+        let mut handler = FileRequestHandler::new(rt, server.url("/lms/files/")).unwrap();
 
         let files = vec![FileHolder {
             name: "test.txt".to_string(),
-            content: "AQBF".to_string(),
+            content: vec![1, 0, 69],
         }];
         let insertion_info = InsertionInfo {
             title: "".to_string(),
             description: "".to_string(),
             timestamp: 0,
             end_time: None,
+            author: None,
+            assignment: None,
+            grade: None,
+            submission: None,
         };
 
-        let result = handler
-            .insert_inner(insertion_info, files, uid.to_string())
-            .await;
+        let config = RemoteFileConfig::combine_info(insertion_info, files);
+        let result = handler.insert_config(config, uid.to_string()).await;
         assert_eq!(result.unwrap(), uid);
     }
 
     #[tokio::test]
     async fn test_insert_fail_remote() {
         let rt = crate::runtime::tests::init();
-        let mut handler = FileRequestHandler::new(rt, "http://example.com".to_string());
+        let mut handler = FileRequestHandler::new(rt, "http://example.com".to_string()).unwrap();
 
         let files = vec![FileHolder {
             name: "test.txt".to_string(),
-            content: "AQBF".to_string(),
+            content: vec![1, 0, 69],
         }];
         let insertion_info = InsertionInfo {
             title: "".to_string(),
             description: "".to_string(),
             timestamp: 0,
             end_time: None,
+            author: None,
+            assignment: None,
+            grade: None,
+            submission: None,
         };
 
         let result = handler.insert(insertion_info, files).await;
@@ -279,16 +739,20 @@ mod tests {
         let name = tmpdir.path().to_str().unwrap();
         let rt = crate::runtime::tests::init();
 
-        let handler = FileRequestHandler::new(rt, name.to_string());
+        let handler = FileRequestHandler::new(rt, name.to_string()).unwrap();
         let info = InsertionInfo {
             title: "title".to_string(),
             description: "description".to_string(),
             timestamp: 1,
             end_time: None,
+            author: None,
+            assignment: None,
+            grade: None,
+            submission: None,
         };
 
         let file_name = "foo.txt";
-        let content = "AQBF".to_string();
+        let content = vec![0x50, 0x4b, 0x03, 0x04, 0x00, 0xff];
         let meta = FileHolder {
             name: file_name.to_string(),
             content: content.clone(),
@@ -305,6 +769,213 @@ mod tests {
         let result = handler.get(&uid, file_name).await.unwrap();
         assert_eq!(result.name, file_name);
         assert_eq!(result.content, content);
+
+        assert_eq!(handler.list().await.unwrap(), vec![uid.clone()]);
+        handler.delete(&uid).await.unwrap();
+        assert!(handler.get_metadata(&uid).await.is_err());
+        assert!(handler.list().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_traversal_in_memory() -> anyhow::Result<()> {
+        let rt = crate::runtime::tests::init();
+        rt.file.write("/lms/auth.json", b"secret").await?;
+        let handler = FileRequestHandler::new(rt.clone(), "/lms/files".to_string())?;
+        let info = || InsertionInfo {
+            title: "title".to_string(),
+            description: "description".to_string(),
+            timestamp: 1,
+            end_time: None,
+            author: None,
+            assignment: None,
+            grade: None,
+            submission: None,
+        };
+        let file = |name: &str| FileHolder {
+            name: name.to_string(),
+            content: b"evil".to_vec(),
+        };
+
+        for name in [
+            "../../auth.json",
+            "..",
+            "config.json",
+            "a/b",
+            "notes.pdf.part",
+        ] {
+            assert!(handler.insert(info(), vec![file(name)]).await.is_err());
+        }
+        let twice = vec![file("notes.pdf"), file(" notes.pdf")];
+        assert!(handler.insert(info(), twice).await.is_err());
+
+        // names are stored normalized
+        let uid = handler.insert(info(), vec![file(" notes.pdf ")]).await?;
+        assert_eq!(handler.get(&uid, "notes.pdf").await?.name, "notes.pdf");
+
+        for name in ["../../auth.json", "../config.json", "config.json"] {
+            assert!(handler.get(&uid, name).await.is_err());
+            assert!(handler.get_stream(&uid, name, None).await.is_err());
+            let content = crate::stream::once(bytes::Bytes::from_static(b"evil"));
+            assert!(handler.put_stream(&uid, name, content).await.is_err());
+        }
+        for id in ["..", "../..", "../../lms", ""] {
+            assert!(handler.get_metadata(id).await.is_err());
+            assert!(handler.get(id, "auth.json").await.is_err());
+            assert!(handler.delete(id).await.is_err());
+        }
+        assert_eq!(rt.file.read("/lms/auth.json").await?, "secret");
+        assert_eq!(handler.list().await?, vec![uid]);
+        Ok(())
+    }
+
+    fn encrypted(
+        rt: &TargetRuntime,
+        path: &str,
+        keyring: &Keyring,
+    ) -> anyhow::Result<FileRequestHandler> {
+        Ok(FileRequestHandler::new(rt.clone(), path.to_string())?
+            .with_keyring(keyring.clone(), true))
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_contents() -> anyhow::Result<()> {
+        let rt = crate::runtime::tests::init();
+        let master = keyring("2024", "master key");
+        let handler = encrypted(&rt, "/lms/files", &master)?;
+        let info = InsertionInfo {
+            title: "title".to_string(),
+            description: "description".to_string(),
+            timestamp: 1,
+            end_time: None,
+            author: None,
+            assignment: None,
+            grade: None,
+            submission: None,
+        };
+        let content = b"%PDF-1.7 submission".to_vec();
+        let file = FileHolder {
+            name: "notes.pdf".to_string(),
+            content: content.clone(),
+        };
+        let uid = handler.insert(info, vec![file]).await?;
+
+        let stored = rt
+            .file
+            .read_bytes(&format!("/lms/files/{}/notes.pdf", uid))
+            .await?;
+        assert_eq!(plaintext_len(stored.len() as u64)?, content.len() as u64);
+        assert!(!stored.windows(10).any(|w| w == b"submission"));
+        assert_eq!(handler.get(&uid, "notes.pdf").await?.content, content);
+        assert_eq!(handler.get_metadata(&uid).await?.data_key, None);
+
+        let file = handler
+            .get_stream(&uid, "notes.pdf", Some(ByteRange::Suffix(10)))
+            .await?;
+        assert_eq!(file.size, content.len() as u64);
+        assert_eq!(stream::collect(file.stream).await?, b"submission");
+        let streamed = stream::once(bytes::Bytes::from_static(b"attached"));
+        assert_eq!(handler.put_stream(&uid, "more.txt", streamed).await?, 8);
+        assert_eq!(handler.get(&uid, "more.txt").await?.content, b"attached");
+
+        // a large file streams through several chunks, a range only reads the chunks it needs
+        let large: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let chunks: Vec<_> = large
+            .chunks(4096)
+            .map(|chunk| Ok(bytes::Bytes::copy_from_slice(chunk)))
+            .collect();
+        let streamed: ByteStream = Box::pin(futures_util::stream::iter(chunks));
+        assert_eq!(
+            handler.put_stream(&uid, "large.bin", streamed).await?,
+            200_000
+        );
+        let range = ByteRange::From {
+            start: 65_000,
+            end: Some(140_000),
+        };
+        let file = handler.get_stream(&uid, "large.bin", Some(range)).await?;
+        assert_eq!((file.size, file.range.clone()), (200_000, 65_000..140_001));
+        assert_eq!(stream::collect(file.stream).await?, &large[65_000..140_001]);
+        assert_eq!(handler.get(&uid, "large.bin").await?.content, large);
+
+        // the name of the file is authenticated, a file can't be swapped for another one
+        let path = |name: &str| format!("/lms/files/{}/{}", uid, name);
+        rt.file.write(&path("more.txt"), &stored).await?;
+        assert!(handler.get(&uid, "more.txt").await.is_err());
+
+        // without a keyring the files are handed out as stored, as a remote FileDB does
+        let plain = FileRequestHandler::new(rt.clone(), "/lms/files".to_string())?;
+        assert_eq!(plain.get(&uid, "notes.pdf").await?.content, stored);
+        assert!(plain.get_metadata(&uid).await?.data_key.is_some());
+        let other = encrypted(&rt, "/lms/files", &keyring("2025", "another key"))?;
+        assert!(other.get(&uid, "notes.pdf").await.is_err());
+        Ok(())
+    }
+
+    async fn test_encrypt_all(path: &str) -> anyhow::Result<()> {
+        let rt = crate::runtime::tests::init();
+        let plain = FileRequestHandler::new(rt.clone(), path.to_string())?;
+        let info = || InsertionInfo {
+            title: "title".to_string(),
+            description: "description".to_string(),
+            timestamp: 1,
+            end_time: None,
+            author: None,
+            assignment: None,
+            grade: None,
+            submission: None,
+        };
+        let file = FileHolder {
+            name: "notes.pdf".to_string(),
+            content: b"%PDF".to_vec(),
+        };
+        let first = plain.insert(info(), vec![file.clone()]).await?;
+        let second = plain.insert(info(), vec![]).await?;
+
+        let old = keyring("2024", "master key");
+        assert_eq!(encrypted(&rt, path, &old)?.encrypt_all().await?, 2);
+        let metadata = plain.get_metadata(&first).await?;
+        assert!(metadata.data_key.is_some() && metadata.plain_files.is_empty());
+        assert_ne!(plain.get(&first, "notes.pdf").await?, file);
+        assert_eq!(encrypted(&rt, path, &old)?.encrypt_all().await?, 0);
+
+        // a run interrupted after storing the files leaves them listed, they aren't
+        // encrypted twice when it's resumed
+        let uid = ContentId::new(&first)?;
+        let mut config = plain.export(&first).await?;
+        config.metadata.plain_files = vec!["notes.pdf".to_string()];
+        plain.store(&uid, config).await?;
+        assert_eq!(encrypted(&rt, path, &old)?.encrypt_all().await?, 1);
+        assert_eq!(
+            encrypted(&rt, path, &old)?.get(&first, "notes.pdf").await?,
+            file
+        );
+
+        // after a rotation only the keys are re-wrapped
+        let new = keyring("2025", "new master key");
+        let stored = plain.get(&first, "notes.pdf").await?;
+        let mut rotated = new.clone();
+        for key in old.keys() {
+            rotated = rotated.retire(key.clone(), 0)?;
+        }
+        assert_eq!(encrypted(&rt, path, &rotated)?.encrypt_all().await?, 2);
+        assert_eq!(plain.get(&first, "notes.pdf").await?, stored);
+        let handler = encrypted(&rt, path, &new)?;
+        assert_eq!(handler.get(&first, "notes.pdf").await?, file);
+        assert_eq!(handler.get_metadata(&second).await?.title, "title");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_encrypt_all_local() -> anyhow::Result<()> {
+        test_encrypt_all("/lms/files").await
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_encrypt_all_sqlite() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = format!("sqlite://{}", dir.path().join("lms.db").to_str().unwrap());
+        test_encrypt_all(&path).await
     }
 
     #[tokio::test]
@@ -312,17 +983,21 @@ mod tests {
         let server = start_mock_server();
         let rt = crate::runtime::tests::init();
 
-        let handler = FileRequestHandler::new(rt, server.base_url());
+        let handler = FileRequestHandler::new(rt, server.base_url()).unwrap();
 
         let info = InsertionInfo {
             title: "title".to_string(),
             description: "description".to_string(),
             timestamp: 1,
             end_time: None,
+            author: None,
+            assignment: None,
+            grade: None,
+            submission: None,
         };
         let meta = FileHolder {
             name: "foo.txt".to_string(),
-            content: "AQBF".to_string(),
+            content: vec![1, 0, 69],
         };
 
         let sample_metadata = Metadata {
@@ -330,11 +1005,18 @@ mod tests {
             description: "description".to_string(),
             timestamp: 1,
             end_time: Some(2),
+            author: Some("faculty".to_string()),
+            data_key: None,
+            plain_files: vec![],
+            assignment: None,
+            grade: None,
+            submission: None,
         };
         let uid = "sample".to_string();
 
         server.mock(|w, t| {
-            w.method(httpmock::Method::GET).path(format!("/{}", uid));
+            w.method(httpmock::Method::GET)
+                .path(format!("/v1/contents/{}", uid));
             t.status(200)
                 .body(serde_json::to_string(&sample_metadata).unwrap());
         });
@@ -343,4 +1025,34 @@ mod tests {
         let md = result.unwrap();
         assert_eq!(sample_metadata, md);
     }
+
+    #[tokio::test]
+    async fn test_get_delete_list_remote() {
+        let server = start_mock_server();
+        let rt = crate::runtime::tests::init();
+        let handler = FileRequestHandler::new(rt, server.url("/lms/files")).unwrap();
+
+        server.mock(|w, t| {
+            w.method(httpmock::Method::GET)
+                .path("/lms/files/v1/contents/sample/files/foo%20bar.txt");
+            t.status(200).body([0x00, 0xff]);
+        });
+        server.mock(|w, t| {
+            w.method(httpmock::Method::DELETE)
+                .path("/lms/files/v1/contents/sample");
+            t.status(204);
+        });
+        server.mock(|w, t| {
+            w.method(httpmock::Method::GET)
+                .path("/lms/files/v1/contents");
+            t.status(200).body(r#"{"contents":["sample"]}"#);
+        });
+
+        let file = handler.get("sample", "foo bar.txt").await.unwrap();
+        assert_eq!(file.name, "foo bar.txt");
+        assert_eq!(file.content, [0x00, 0xff]);
+        handler.delete("sample").await.unwrap();
+        assert_eq!(handler.list().await.unwrap(), vec!["sample"]);
+        assert!(handler.delete("unknown").await.is_err());
+    }
 }
@@ -0,0 +1,131 @@
+//! Names the FileDB turns into paths. Ids and file names of a content are single path
+//! components, so a caller can't read or write outside of `fileDb`.
+
+use std::fmt::{Display, Formatter};
+
+use anyhow::{anyhow, Result};
+use unicode_normalization::UnicodeNormalization;
+
+/// Metadata of a content in a local FileDB, next to its files.
+pub const CONFIG_FILE: &str = "config.json";
+/// Suffix of a file being streamed to disk, see [crate::FileIO::write_stream].
+pub const PART_SUFFIX: &str = ".part";
+/// Bytes a file name can have, the limit of most file systems.
+const MAX_NAME_LEN: usize = 255;
+
+/// Id of a content, made of ascii letters, digits, `_` and `-` like the generated ids.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ContentId(String);
+
+/// Name of a file of a content, see [SafeFileName::new].
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SafeFileName(String);
+
+impl ContentId {
+    pub fn new(id: &str) -> Result<Self> {
+        let valid = !id.is_empty()
+            && id.len() <= 64
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if !valid {
+            return Err(anyhow!("Invalid content id {:?}", id));
+        }
+        Ok(Self(id.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl SafeFileName {
+    /// Normalizes the name to NFC without surrounding whitespace, and rejects empty or hidden
+    /// names, separators, control characters and the names the FileDB uses itself.
+    pub fn new(name: &str) -> Result<Self> {
+        let name = name.trim().nfc().collect::<String>();
+        let invalid = |reason: &str| Err(anyhow!("Invalid file name {:?}, {}", name, reason));
+        if name.is_empty() {
+            return invalid("it's empty");
+        }
+        if name.len() > MAX_NAME_LEN {
+            return invalid("it's too long");
+        }
+        // covers `.` and `..`
+        if name.starts_with('.') || name.ends_with('.') {
+            return invalid("it can't start or end with a dot");
+        }
+        if name
+            .chars()
+            .any(|c| matches!(c, '/' | '\\' | ':') || c.is_control())
+        {
+            return invalid("it contains a separator or a control character");
+        }
+        if name.eq_ignore_ascii_case(CONFIG_FILE) || name.ends_with(PART_SUFFIX) {
+            return invalid("the name is reserved");
+        }
+        Ok(Self(name))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for ContentId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Display for SafeFileName {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_id() {
+        assert!(ContentId::new("-N0u_8Bk3xYz").is_ok());
+        for id in ["", "..", "../auth", "a/b", "a b", "%2E%2E", &"a".repeat(65)] {
+            assert!(ContentId::new(id).is_err(), "{:?}", id);
+        }
+    }
+
+    #[test]
+    fn test_safe_file_name() -> Result<()> {
+        assert_eq!(SafeFileName::new(" notes 1.pdf ")?.as_str(), "notes 1.pdf");
+        // a decomposed é is stored composed
+        assert_eq!(
+            SafeFileName::new("re\u{301}sume\u{301}.pdf")?.as_str(),
+            "résumé.pdf"
+        );
+
+        for name in [
+            "",
+            " ",
+            ".",
+            "..",
+            "../../auth",
+            "/etc/passwd",
+            "C:\\auth",
+            "..\\auth",
+            "a/b.txt",
+            "notes\0.pdf",
+            "notes\n.pdf",
+            ".env",
+            "notes.",
+            "config.json",
+            "CONFIG.JSON",
+            "notes.pdf.part",
+            &"a".repeat(256),
+        ] {
+            assert!(SafeFileName::new(name).is_err(), "{:?}", name);
+        }
+        Ok(())
+    }
+}
@@ -1,3 +1,4 @@
+pub mod multipart;
 pub mod request;
 pub mod request_handler;
 pub mod response;
@@ -0,0 +1,194 @@
+//! Parser of `multipart/form-data` bodies, see RFC 7578.
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+
+/// A field of a form, a file when `file_name` is set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Part {
+    pub name: String,
+    pub file_name: Option<String>,
+    pub content_type: Option<String>,
+    pub content: Bytes,
+}
+
+/// Splits `body` into its parts, `content_type` is the `Content-Type` header of the request.
+pub fn parse(content_type: &str, body: Bytes) -> Result<Vec<Part>> {
+    let boundary = boundary(content_type)?;
+    let delimiter = format!("--{}", boundary);
+    let close = format!("\r\n--{}", boundary);
+
+    // anything before the first delimiter is a preamble
+    let start = find(&body, delimiter.as_bytes()).ok_or_else(|| anyhow!("Missing boundary"))?;
+    let mut offset = start + delimiter.len();
+    let mut parts = vec![];
+    loop {
+        let rest = &body[offset..];
+        if rest.starts_with(b"--") {
+            return Ok(parts);
+        }
+        if !rest.starts_with(b"\r\n") {
+            return Err(anyhow!("Malformed boundary"));
+        }
+        offset += 2;
+        let end =
+            find(&body[offset..], close.as_bytes()).ok_or_else(|| anyhow!("Unterminated part"))?;
+        parts.push(Part::parse(body.slice(offset..offset + end))?);
+        offset += end + close.len();
+    }
+}
+
+impl Part {
+    fn parse(part: Bytes) -> Result<Self> {
+        let end = find(&part, b"\r\n\r\n").ok_or_else(|| anyhow!("Missing part headers"))?;
+        let headers = std::str::from_utf8(&part[..end])
+            .map_err(|_| anyhow!("Part headers are not valid utf-8"))?;
+
+        let mut disposition = None;
+        let mut content_type = None;
+        for line in headers.split("\r\n") {
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| anyhow!("Malformed part header {}", line))?;
+            match name.trim().to_ascii_lowercase().as_str() {
+                "content-disposition" => disposition = Some(value.trim()),
+                "content-type" => content_type = Some(value.trim().to_string()),
+                _ => {}
+            }
+        }
+        let disposition = disposition.ok_or_else(|| anyhow!("Missing Content-Disposition"))?;
+        let (kind, params) = disposition.split_once(';').unwrap_or((disposition, ""));
+        if !kind.trim().eq_ignore_ascii_case("form-data") {
+            return Err(anyhow!("Expected a form-data part, found {}", kind));
+        }
+        let params = params_of(params)?;
+        let param = |key: &str| {
+            params
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(key))
+                .map(|(_, value)| value.clone())
+        };
+
+        Ok(Self {
+            name: param("name").ok_or_else(|| anyhow!("Part without a name"))?,
+            file_name: param("filename"),
+            content_type,
+            content: part.slice(end + 4..),
+        })
+    }
+}
+
+fn boundary(content_type: &str) -> Result<String> {
+    let (mime, params) = content_type.split_once(';').unwrap_or((content_type, ""));
+    if !mime.trim().eq_ignore_ascii_case("multipart/form-data") {
+        return Err(anyhow!("Expected multipart/form-data, found {}", mime));
+    }
+    params_of(params)?
+        .into_iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("boundary"))
+        .map(|(_, boundary)| boundary)
+        .filter(|boundary| !boundary.is_empty())
+        .ok_or_else(|| anyhow!("Missing boundary"))
+}
+
+/// `; key=value; key="quoted value"` parameters of a header.
+fn params_of(params: &str) -> Result<Vec<(String, String)>> {
+    let mut parsed = vec![];
+    let mut rest = params.trim_start_matches([';', ' ']);
+    while !rest.is_empty() {
+        let (key, value) = rest
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Malformed parameter {}", rest))?;
+        let key = key.trim().to_string();
+        let value = value.trim_start();
+        let (value, next) = match value.strip_prefix('"') {
+            Some(quoted) => {
+                let mut value = String::new();
+                let mut chars = quoted.char_indices();
+                let end = loop {
+                    match chars.next() {
+                        Some((_, '\\')) => {
+                            if let Some((_, c)) = chars.next() {
+                                value.push(c);
+                            }
+                        }
+                        Some((i, '"')) => break i,
+                        Some((_, c)) => value.push(c),
+                        None => return Err(anyhow!("Unterminated quoted parameter {}", key)),
+                    }
+                };
+                (value, &quoted[end + 1..])
+            }
+            None => {
+                let end = value.find(';').unwrap_or(value.len());
+                (value[..end].trim().to_string(), &value[end..])
+            }
+        };
+        parsed.push((key, value));
+        rest = next.trim_start_matches([';', ' ']);
+    }
+    Ok(parsed)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &[u8] = b"preamble\r\n\
+--XyZ\r\n\
+Content-Disposition: form-data; name=\"title\"\r\n\
+\r\n\
+Week 1\r\n\
+--XyZ\r\n\
+content-disposition: form-data; name=\"files\"; filename=\"notes; \\\"v1\\\".pdf\"\r\n\
+Content-Type: application/pdf\r\n\
+\r\n\
+%PDF\r\n\x00\xff\r\n\
+--XyZ--\r\n";
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        let parts = parse(
+            "multipart/form-data; boundary=\"XyZ\"",
+            Bytes::from_static(BODY),
+        )?;
+        assert_eq!(
+            parts,
+            vec![
+                Part {
+                    name: "title".to_string(),
+                    file_name: None,
+                    content_type: None,
+                    content: Bytes::from_static(b"Week 1"),
+                },
+                Part {
+                    name: "files".to_string(),
+                    file_name: Some("notes; \"v1\".pdf".to_string()),
+                    content_type: Some("application/pdf".to_string()),
+                    content: Bytes::from_static(b"%PDF\r\n\x00\xff"),
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejected_bodies() {
+        let body = Bytes::from_static(BODY);
+        assert!(parse("application/json", body.clone()).is_err());
+        assert!(parse("multipart/form-data", body.clone()).is_err());
+        assert!(parse("multipart/form-data; boundary=other", body.clone()).is_err());
+
+        let unterminated = Bytes::from_static(&BODY[..BODY.len() - 9]);
+        assert!(parse("multipart/form-data; boundary=XyZ", unterminated).is_err());
+        let without_name = b"--XyZ\r\nContent-Disposition: form-data\r\n\r\nvalue\r\n--XyZ--";
+        let without_name = Bytes::from_static(without_name);
+        assert!(parse("multipart/form-data; boundary=XyZ", without_name).is_err());
+    }
+}
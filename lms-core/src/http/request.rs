@@ -1,3 +1,5 @@
+use std::net::IpAddr;
+
 use http_body_util::BodyExt;
 use hyper::body::Incoming;
 
@@ -6,10 +8,15 @@ pub struct Request {
     pub url: hyper::Uri,
     pub headers: hyper::HeaderMap,
     pub body: bytes::Bytes,
+    /// Address of the peer, used to throttle failed logins
+    pub client_ip: Option<IpAddr>,
 }
 
 impl Request {
-    pub async fn from_hyper(req: hyper::Request<Incoming>) -> anyhow::Result<Self> {
+    pub async fn from_hyper(
+        req: hyper::Request<Incoming>,
+        client_ip: Option<IpAddr>,
+    ) -> anyhow::Result<Self> {
         let (part, body) = req.into_parts();
         let body = body.collect().await?.to_bytes();
 
@@ -18,6 +25,7 @@ impl Request {
             url: part.uri,
             headers: part.headers,
             body,
+            client_ip,
         })
     }
 }
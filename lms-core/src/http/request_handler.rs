@@ -1,9 +1,15 @@
-use crate::actions_db::actions_db::ActionsDB;
+use crate::actions_db::actions::{ActionsFile, ActionsResult};
+use crate::actions_db::actions_db::{actions_status, ActionsDB};
 use crate::authdb::auth_db::AuthDB;
 use crate::http::request::Request;
-use anyhow::Result;
+use crate::stream::{self, ByteRange, ByteStream};
+use anyhow::{anyhow, Result};
 use bytes::Bytes;
 use http_body_util::Full;
+use hyper::header::{
+    ACCEPT_RANGES, AUTHORIZATION, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, RANGE,
+};
+use percent_encoding::percent_decode_str;
 
 use super::{AUTH_PAGE, INDEX_JS};
 use crate::app_ctx::AppContext;
@@ -13,6 +19,11 @@ use lazy_static::lazy_static;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
+/// Prefix of the files streamed by [handle_stream_request].
+const FILES: &str = "/fs/files/";
+/// Forms posted by [handle_stream_request], read up to `server.maxUploadSize`.
+const UPLOAD: &str = "/fs/upload";
+
 pub async fn handle_request(
     req: Request,
     app_context: Arc<AppContext>,
@@ -37,7 +48,55 @@ async fn handle_post(
         "/auth" => auth_db
             .write()
             .await
-            .handle_request(req.body)
+            .handle_request(req.body, req.client_ip)
+            .await
+            .into_hyper_response(),
+        "/auth/logout" => auth_db
+            .read()
+            .await
+            .handle_logout(req.body)
+            .await
+            .into_hyper_response(),
+        "/auth/refresh" => auth_db
+            .read()
+            .await
+            .handle_refresh(req.body)
+            .await
+            .into_hyper_response(),
+        "/auth/revoke" => auth_db
+            .read()
+            .await
+            .handle_revoke(req.body)
+            .await
+            .into_hyper_response(),
+        "/auth/password" => auth_db
+            .write()
+            .await
+            .handle_change_password(req.body, req.client_ip)
+            .await
+            .into_hyper_response(),
+        "/auth/password/reset" => auth_db
+            .write()
+            .await
+            .handle_reset_password(req.body)
+            .await
+            .into_hyper_response(),
+        "/auth/2fa/enroll" => auth_db
+            .write()
+            .await
+            .handle_enroll_2fa(req.body, req.client_ip)
+            .await
+            .into_hyper_response(),
+        "/auth/2fa/verify" => auth_db
+            .write()
+            .await
+            .handle_verify_2fa(req.body, req.client_ip)
+            .await
+            .into_hyper_response(),
+        "/auth/2fa/disable" => auth_db
+            .write()
+            .await
+            .handle_disable_2fa(req.body, req.client_ip)
             .await
             .into_hyper_response(),
         "/fs" => actions_db
@@ -48,6 +107,114 @@ async fn handle_post(
     }
 }
 
+/// Requests whose body or answer is streamed instead of held in memory, they are served by
+/// [handle_stream_request] instead of [handle_request].
+pub fn is_stream(method: &Method, path: &str) -> bool {
+    ((method == Method::PUT || method == Method::GET) && path.starts_with(FILES))
+        || (method == Method::POST && path == UPLOAD)
+}
+
+/// Uploads with `PUT` and downloads with `GET` a file of some content at
+/// `/fs/files/{content_id}/{file_name}?group={group_id}`, authenticated with
+/// `Authorization: Bearer {token}`. Downloads honour a single `Range`.
+///
+/// Forms are posted to `/fs/upload`, see [ActionsDB::handle_form].
+pub async fn handle_stream_request(
+    req: hyper::Request<ByteStream>,
+    actions_db: Arc<ActionsDB>,
+) -> Result<Response<ByteStream>> {
+    log::info!("Request: {} {}", req.method(), req.uri().path());
+    let content_length = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok()?.parse().ok());
+    if req.uri().path() == UPLOAD {
+        let token = bearer(req.headers()).map(str::to_string);
+        let content_type = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let result = actions_db
+            .handle_form(
+                token.as_deref(),
+                content_type.as_deref(),
+                content_length,
+                req.into_body(),
+            )
+            .await;
+        return stream_result(result);
+    }
+    let file = match actions_file(&req) {
+        Ok(file) => file,
+        Err(e) => return stream_result(actions_status(400, e.to_string())),
+    };
+    if req.method() == Method::PUT {
+        let result = actions_db
+            .handle_upload(file, content_length, req.into_body())
+            .await;
+        return stream_result(result);
+    }
+
+    // a range which can't be parsed is ignored, the whole file is sent
+    let range = req
+        .headers()
+        .get(RANGE)
+        .and_then(|value| ByteRange::parse(value.to_str().ok()?).ok());
+    let file = match actions_db.handle_download(file, range).await {
+        Ok(file) => file,
+        Err(result) => return stream_result(result),
+    };
+    let builder = Response::builder()
+        .header(CONTENT_TYPE, "application/octet-stream")
+        .header(CONTENT_LENGTH, file.range.end - file.range.start)
+        .header(ACCEPT_RANGES, "bytes");
+    let builder = match range {
+        Some(_) => builder
+            .status(206)
+            .header(CONTENT_RANGE, file.content_range()),
+        None => builder.status(200),
+    };
+    Ok(builder.body(file.stream)?)
+}
+
+fn actions_file<T>(req: &hyper::Request<T>) -> Result<ActionsFile> {
+    let path = req.uri().path().strip_prefix(FILES).unwrap_or_default();
+    let (content_id, file_name) = path
+        .split_once('/')
+        .ok_or_else(|| anyhow!("Expected /fs/files/{{content_id}}/{{file_name}}"))?;
+    let decode = |segment: &str| -> Result<String> {
+        Ok(percent_decode_str(segment).decode_utf8()?.to_string())
+    };
+    let token = bearer(req.headers()).ok_or_else(|| anyhow!("Missing bearer token"))?;
+    let group_id = url::form_urlencoded::parse(req.uri().query().unwrap_or_default().as_bytes())
+        .find(|(key, _)| key == "group")
+        .map(|(_, value)| value.to_string())
+        .ok_or_else(|| anyhow!("Missing group"))?;
+    Ok(ActionsFile {
+        token: token.to_string(),
+        group_id,
+        content_id: decode(content_id)?,
+        file_name: decode(file_name)?,
+    })
+}
+
+/// Token of an `Authorization: Bearer {token}` header.
+fn bearer(headers: &hyper::HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok()?.strip_prefix("Bearer "))
+}
+
+fn stream_result(result: ActionsResult) -> Result<Response<ByteStream>> {
+    let body = serde_json::to_vec(&result)?;
+    let response = Response::builder()
+        .status(result.status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(stream::once(Bytes::from(body)))?;
+    Ok(response)
+}
+
 /// Get requests should return a html response
 async fn handle_get(req: Request, app_context: Arc<AppContext>) -> Result<Response<Full<Bytes>>> {
     let path = req.url.path();
@@ -124,9 +291,47 @@ fn not_found() -> Result<Response<Full<Bytes>>> {
 
 #[cfg(test)]
 mod test {
+    use super::*;
     use crate::http::request_handler::PAGE_404;
     use anyhow::Result;
 
+    #[test]
+    fn test_actions_file() -> Result<()> {
+        let req = |uri: &str, token: &str| {
+            hyper::Request::builder()
+                .method(Method::PUT)
+                .uri(uri)
+                .header(AUTHORIZATION, token)
+                .body(())
+        };
+        let put = req(
+            "/fs/files/abc/notes%201.pdf?group=22BCS_course1",
+            "Bearer token",
+        )?;
+        assert!(is_stream(put.method(), put.uri().path()));
+        assert!(!is_stream(&Method::POST, put.uri().path()));
+        assert!(!is_stream(&Method::GET, "/fs"));
+        assert!(is_stream(&Method::POST, "/fs/upload"));
+        assert!(!is_stream(&Method::GET, "/fs/upload"));
+        assert_eq!(
+            actions_file(&put)?,
+            ActionsFile {
+                token: "token".to_string(),
+                group_id: "22BCS_course1".to_string(),
+                content_id: "abc".to_string(),
+                file_name: "notes 1.pdf".to_string(),
+            }
+        );
+
+        let put = req("/fs/files/abc/notes.pdf?group=22BCS_course1", "token")?;
+        assert!(actions_file(&put).is_err());
+        let put = req("/fs/files/abc/notes.pdf", "Bearer token")?;
+        assert!(actions_file(&put).is_err());
+        let put = req("/fs/files/abc?group=22BCS_course1", "Bearer token")?;
+        assert!(actions_file(&put).is_err());
+        Ok(())
+    }
+
     #[tokio::test]
     async fn contains_all_routes() -> Result<()> {
         assert!(PAGE_404.as_str().contains("home"));
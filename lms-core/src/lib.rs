@@ -2,6 +2,9 @@
 #![allow(clippy::mutable_key_type)]
 
 use std::borrow::Cow;
+use std::fmt::{Display, Formatter};
+
+use stream::{ByteRange, ByteStream, FileStream};
 
 pub mod actions_db;
 pub mod app_ctx;
@@ -11,12 +14,20 @@ pub mod config;
 pub mod file_db;
 pub mod http;
 pub mod runtime;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod stream;
 pub mod uid_gen;
 
 pub fn is_default<T: Default + Eq>(val: &T) -> bool {
     *val == T::default()
 }
 
+/// Path of the SQLite database if the path uses the `sqlite://` scheme.
+pub fn sqlite_path(path: &str) -> Option<&str> {
+    path.strip_prefix("sqlite://")
+}
+
 pub trait EnvIO: Send + Sync + 'static {
     fn get(&self, key: &str) -> Option<Cow<'_, str>>;
 }
@@ -33,9 +44,69 @@ pub trait HttpIO: Sync + Send + 'static {
     ) -> anyhow::Result<http::response::Response<bytes::Bytes>>;
 }
 
+/// The file doesn't exist, every FileIO fails with it so that callers can tell a missing file
+/// from one that couldn't be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotFound {
+    pub path: String,
+}
+
+impl NotFound {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+        }
+    }
+}
+
+impl Display for NotFound {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "File {} not found", self.path)
+    }
+}
+
+impl std::error::Error for NotFound {}
+
 #[async_trait::async_trait]
 pub trait FileIO: Send + Sync {
+    /// Creates or replaces the file with the raw `content`.
     async fn write<'a>(&'a self, path: &'a str, content: &'a [u8]) -> anyhow::Result<()>;
-    async fn read<'a>(&'a self, path: &'a str) -> anyhow::Result<String>;
+    /// Raw content of the file, fails with [NotFound] if it doesn't exist.
+    async fn read_bytes<'a>(&'a self, path: &'a str) -> anyhow::Result<Vec<u8>>;
+    /// Content of a text file, fails if it isn't utf-8.
+    async fn read<'a>(&'a self, path: &'a str) -> anyhow::Result<String> {
+        let content = self.read_bytes(path).await?;
+        String::from_utf8(content).map_err(|_| anyhow::anyhow!("File {} is not utf-8", path))
+    }
     async fn create_dirs<'a>(&'a self, path: &'a str) -> anyhow::Result<()>;
+    /// Streams the bytes of `range` in the file, the whole file without one.
+    async fn read_stream<'a>(
+        &'a self,
+        path: &'a str,
+        range: Option<ByteRange>,
+    ) -> anyhow::Result<FileStream> {
+        FileStream::from_bytes(self.read_bytes(path).await?, range)
+    }
+    /// Creates or replaces the file with the chunks of `stream` and returns its size.
+    /// The file isn't written if the stream fails.
+    async fn write_stream<'a>(&'a self, path: &'a str, stream: ByteStream) -> anyhow::Result<u64> {
+        let content = stream::collect(stream).await?;
+        self.write(path, &content).await?;
+        Ok(content.len() as u64)
+    }
+    /// Appends `content` to the file, creating it if needed. The default rewrites the whole
+    /// file, override it where the storage can append.
+    async fn append<'a>(&'a self, path: &'a str, content: &'a [u8]) -> anyhow::Result<()> {
+        let mut file = match self.read_bytes(path).await {
+            Ok(file) => file,
+            Err(err) if err.is::<NotFound>() => vec![],
+            Err(err) => return Err(err),
+        };
+        file.extend_from_slice(content);
+        self.write(path, &file).await
+    }
+    /// Removes the file, or the dir along with everything in it.
+    async fn delete<'a>(&'a self, path: &'a str) -> anyhow::Result<()>;
+    /// Names of the entries directly in the dir, empty if it doesn't exist.
+    async fn list<'a>(&'a self, path: &'a str) -> anyhow::Result<Vec<String>>;
 }
@@ -21,17 +21,18 @@ pub struct TargetRuntime {
 #[cfg(test)]
 pub mod tests {
     use std::borrow::Cow;
-    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
     use std::time::SystemTime;
 
-    use anyhow::{Context, Result};
+    use anyhow::{bail, Result};
     use dashmap::DashMap;
     use hyper::body::Bytes;
     use reqwest::Client;
 
     use crate::http::response::Response;
     use crate::runtime::TargetRuntime;
-    use crate::{EnvIO, FileIO, HttpIO, Instance};
+    use crate::{EnvIO, FileIO, HttpIO, Instance, NotFound};
 
     #[derive(Default)]
     struct TestHttp {
@@ -57,30 +58,49 @@ pub mod tests {
         }
     }
 
+    /// Switches that make the file IO of [init_with_faults] fail, as a broken disk would.
+    #[derive(Default)]
+    pub struct Faults {
+        pub reads: AtomicBool,
+        /// Writes to the paths starting with it fail
+        pub writes: Mutex<Option<String>>,
+    }
+
     #[derive(Clone)]
     struct TestFileIO {
-        hm: DashMap<String, String>,
+        hm: DashMap<String, Vec<u8>>,
+        faults: Arc<Faults>,
     }
 
     impl TestFileIO {
-        fn init() -> Self {
-            TestFileIO { hm: DashMap::new() }
+        fn init(faults: Arc<Faults>) -> Self {
+            TestFileIO {
+                hm: DashMap::new(),
+                faults,
+            }
         }
     }
 
     #[async_trait::async_trait]
     impl FileIO for TestFileIO {
         async fn write<'a>(&'a self, path: &'a str, content: &'a [u8]) -> anyhow::Result<()> {
-            self.hm
-                .insert(path.to_string(), String::from_utf8(content.to_vec())?);
+            if let Some(prefix) = &*self.faults.writes.lock().unwrap() {
+                if path.starts_with(prefix) {
+                    bail!("Failed to write file: {}", path);
+                }
+            }
+            self.hm.insert(path.to_string(), content.to_vec());
             Ok(())
         }
 
-        async fn read<'a>(&'a self, path: &'a str) -> anyhow::Result<String> {
+        async fn read_bytes<'a>(&'a self, path: &'a str) -> anyhow::Result<Vec<u8>> {
+            if self.faults.reads.load(Ordering::SeqCst) {
+                bail!("Failed to read file: {}", path);
+            }
             let buffer = self
                 .hm
                 .get(path)
-                .context(format!("File: {} not found", path))?
+                .ok_or_else(|| NotFound::new(path))?
                 .clone();
             Ok(buffer)
         }
@@ -88,6 +108,28 @@ pub mod tests {
         async fn create_dirs<'a>(&'a self, _path: &'a str) -> Result<()> {
             Ok(())
         }
+
+        async fn delete<'a>(&'a self, path: &'a str) -> Result<()> {
+            let dir = format!("{}/", path.trim_end_matches('/'));
+            self.hm
+                .retain(|key, _| key.as_str() != path && !key.starts_with(&dir));
+            Ok(())
+        }
+
+        async fn list<'a>(&'a self, path: &'a str) -> Result<Vec<String>> {
+            let dir = format!("{}/", path.trim_end_matches('/'));
+            let mut names = self
+                .hm
+                .iter()
+                .filter_map(|entry| {
+                    let rest = entry.key().strip_prefix(&dir)?;
+                    rest.split('/').next().map(|name| name.to_string())
+                })
+                .collect::<Vec<_>>();
+            names.sort();
+            names.dedup();
+            Ok(names)
+        }
     }
 
     #[derive(Clone)]
@@ -111,14 +153,36 @@ pub mod tests {
     }
 
     pub fn init() -> TargetRuntime {
+        init_with_faults().0
+    }
+
+    /// A runtime whose file IO fails once the returned [Faults] are switched on.
+    pub fn init_with_faults() -> (TargetRuntime, Arc<Faults>) {
         let http = TestHttp::init();
 
-        let file = TestFileIO::init();
-        TargetRuntime {
+        let faults = Arc::new(Faults::default());
+        let file = TestFileIO::init(faults.clone());
+        let rt = TargetRuntime {
             http,
             file: Arc::new(file),
             env: Arc::new(TestEnv {}),
             instance: Arc::new(TestInstance {}),
-        }
+        };
+        (rt, faults)
+    }
+
+    #[tokio::test]
+    async fn test_append_only_creates_missing_file() -> Result<()> {
+        let (rt, faults) = init_with_faults();
+        rt.file.append("/lms/log", b"a\n").await?;
+        rt.file.append("/lms/log", b"b\n").await?;
+        assert_eq!(rt.file.read("/lms/log").await?, "a\nb\n");
+
+        // a file that can't be read isn't replaced by the appended content alone
+        faults.reads.store(true, Ordering::SeqCst);
+        assert!(rt.file.append("/lms/log", b"c\n").await.is_err());
+        faults.reads.store(false, Ordering::SeqCst);
+        assert_eq!(rt.file.read("/lms/log").await?, "a\nb\n");
+        Ok(())
     }
 }
@@ -0,0 +1,773 @@
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::{anyhow, Result};
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+
+use crate::actions_db::actions::{ActionsActivity, ActionsContent, ContentKind};
+use crate::actions_db::actions_db::ActionsDB;
+use crate::authdb::auth_store::{AuthStore, LocalAuthStore};
+use crate::blueprint::Blueprint;
+use crate::file_db::file_config::{FileHolder, Metadata, RemoteFileConfig};
+use crate::file_db::request_handler::FileRequestHandler;
+use crate::runtime::TargetRuntime;
+
+/// Schema migrations, the n-th entry upgrades the database from `user_version` n to n + 1.
+/// Append new migrations, never edit the applied ones.
+const MIGRATIONS: &[&str] = &[
+    r#"
+CREATE TABLE users (
+    username TEXT PRIMARY KEY,
+    -- the user as json, encrypted with the auth key
+    data TEXT NOT NULL
+);
+CREATE TABLE actions (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    group_id TEXT NOT NULL,
+    content_id TEXT NOT NULL,
+    is_notif INTEGER NOT NULL
+);
+CREATE INDEX actions_group_id ON actions (group_id, seq);
+CREATE TABLE contents (
+    content_id TEXT PRIMARY KEY,
+    title TEXT NOT NULL,
+    description TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    end_time INTEGER
+);
+CREATE TABLE files (
+    content_id TEXT NOT NULL REFERENCES contents (content_id),
+    name TEXT NOT NULL,
+    content TEXT NOT NULL,
+    PRIMARY KEY (content_id, name)
+);
+"#,
+    r#"
+-- files hold raw bytes
+CREATE TABLE files_v2 (
+    content_id TEXT NOT NULL REFERENCES contents (content_id),
+    name TEXT NOT NULL,
+    content BLOB NOT NULL,
+    PRIMARY KEY (content_id, name)
+);
+INSERT INTO files_v2 SELECT content_id, name, CAST(content AS BLOB) FROM files;
+DROP TABLE files;
+ALTER TABLE files_v2 RENAME TO files;
+"#,
+    r#"
+ALTER TABLE contents ADD COLUMN author TEXT;
+"#,
+    r#"
+-- wrapped key of the files, see `file_db::encryption`
+ALTER TABLE contents ADD COLUMN data_key TEXT;
+"#,
+    r#"
+-- actions without a kind are read by is_notif, see `ContentKind::legacy`
+ALTER TABLE actions ADD COLUMN kind TEXT;
+ALTER TABLE actions ADD COLUMN assignment TEXT;
+ALTER TABLE actions ADD COLUMN submitter TEXT;
+-- details of an assignment as json
+ALTER TABLE contents ADD COLUMN assignment TEXT;
+"#,
+    r#"
+ALTER TABLE actions ADD COLUMN submission TEXT;
+ALTER TABLE actions ADD COLUMN released INTEGER NOT NULL DEFAULT 0;
+-- grade with its rubric as json
+ALTER TABLE contents ADD COLUMN grade TEXT;
+"#,
+    r#"
+-- lateness of a submission as json
+ALTER TABLE contents ADD COLUMN submission TEXT;
+"#,
+    r#"
+-- files left in plaintext in an encrypted content as json, see `file_db::encryption`
+ALTER TABLE contents ADD COLUMN plain_files TEXT;
+"#,
+];
+
+/// A local SQLite database holding the users, the actions and the contents of the FileDB.
+/// Every write runs in a transaction, calls block the thread for the duration of the query.
+pub struct SqliteDb {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteDb {
+    /// Opens or creates the database at `path`, see [crate::sqlite_path], and migrates it.
+    pub fn open(path: &str) -> Result<Self> {
+        if let Some(parent) = Path::new(path).parent() {
+            if !parent.as_os_str().is_empty() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+        let mut conn = Connection::open(path)
+            .map_err(|e| anyhow!("Unable to open SQLite database {}: {}", path, e))?;
+        conn.busy_timeout(std::time::Duration::from_secs(5))?;
+        // readers don't block the writer, several stores may share the file
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "foreign_keys", true)?;
+        migrate(&mut conn)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn with<T>(&self, f: impl FnOnce(&mut Connection) -> rusqlite::Result<T>) -> Result<T> {
+        let mut conn = self
+            .conn
+            .lock()
+            .map_err(|_| anyhow!("SQLite connection poisoned"))?;
+        Ok(f(&mut conn)?)
+    }
+
+    fn transaction<T>(&self, f: impl FnOnce(&Transaction) -> rusqlite::Result<T>) -> Result<T> {
+        self.with(|conn| {
+            let tx = conn.transaction()?;
+            let result = f(&tx)?;
+            tx.commit()?;
+            Ok(result)
+        })
+    }
+
+    /// Encrypted user, see [SqliteDb::upsert_user].
+    pub fn get_user(&self, username: &str) -> Result<Option<String>> {
+        self.with(|conn| {
+            conn.query_row(
+                "SELECT data FROM users WHERE username = ?1",
+                [username],
+                |row| row.get(0),
+            )
+            .optional()
+        })
+    }
+
+    pub fn upsert_user(&self, username: &str, data: &str) -> Result<()> {
+        self.transaction(|tx| upsert_user(tx, username, data))
+    }
+
+    pub fn delete_user(&self, username: &str) -> Result<()> {
+        self.transaction(|tx| {
+            tx.execute("DELETE FROM users WHERE username = ?1", [username])?;
+            Ok(())
+        })
+    }
+
+    pub fn list_users(&self) -> Result<Vec<String>> {
+        self.with(|conn| {
+            let mut stmt = conn.prepare("SELECT data FROM users ORDER BY username")?;
+            let rows = stmt.query_map([], |row| row.get(0))?;
+            rows.collect()
+        })
+    }
+
+    /// Replaces the data of every user with `f(data)` in a single transaction.
+    pub fn update_users(&self, f: impl Fn(&str) -> Result<String>) -> Result<usize> {
+        let mut conn = self
+            .conn
+            .lock()
+            .map_err(|_| anyhow!("SQLite connection poisoned"))?;
+        let tx = conn.transaction()?;
+        let users = {
+            let mut stmt = tx.prepare("SELECT username, data FROM users")?;
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            rows.collect::<rusqlite::Result<Vec<(String, String)>>>()?
+        };
+        for (username, data) in &users {
+            upsert_user(&tx, username, &f(data)?)?;
+        }
+        tx.commit()?;
+        Ok(users.len())
+    }
+
+    pub fn load_actions(&self) -> Result<ActionsActivity> {
+        let rows = self.with(|conn| {
+            let mut stmt = conn.prepare(
+                "SELECT group_id, content_id, is_notif, kind, assignment, submitter, submission,
+                 released FROM actions ORDER BY seq",
+            )?;
+            let rows = stmt.query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, bool>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                    ActionsContent {
+                        kind: ContentKind::Notice,
+                        content_id: row.get(1)?,
+                        assignment: row.get(4)?,
+                        submitter: row.get(5)?,
+                        submission: row.get(6)?,
+                        released: row.get(7)?,
+                    },
+                ))
+            })?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()
+        })?;
+        let activity = ActionsActivity::default();
+        for (group_id, is_notif, kind, mut content) in rows {
+            content.kind = match kind {
+                Some(kind) => kind.parse()?,
+                None => ContentKind::legacy(is_notif),
+            };
+            activity.actions.entry(group_id).or_default().push(content);
+        }
+        Ok(activity)
+    }
+
+    /// Replaces the stored actions of `groups` with the ones in `activity`, in a single transaction.
+    pub fn save_actions(&self, activity: &ActionsActivity, groups: &[String]) -> Result<()> {
+        self.transaction(|tx| save_actions(tx, activity, groups))
+    }
+
+    pub fn insert_content(&self, content_id: &str, config: &RemoteFileConfig) -> Result<()> {
+        self.transaction(|tx| insert_content(tx, content_id, config))
+    }
+
+    pub fn get_metadata(&self, content_id: &str) -> Result<Metadata> {
+        let (mut metadata, assignment, grade, submission, plain_files) = self
+            .with(|conn| {
+                conn.query_row(
+                    "SELECT title, description, timestamp, end_time, author, data_key, assignment,
+                     grade, submission, plain_files FROM contents WHERE content_id = ?1",
+                    [content_id],
+                    |row| {
+                        let metadata = Metadata {
+                            title: row.get(0)?,
+                            description: row.get(1)?,
+                            timestamp: row.get::<_, i64>(2)? as u128,
+                            end_time: row.get::<_, Option<i64>>(3)?.map(|t| t as u128),
+                            author: row.get(4)?,
+                            data_key: row.get(5)?,
+                            plain_files: vec![],
+                            assignment: None,
+                            grade: None,
+                            submission: None,
+                        };
+                        Ok((
+                            metadata,
+                            row.get::<_, Option<String>>(6)?,
+                            row.get::<_, Option<String>>(7)?,
+                            row.get::<_, Option<String>>(8)?,
+                            row.get::<_, Option<String>>(9)?,
+                        ))
+                    },
+                )
+                .optional()
+            })?
+            .ok_or_else(|| anyhow!("Content {} not found", content_id))?;
+        if let Some(assignment) = assignment {
+            metadata.assignment = Some(serde_json::from_str(&assignment)?);
+        }
+        if let Some(grade) = grade {
+            metadata.grade = Some(serde_json::from_str(&grade)?);
+        }
+        if let Some(submission) = submission {
+            metadata.submission = Some(serde_json::from_str(&submission)?);
+        }
+        if let Some(plain_files) = plain_files {
+            metadata.plain_files = serde_json::from_str(&plain_files)?;
+        }
+        Ok(metadata)
+    }
+
+    pub fn get_file(&self, content_id: &str, file_name: &str) -> Result<FileHolder> {
+        self.with(|conn| {
+            conn.query_row(
+                "SELECT name, content FROM files WHERE content_id = ?1 AND name = ?2",
+                [content_id, file_name],
+                |row| {
+                    Ok(FileHolder {
+                        name: row.get(0)?,
+                        content: row.get(1)?,
+                    })
+                },
+            )
+            .optional()
+        })?
+        .ok_or_else(|| anyhow!("File {} not found in {}", file_name, content_id))
+    }
+
+    /// Metadata along with every file of a content.
+    pub fn get_content(&self, content_id: &str) -> Result<RemoteFileConfig> {
+        let metadata = self.get_metadata(content_id)?;
+        let files = self.with(|conn| {
+            let mut stmt = conn
+                .prepare("SELECT name, content FROM files WHERE content_id = ?1 ORDER BY name")?;
+            let rows = stmt.query_map([content_id], |row| {
+                Ok(FileHolder {
+                    name: row.get(0)?,
+                    content: row.get(1)?,
+                })
+            })?;
+            rows.collect()
+        })?;
+        Ok(RemoteFileConfig { files, metadata })
+    }
+
+    /// Adds a file to a stored content, replacing the file with the same name.
+    pub fn put_file(&self, content_id: &str, file: &FileHolder) -> Result<()> {
+        let inserted = self.transaction(|tx| {
+            let known = tx
+                .query_row(
+                    "SELECT 1 FROM contents WHERE content_id = ?1",
+                    [content_id],
+                    |_| Ok(()),
+                )
+                .optional()?;
+            if known.is_none() {
+                return Ok(false);
+            }
+            tx.execute(
+                "INSERT INTO files (content_id, name, content) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (content_id, name) DO UPDATE SET content = excluded.content",
+                params![content_id, file.name, file.content],
+            )?;
+            Ok(true)
+        })?;
+        if !inserted {
+            return Err(anyhow!("Content {} not found", content_id));
+        }
+        Ok(())
+    }
+
+    /// Removes the content along with its files, false if it wasn't there.
+    pub fn delete_content(&self, content_id: &str) -> Result<bool> {
+        self.transaction(|tx| {
+            tx.execute("DELETE FROM files WHERE content_id = ?1", [content_id])?;
+            Ok(tx.execute("DELETE FROM contents WHERE content_id = ?1", [content_id])? > 0)
+        })
+    }
+
+    pub fn list_contents(&self) -> Result<Vec<String>> {
+        self.with(|conn| {
+            let mut stmt = conn.prepare("SELECT content_id FROM contents ORDER BY content_id")?;
+            let rows = stmt.query_map([], |row| row.get(0))?;
+            rows.collect()
+        })
+    }
+
+    /// Writes everything read from the JSON layout in a single transaction,
+    /// rows already present are replaced, so an import can be re-run.
+    pub fn import(&self, import: &Import) -> Result<()> {
+        self.transaction(|tx| {
+            for (username, data) in &import.users {
+                upsert_user(tx, username, data)?;
+            }
+            for (content_id, config) in &import.contents {
+                insert_content(tx, content_id, config)?;
+            }
+            let groups = import
+                .activity
+                .actions
+                .iter()
+                .map(|entry| entry.key().clone())
+                .collect::<Vec<_>>();
+            save_actions(tx, &import.activity, &groups)
+        })
+    }
+}
+
+/// State of the JSON layout, see [SqliteDb::import].
+#[derive(Default)]
+pub struct Import {
+    /// username and encrypted user
+    pub users: Vec<(String, String)>,
+    pub activity: ActionsActivity,
+    pub contents: Vec<(String, RemoteFileConfig)>,
+}
+
+/// Reads the users, actions and contents from the JSON layout configured in `blueprint`,
+/// the encrypted `authDbPath` file, `actionsDb` and the `fileDb` dir, and writes them to `db`.
+pub async fn import_json(
+    db: &SqliteDb,
+    blueprint: &Blueprint,
+    runtime: &TargetRuntime,
+) -> Result<Import> {
+    let server = &blueprint.server;
+    let auth = &blueprint.extensions.auth;
+    for path in [auth.db_path(), &server.actions_db, &server.file_db] {
+        if path.starts_with("http") || crate::sqlite_path(path).is_some() {
+            return Err(anyhow!(
+                "Only local JSON files can be imported, found {}",
+                path
+            ));
+        }
+    }
+
+    let mut import = Import::default();
+    let users = LocalAuthStore::new(auth.clone(), runtime.clone())
+        .list()
+        .await?;
+    for user in users.get_all().values() {
+        let data = auth.encrypt_aes(serde_json::to_string(user)?)?;
+        import.users.push((user.username.clone(), data));
+    }
+    import.activity = ActionsDB::fetch_activity(&server.actions_db, runtime)
+        .await
+        .map_err(|e| anyhow!("Unable to read {}: {}", server.actions_db, e))?;
+    let files = FileRequestHandler::new(runtime.clone(), server.file_db.clone())?;
+    for entry in import.activity.actions.iter() {
+        for action in entry.value() {
+            let config = files
+                .export(&action.content_id)
+                .await
+                .map_err(|e| anyhow!("Unable to read content {}: {}", action.content_id, e))?;
+            import.contents.push((action.content_id.clone(), config));
+        }
+    }
+
+    db.import(&import)?;
+    Ok(import)
+}
+
+fn migrate(conn: &mut Connection) -> Result<()> {
+    let tx = conn.transaction()?;
+    let version: usize = tx.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version > MIGRATIONS.len() {
+        return Err(anyhow!(
+            "SQLite database is at version {}, newer than the supported version {}",
+            version,
+            MIGRATIONS.len()
+        ));
+    }
+    for migration in &MIGRATIONS[version..] {
+        tx.execute_batch(migration)?;
+    }
+    tx.pragma_update(None, "user_version", MIGRATIONS.len())?;
+    tx.commit()?;
+    Ok(())
+}
+
+fn upsert_user(tx: &Transaction, username: &str, data: &str) -> rusqlite::Result<()> {
+    tx.execute(
+        "INSERT INTO users (username, data) VALUES (?1, ?2)
+         ON CONFLICT (username) DO UPDATE SET data = excluded.data",
+        [username, data],
+    )?;
+    Ok(())
+}
+
+fn save_actions(
+    tx: &Transaction,
+    activity: &ActionsActivity,
+    groups: &[String],
+) -> rusqlite::Result<()> {
+    let mut insert = tx.prepare(
+        "INSERT INTO actions
+         (group_id, content_id, is_notif, kind, assignment, submitter, submission, released)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+    )?;
+    for group_id in groups {
+        tx.execute("DELETE FROM actions WHERE group_id = ?1", [group_id])?;
+        let Some(actions) = activity.actions.get(group_id) else {
+            continue;
+        };
+        for action in actions.iter() {
+            insert.execute(params![
+                group_id,
+                action.content_id,
+                action.kind == ContentKind::Notice,
+                action.kind.as_str(),
+                action.assignment,
+                action.submitter,
+                action.submission,
+                action.released
+            ])?;
+        }
+    }
+    Ok(())
+}
+
+fn insert_content(
+    tx: &Transaction,
+    content_id: &str,
+    config: &RemoteFileConfig,
+) -> rusqlite::Result<()> {
+    let metadata = &config.metadata;
+    let assignment = json_column(metadata.assignment.as_ref())?;
+    let grade = json_column(metadata.grade.as_ref())?;
+    let submission = json_column(metadata.submission.as_ref())?;
+    let plain_files = json_column(Some(&metadata.plain_files).filter(|files| !files.is_empty()))?;
+    tx.execute(
+        "INSERT INTO contents
+         (content_id, title, description, timestamp, end_time, author, data_key, assignment,
+         grade, submission, plain_files)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+         ON CONFLICT (content_id) DO UPDATE SET title = excluded.title,
+         description = excluded.description, timestamp = excluded.timestamp,
+         end_time = excluded.end_time, author = excluded.author, data_key = excluded.data_key,
+         assignment = excluded.assignment, grade = excluded.grade,
+         submission = excluded.submission, plain_files = excluded.plain_files",
+        params![
+            content_id,
+            metadata.title,
+            metadata.description,
+            metadata.timestamp as i64,
+            metadata.end_time.map(|t| t as i64),
+            metadata.author,
+            metadata.data_key,
+            assignment,
+            grade,
+            submission,
+            plain_files
+        ],
+    )?;
+    tx.execute("DELETE FROM files WHERE content_id = ?1", [content_id])?;
+    let mut insert =
+        tx.prepare("INSERT INTO files (content_id, name, content) VALUES (?1, ?2, ?3)")?;
+    for file in &config.files {
+        insert.execute(params![content_id, file.name, file.content])?;
+    }
+    Ok(())
+}
+
+fn json_column<T: serde::Serialize>(value: Option<&T>) -> rusqlite::Result<Option<String>> {
+    value
+        .map(serde_json::to_string)
+        .transpose()
+        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_db::file_config::{AssignmentInfo, GradeInfo, RubricScore, SubmissionInfo};
+
+    fn config(title: &str) -> RemoteFileConfig {
+        RemoteFileConfig {
+            files: vec![FileHolder {
+                name: "foo.txt".to_string(),
+                content: vec![0x00, 0xff, b'\n'],
+            }],
+            metadata: Metadata {
+                title: title.to_string(),
+                description: "description".to_string(),
+                timestamp: 1_700_000_000_000,
+                end_time: Some(1_700_000_060_000),
+                author: Some("faculty".to_string()),
+                data_key: None,
+                plain_files: vec!["foo.txt".to_string()],
+                assignment: Some(AssignmentInfo::new(10, vec!["PDF".to_string()]).unwrap()),
+                grade: Some(GradeInfo {
+                    points: 8,
+                    rubric: vec![RubricScore {
+                        criterion: "correctness".to_string(),
+                        points: 8,
+                    }],
+                    penalty: 10,
+                }),
+                submission: Some(SubmissionInfo {
+                    due: Some(1_700_000_060_000),
+                    late_by: 90_000_000,
+                    penalty: 10,
+                }),
+            },
+        }
+    }
+
+    #[test]
+    fn test_migrations_and_reopen() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("lms.db");
+        let path = path.to_str().unwrap();
+
+        let db = SqliteDb::open(path)?;
+        db.upsert_user("foo", "encrypted foo")?;
+        db.upsert_user("foo", "encrypted foo v2")?;
+        db.insert_content("content", &config("title"))?;
+        let activity = ActionsActivity::default();
+        activity.actions.insert(
+            "22BCS_course1".to_string(),
+            vec![
+                ActionsContent {
+                    kind: ContentKind::Submission,
+                    content_id: "content".to_string(),
+                    assignment: Some("assignment".to_string()),
+                    submitter: Some("student".to_string()),
+                    submission: None,
+                    released: false,
+                },
+                ActionsContent {
+                    kind: ContentKind::Grade,
+                    content_id: "grade".to_string(),
+                    assignment: Some("assignment".to_string()),
+                    submitter: Some("student".to_string()),
+                    submission: Some("content".to_string()),
+                    released: true,
+                },
+            ],
+        );
+        db.save_actions(&activity, &["22BCS_course1".to_string()])?;
+        drop(db);
+
+        let db = SqliteDb::open(path)?;
+        let version: usize =
+            db.with(|conn| conn.query_row("PRAGMA user_version", [], |row| row.get(0)))?;
+        assert_eq!(version, MIGRATIONS.len());
+        assert_eq!(db.get_user("foo")?.as_deref(), Some("encrypted foo v2"));
+        assert_eq!(db.list_users()?.len(), 1);
+        assert_eq!(db.get_metadata("content")?, config("title").metadata);
+        assert_eq!(db.get_file("content", "foo.txt")?, config("title").files[0]);
+        assert_eq!(db.get_content("content")?, config("title"));
+        assert!(db.get_file("content", "bar.txt").is_err());
+        let actions = db.load_actions()?.get_actions(&"22BCS_course1".parse()?);
+        assert_eq!(
+            actions.as_deref(),
+            activity.get_actions(&"22BCS_course1".parse()?).as_deref()
+        );
+
+        // actions stored before there were kinds
+        db.with(|conn| {
+            conn.execute(
+                "INSERT INTO actions (group_id, content_id, is_notif) VALUES ('legacy', 'a', 0)",
+                [],
+            )
+        })?;
+        let legacy = db.load_actions()?.actions.get("legacy").unwrap().clone();
+        assert_eq!(
+            legacy,
+            vec![ActionsContent::new(ContentKind::Submission, "a")]
+        );
+        db.with(|conn| conn.execute("DELETE FROM actions WHERE group_id = 'legacy'", []))?;
+
+        // moving a group rewrites both groups in the same transaction
+        let (_, moved) = activity.actions.remove("22BCS_course1").unwrap();
+        activity.actions.insert("22BCS_course2".to_string(), moved);
+        db.save_actions(
+            &activity,
+            &["22BCS_course1".to_string(), "22BCS_course2".to_string()],
+        )?;
+        let loaded = db.load_actions()?;
+        assert!(loaded.actions.get("22BCS_course1").is_none());
+        assert_eq!(loaded.actions.get("22BCS_course2").unwrap().len(), 2);
+
+        let bar = FileHolder {
+            name: "bar.txt".to_string(),
+            content: b"bar".to_vec(),
+        };
+        db.put_file("content", &bar)?;
+        assert_eq!(db.get_file("content", "bar.txt")?, bar);
+        assert!(db.put_file("unknown", &bar).is_err());
+
+        assert_eq!(db.list_contents()?, vec!["content".to_string()]);
+        assert!(db.delete_content("content")?);
+        assert!(!db.delete_content("content")?);
+        assert!(db.get_file("content", "foo.txt").is_err());
+        assert!(db.list_contents()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrates_text_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("lms.db");
+        let path = path.to_str().unwrap();
+
+        let conn = Connection::open(path)?;
+        conn.execute_batch(MIGRATIONS[0])?;
+        conn.pragma_update(None, "user_version", 1)?;
+        conn.execute(
+            "INSERT INTO contents (content_id, title, description, timestamp) VALUES ('content', '', '', 0)",
+            [],
+        )?;
+        conn.execute(
+            "INSERT INTO files (content_id, name, content) VALUES ('content', 'foo.txt', 'AQBF')",
+            [],
+        )?;
+        drop(conn);
+
+        let db = SqliteDb::open(path)?;
+        assert_eq!(db.get_file("content", "foo.txt")?.content, b"AQBF");
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_transaction_rolls_back() -> Result<()> {
+        let db = SqliteDb::open(":memory:")?;
+        db.upsert_user("foo", "foo")?;
+        db.upsert_user("bar", "bar")?;
+        let result = db.update_users(|data| {
+            if data == "bar" {
+                Err(anyhow!("failed"))
+            } else {
+                Ok(format!("{} v2", data))
+            }
+        });
+        assert!(result.is_err());
+        assert_eq!(db.list_users()?, vec!["bar", "foo"]);
+
+        assert_eq!(db.update_users(|data| Ok(format!("{} v2", data)))?, 2);
+        assert_eq!(db.list_users()?, vec!["bar v2", "foo v2"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_import_json() -> Result<()> {
+        use crate::authdb::auth_actors::{Authority, User};
+        use crate::authdb::auth_store::SqliteAuthStore;
+        use crate::config::config_module::ConfigModule;
+        use crate::file_db::file_config::InsertionInfo;
+
+        let runtime = crate::runtime::tests::init();
+        let mut module = ConfigModule::default();
+        module.server.actions_db = "actions.json".to_string();
+        module.server.file_db = "files".to_string();
+        module.auth.aes_key = "32bytebase64encodedkey".to_string();
+        module.auth.totp.totp_secret = "base32encodedkey".to_string();
+        module.auth.auth_db_path = "auth".to_string();
+        let blueprint = Blueprint::try_from(module.resolve(&runtime, None).await?)?;
+
+        let user = User {
+            username: "foo".to_string(),
+            name: "Foo".to_string(),
+            password: "password".to_string(),
+            authority: Authority::Admin,
+            batch: None,
+            two_factor: None,
+            password_reset: None,
+        };
+        let auth = blueprint.extensions.auth.clone();
+        LocalAuthStore::new(auth.clone(), runtime.clone())
+            .upsert(user.clone())
+            .await?;
+        let info = InsertionInfo {
+            title: "title".to_string(),
+            description: "description".to_string(),
+            timestamp: 1,
+            end_time: None,
+            author: None,
+            assignment: None,
+            grade: None,
+            submission: None,
+        };
+        let content_id = FileRequestHandler::new(runtime.clone(), "files".to_string())?
+            .insert(info, config("title").files)
+            .await?;
+        let actions = format!(
+            r#"{{"actions":{{"22BCS_course1":[{{"is_notif":true,"content_id":"{}"}}]}}}}"#,
+            content_id
+        );
+        runtime
+            .file
+            .write("actions.json", actions.as_bytes())
+            .await?;
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("lms.db");
+        let path = path.to_str().unwrap();
+        let db = SqliteDb::open(path)?;
+        let import = import_json(&db, &blueprint, &runtime).await?;
+        assert_eq!(import.contents.len(), 1);
+        // importing twice doesn't duplicate rows
+        import_json(&db, &blueprint, &runtime).await?;
+
+        let store = SqliteAuthStore::open(auth, path)?;
+        assert_eq!(store.get("foo").await?, Some(user));
+        assert_eq!(db.get_metadata(&content_id)?.title, "title");
+        assert_eq!(
+            db.get_file(&content_id, "foo.txt")?.content,
+            config("title").files[0].content
+        );
+        let actions = db.load_actions()?;
+        assert_eq!(actions.actions.get("22BCS_course1").unwrap().len(), 1);
+        Ok(())
+    }
+}
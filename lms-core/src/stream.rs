@@ -0,0 +1,246 @@
+//! Streamed files, see [crate::FileIO::read_stream] and [crate::FileIO::write_stream].
+
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+use std::pin::Pin;
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use futures_util::{stream, Stream, StreamExt};
+
+pub type ByteStream = Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>;
+
+/// Part of a file being read.
+pub struct FileStream {
+    /// Size of the whole file
+    pub size: u64,
+    /// Bytes of the file in `stream`
+    pub range: Range<u64>,
+    pub stream: ByteStream,
+}
+
+/// The single range of a `Range: bytes=...` header, several ranges aren't supported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    /// `bytes=start-` or `bytes=start-end`, `end` is inclusive
+    From { start: u64, end: Option<u64> },
+    /// `bytes=-len`, the last `len` bytes
+    Suffix(u64),
+}
+
+/// No byte of the file is in the requested range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RangeNotSatisfiable {
+    pub size: u64,
+}
+
+/// A streamed file went over the size cap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TooLarge {
+    pub max: u64,
+}
+
+impl Display for RangeNotSatisfiable {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Range not satisfiable, the file has {} bytes", self.size)
+    }
+}
+
+impl std::error::Error for RangeNotSatisfiable {}
+
+impl Display for TooLarge {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "File exceeds the size limit of {} bytes", self.max)
+    }
+}
+
+impl std::error::Error for TooLarge {}
+
+impl Display for ByteRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ByteRange::From { start, end: None } => write!(f, "bytes={}-", start),
+            ByteRange::From {
+                start,
+                end: Some(end),
+            } => write!(f, "bytes={}-{}", start, end),
+            ByteRange::Suffix(len) => write!(f, "bytes=-{}", len),
+        }
+    }
+}
+
+impl ByteRange {
+    pub fn parse(header: &str) -> Result<Self> {
+        let spec = header
+            .trim()
+            .strip_prefix("bytes=")
+            .ok_or_else(|| anyhow!("Only byte ranges are supported"))?;
+        if spec.contains(',') {
+            return Err(anyhow!("Multiple ranges are not supported"));
+        }
+        let (start, end) = spec
+            .split_once('-')
+            .ok_or_else(|| anyhow!("Invalid range {}", spec))?;
+        let int = |s: &str| {
+            s.trim()
+                .parse::<u64>()
+                .map_err(|_| anyhow!("Invalid range {}", spec))
+        };
+        match (start.trim(), end.trim()) {
+            ("", end) => Ok(ByteRange::Suffix(int(end)?)),
+            (start, "") => Ok(ByteRange::From {
+                start: int(start)?,
+                end: None,
+            }),
+            (start, end) => {
+                let (start, end) = (int(start)?, int(end)?);
+                if end < start {
+                    return Err(anyhow!("Invalid range {}", spec));
+                }
+                Ok(ByteRange::From {
+                    start,
+                    end: Some(end),
+                })
+            }
+        }
+    }
+
+    /// Bytes of a file of `size` covered by the range, fails with [RangeNotSatisfiable].
+    pub fn resolve(self, size: u64) -> Result<Range<u64>> {
+        let range = match self {
+            ByteRange::From { start, end } => {
+                start..end.map_or(size, |end| end.saturating_add(1).min(size))
+            }
+            ByteRange::Suffix(len) => size.saturating_sub(len)..size,
+        };
+        if range.start >= range.end {
+            return Err(RangeNotSatisfiable { size }.into());
+        }
+        Ok(range)
+    }
+}
+
+/// The whole file without a range.
+pub fn resolve(range: Option<ByteRange>, size: u64) -> Result<Range<u64>> {
+    range.map_or(Ok(0..size), |range| range.resolve(size))
+}
+
+impl FileStream {
+    /// Streams `range` of a file held in memory.
+    pub fn from_bytes(content: Vec<u8>, range: Option<ByteRange>) -> Result<Self> {
+        let size = content.len() as u64;
+        let range = resolve(range, size)?;
+        let chunk = Bytes::from(content).slice(range.start as usize..range.end as usize);
+        Ok(Self {
+            size,
+            range,
+            stream: once(chunk),
+        })
+    }
+
+    /// Value of the `Content-Range` header for the streamed bytes.
+    pub fn content_range(&self) -> String {
+        format!(
+            "bytes {}-{}/{}",
+            self.range.start,
+            self.range.end.saturating_sub(1),
+            self.size
+        )
+    }
+
+    /// Parses a `Content-Range` header, see [FileStream::content_range].
+    pub fn parse_content_range(value: &str) -> Result<(Range<u64>, u64)> {
+        let invalid = || anyhow!("Invalid Content-Range {}", value);
+        let (range, size) = value
+            .strip_prefix("bytes ")
+            .and_then(|value| value.split_once('/'))
+            .ok_or_else(invalid)?;
+        let (start, end) = range.split_once('-').ok_or_else(invalid)?;
+        let int = |s: &str| s.parse::<u64>().map_err(|_| invalid());
+        Ok((int(start)?..int(end)? + 1, int(size)?))
+    }
+}
+
+pub fn once(chunk: Bytes) -> ByteStream {
+    Box::pin(stream::once(async move { Ok(chunk) }))
+}
+
+/// Fails the stream with [TooLarge] once more than `max` bytes went through.
+pub fn limit(stream: ByteStream, max: u64) -> ByteStream {
+    let mut total = 0u64;
+    Box::pin(stream.map(move |chunk| {
+        let chunk = chunk?;
+        total += chunk.len() as u64;
+        if total > max {
+            return Err(TooLarge { max }.into());
+        }
+        Ok(chunk)
+    }))
+}
+
+/// Reads the whole stream into memory.
+pub async fn collect(mut stream: ByteStream) -> Result<Vec<u8>> {
+    let mut content = vec![];
+    while let Some(chunk) = stream.next().await {
+        content.extend_from_slice(&chunk?);
+    }
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_byte_range() -> Result<()> {
+        let from = |start, end| ByteRange::From { start, end };
+        assert_eq!(ByteRange::parse("bytes=0-99")?, from(0, Some(99)));
+        assert_eq!(ByteRange::parse("bytes=100-")?, from(100, None));
+        assert_eq!(ByteRange::parse("bytes=-10")?, ByteRange::Suffix(10));
+        assert!(ByteRange::parse("bytes=5-1").is_err());
+        assert!(ByteRange::parse("bytes=0-1,5-9").is_err());
+        assert!(ByteRange::parse("items=0-1").is_err());
+        for range in [from(0, Some(99)), from(100, None), ByteRange::Suffix(10)] {
+            assert_eq!(ByteRange::parse(&range.to_string())?, range);
+        }
+
+        assert_eq!(from(0, Some(99)).resolve(50)?, 0..50);
+        assert_eq!(from(10, None).resolve(50)?, 10..50);
+        assert_eq!(ByteRange::Suffix(10).resolve(50)?, 40..50);
+        assert_eq!(ByteRange::Suffix(100).resolve(50)?, 0..50);
+        let err = from(50, None).resolve(50).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<RangeNotSatisfiable>(),
+            Some(&RangeNotSatisfiable { size: 50 })
+        );
+        assert!(ByteRange::Suffix(0).resolve(50).is_err());
+        assert!(ByteRange::Suffix(10).resolve(0).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_file_stream() -> Result<()> {
+        let file = FileStream::from_bytes(b"0123456789".to_vec(), Some(ByteRange::Suffix(3)))?;
+        assert_eq!(file.content_range(), "bytes 7-9/10");
+        assert_eq!(
+            FileStream::parse_content_range(&file.content_range())?,
+            (7..10, 10)
+        );
+        assert_eq!(collect(file.stream).await?, b"789");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_limit() -> Result<()> {
+        let chunks = || {
+            Box::pin(stream::iter(vec![
+                Ok(Bytes::from_static(b"0123")),
+                Ok(Bytes::from_static(b"4567")),
+            ])) as ByteStream
+        };
+        assert_eq!(collect(limit(chunks(), 8)).await?, b"01234567");
+        let err = collect(limit(chunks(), 7)).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<TooLarge>(), Some(&TooLarge { max: 7 }));
+        Ok(())
+    }
+}
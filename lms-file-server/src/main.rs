@@ -0,0 +1,422 @@
+//! Reference server of the remote FileDB protocol, see [lms_core::file_db::remote].
+//! It stores the contents in a local dir, laid out like a local `fileDb`.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use clap::Parser;
+use http_body_util::Full;
+use hyper::service::service_fn;
+use hyper::Response;
+use tokio::net::TcpListener;
+
+use lms_core::file_db::remote::RemoteFileServer;
+use lms_core::file_db::request_handler::FileRequestHandler;
+
+#[derive(Parser)]
+#[command(name = "lms-file-server")]
+struct Cli {
+    /// Dir the contents are stored in
+    #[arg(required = true)]
+    dir: String,
+    /// Path the protocol is served under, the path of `fileDb` when a proxy doesn't strip it
+    #[arg(long, default_value = "/")]
+    base_path: String,
+    #[arg(long, default_value = "127.0.0.1")]
+    host: String,
+    #[arg(short, long, default_value_t = 19196)]
+    port: u16,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::new().filter_or("LMS_LOG_LEVEL", "info")).init();
+    let cli = Cli::parse();
+
+    let files = FileRequestHandler::new(lms::cli::rt::init(), cli.dir)?;
+    let server = Arc::new(RemoteFileServer::new(files, &cli.base_path));
+
+    let listener = TcpListener::bind(format!("{}:{}", cli.host, cli.port)).await?;
+    log::info!("Listening on: http://{}", listener.local_addr()?);
+    serve(listener, server).await
+}
+
+async fn serve(listener: TcpListener, server: Arc<RemoteFileServer>) -> anyhow::Result<()> {
+    loop {
+        let (stream, remote_addr) = listener.accept().await?;
+        let io = hyper_util::rt::TokioIo::new(stream);
+        let server = server.clone();
+        tokio::spawn(async move {
+            let service = service_fn(move |req| handle(req, remote_addr, server.clone()));
+            if let Err(e) = hyper::server::conn::http1::Builder::new()
+                .serve_connection(io, service)
+                .await
+            {
+                log::error!("An error occurred while handling a request: {e}");
+            }
+        });
+    }
+}
+
+async fn handle(
+    req: hyper::Request<hyper::body::Incoming>,
+    remote_addr: SocketAddr,
+    server: Arc<RemoteFileServer>,
+) -> anyhow::Result<Response<Full<bytes::Bytes>>> {
+    let req = lms_core::http::request::Request::from_hyper(req, Some(remote_addr.ip())).await?;
+    let response = server
+        .handle(&req.method, req.url.path(), &req.headers, &req.body)
+        .await;
+    if !response.status.is_success() {
+        log::warn!(
+            "{}: {} {} {}",
+            remote_addr.ip(),
+            req.method,
+            req.url.path(),
+            response.status
+        );
+    }
+    response.into_hyper()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::prelude::BASE64_STANDARD;
+    use base64::Engine;
+    use lms_core::actions_db::actions::{ActionsRead, ActionsRequest, ActionsWrite, FileWrite};
+    use lms_core::actions_db::actions_db::ActionsDB;
+    use lms_core::app_ctx::AppContext;
+    use lms_core::authdb::auth_actors::{Authority, User, Users};
+    use lms_core::authdb::auth_db::gen_token;
+    use lms_core::blueprint::Blueprint;
+    use lms_core::config::batch_info::BatchInfo;
+    use lms_core::config::config_module::ConfigModule;
+    use lms_core::config::course_info::CourseInfo;
+    use lms_core::file_db::encryption;
+    use lms_core::file_db::file_config::{Encoding, FileHolder, InsertionInfo, Metadata};
+    use lms_core::stream::{self, ByteRange, RangeNotSatisfiable};
+
+    /// Serves `dir` under `/lms/files` and returns the url to use as `fileDb`.
+    async fn start(dir: &str) -> anyhow::Result<String> {
+        let files = FileRequestHandler::new(lms::cli::rt::init(), dir.to_string())?;
+        let server = RemoteFileServer::new(files, "/lms/files");
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}/lms/files", listener.local_addr()?);
+        tokio::spawn(serve(listener, Arc::new(server)));
+        Ok(url)
+    }
+
+    fn app_ctx(file_db: String, actions_db: &str) -> anyhow::Result<AppContext> {
+        let mut module = ConfigModule::default();
+        module.auth.aes_key = "32bytebase64encodedkey".to_string();
+        module.auth.totp.totp_secret = "base32encodedkey".to_string();
+        module.auth.auth_db_path = "invalid".to_string();
+        module.courses.insert(
+            "course1".to_string(),
+            CourseInfo {
+                name: "Course 1".to_string(),
+                description: None,
+            },
+        );
+        module.batches = vec![BatchInfo {
+            id: "22BCS".to_string(),
+            courses: vec!["course1".to_string()],
+        }];
+        module.server.file_db = file_db;
+        module.server.actions_db = actions_db.to_string();
+        module.extensions.users = Some(Users::default());
+        module.extensions.auth = Some(module.auth_provider("invalid".to_string())?);
+
+        Ok(AppContext {
+            blueprint: Blueprint::try_from(module)?,
+            runtime: lms::cli::rt::init(),
+        })
+    }
+
+    fn request(
+        token: &str,
+        read: Option<ActionsRead>,
+        write: Option<ActionsWrite>,
+    ) -> bytes::Bytes {
+        let request = ActionsRequest {
+            token: token.to_string(),
+            group_id: "22BCS_course1".to_string(),
+            read,
+            write,
+            release: None,
+        };
+        bytes::Bytes::from(request.into_serrequet().unwrap())
+    }
+
+    fn decode(message: &str) -> anyhow::Result<String> {
+        Ok(String::from_utf8(BASE64_STANDARD.decode(message)?)?)
+    }
+
+    #[tokio::test]
+    async fn test_actions_db_on_remote_file_db() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let files_dir = dir.path().join("files").to_string_lossy().to_string();
+        let actions_path = dir
+            .path()
+            .join("actions.json")
+            .to_string_lossy()
+            .to_string();
+        let file_db = start(&files_dir).await?;
+
+        let app_context = Arc::new(app_ctx(file_db.clone(), &actions_path)?);
+        let user = User {
+            username: "admin".to_string(),
+            name: "Admin".to_string(),
+            password: "password".to_string(),
+            authority: Authority::Admin,
+            batch: None,
+            two_factor: None,
+            password_reset: None,
+        };
+        let token = gen_token(&user, &app_context)?;
+        let actions_db = ActionsDB::init(app_context.clone()).await?;
+
+        let write = ActionsWrite {
+            title: "title".to_string(),
+            description: "description".to_string(),
+            files: Some(vec![FileWrite {
+                file_name: "notes 1.pdf".to_string(),
+                encoding: Encoding::Base64,
+                content: "JVBERi0xLjcK/wCA".to_string(),
+            }]),
+            end_time: None,
+            reference: "notice".to_string(),
+            max_points: None,
+            allowed_types: None,
+            grade: None,
+            late_policy: None,
+            extension: None,
+        };
+        let result = actions_db
+            .handle_request(request(&token, None, Some(write)))
+            .await;
+        assert_eq!(result.status, 200, "{}", decode(&result.message)?);
+        let content_id = decode(&result.message)?;
+
+        let read = ActionsRead {
+            content_id: content_id.clone(),
+            file_name: None,
+            submissions: None,
+            grades: None,
+        };
+        let result = actions_db
+            .handle_request(request(&token, Some(read), None))
+            .await;
+        assert_eq!(result.status, 200);
+        let metadata: Metadata = serde_json::from_str(&decode(&result.message)?)?;
+        assert_eq!(metadata.title, "title");
+
+        let read = ActionsRead {
+            content_id: content_id.clone(),
+            file_name: Some("notes 1.pdf".to_string()),
+            submissions: None,
+            grades: None,
+        };
+        let result = actions_db
+            .handle_request(request(&token, Some(read), None))
+            .await;
+        assert_eq!(result.status, 200);
+        let file: FileHolder = serde_json::from_str(&decode(&result.message)?)?;
+        assert_eq!(file.content, b"%PDF-1.7\n\xff\x00\x80");
+
+        // the contents land in the dir of the server, under the layout of a local FileDB
+        let local = FileRequestHandler::new(lms::cli::rt::init(), files_dir)?;
+        assert_eq!(local.get_metadata(&content_id).await?, metadata);
+
+        let remote = FileRequestHandler::new(lms::cli::rt::init(), file_db)?;
+        assert_eq!(remote.list().await?, vec![content_id.clone()]);
+        remote.delete(&content_id).await?;
+        assert!(remote.list().await?.is_empty());
+        let read = ActionsRead {
+            content_id,
+            file_name: None,
+            submissions: None,
+            grades: None,
+        };
+        let result = actions_db
+            .handle_request(request(&token, Some(read), None))
+            .await;
+        assert_eq!(result.status, 500);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_streams_through_remote_file_db() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let files_dir = dir.path().join("files").to_string_lossy().to_string();
+        let file_db = start(&files_dir).await?;
+
+        let remote = FileRequestHandler::new(lms::cli::rt::init(), file_db)?;
+        let info = InsertionInfo {
+            title: "title".to_string(),
+            description: "description".to_string(),
+            timestamp: 1,
+            end_time: None,
+            author: Some("faculty".to_string()),
+            assignment: None,
+            grade: None,
+            submission: None,
+        };
+        let content_id = remote.insert(info, vec![]).await?;
+        let content = stream::once(bytes::Bytes::from_static(b"0123456789"));
+        assert_eq!(
+            remote.put_stream(&content_id, "notes.pdf", content).await?,
+            10
+        );
+
+        let range = ByteRange::Suffix(4);
+        let file = remote
+            .get_stream(&content_id, "notes.pdf", Some(range))
+            .await?;
+        assert_eq!(file.content_range(), "bytes 6-9/10");
+        assert_eq!(stream::collect(file.stream).await?, b"6789");
+        let file = remote.get_stream(&content_id, "notes.pdf", None).await?;
+        assert_eq!(file.range, 0..10);
+
+        let range = ByteRange::From {
+            start: 10,
+            end: None,
+        };
+        let err = remote
+            .get_stream(&content_id, "notes.pdf", Some(range))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.downcast_ref::<RangeNotSatisfiable>(),
+            Some(&RangeNotSatisfiable { size: 10 })
+        );
+        let content = stream::once(bytes::Bytes::new());
+        assert!(remote
+            .put_stream("unknown", "notes.pdf", content)
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_encrypts_before_remote_file_db() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let files_dir = dir.path().join("files").to_string_lossy().to_string();
+        let file_db = start(&files_dir).await?;
+        let app_context = app_ctx(file_db.clone(), "actions.json")?;
+        let keyring = app_context.blueprint.extensions.auth.keyring().clone();
+
+        let remote = FileRequestHandler::new(lms::cli::rt::init(), file_db)?
+            .with_keyring(keyring.clone(), true);
+        let info = InsertionInfo {
+            title: "title".to_string(),
+            description: "description".to_string(),
+            timestamp: 1,
+            end_time: None,
+            author: None,
+            assignment: None,
+            grade: None,
+            submission: None,
+        };
+        let notes = FileHolder {
+            name: "notes.pdf".to_string(),
+            content: b"%PDF-1.7 notes".to_vec(),
+        };
+        let content_id = remote.insert(info, vec![notes.clone()]).await?;
+        assert_eq!(remote.get(&content_id, "notes.pdf").await?, notes);
+        let file = remote
+            .get_stream(&content_id, "notes.pdf", Some(ByteRange::Suffix(5)))
+            .await?;
+        assert_eq!(stream::collect(file.stream).await?, b"notes");
+
+        // the server only ever sees the encrypted file and the wrapped key
+        let local = FileRequestHandler::new(lms::cli::rt::init(), files_dir.clone())?;
+        let stored = local.get(&content_id, "notes.pdf").await?.content;
+        assert_eq!(
+            encryption::plaintext_len(stored.len() as u64)?,
+            notes.content.len() as u64
+        );
+        assert!(!stored.windows(5).any(|w| w == b"notes"));
+        assert!(local.get_metadata(&content_id).await?.data_key.is_some());
+
+        // which the tool can encrypt in place through the dir of the server
+        let plain = FileRequestHandler::new(lms::cli::rt::init(), files_dir.clone())?;
+        let info = InsertionInfo {
+            title: "plain".to_string(),
+            description: "description".to_string(),
+            timestamp: 2,
+            end_time: None,
+            author: None,
+            assignment: None,
+            grade: None,
+            submission: None,
+        };
+        let plain_id = plain.insert(info, vec![notes.clone()]).await?;
+        let local = local.with_keyring(keyring, true);
+        assert_eq!(local.encrypt_all().await?, 1);
+        assert_eq!(remote.get(&plain_id, "notes.pdf").await?, notes);
+        assert!(remote.encrypt_all().await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_submission_checks_remote_assignment() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let files_dir = dir.path().join("files").to_string_lossy().to_string();
+        let actions_path = dir
+            .path()
+            .join("actions.json")
+            .to_string_lossy()
+            .to_string();
+        let file_db = start(&files_dir).await?;
+
+        let app_context = Arc::new(app_ctx(file_db, &actions_path)?);
+        let mut user = User {
+            username: "admin".to_string(),
+            name: "Admin".to_string(),
+            password: "password".to_string(),
+            authority: Authority::Admin,
+            batch: None,
+            two_factor: None,
+            password_reset: None,
+        };
+        let admin = gen_token(&user, &app_context)?;
+        user.username = "student".to_string();
+        user.authority = Authority::Student;
+        user.batch = Some("22BCS".to_string());
+        let student = gen_token(&user, &app_context)?;
+        let actions_db = ActionsDB::init(app_context.clone()).await?;
+
+        let write = |reference: &str, end_time| ActionsWrite {
+            title: "title".to_string(),
+            description: "description".to_string(),
+            files: None,
+            end_time,
+            reference: reference.to_string(),
+            max_points: (reference == "assignment").then_some(10),
+            allowed_types: None,
+            grade: None,
+            late_policy: None,
+            extension: None,
+        };
+        let result = actions_db
+            .handle_request(request(&admin, None, Some(write("assignment", Some(1)))))
+            .await;
+        assert_eq!(result.status, 200);
+        let assignment = decode(&result.message)?;
+
+        // the end time is read back from the remote FileDB
+        let result = actions_db
+            .handle_request(request(&student, None, Some(write(&assignment, None))))
+            .await;
+        assert_eq!(decode(&result.message)?, "Submission time has passed");
+
+        // and the activity survives a restart
+        let actions_db = ActionsDB::init(app_context).await?;
+        let result = actions_db.handle_request(request(&admin, None, None)).await;
+        assert!(decode(&result.message)?.contains(&assignment));
+        Ok(())
+    }
+}
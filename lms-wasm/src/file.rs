@@ -2,11 +2,18 @@ use std::rc::Rc;
 
 use anyhow::anyhow;
 use async_std::task::spawn_local;
-use lms_core::FileIO;
+use futures_util::StreamExt;
+use lms_core::stream::{self, ByteRange, ByteStream, FileStream};
+use lms_core::{FileIO, NotFound};
 use worker::Env;
 
+use crate::stream::SendStream;
 use crate::to_anyhow;
 
+/// Size of the parts of a multipart upload, R2 wants every part but the last
+/// to have the same size of at least 5MiB.
+const PART_SIZE: usize = 8 * 1024 * 1024;
+
 #[derive(Clone)]
 pub struct WasmFileIO {
     bucket: Rc<worker::Bucket>,
@@ -26,26 +33,178 @@ impl WasmFileIO {
 unsafe impl Sync for WasmFileIO {}
 unsafe impl Send for WasmFileIO {}
 
-async fn get(bucket: Rc<worker::Bucket>, path: String) -> anyhow::Result<String> {
+async fn get(bucket: Rc<worker::Bucket>, path: String) -> anyhow::Result<Vec<u8>> {
     let maybe_object = bucket
         .get(path.clone())
         .execute()
         .await
         .map_err(to_anyhow)?;
-    let object = maybe_object.ok_or(anyhow!("File '{}' was not found in bucket", path))?;
+    let object = maybe_object.ok_or_else(|| NotFound::new(&path))?;
 
     let body = match object.body() {
-        Some(body) => body.text().await.map_err(to_anyhow),
-        None => Ok("".to_string()),
+        Some(body) => body.bytes().await.map_err(to_anyhow),
+        None => Ok(vec![]),
     };
     body
 }
 
+/// Deletes `path` along with every object under `path/`.
+async fn delete(bucket: Rc<worker::Bucket>, path: String) -> anyhow::Result<()> {
+    let mut keys = list_keys(&bucket, format!("{}/", path.trim_end_matches('/')), None).await?;
+    keys.push(path);
+    for key in keys {
+        bucket.delete(key).await.map_err(to_anyhow)?;
+    }
+    Ok(())
+}
+
+/// Names directly under `path`, the objects and the prefixes ending with a `/`.
+async fn list(bucket: Rc<worker::Bucket>, path: String) -> anyhow::Result<Vec<String>> {
+    let prefix = format!("{}/", path.trim_end_matches('/'));
+    let mut names = list_keys(&bucket, prefix.clone(), Some("/")).await?;
+    for name in names.iter_mut() {
+        *name = name
+            .trim_start_matches(&prefix)
+            .trim_end_matches('/')
+            .to_string();
+    }
+    names.sort();
+    names.dedup();
+    Ok(names)
+}
+
+async fn list_keys(
+    bucket: &worker::Bucket,
+    prefix: String,
+    delimiter: Option<&str>,
+) -> anyhow::Result<Vec<String>> {
+    let mut keys = vec![];
+    let mut cursor = None;
+    loop {
+        let mut builder = bucket.list().prefix(prefix.clone());
+        if let Some(delimiter) = delimiter {
+            builder = builder.delimiter(delimiter);
+        }
+        if let Some(cursor) = cursor {
+            builder = builder.cursor(cursor);
+        }
+        let objects = builder.execute().await.map_err(to_anyhow)?;
+        keys.extend(objects.objects().iter().map(|object| object.key()));
+        keys.extend(objects.delimited_prefixes());
+        cursor = objects.cursor();
+        if !objects.truncated() || cursor.is_none() {
+            return Ok(keys);
+        }
+    }
+}
+
 async fn put(bucket: Rc<worker::Bucket>, path: String, value: Vec<u8>) -> anyhow::Result<()> {
     bucket.put(path, value).execute().await.map_err(to_anyhow)?;
     Ok(())
 }
 
+/// R2 objects can't be appended to, so the object is put again with `content` at its end.
+/// Only a missing object is taken as empty, any other failure to get it is returned.
+async fn append(bucket: Rc<worker::Bucket>, path: String, content: Vec<u8>) -> anyhow::Result<()> {
+    let mut object = match get(bucket.clone(), path.clone()).await {
+        Ok(object) => object,
+        Err(err) if err.is::<NotFound>() => vec![],
+        Err(err) => return Err(err),
+    };
+    object.extend_from_slice(&content);
+    put(bucket, path, object).await
+}
+
+async fn get_stream(
+    bucket: Rc<worker::Bucket>,
+    path: String,
+    range: Option<ByteRange>,
+) -> anyhow::Result<FileStream> {
+    let head = bucket.head(path.clone()).await.map_err(to_anyhow)?;
+    let head = head.ok_or_else(|| NotFound::new(&path))?;
+    let size = head.size() as u64;
+    let range = stream::resolve(range, size)?;
+
+    let mut get = bucket.get(path.clone());
+    if range != (0..size) {
+        get = get.range(worker::Range::OffsetWithLength {
+            offset: u32::try_from(range.start)?,
+            length: u32::try_from(range.end - range.start)?,
+        });
+    }
+    let object = get.execute().await.map_err(to_anyhow)?;
+    let object = object.ok_or_else(|| NotFound::new(&path))?;
+    let stream = match object.body() {
+        Some(body) => Box::pin(SendStream::new(body.stream().map_err(to_anyhow)?)) as ByteStream,
+        None => stream::once(bytes::Bytes::new()),
+    };
+    Ok(FileStream {
+        size,
+        range,
+        stream,
+    })
+}
+
+/// Sends the stream as a multipart upload, in parts of [PART_SIZE],
+/// a stream shorter than a part is put at once.
+async fn put_stream(
+    bucket: Rc<worker::Bucket>,
+    path: String,
+    mut stream: ByteStream,
+) -> anyhow::Result<u64> {
+    let mut buffer = Vec::new();
+    let mut size = 0;
+    let mut upload = None;
+    let mut parts = vec![];
+    let result = async {
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            size += chunk.len() as u64;
+            buffer.extend_from_slice(&chunk);
+            while buffer.len() >= PART_SIZE {
+                let rest = buffer.split_off(PART_SIZE);
+                let part = std::mem::replace(&mut buffer, rest);
+                if upload.is_none() {
+                    let created = bucket.create_multipart_upload(path.clone()).execute();
+                    upload = Some(created.await.map_err(to_anyhow)?);
+                }
+                if let Some(upload) = &upload {
+                    let part = upload.upload_part(parts.len() as u16 + 1, part);
+                    parts.push(part.await.map_err(to_anyhow)?);
+                }
+            }
+        }
+        if let Some(upload) = &upload {
+            if !buffer.is_empty() {
+                let part = upload.upload_part(parts.len() as u16 + 1, std::mem::take(&mut buffer));
+                parts.push(part.await.map_err(to_anyhow)?);
+            }
+        }
+        anyhow::Ok(())
+    }
+    .await;
+
+    match (result, upload) {
+        (Ok(()), None) => {
+            bucket
+                .put(path, buffer)
+                .execute()
+                .await
+                .map_err(to_anyhow)?;
+        }
+        (Ok(()), Some(upload)) => {
+            upload.complete(parts).await.map_err(to_anyhow)?;
+        }
+        (Err(err), upload) => {
+            if let Some(upload) = upload {
+                let _ = upload.abort().await;
+            }
+            return Err(err);
+        }
+    }
+    Ok(size)
+}
+
 #[async_trait::async_trait]
 impl FileIO for WasmFileIO {
     async fn write<'a>(&'a self, path: &'a str, content: &'a [u8]) -> anyhow::Result<()> {
@@ -57,7 +216,7 @@ impl FileIO for WasmFileIO {
         Ok(())
     }
 
-    async fn read<'a>(&'a self, path: &'a str) -> anyhow::Result<String> {
+    async fn read_bytes<'a>(&'a self, path: &'a str) -> anyhow::Result<Vec<u8>> {
         let bucket = self.bucket.clone();
         let path_cloned = path.to_string();
         let content = spawn_local(get(bucket, path_cloned)).await?;
@@ -65,8 +224,45 @@ impl FileIO for WasmFileIO {
         Ok(content)
     }
 
+    async fn append<'a>(&'a self, path: &'a str, content: &'a [u8]) -> anyhow::Result<()> {
+        let bucket = self.bucket.clone();
+        spawn_local(append(bucket, path.to_string(), content.to_vec())).await?;
+        log::info!("File append: {} ({} bytes) ... ok", path, content.len());
+        Ok(())
+    }
+
+    async fn read_stream<'a>(
+        &'a self,
+        path: &'a str,
+        range: Option<ByteRange>,
+    ) -> anyhow::Result<FileStream> {
+        let bucket = self.bucket.clone();
+        let file = spawn_local(get_stream(bucket, path.to_string(), range)).await?;
+        log::info!("File stream: {} {} ... ok", path, file.content_range());
+        Ok(file)
+    }
+
+    async fn write_stream<'a>(&'a self, path: &'a str, stream: ByteStream) -> anyhow::Result<u64> {
+        let bucket = self.bucket.clone();
+        let size = spawn_local(put_stream(bucket, path.to_string(), stream)).await?;
+        log::info!("File write: {} ({} bytes) ... ok", path, size);
+        Ok(size)
+    }
+
     async fn create_dirs<'a>(&'a self, _path: &'a str) -> anyhow::Result<()> {
         // Cloudflare Workers KV doesn't have directories
         Ok(())
     }
+
+    async fn delete<'a>(&'a self, path: &'a str) -> anyhow::Result<()> {
+        let bucket = self.bucket.clone();
+        spawn_local(delete(bucket, path.to_string())).await?;
+        log::info!("Delete: {} ... ok", path);
+        Ok(())
+    }
+
+    async fn list<'a>(&'a self, path: &'a str) -> anyhow::Result<Vec<String>> {
+        let bucket = self.bucket.clone();
+        spawn_local(list(bucket, path.to_string())).await
+    }
 }
@@ -1,5 +1,6 @@
-use crate::http::{to_request, to_response};
+use crate::http::{to_method, to_request, to_response, to_stream_request, to_stream_response};
 use crate::runtime;
+use crate::to_anyhow;
 use http_body_util::Full;
 use lazy_static::lazy_static;
 use lms_core::actions_db::actions_db::ActionsDB;
@@ -7,8 +8,7 @@ use lms_core::app_ctx::AppContext;
 use lms_core::authdb::auth_db::AuthDB;
 use lms_core::blueprint::Blueprint;
 use lms_core::config::reader::ConfigReader;
-use lms_core::http::request::Request;
-use lms_core::http::request_handler::handle_request;
+use lms_core::http::request_handler::{handle_request, handle_stream_request, is_stream};
 use std::collections::HashMap;
 use std::rc::Rc;
 use std::sync::{Arc, RwLock};
@@ -34,13 +34,19 @@ pub async fn fetch(
         req.url().map(|u| u.to_string())
     );
 
-    let req = to_request(req).await?;
-
+    let query = req.url().map_err(to_anyhow)?.query().map(|q| q.to_string());
     let env = Rc::new(env);
-    let wasm_ctx = match get_app_ctx(env, &req).await? {
+    let wasm_ctx = match get_app_ctx(env, query.as_deref()).await? {
         Ok(app_ctx) => app_ctx,
         Err(e) => return to_response(e).await,
     };
+
+    if is_stream(&to_method(req.method())?, &req.path()) {
+        let req = to_stream_request(req)?;
+        let resp = handle_stream_request(req, wasm_ctx.actions_db.clone()).await?;
+        return to_stream_response(resp);
+    }
+    let req = to_request(req).await?;
     let resp = handle_request(
         req,
         wasm_ctx.app_context.clone(),
@@ -55,15 +61,13 @@ pub async fn fetch(
 /// for future requests.
 async fn get_app_ctx(
     env: Rc<worker::Env>,
-    req: &Request,
+    query: Option<&str>,
 ) -> anyhow::Result<Result<Arc<WasmContext>, hyper::Response<Full<bytes::Bytes>>>> {
     if let Some(app_ctx) = read_app_ctx() {
         return Ok(Ok(app_ctx));
     }
     // Read context from cache
-    let file_path = req
-        .url
-        .query()
+    let file_path = query
         .and_then(|x| serde_qs::from_str::<HashMap<String, String>>(x).ok())
         .and_then(|x| x.get("config").cloned());
 
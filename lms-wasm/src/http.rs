@@ -1,10 +1,13 @@
+use crate::stream::SendStream;
 use crate::to_anyhow;
 use anyhow::{anyhow, Context};
+use futures_util::TryStreamExt;
 use http_body_util::{BodyExt, Full};
 use hyper::body::Bytes;
 use hyper::http::{HeaderName, HeaderValue};
 use lms_core::http::request::Request;
 use lms_core::http::response::Response;
+use lms_core::stream::{self, ByteStream};
 use lms_core::HttpIO;
 use reqwest::Client;
 use std::str::FromStr;
@@ -73,7 +76,8 @@ pub async fn to_response(
 }
 
 pub async fn to_request(mut req: worker::Request) -> anyhow::Result<Request> {
-    let body = req.text().await.map_err(to_anyhow)?;
+    // not text, a multipart body may hold binary files
+    let body = req.bytes().await.map_err(to_anyhow)?;
     let method = req.method();
     let uri = req.url().map_err(to_anyhow)?.as_str().to_string();
     let req_headers = req.headers();
@@ -86,16 +90,64 @@ pub async fn to_request(mut req: worker::Request) -> anyhow::Result<Request> {
         );
     }
 
+    // set by cloudflare to the address of the client
+    let client_ip = headers
+        .get("cf-connecting-ip")
+        .and_then(|ip| ip.to_str().ok())
+        .and_then(|ip| ip.parse().ok());
+
     let req = Request {
         method: to_method(method)?,
         url: hyper::Uri::from_str(&uri)?,
         headers,
         body: bytes::Bytes::from(body),
+        client_ip,
     };
 
     Ok(req)
 }
 
+/// Like [to_request], but the body is streamed instead of read in full.
+pub fn to_stream_request(mut req: worker::Request) -> anyhow::Result<hyper::Request<ByteStream>> {
+    let method = to_method(req.method())?;
+    let body = if method == hyper::Method::GET {
+        stream::once(Bytes::new())
+    } else {
+        Box::pin(SendStream::new(req.stream().map_err(to_anyhow)?))
+    };
+    let mut builder = hyper::Request::builder()
+        .method(method)
+        .uri(req.url().map_err(to_anyhow)?.as_str());
+    for (k, v) in req.headers() {
+        builder = builder.header(HeaderName::from_str(&k)?, HeaderValue::from_str(&v)?);
+    }
+    Ok(builder.body(body)?)
+}
+
+/// Like [to_response], but the body is streamed to the client.
+pub fn to_stream_response(
+    response: hyper::Response<ByteStream>,
+) -> anyhow::Result<worker::Response> {
+    let status = response.status().as_u16();
+    let headers = response.headers().clone();
+
+    let body = response
+        .into_body()
+        .map_err(|e| worker::Error::RustError(e.to_string()));
+    let mut w_response = worker::Response::from_stream(body)
+        .map_err(to_anyhow)?
+        .with_status(status);
+    let mut_headers = w_response.headers_mut();
+    for (name, value) in headers.iter() {
+        let value = String::from_utf8(value.as_bytes().to_vec())?;
+        mut_headers
+            .append(name.as_str(), &value)
+            .map_err(to_anyhow)?;
+    }
+
+    Ok(w_response)
+}
+
 pub fn to_method(method: worker::Method) -> anyhow::Result<hyper::Method> {
     let method = &*method.to_string().to_uppercase();
     match method {
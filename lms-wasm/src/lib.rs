@@ -6,6 +6,7 @@ mod handle;
 mod http;
 mod instance;
 mod runtime;
+mod stream;
 
 #[worker::event(fetch)]
 async fn fetch(
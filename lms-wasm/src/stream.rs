@@ -0,0 +1,29 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use futures_util::{Stream, StreamExt};
+
+use crate::to_anyhow;
+
+/// A stream of the worker runtime as a [lms_core::stream::ByteStream].
+pub struct SendStream(Pin<Box<worker::ByteStream>>);
+
+impl SendStream {
+    pub fn new(stream: worker::ByteStream) -> Self {
+        Self(Box::pin(stream))
+    }
+}
+
+// Workers are single threaded, see WasmFileIO.
+unsafe impl Send for SendStream {}
+
+impl Stream for SendStream {
+    type Item = anyhow::Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.0
+            .poll_next_unpin(cx)
+            .map(|chunk| chunk.map(|chunk| chunk.map(Bytes::from).map_err(to_anyhow)))
+    }
+}
@@ -52,4 +52,43 @@ pub enum Command {
         #[arg(short, long)]
         username: String,
     },
+    /// Re-keys the groups in ActionsDB to `{batch}_{course}` ids
+    /// and lists the groups that don't match the configured batches and courses
+    MigrateGroups {
+        /// Path for the configuration file or http(s) link to config file.
+        #[arg(required = true)]
+        config_path: String,
+        /// Group to re-key, as `old_id=batch_course`
+        #[arg(short, long)]
+        rename: Vec<String>,
+    },
+    /// Re-encrypts the user DB with the active key
+    /// Move the previous key to `auth.retiredKeys` before running it,
+    /// then run `encrypt-files` to re-wrap the keys of encrypted files
+    RotateKeys {
+        /// Path for the configuration file or http(s) link to config file.
+        #[arg(required = true)]
+        config_path: String,
+    },
+    /// Encrypts the files stored in plaintext in FileDB
+    /// and re-wraps the keys of the encrypted ones with the active key
+    EncryptFiles {
+        /// Path for the configuration file or http(s) link to config file.
+        #[arg(required = true)]
+        config_path: String,
+        /// FileDB to encrypt instead of `fileDb`, e.g. the dir of `lms-file-server`
+        #[arg(long)]
+        file_db: Option<String>,
+    },
+    /// Copies the users, actions and contents from the JSON files in the config
+    /// to a SQLite database, point `authDbPath`, `actionsDb` and `fileDb` to it afterwards
+    #[cfg(feature = "sqlite")]
+    ImportSqlite {
+        /// Path for the configuration file or http(s) link to config file.
+        #[arg(required = true)]
+        config_path: String,
+        /// Database to import into, as `sqlite://path`
+        #[arg(short, long)]
+        database: String,
+    },
 }
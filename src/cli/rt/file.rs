@@ -1,15 +1,29 @@
 use anyhow::{anyhow, Context};
-use lms_core::FileIO;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use futures_util::StreamExt;
+use lms_core::file_db::safe_name::PART_SUFFIX;
+use lms_core::stream::{self, ByteRange, ByteStream, FileStream};
+use lms_core::{FileIO, NotFound};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+/// Size of the chunks a file is streamed in.
+const CHUNK_SIZE: usize = 64 * 1024;
 
 #[derive(Default, Clone)]
 pub struct NativeFileIO {}
 
-async fn read(path: &str) -> anyhow::Result<String> {
+async fn read(path: &str) -> anyhow::Result<Vec<u8>> {
     let mut file = tokio::fs::File::open(path).await?;
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer).await?;
-    Ok(String::from_utf8(buffer)?)
+    Ok(buffer)
+}
+
+/// Keeps a missing file apart from the other failures to read `path`.
+fn read_error(path: &str, err: anyhow::Error) -> anyhow::Error {
+    match err.downcast_ref::<std::io::Error>() {
+        Some(io) if io.kind() == std::io::ErrorKind::NotFound => NotFound::new(path).into(),
+        _ => anyhow!("Failed to read file: {}", path),
+    }
 }
 
 async fn write<'a>(path: &'a str, content: &'a [u8]) -> anyhow::Result<()> {
@@ -18,6 +32,67 @@ async fn write<'a>(path: &'a str, content: &'a [u8]) -> anyhow::Result<()> {
     Ok(())
 }
 
+async fn read_stream(path: &str, range: Option<ByteRange>) -> anyhow::Result<FileStream> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let size = file.metadata().await?.len();
+    let range = stream::resolve(range, size)?;
+    file.seek(std::io::SeekFrom::Start(range.start)).await?;
+
+    let chunks = futures_util::stream::try_unfold(
+        (file.take(range.end - range.start), vec![0; CHUNK_SIZE]),
+        |(mut file, mut buffer)| async move {
+            let read = file.read(&mut buffer).await?;
+            if read == 0 {
+                return Ok(None);
+            }
+            let chunk = bytes::Bytes::copy_from_slice(&buffer[..read]);
+            Ok(Some((chunk, (file, buffer))))
+        },
+    );
+    Ok(FileStream {
+        size,
+        range,
+        stream: Box::pin(chunks),
+    })
+}
+
+/// Appends and syncs the content, so it's on disk once this returns.
+async fn append(path: &str, content: &[u8]) -> anyhow::Result<()> {
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(content).await?;
+    file.sync_data().await?;
+    Ok(())
+}
+
+/// Writes next to `path` and moves the file in place once the stream is done,
+/// so readers never see a partial file.
+async fn write_stream(path: &str, mut stream: ByteStream) -> anyhow::Result<u64> {
+    let part = format!("{}{}", path, PART_SUFFIX);
+    let mut file = tokio::fs::File::create(&part).await?;
+    let mut size = 0;
+    let result = async {
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            file.write_all(&chunk).await?;
+            size += chunk.len() as u64;
+        }
+        file.flush().await?;
+        // on disk before the rename, so a crash leaves either file whole
+        file.sync_all().await?;
+        tokio::fs::rename(&part, path).await?;
+        Ok(size)
+    }
+    .await;
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&part).await;
+    }
+    result
+}
+
 #[async_trait::async_trait]
 impl FileIO for NativeFileIO {
     async fn write<'a>(&'a self, path: &'a str, content: &'a [u8]) -> anyhow::Result<()> {
@@ -28,14 +103,44 @@ impl FileIO for NativeFileIO {
         Ok(())
     }
 
-    async fn read<'a>(&'a self, path: &'a str) -> anyhow::Result<String> {
-        let content = read(path)
-            .await
-            .map_err(|_err| anyhow!("Failed to read file: {}", path))?;
+    async fn read_bytes<'a>(&'a self, path: &'a str) -> anyhow::Result<Vec<u8>> {
+        let content = read(path).await.map_err(|err| read_error(path, err))?;
         log::info!("File read: {} ... ok", path);
         Ok(content)
     }
 
+    async fn read_stream<'a>(
+        &'a self,
+        path: &'a str,
+        range: Option<ByteRange>,
+    ) -> anyhow::Result<FileStream> {
+        let file = read_stream(path, range).await.map_err(|err| {
+            if err.is::<stream::RangeNotSatisfiable>() {
+                err
+            } else {
+                read_error(path, err)
+            }
+        })?;
+        log::info!("File stream: {} {} ... ok", path, file.content_range());
+        Ok(file)
+    }
+
+    async fn write_stream<'a>(&'a self, path: &'a str, stream: ByteStream) -> anyhow::Result<u64> {
+        let size = write_stream(path, stream)
+            .await
+            .context(format!("Failed to write file: {}", path))?;
+        log::info!("File write: {} ({} bytes) ... ok", path, size);
+        Ok(size)
+    }
+
+    async fn append<'a>(&'a self, path: &'a str, content: &'a [u8]) -> anyhow::Result<()> {
+        append(path, content)
+            .await
+            .context(format!("Failed to append to file: {}", path))?;
+        log::info!("File append: {} ({} bytes) ... ok", path, content.len());
+        Ok(())
+    }
+
     async fn create_dirs<'a>(&'a self, path: &'a str) -> anyhow::Result<()> {
         tokio::fs::create_dir_all(path)
             .await
@@ -43,6 +148,34 @@ impl FileIO for NativeFileIO {
         log::info!("Create directories: {} ... ok", path);
         Ok(())
     }
+
+    async fn delete<'a>(&'a self, path: &'a str) -> anyhow::Result<()> {
+        let metadata = tokio::fs::metadata(path)
+            .await
+            .context(format!("Failed to delete: {}", path))?;
+        if metadata.is_dir() {
+            tokio::fs::remove_dir_all(path).await
+        } else {
+            tokio::fs::remove_file(path).await
+        }
+        .context(format!("Failed to delete: {}", path))?;
+        log::info!("Delete: {} ... ok", path);
+        Ok(())
+    }
+
+    async fn list<'a>(&'a self, path: &'a str) -> anyhow::Result<Vec<String>> {
+        let mut entries = match tokio::fs::read_dir(path).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(anyhow!("Failed to list dir: {} with err: {}", path, err)),
+        };
+        let mut names = vec![];
+        while let Some(entry) = entries.next_entry().await? {
+            names.push(entry.file_name().to_string_lossy().to_string());
+        }
+        names.sort();
+        Ok(names)
+    }
 }
 
 #[cfg(test)]
@@ -78,6 +211,193 @@ mod tests {
         assert_eq!(read_content, String::from_utf8_lossy(content));
     }
 
+    #[tokio::test]
+    async fn test_binary_content() -> anyhow::Result<()> {
+        let tmp_file = NamedTempFile::new()?;
+        let tmp_path = tmp_file.path().to_str().unwrap();
+        let file_io = NativeFileIO::default();
+
+        let content = [0x25, 0x50, 0x44, 0x46, 0x00, 0xc3, 0x28, 0xff];
+        file_io.write(tmp_path, &content).await?;
+        assert_eq!(file_io.read_bytes(tmp_path).await?, content);
+        assert!(file_io.read(tmp_path).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_missing_file() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("missing.txt");
+        let path = path.to_str().unwrap();
+        let file_io = NativeFileIO::default();
+
+        let err = file_io.read_bytes(path).await.unwrap_err();
+        assert!(err.is::<NotFound>());
+        let err = file_io.read_stream(path, None).await.err().unwrap();
+        assert!(err.is::<NotFound>());
+        // a dir exists, so it's not reported as missing
+        let dir = dir.path().to_str().unwrap();
+        let err = file_io.read_bytes(dir).await.unwrap_err();
+        assert!(!err.is::<NotFound>());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_streams() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("large.bin");
+        let path = path.to_str().unwrap();
+        let file_io = NativeFileIO::default();
+
+        let content = (0..3 * CHUNK_SIZE + 10)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+        let chunks = content
+            .chunks(1000)
+            .map(|chunk| Ok(bytes::Bytes::copy_from_slice(chunk)))
+            .collect::<Vec<_>>();
+        let size = file_io
+            .write_stream(path, Box::pin(futures_util::stream::iter(chunks)))
+            .await?;
+        assert_eq!(size, content.len() as u64);
+
+        let file = file_io.read_stream(path, None).await?;
+        assert_eq!(file.range, 0..size);
+        assert_eq!(stream::collect(file.stream).await?, content);
+
+        let range = ByteRange::From {
+            start: CHUNK_SIZE as u64 - 5,
+            end: Some(2 * CHUNK_SIZE as u64 + 5),
+        };
+        let file = file_io.read_stream(path, Some(range)).await?;
+        assert_eq!(
+            stream::collect(file.stream).await?,
+            content[CHUNK_SIZE - 5..2 * CHUNK_SIZE + 6]
+        );
+
+        let err = file_io
+            .read_stream(
+                path,
+                Some(ByteRange::From {
+                    start: size,
+                    end: None,
+                }),
+            )
+            .await
+            .err()
+            .unwrap();
+        assert!(err.is::<stream::RangeNotSatisfiable>());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_write_stream() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("file.bin");
+        let path = path.to_str().unwrap();
+        let file_io = NativeFileIO::default();
+        file_io.write(path, b"old").await?;
+
+        let chunks = vec![
+            Ok(bytes::Bytes::from_static(b"new")),
+            Err(anyhow!("connection reset")),
+        ];
+        let result = file_io
+            .write_stream(path, Box::pin(futures_util::stream::iter(chunks)))
+            .await;
+        assert!(result.is_err());
+        assert_eq!(file_io.read(path).await?, "old");
+        assert_eq!(
+            file_io.list(dir.path().to_str().unwrap()).await?,
+            vec!["file.bin"]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_append() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("actions.json.log");
+        let path = path.to_str().unwrap();
+        let file_io = NativeFileIO::default();
+
+        file_io.append(path, b"first\n").await?;
+        file_io.append(path, b"second\n").await?;
+        assert_eq!(file_io.read(path).await?, "first\nsecond\n");
+        file_io.write(path, b"").await?;
+        file_io.append(path, b"third\n").await?;
+        assert_eq!(file_io.read(path).await?, "third\n");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_list_and_delete() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path().to_str().unwrap();
+        let file_io = NativeFileIO::default();
+        file_io.create_dirs(&format!("{}/a", root)).await?;
+        file_io
+            .write(&format!("{}/a/foo.txt", root), b"foo")
+            .await?;
+        file_io.write(&format!("{}/b.txt", root), b"b").await?;
+        assert_eq!(file_io.list(root).await?, vec!["a", "b.txt"]);
+
+        file_io.delete(&format!("{}/a", root)).await?;
+        file_io.delete(&format!("{}/b.txt", root)).await?;
+        assert!(file_io.list(root).await?.is_empty());
+        assert!(file_io.list(&format!("{}/a", root)).await?.is_empty());
+        assert!(file_io.delete(&format!("{}/a", root)).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_file_db_stays_in_dir() -> anyhow::Result<()> {
+        use lms_core::file_db::file_config::{FileHolder, InsertionInfo};
+        use lms_core::file_db::request_handler::FileRequestHandler;
+
+        let dir = tempfile::tempdir()?;
+        let secret = dir.path().join("auth.json");
+        let secret = secret.to_str().unwrap();
+        let file_io = NativeFileIO::default();
+        file_io.write(secret, b"secret").await?;
+
+        let files_dir = dir.path().join("files").to_string_lossy().to_string();
+        let handler = FileRequestHandler::new(super::super::init(), files_dir)?;
+        let info = InsertionInfo {
+            title: "title".to_string(),
+            description: "description".to_string(),
+            timestamp: 1,
+            end_time: None,
+            author: None,
+            assignment: None,
+            grade: None,
+            submission: None,
+        };
+        let notes = FileHolder {
+            name: "notes.pdf".to_string(),
+            content: b"%PDF".to_vec(),
+        };
+        let uid = handler.insert(info, vec![notes]).await?;
+
+        for name in [
+            "../../auth.json",
+            "../auth.json",
+            "config.json",
+            "CONFIG.JSON",
+        ] {
+            assert!(handler.get(&uid, name).await.is_err());
+            let content = stream::once(bytes::Bytes::from_static(b"evil"));
+            assert!(handler.put_stream(&uid, name, content).await.is_err());
+        }
+        assert!(handler.get_metadata("..").await.is_err());
+        assert!(handler.get("..", "auth.json").await.is_err());
+        assert!(handler.delete("..").await.is_err());
+
+        assert_eq!(file_io.read(secret).await?, "secret");
+        assert_eq!(handler.get_metadata(&uid).await?.title, "title");
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_write_error() {
         // Attempt to write to an invalid path
@@ -1,13 +1,22 @@
 use crate::cli::commands::{Cli, Command};
 use crate::cli::{self, rt};
 use clap::Parser;
-use lms_auth::local_crypto::hash_256;
+use lms_auth::local_crypto::{hash_256, hash_password};
+use lms_core::actions_db::actions_db::ActionsDB;
+use lms_core::actions_db::group_id::GroupId;
 use lms_core::app_ctx::AppContext;
 use lms_core::authdb::auth_actors::User;
-use lms_core::authdb::auth_db::user_entry;
+#[cfg(feature = "sqlite")]
+use lms_core::authdb::auth_store::SqliteAuthStore;
+use lms_core::authdb::auth_store::{self, LocalAuthStore};
 use lms_core::blueprint::Blueprint;
 use lms_core::config::reader::ConfigReader;
+use lms_core::file_db::request_handler::FileRequestHandler;
 use lms_core::runtime::TargetRuntime;
+#[cfg(feature = "sqlite")]
+use lms_core::sqlite::{self, SqliteDb};
+use std::collections::BTreeMap;
+use std::sync::Arc;
 
 pub async fn fork_run() -> anyhow::Result<()> {
     logger_init();
@@ -48,41 +57,139 @@ async fn run(cli: Cli, runtime: TargetRuntime) -> anyhow::Result<()> {
         } => {
             let config_module = config_reader.read(config_path).await?;
             let blueprint = Blueprint::try_from(config_module)?;
-            let mut users = blueprint.extensions.users.clone();
-            let app_context = AppContext { blueprint, runtime };
+            let store = auth_store::init(&blueprint.extensions.auth, &runtime)?;
 
-            users.insert(User {
+            let user = User {
                 username,
                 name,
-                password: hash_256(password),
+                // the browser sends sha256 of the password, see `AuthRequest::new`
+                password: hash_password(hash_256(password))?,
                 authority,
                 batch,
-            });
+                two_factor: None,
+                password_reset: None,
+            };
+            store
+                .upsert(user)
+                .await
+                .map_err(|e| anyhow::anyhow!("Unable to create user with error: {}", e))?;
 
             if print.unwrap_or_default() {
-                display(serde_json::to_string_pretty(&users).unwrap());
+                display(serde_json::to_string_pretty(&store.list().await?).unwrap());
             }
-
-            // TODO: Fix user_entry to take single user
-            user_entry(&app_context, users)
-                .await
-                .map_err(|e| anyhow::anyhow!("Unable to create user with error: {}", e))?;
         }
         Command::Delete {
             config_path,
             username,
         } => {
-            let mut config_module = config_reader.read(config_path).await?;
-            if let Some(users) = config_module.extensions.users.as_mut() {
-                users.delete(&username);
-            }
-
+            let config_module = config_reader.read(config_path).await?;
             let blueprint = Blueprint::try_from(config_module)?;
-            let users = blueprint.extensions.users.clone();
+            auth_store::init(&blueprint.extensions.auth, &runtime)?
+                .delete(&username)
+                .await
+                .map_err(|e| anyhow::anyhow!("Unable to delete user with error: {}", e))?;
+        }
+        Command::MigrateGroups {
+            config_path,
+            rename,
+        } => {
+            let config_module = config_reader.read(config_path).await?;
+            let blueprint = Blueprint::try_from(config_module)?;
+            let renames = rename
+                .iter()
+                .map(|rename| {
+                    let (old_id, new_id) = rename.split_once('=').ok_or(anyhow::anyhow!(
+                        "Invalid rename {}, expected old=new",
+                        rename
+                    ))?;
+                    Ok((old_id.to_string(), GroupId::parse(new_id, &blueprint)?))
+                })
+                .collect::<anyhow::Result<BTreeMap<_, _>>>()?;
+
             let app_context = AppContext { blueprint, runtime };
-            user_entry(&app_context, users)
+            let actions_db = ActionsDB::init(Arc::new(app_context)).await?;
+            let unknown = actions_db.migrate_groups(&renames).await?;
+            log::info!("Migrated {} groups", renames.len());
+            for group_id in unknown {
+                log::warn!("Unknown group {}, re-key it with `--rename`", group_id);
+            }
+        }
+        Command::RotateKeys { config_path } => {
+            let config_module = config_reader.read(config_path).await?;
+            let blueprint = Blueprint::try_from(config_module)?;
+            if blueprint.extensions.auth.db_path().starts_with("http") {
+                return Err(anyhow::anyhow!(
+                    "Keys of a remote AuthDB are managed by the remote server"
+                ));
+            }
+            let key_id = blueprint
+                .extensions
+                .auth
+                .keyring()
+                .active()
+                .id()
+                .to_string();
+            #[cfg(feature = "sqlite")]
+            if let Some(path) = lms_core::sqlite_path(blueprint.extensions.auth.db_path()) {
+                let store = SqliteAuthStore::open(blueprint.extensions.auth.clone(), path)?;
+                let users = store
+                    .reencrypt()
+                    .map_err(|e| anyhow::anyhow!("Unable to re-encrypt users with error: {}", e))?;
+                log::info!("Re-encrypted {} users with key `{}`", users, key_id);
+                return Ok(());
+            }
+            let store = LocalAuthStore::new(blueprint.extensions.auth.clone(), runtime);
+            let users = store
+                .reencrypt()
                 .await
-                .map_err(|e| anyhow::anyhow!("Unable to create user with error: {}", e))?;
+                .map_err(|e| anyhow::anyhow!("Unable to re-encrypt users with error: {}", e))?;
+            log::info!(
+                "Re-encrypted {} users with key `{}`",
+                users.get_all().len(),
+                key_id
+            );
+        }
+        Command::EncryptFiles {
+            config_path,
+            file_db,
+        } => {
+            let config_module = config_reader.read(config_path).await?;
+            let blueprint = Blueprint::try_from(config_module)?;
+            let file_db = file_db.unwrap_or(blueprint.server.file_db);
+            let keyring = blueprint.extensions.auth.keyring();
+            let files = FileRequestHandler::new(runtime, file_db.clone())?
+                .with_keyring(keyring.clone(), true);
+            let contents = files
+                .encrypt_all()
+                .await
+                .map_err(|e| anyhow::anyhow!("Unable to encrypt files with error: {}", e))?;
+            log::info!(
+                "Encrypted {} contents of {} with key `{}`",
+                contents,
+                file_db,
+                keyring.active().id()
+            );
+        }
+        #[cfg(feature = "sqlite")]
+        Command::ImportSqlite {
+            config_path,
+            database,
+        } => {
+            let config_module = config_reader.read(config_path).await?;
+            let blueprint = Blueprint::try_from(config_module)?;
+            let path = lms_core::sqlite_path(&database).ok_or(anyhow::anyhow!(
+                "Invalid database {}, expected sqlite://path",
+                database
+            ))?;
+            let db = SqliteDb::open(path)?;
+            let import = sqlite::import_json(&db, &blueprint, &runtime).await?;
+            log::info!(
+                "Imported {} users, {} groups and {} contents into {}",
+                import.users.len(),
+                import.activity.actions.len(),
+                import.contents.len(),
+                database
+            );
         }
     }
 
@@ -1,8 +1,15 @@
 use crate::cli::server::server_config::ServerConfig;
 
+use bytes::Bytes;
+use futures_util::TryStreamExt;
+use http_body_util::combinators::UnsyncBoxBody;
+use http_body_util::{BodyExt, BodyStream, StreamBody};
+use hyper::body::{Frame, Incoming};
 use hyper::service::service_fn;
 use lms_core::http;
-use lms_core::http::request_handler::handle_request;
+use lms_core::http::request_handler::{handle_request, handle_stream_request, is_stream};
+use lms_core::stream::ByteStream;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::net::TcpListener;
 use tokio::sync::oneshot;
@@ -22,26 +29,14 @@ pub async fn run(
     loop {
         let stream_result = listener.accept().await;
         match stream_result {
-            Ok((stream, _)) => {
+            Ok((stream, remote_addr)) => {
                 let io = hyper_util::rt::TokioIo::new(stream);
                 let sc = sc.clone();
                 tokio::spawn(async move {
                     let server = hyper::server::conn::http1::Builder::new()
                         .serve_connection(
                             io,
-                            service_fn(move |req| {
-                                let sc = sc.clone();
-                                async move {
-                                    let req = http::request::Request::from_hyper(req).await?;
-                                    handle_request(
-                                        req,
-                                        sc.app_ctx.clone(),
-                                        sc.auth_db.clone(),
-                                        sc.actions_db.clone(),
-                                    )
-                                    .await
-                                }
-                            }),
+                            service_fn(move |req| handle(req, remote_addr, sc.clone())),
                         )
                         .await;
                     if let Err(e) = server {
@@ -53,3 +48,28 @@ pub async fn run(
         }
     }
 }
+
+async fn handle(
+    req: hyper::Request<Incoming>,
+    remote_addr: SocketAddr,
+    sc: Arc<ServerConfig>,
+) -> anyhow::Result<hyper::Response<UnsyncBoxBody<Bytes, anyhow::Error>>> {
+    if is_stream(req.method(), req.uri().path()) {
+        let req = req.map(|body| {
+            let frames = BodyStream::new(body.map_err(anyhow::Error::from));
+            Box::pin(frames.try_filter_map(|frame| async move { Ok(frame.into_data().ok()) }))
+                as ByteStream
+        });
+        let resp = handle_stream_request(req, sc.actions_db.clone()).await?;
+        return Ok(resp.map(|body| StreamBody::new(body.map_ok(Frame::data)).boxed_unsync()));
+    }
+    let req = http::request::Request::from_hyper(req, Some(remote_addr.ip())).await?;
+    let resp = handle_request(
+        req,
+        sc.app_ctx.clone(),
+        sc.auth_db.clone(),
+        sc.actions_db.clone(),
+    )
+    .await?;
+    Ok(resp.map(|body| body.map_err(|never| match never {}).boxed_unsync()))
+}
@@ -8,7 +8,7 @@ pub mod test {
     use hyper::body::Bytes;
     use lms_core::http::response::Response;
     use lms_core::runtime::TargetRuntime;
-    use lms_core::{EnvIO, FileIO, HttpIO, Instance};
+    use lms_core::{EnvIO, FileIO, HttpIO, Instance, NotFound};
     use reqwest::Client;
     use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
@@ -63,18 +63,39 @@ pub mod test {
             Ok(())
         }
 
-        async fn read<'a>(&'a self, path: &'a str) -> anyhow::Result<String> {
-            let mut file = tokio::fs::File::open(path).await?;
+        async fn read_bytes<'a>(&'a self, path: &'a str) -> anyhow::Result<Vec<u8>> {
+            let mut file = match tokio::fs::File::open(path).await {
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(NotFound::new(path))?,
+                file => file?,
+            };
             let mut buffer = Vec::new();
             file.read_to_end(&mut buffer)
                 .await
                 .map_err(|e| anyhow!("{}", e))?;
-            Ok(String::from_utf8(buffer)?)
+            Ok(buffer)
         }
 
         async fn create_dirs<'a>(&'a self, _path: &'a str) -> Result<()> {
             Ok(())
         }
+
+        async fn delete<'a>(&'a self, path: &'a str) -> Result<()> {
+            if tokio::fs::metadata(path).await?.is_dir() {
+                tokio::fs::remove_dir_all(path).await?;
+            } else {
+                tokio::fs::remove_file(path).await?;
+            }
+            Ok(())
+        }
+
+        async fn list<'a>(&'a self, path: &'a str) -> Result<Vec<String>> {
+            let mut names = vec![];
+            let mut entries = tokio::fs::read_dir(path).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                names.push(entry.file_name().to_string_lossy().to_string());
+            }
+            Ok(names)
+        }
     }
     #[derive(Clone)]
     struct TestInstance {}
@@ -159,6 +180,9 @@ mod server_spec {
                 Ok(mut response) => {
                     if let Some(succ) = response.success.as_mut() {
                         succ.token = String::new(); // can't assert totp token
+                        if let Some(refresh_token) = succ.refresh_token.as_mut() {
+                            refresh_token.clear();
+                        }
                     }
                     insta::assert_snapshot!(serde_json::to_string_pretty(&response).unwrap());
                 }
@@ -181,6 +205,8 @@ mod server_spec {
                 password: hash_256("admin"),
                 authority: Authority::Admin,
                 batch: None,
+                two_factor: None,
+                password_reset: None,
             });
         }
         let auth = config_module.extensions.auth.as_ref().unwrap();